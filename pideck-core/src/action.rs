@@ -0,0 +1,106 @@
+// What a key or gesture sends. An action names its report explicitly, so the
+// same action behaves the same whichever layer it sits on.
+
+use core::fmt;
+
+use crate::modifier::Modifiers;
+use crate::mouse::MouseAction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Action {
+    #[default]
+    None,
+    // Falls through to the next active layer down
+    Transparent,
+    // Keyboard usage id, e.g. 0x68 for F13
+    Key(u8),
+    // Keyboard usage id with modifiers held along with it, e.g. Ctrl+Alt+T
+    Shortcut {
+        modifiers: u8,
+        keycode: u8,
+    },
+    // Consumer usage id, e.g. 0xCD for play/pause
+    Media(u16),
+    // System Control usage id, e.g. 0x82 for sleep
+    System(u8),
+    // Mouse movement, button or wheel
+    Mouse(MouseAction),
+    // Gamepad button, numbered from 1
+    Gamepad(u8),
+    // Layer active while the key is held
+    Momentary(u8),
+    // Layer switched on or off on each press
+    Toggle(u8),
+    // Layer active for the next key press only
+    OneShot(u8),
+    // Reboot into the RP2040 USB bootloader for flashing
+    Bootloader,
+    // Play the keystroke macro in this slot
+    Macro(u8),
+    // Type the text in this slot
+    Text(u8),
+    // Switch to the profile with this number
+    Profile(u8),
+    // Switch to the profile after the active one, round to the first
+    NextProfile,
+}
+
+impl Action {
+    pub fn is_none(&self) -> bool {
+        *self == Action::None
+    }
+
+    pub fn is_layer(&self) -> bool {
+        matches!(
+            self,
+            Action::Momentary(_) | Action::Toggle(_) | Action::OneShot(_)
+        )
+    }
+
+    /// Build an action from its name and code as used by the config protocol.
+    /// `none`, `trans` and `bootloader` take no code, the others need one.
+    /// A `shortcut` is built directly as it also needs its modifiers, `media` as
+    /// its usage is 16 bits, `system` and `gamepad` as they have a range to check,
+    /// `mouse` as it takes a name and `profile next` as it takes a word.
+    pub fn from_parts(name: &[u8], code: Option<u8>) -> Option<Self> {
+        match (name, code) {
+            (b"none", None) => Some(Action::None),
+            (b"trans", None) => Some(Action::Transparent),
+            (b"key", Some(code)) => Some(Action::Key(code)),
+            (b"momentary", Some(layer)) => Some(Action::Momentary(layer)),
+            (b"toggle", Some(layer)) => Some(Action::Toggle(layer)),
+            (b"oneshot", Some(layer)) => Some(Action::OneShot(layer)),
+            (b"bootloader", None) => Some(Action::Bootloader),
+            (b"macro", Some(slot)) => Some(Action::Macro(slot)),
+            (b"text", Some(slot)) => Some(Action::Text(slot)),
+            (b"profile", Some(profile)) => Some(Action::Profile(profile)),
+            _ => None,
+        }
+    }
+}
+
+// e.g. `none`, `key 0x68` or `toggle 1`, the inverse of `from_parts`
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::None => f.write_str("none"),
+            Action::Transparent => f.write_str("trans"),
+            Action::Key(code) => write!(f, "key {:#04x}", code),
+            Action::Shortcut { modifiers, keycode } => {
+                write!(f, "shortcut {} {:#04x}", Modifiers(*modifiers), keycode)
+            }
+            Action::Media(code) => write!(f, "media {:#04x}", code),
+            Action::System(code) => write!(f, "system {:#04x}", code),
+            Action::Mouse(action) => write!(f, "mouse {}", action.name()),
+            Action::Gamepad(button) => write!(f, "gamepad {}", button),
+            Action::Momentary(layer) => write!(f, "momentary {}", layer),
+            Action::Toggle(layer) => write!(f, "toggle {}", layer),
+            Action::OneShot(layer) => write!(f, "oneshot {}", layer),
+            Action::Bootloader => f.write_str("bootloader"),
+            Action::Macro(slot) => write!(f, "macro {}", slot),
+            Action::Text(slot) => write!(f, "text {}", slot),
+            Action::Profile(profile) => write!(f, "profile {}", profile),
+            Action::NextProfile => f.write_str("profile next"),
+        }
+    }
+}
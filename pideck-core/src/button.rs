@@ -0,0 +1,16 @@
+use crate::gesture::GestureEvent;
+use crate::key_config::KeyConfig;
+
+/// A debounced press or release, queued from the scan task to the HID task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub id: KeyConfig,
+    pub pressed: bool,
+}
+
+/// A recognised gesture on a button with gestures bound, queued the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonGesture {
+    pub id: KeyConfig,
+    pub event: GestureEvent,
+}
@@ -0,0 +1,263 @@
+// Combos: a set of buttons pressed together sends its own action instead of the
+// buttons' own keys, e.g. 1+2 toggling the media layer or 5+6 entering the
+// bootloader.
+//
+// The engine sits between the debouncer and the HID task. A press that could still
+// be the start of a combo is held back until either
+//   - the pressed set matches a combo that no bigger combo could still grow into,
+//     which fires the combo straight away
+//   - the combo window runs out, a held back button is released or a button that
+//     fits no combo with the others is pressed, which fires the combo the pressed
+//     set matches or else lets the held back presses through in order
+// Buttons of a fired combo send nothing of their own until they are released, and
+// the combo action is released with the first of them. Buttons in no combo are
+// never held back.
+
+use core::fmt;
+
+use enum_map::Enum;
+use heapless::Vec;
+
+use crate::action::Action;
+use crate::button::ButtonEvent;
+use crate::constants::BUTTON_COUNT;
+use crate::key_config::KeyConfig;
+use crate::layer::{MEDIA_LAYER, MOUSE_LAYER};
+
+pub const MAX_COMBOS: usize = 8;
+
+/// Bit for `id` in a combo's button set.
+pub fn button_bit(id: KeyConfig) -> u8 {
+    1 << id.into_usize()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Combo {
+    // Bit n set for button n + 1
+    pub buttons: u8,
+    pub action: Action,
+}
+
+impl Combo {
+    /// A combo takes at least two buttons and does something. Other slots are unused.
+    pub fn is_enabled(&self) -> bool {
+        self.buttons.count_ones() >= 2 && !self.action.is_none()
+    }
+}
+
+// e.g. `1+2 toggle 1`, or `none none` for an empty slot
+impl fmt::Display for Combo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.buttons == 0 {
+            f.write_str("none")?;
+        }
+        let mut separator = "";
+        for index in (0..8).filter(|index| self.buttons & (1 << index) != 0) {
+            write!(f, "{}{}", separator, index + 1)?;
+            separator = "+";
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComboConfig {
+    // Longest time from the first press of a combo to the last
+    pub window_us: u32,
+    pub combos: [Combo; MAX_COMBOS],
+}
+
+impl ComboConfig {
+    /// 1+2 toggles the media layer, like the keyboard/media mode switch it replaces,
+    /// and 2+3 the mouse layer.
+    pub const DEFAULT: ComboConfig = {
+        let mut combos = [Combo {
+            buttons: 0,
+            action: Action::None,
+        }; MAX_COMBOS];
+        combos[0] = Combo {
+            buttons: 0b00_0011,
+            action: Action::Toggle(MEDIA_LAYER),
+        };
+        combos[1] = Combo {
+            buttons: 0b00_0110,
+            action: Action::Toggle(MOUSE_LAYER),
+        };
+        ComboConfig {
+            window_us: 50_000,
+            combos,
+        }
+    };
+}
+
+impl Default for ComboConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// What the engine lets through to the HID task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboOutput {
+    // A press or release that is not part of a combo
+    Button(ButtonEvent),
+    // A combo's action pressed or released
+    Action { action: Action, pressed: bool },
+}
+
+pub struct ComboEngine {
+    config: ComboConfig,
+    // Presses held back while they could still be a combo, in press order
+    pending: Vec<KeyConfig, BUTTON_COUNT>,
+    pending_since: u32,
+    // Buttons of fired combos, their releases are swallowed
+    suppressed: u8,
+    // Buttons and action of each fired combo not released yet. The action is kept
+    // so a config change in between still releases what was pressed.
+    active: Vec<Combo, MAX_COMBOS>,
+}
+
+impl ComboEngine {
+    pub fn new(config: ComboConfig) -> Self {
+        ComboEngine {
+            config,
+            pending: Vec::new(),
+            pending_since: 0,
+            suppressed: 0,
+            active: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ComboConfig {
+        &self.config
+    }
+
+    pub fn configure(&mut self, config: ComboConfig) {
+        self.config = config;
+    }
+
+    /// Feed this scan's debounced edge of one button, if any, and pass what comes
+    /// out to `emit`. Called on every scan so the window runs out without waiting
+    /// for another edge.
+    pub fn update(
+        &mut self,
+        current_time: u32,
+        event: Option<ButtonEvent>,
+        mut emit: impl FnMut(ComboOutput),
+    ) {
+        if !self.pending.is_empty()
+            && current_time.wrapping_sub(self.pending_since) >= self.config.window_us
+        {
+            self.settle(&mut emit);
+        }
+
+        let event = match event {
+            Some(event) => event,
+            None => return,
+        };
+        let bit = button_bit(event.id);
+
+        if event.pressed {
+            let mut held = self.pending_buttons() | bit;
+            if !self.could_match(held) {
+                self.settle(&mut emit);
+                held = bit;
+                if !self.could_match(held) {
+                    emit(ComboOutput::Button(event));
+                    return;
+                }
+            }
+
+            if self.pending.is_empty() {
+                self.pending_since = current_time;
+            }
+            // Can't overflow, a button is only pending once
+            let _ = self.pending.push(event.id);
+
+            // Nothing bigger left to wait for
+            if let Some(combo) = self.matching(held) {
+                if self.candidates(held).count() == 1 {
+                    self.fire(combo, &mut emit);
+                }
+            }
+        } else {
+            if self.pending_buttons() & bit != 0 {
+                self.settle(&mut emit);
+            }
+
+            if self.suppressed & bit != 0 {
+                self.suppressed &= !bit;
+                if let Some(index) = self
+                    .active
+                    .iter()
+                    .position(|combo| combo.buttons & bit != 0)
+                {
+                    let combo = self.active.swap_remove(index);
+                    emit(ComboOutput::Action {
+                        action: combo.action,
+                        pressed: false,
+                    });
+                }
+            } else {
+                emit(ComboOutput::Button(event));
+            }
+        }
+    }
+
+    fn pending_buttons(&self) -> u8 {
+        self.pending
+            .iter()
+            .fold(0, |buttons, id| buttons | button_bit(*id))
+    }
+
+    // Enabled combos `buttons` is part of
+    fn candidates(&self, buttons: u8) -> impl Iterator<Item = &Combo> {
+        self.config
+            .combos
+            .iter()
+            .filter(move |combo| combo.is_enabled() && buttons & !combo.buttons == 0)
+    }
+
+    fn could_match(&self, buttons: u8) -> bool {
+        self.candidates(buttons).next().is_some()
+    }
+
+    fn matching(&self, buttons: u8) -> Option<Combo> {
+        self.candidates(buttons)
+            .find(|combo| combo.buttons == buttons)
+            .copied()
+    }
+
+    // Fire the combo the held back presses make up, or let them through
+    fn settle(&mut self, emit: &mut impl FnMut(ComboOutput)) {
+        match self.matching(self.pending_buttons()) {
+            Some(combo) => self.fire(combo, emit),
+            None => {
+                for id in self.pending.iter() {
+                    emit(ComboOutput::Button(ButtonEvent {
+                        id: *id,
+                        pressed: true,
+                    }));
+                }
+                self.pending.clear();
+            }
+        }
+    }
+
+    fn fire(&mut self, combo: Combo, emit: &mut impl FnMut(ComboOutput)) {
+        self.pending.clear();
+        self.suppressed |= combo.buttons;
+        // Can't overflow, active combos never share a button
+        let _ = self.active.push(combo);
+        emit(ComboOutput::Action {
+            action: combo.action,
+            pressed: true,
+        });
+    }
+}
+
+impl Default for ComboEngine {
+    fn default() -> Self {
+        Self::new(ComboConfig::DEFAULT)
+    }
+}
@@ -0,0 +1,589 @@
+// Byte-level format of the key config image kept in the reserved flash sector.
+//
+// Layout (all multi-byte values little endian):
+//   0..4   magic "PDCK"
+//   4      format version
+//   5      entry count (one entry per button)
+//   6      entry size in bytes
+//   7      active profile
+//   8..    every profile
+//   ..+4   CRC-32 of everything before it
+//
+// Each profile is:
+//   0..12  name, padded with 0
+//   12..   entries
+//   ..+34  combos
+//   ..+256 macros
+//   ..+3   lock layers
+//   ..+258 texts
+//   ..+168 labels
+//
+// Each entry (multi-byte values little endian) is:
+//   0..12  action on each layer
+//   12..14 debounce strategy and its value
+//   14..20 gesture thresholds in ms: double tap, long press, hold
+//   20..32 gesture actions: tap, double tap, long press, hold
+//
+// An action is its kind, its code and the modifiers of a shortcut, 0 for every
+// other kind. A media action's usage takes both bytes after the kind.
+//
+// The combo section is the combo window in ms as a u16, then every combo slot as
+// its button bits followed by its action.
+//
+// The macro section is every macro slot's bytecode, padded with 0 to its full
+// length.
+//
+// The lock section is the layer Num, Caps and Scroll Lock each switch on, 0xFF
+// for none.
+//
+// The text section is every text slot's UTF-8, padded with 0 to its full length,
+// then the Unicode input method and the host's keyboard layout.
+//
+// The label section is every button's label on each layer, padded with 0 to its
+// full length, in button order.
+//
+// Erased flash reads back as 0xFF so a missing image fails on the magic check.
+
+use enum_map::Enum;
+
+use crate::action::Action;
+use crate::combo::{Combo, ComboConfig, MAX_COMBOS};
+use crate::constants::LAYER_COUNT;
+use crate::debouncer::DebounceStrategy;
+use crate::gamepad;
+use crate::gesture::{Gesture, GestureConfig, GestureTiming};
+use crate::key_config::KeyConfig;
+use crate::label::{Labels, LABEL_LEN};
+use crate::layout::Layout;
+use crate::lock::{Lock, LockLayers};
+use crate::macro_player::{self, MacroCode, MACRO_LEN, MAX_MACROS};
+use crate::mouse::MouseAction;
+use crate::profile::{self, Profile, MAX_PROFILES, PROFILE_NAME_LEN};
+use crate::system_control;
+use crate::text::{self, TextConfig, UnicodeMethod, MAX_TEXTS, TEXT_LEN};
+
+pub const IMAGE_MAGIC: [u8; 4] = *b"PDCK";
+pub const IMAGE_VERSION: u8 = 13;
+
+pub const HEADER_LEN: usize = 8;
+pub const ENTRY_LEN: usize = 32;
+pub const COMBOS_LEN: usize = 2 + MAX_COMBOS * COMBO_LEN;
+pub const MACROS_LEN: usize = MAX_MACROS * MACRO_LEN;
+pub const LOCKS_LEN: usize = Lock::LENGTH;
+pub const TEXTS_LEN: usize = MAX_TEXTS * TEXT_LEN + 2;
+pub const LABELS_LEN: usize = KeyConfig::LENGTH * LAYER_COUNT * LABEL_LEN;
+pub const PROFILE_LEN: usize = LABELS_OFFSET + LABELS_LEN;
+pub const CRC_LEN: usize = 4;
+pub const IMAGE_LEN: usize = HEADER_LEN + MAX_PROFILES * PROFILE_LEN + CRC_LEN;
+
+const DEBOUNCE_DEFERRED: u8 = 0;
+const DEBOUNCE_EAGER: u8 = 1;
+const DEBOUNCE_INTEGRATOR: u8 = 2;
+
+const ACTION_NONE: u8 = 0;
+const ACTION_KEY: u8 = 1;
+const ACTION_MEDIA: u8 = 2;
+const ACTION_TRANSPARENT: u8 = 3;
+const ACTION_MOMENTARY: u8 = 4;
+const ACTION_TOGGLE: u8 = 5;
+const ACTION_ONE_SHOT: u8 = 6;
+const ACTION_BOOTLOADER: u8 = 7;
+const ACTION_MACRO: u8 = 8;
+const ACTION_SHORTCUT: u8 = 9;
+const ACTION_SYSTEM: u8 = 10;
+const ACTION_MOUSE: u8 = 11;
+const ACTION_GAMEPAD: u8 = 12;
+const ACTION_TEXT: u8 = 13;
+const ACTION_PROFILE: u8 = 14;
+const ACTION_NEXT_PROFILE: u8 = 15;
+
+const UNICODE_LINUX: u8 = 0;
+const UNICODE_WINDOWS: u8 = 1;
+const UNICODE_WINCOMPOSE: u8 = 2;
+
+const LAYOUT_US: u8 = 0;
+const LAYOUT_UK: u8 = 1;
+const LAYOUT_DE: u8 = 2;
+const LAYOUT_FR: u8 = 3;
+const LAYOUT_DVORAK: u8 = 4;
+
+const LOCK_NO_LAYER: u8 = 0xFF;
+
+const ACTION_LEN: usize = 3;
+const COMBO_LEN: usize = 1 + ACTION_LEN;
+const DEBOUNCE_OFFSET: usize = LAYER_COUNT * ACTION_LEN;
+const TIMING_OFFSET: usize = DEBOUNCE_OFFSET + 2;
+const GESTURES_OFFSET: usize = TIMING_OFFSET + 6;
+
+// Sections of a profile
+const ENTRIES_OFFSET: usize = PROFILE_NAME_LEN;
+const COMBOS_OFFSET: usize = ENTRIES_OFFSET + KeyConfig::LENGTH * ENTRY_LEN;
+const MACROS_OFFSET: usize = COMBOS_OFFSET + COMBOS_LEN;
+const LOCKS_OFFSET: usize = MACROS_OFFSET + MACROS_LEN;
+const TEXTS_OFFSET: usize = LOCKS_OFFSET + LOCKS_LEN;
+const LABELS_OFFSET: usize = TEXTS_OFFSET + TEXTS_LEN;
+
+// Everything stored for one button of a profile
+#[derive(Clone, Copy)]
+struct ButtonRecord {
+    layers: [Action; LAYER_COUNT],
+    debounce: DebounceStrategy,
+    gestures: GestureConfig,
+}
+
+impl ButtonRecord {
+    fn to_bytes(self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0u8; ENTRY_LEN];
+
+        for (chunk, action) in bytes[..DEBOUNCE_OFFSET]
+            .chunks_exact_mut(ACTION_LEN)
+            .zip(self.layers)
+        {
+            chunk.copy_from_slice(&action_to_bytes(action));
+        }
+
+        let kind = match self.debounce {
+            DebounceStrategy::Deferred { .. } => DEBOUNCE_DEFERRED,
+            DebounceStrategy::Eager { .. } => DEBOUNCE_EAGER,
+            DebounceStrategy::Integrator { .. } => DEBOUNCE_INTEGRATOR,
+        };
+        bytes[DEBOUNCE_OFFSET..TIMING_OFFSET].copy_from_slice(&[kind, self.debounce.value()]);
+
+        for (chunk, ms) in bytes[TIMING_OFFSET..GESTURES_OFFSET]
+            .chunks_exact_mut(2)
+            .zip(self.gestures.timing.to_ms())
+        {
+            chunk.copy_from_slice(&ms.to_le_bytes());
+        }
+
+        for (chunk, action) in bytes[GESTURES_OFFSET..]
+            .chunks_exact_mut(ACTION_LEN)
+            .zip(self.gestures.actions.values())
+        {
+            chunk.copy_from_slice(&action_to_bytes(*action));
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut layers = [Action::None; LAYER_COUNT];
+        for (action, chunk) in layers
+            .iter_mut()
+            .zip(bytes[..DEBOUNCE_OFFSET].chunks_exact(ACTION_LEN))
+        {
+            *action = action_from_bytes(chunk)?;
+        }
+
+        let value = bytes[DEBOUNCE_OFFSET + 1];
+        let debounce = match bytes[DEBOUNCE_OFFSET] {
+            DEBOUNCE_DEFERRED => DebounceStrategy::Deferred {
+                stable_us: value as u32 * 1000,
+            },
+            DEBOUNCE_EAGER => DebounceStrategy::Eager {
+                lockout_us: value as u32 * 1000,
+            },
+            DEBOUNCE_INTEGRATOR if value > 0 => DebounceStrategy::Integrator { samples: value },
+            _ => return None,
+        };
+
+        let mut ms = [0u16; 3];
+        for (ms, chunk) in ms
+            .iter_mut()
+            .zip(bytes[TIMING_OFFSET..GESTURES_OFFSET].chunks_exact(2))
+        {
+            *ms = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+
+        let mut gestures = GestureConfig {
+            timing: GestureTiming::from_ms(ms),
+            ..GestureConfig::default()
+        };
+        for (index, chunk) in bytes[GESTURES_OFFSET..ENTRY_LEN]
+            .chunks_exact(ACTION_LEN)
+            .enumerate()
+        {
+            gestures.actions[Gesture::from_usize(index)] = action_from_bytes(chunk)?;
+        }
+
+        Some(ButtonRecord {
+            layers,
+            debounce,
+            gestures,
+        })
+    }
+}
+
+fn action_to_bytes(action: Action) -> [u8; ACTION_LEN] {
+    match action {
+        Action::None => [ACTION_NONE, 0, 0],
+        Action::Transparent => [ACTION_TRANSPARENT, 0, 0],
+        Action::Key(code) => [ACTION_KEY, code, 0],
+        Action::Shortcut { modifiers, keycode } => [ACTION_SHORTCUT, keycode, modifiers],
+        Action::System(code) => [ACTION_SYSTEM, code, 0],
+        Action::Mouse(action) => [ACTION_MOUSE, action.into_usize() as u8, 0],
+        Action::Gamepad(button) => [ACTION_GAMEPAD, button, 0],
+        Action::Media(usage) => {
+            let [low, high] = usage.to_le_bytes();
+            [ACTION_MEDIA, low, high]
+        }
+        Action::Momentary(layer) => [ACTION_MOMENTARY, layer, 0],
+        Action::Toggle(layer) => [ACTION_TOGGLE, layer, 0],
+        Action::OneShot(layer) => [ACTION_ONE_SHOT, layer, 0],
+        Action::Bootloader => [ACTION_BOOTLOADER, 0, 0],
+        Action::Macro(slot) => [ACTION_MACRO, slot, 0],
+        Action::Text(slot) => [ACTION_TEXT, slot, 0],
+        Action::Profile(index) => [ACTION_PROFILE, index, 0],
+        Action::NextProfile => [ACTION_NEXT_PROFILE, 0, 0],
+    }
+}
+
+fn action_from_bytes(bytes: &[u8]) -> Option<Action> {
+    let code = bytes[1];
+    let action = match bytes[0] {
+        ACTION_NONE => Action::None,
+        ACTION_TRANSPARENT => Action::Transparent,
+        ACTION_KEY => Action::Key(code),
+        ACTION_MEDIA => Action::Media(u16::from_le_bytes([code, bytes[2]])),
+        ACTION_MOMENTARY => Action::Momentary(code),
+        ACTION_TOGGLE => Action::Toggle(code),
+        ACTION_ONE_SHOT => Action::OneShot(code),
+        ACTION_BOOTLOADER => Action::Bootloader,
+        ACTION_MACRO if (code as usize) < MAX_MACROS => Action::Macro(code),
+        ACTION_TEXT if (code as usize) < MAX_TEXTS => Action::Text(code),
+        ACTION_PROFILE if (code as usize) < MAX_PROFILES => Action::Profile(code),
+        ACTION_NEXT_PROFILE => Action::NextProfile,
+        ACTION_SYSTEM
+            if (system_control::FIRST_USAGE..=system_control::LAST_USAGE).contains(&code) =>
+        {
+            Action::System(code)
+        }
+        ACTION_MOUSE if (code as usize) < MouseAction::LENGTH => {
+            Action::Mouse(MouseAction::from_usize(code as usize))
+        }
+        ACTION_GAMEPAD if gamepad::is_button(code) => Action::Gamepad(code),
+        ACTION_SHORTCUT => Action::Shortcut {
+            modifiers: bytes[2],
+            keycode: code,
+        },
+        _ => return None,
+    };
+    if action.is_layer() && code as usize >= LAYER_COUNT {
+        return None;
+    }
+    Some(action)
+}
+
+fn combos_to_bytes(config: &ComboConfig) -> [u8; COMBOS_LEN] {
+    let mut bytes = [0u8; COMBOS_LEN];
+    let window_ms = (config.window_us / 1000).min(u16::MAX as u32) as u16;
+    bytes[..2].copy_from_slice(&window_ms.to_le_bytes());
+    for (chunk, combo) in bytes[2..]
+        .chunks_exact_mut(COMBO_LEN)
+        .zip(config.combos.iter())
+    {
+        chunk[0] = combo.buttons;
+        chunk[1..].copy_from_slice(&action_to_bytes(combo.action));
+    }
+    bytes
+}
+
+fn combos_from_bytes(bytes: &[u8]) -> Option<ComboConfig> {
+    let mut config = ComboConfig {
+        window_us: u16::from_le_bytes([bytes[0], bytes[1]]) as u32 * 1000,
+        combos: [Combo::default(); MAX_COMBOS],
+    };
+    for (combo, chunk) in config
+        .combos
+        .iter_mut()
+        .zip(bytes[2..COMBOS_LEN].chunks_exact(COMBO_LEN))
+    {
+        // No bits past the last button
+        if (chunk[0] as u32) >> KeyConfig::LENGTH != 0 {
+            return None;
+        }
+        *combo = Combo {
+            buttons: chunk[0],
+            action: action_from_bytes(&chunk[1..])?,
+        };
+    }
+    Some(config)
+}
+
+fn macros_from_bytes(bytes: &[u8]) -> Option<[MacroCode; MAX_MACROS]> {
+    let mut macros = [[0u8; MACRO_LEN]; MAX_MACROS];
+    for (code, chunk) in macros.iter_mut().zip(bytes.chunks_exact(MACRO_LEN)) {
+        macro_player::validate(chunk)?;
+        code.copy_from_slice(chunk);
+    }
+    Some(macros)
+}
+
+fn locks_to_bytes(locks: &LockLayers) -> [u8; LOCKS_LEN] {
+    let mut bytes = [LOCK_NO_LAYER; LOCKS_LEN];
+    for (byte, layer) in bytes.iter_mut().zip(locks.values()) {
+        if let Some(layer) = layer {
+            *byte = *layer;
+        }
+    }
+    bytes
+}
+
+fn locks_from_bytes(bytes: &[u8]) -> Option<LockLayers> {
+    let mut locks = LockLayers::default();
+    for (layer, byte) in locks.values_mut().zip(bytes) {
+        *layer = match *byte {
+            LOCK_NO_LAYER => None,
+            layer if (layer as usize) < LAYER_COUNT => Some(layer),
+            _ => return None,
+        };
+    }
+    Some(locks)
+}
+
+fn texts_to_bytes(config: &TextConfig) -> [u8; TEXTS_LEN] {
+    let mut bytes = [0u8; TEXTS_LEN];
+    for (chunk, text) in bytes.chunks_exact_mut(TEXT_LEN).zip(config.texts.iter()) {
+        chunk.copy_from_slice(text);
+    }
+    bytes[TEXTS_LEN - 2] = match config.method {
+        UnicodeMethod::Linux => UNICODE_LINUX,
+        UnicodeMethod::Windows => UNICODE_WINDOWS,
+        UnicodeMethod::WinCompose => UNICODE_WINCOMPOSE,
+    };
+    bytes[TEXTS_LEN - 1] = match config.layout {
+        Layout::Us => LAYOUT_US,
+        Layout::Uk => LAYOUT_UK,
+        Layout::De => LAYOUT_DE,
+        Layout::Fr => LAYOUT_FR,
+        Layout::Dvorak => LAYOUT_DVORAK,
+    };
+    bytes
+}
+
+fn texts_from_bytes(bytes: &[u8]) -> Option<TextConfig> {
+    let mut config = TextConfig::default();
+    for (text, chunk) in config.texts.iter_mut().zip(bytes.chunks_exact(TEXT_LEN)) {
+        text::validate(chunk)?;
+        text.copy_from_slice(chunk);
+    }
+    config.method = match bytes[TEXTS_LEN - 2] {
+        UNICODE_LINUX => UnicodeMethod::Linux,
+        UNICODE_WINDOWS => UnicodeMethod::Windows,
+        UNICODE_WINCOMPOSE => UnicodeMethod::WinCompose,
+        _ => return None,
+    };
+    config.layout = match bytes[TEXTS_LEN - 1] {
+        LAYOUT_US => Layout::Us,
+        LAYOUT_UK => Layout::Uk,
+        LAYOUT_DE => Layout::De,
+        LAYOUT_FR => Layout::Fr,
+        LAYOUT_DVORAK => Layout::Dvorak,
+        _ => return None,
+    };
+    Some(config)
+}
+
+fn labels_to_bytes(labels: &Labels, bytes: &mut [u8]) {
+    for (chunk, label) in bytes
+        .chunks_exact_mut(LABEL_LEN)
+        .zip(labels.values().flatten())
+    {
+        chunk.copy_from_slice(label);
+    }
+}
+
+fn labels_from_bytes(bytes: &[u8]) -> Option<Labels> {
+    let mut labels = Profile::default().labels;
+    for (label, chunk) in labels
+        .values_mut()
+        .flatten()
+        .zip(bytes.chunks_exact(LABEL_LEN))
+    {
+        profile::validate_name(chunk)?;
+        label.copy_from_slice(chunk);
+    }
+    Some(labels)
+}
+
+fn profile_to_bytes(profile: &Profile, bytes: &mut [u8]) {
+    bytes[..ENTRIES_OFFSET].copy_from_slice(&profile.name);
+    for (chunk, (id, layers)) in bytes[ENTRIES_OFFSET..COMBOS_OFFSET]
+        .chunks_exact_mut(ENTRY_LEN)
+        .zip(profile.keymap.iter())
+    {
+        let record = ButtonRecord {
+            layers: *layers,
+            debounce: profile.debounce[id],
+            gestures: profile.gestures[id],
+        };
+        chunk.copy_from_slice(&record.to_bytes());
+    }
+    bytes[COMBOS_OFFSET..MACROS_OFFSET].copy_from_slice(&combos_to_bytes(&profile.combos));
+    for (chunk, code) in bytes[MACROS_OFFSET..LOCKS_OFFSET]
+        .chunks_exact_mut(MACRO_LEN)
+        .zip(profile.macros.iter())
+    {
+        chunk.copy_from_slice(code);
+    }
+    bytes[LOCKS_OFFSET..TEXTS_OFFSET].copy_from_slice(&locks_to_bytes(&profile.lock_layers));
+    bytes[TEXTS_OFFSET..LABELS_OFFSET].copy_from_slice(&texts_to_bytes(&profile.texts));
+    labels_to_bytes(&profile.labels, &mut bytes[LABELS_OFFSET..PROFILE_LEN]);
+}
+
+fn profile_from_bytes(bytes: &[u8]) -> Option<Profile> {
+    let mut profile = Profile::default();
+    profile::validate_name(&bytes[..ENTRIES_OFFSET])?;
+    profile.name.copy_from_slice(&bytes[..ENTRIES_OFFSET]);
+    for (index, chunk) in bytes[ENTRIES_OFFSET..COMBOS_OFFSET]
+        .chunks_exact(ENTRY_LEN)
+        .enumerate()
+    {
+        let id = KeyConfig::from_usize(index);
+        let record = ButtonRecord::from_bytes(chunk)?;
+        profile.keymap[id] = record.layers;
+        profile.debounce[id] = record.debounce;
+        profile.gestures[id] = record.gestures;
+    }
+    profile.combos = combos_from_bytes(&bytes[COMBOS_OFFSET..MACROS_OFFSET])?;
+    profile.macros = macros_from_bytes(&bytes[MACROS_OFFSET..LOCKS_OFFSET])?;
+    profile.lock_layers = locks_from_bytes(&bytes[LOCKS_OFFSET..TEXTS_OFFSET])?;
+    profile.texts = texts_from_bytes(&bytes[TEXTS_OFFSET..LABELS_OFFSET])?;
+    profile.labels = labels_from_bytes(&bytes[LABELS_OFFSET..PROFILE_LEN])?;
+    Some(profile)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    BufferTooSmall,
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadLength,
+    BadCrc,
+    BadEntry,
+}
+
+/// Writes the image for `profiles` with `active` the active one into the start of
+/// `buf` and returns its length.
+pub fn encode(
+    profiles: &[Profile; MAX_PROFILES],
+    active: u8,
+    buf: &mut [u8],
+) -> Result<usize, StoreError> {
+    if buf.len() < IMAGE_LEN {
+        return Err(StoreError::BufferTooSmall);
+    }
+
+    buf[0..4].copy_from_slice(&IMAGE_MAGIC);
+    buf[4] = IMAGE_VERSION;
+    buf[5] = KeyConfig::LENGTH as u8;
+    buf[6] = ENTRY_LEN as u8;
+    buf[7] = active;
+
+    for (chunk, profile) in buf[HEADER_LEN..IMAGE_LEN - CRC_LEN]
+        .chunks_exact_mut(PROFILE_LEN)
+        .zip(profiles.iter())
+    {
+        profile_to_bytes(profile, chunk);
+    }
+
+    let crc = crc32(&buf[..IMAGE_LEN - CRC_LEN]);
+    buf[IMAGE_LEN - CRC_LEN..IMAGE_LEN].copy_from_slice(&crc.to_le_bytes());
+
+    Ok(IMAGE_LEN)
+}
+
+// Checks everything but the profiles themselves
+fn check(buf: &[u8]) -> Result<(), StoreError> {
+    if buf.len() < HEADER_LEN {
+        return Err(StoreError::BufferTooSmall);
+    }
+    if buf[0..4] != IMAGE_MAGIC {
+        return Err(StoreError::BadMagic);
+    }
+    if buf[4] != IMAGE_VERSION {
+        return Err(StoreError::UnsupportedVersion(buf[4]));
+    }
+    if buf[5] as usize != KeyConfig::LENGTH || buf[6] as usize != ENTRY_LEN {
+        return Err(StoreError::BadLength);
+    }
+    if buf.len() < IMAGE_LEN {
+        return Err(StoreError::BufferTooSmall);
+    }
+
+    let mut stored_crc = [0u8; CRC_LEN];
+    stored_crc.copy_from_slice(&buf[IMAGE_LEN - CRC_LEN..IMAGE_LEN]);
+    if crc32(&buf[..IMAGE_LEN - CRC_LEN]) != u32::from_le_bytes(stored_crc) {
+        return Err(StoreError::BadCrc);
+    }
+    Ok(())
+}
+
+/// Validates the image at the start of `buf`, copies it into `profiles` and
+/// returns the active profile.
+///
+/// Nothing is written until the whole image has been checked, so on error the
+/// caller's defaults are left untouched.
+pub fn decode(buf: &[u8], profiles: &mut [Profile; MAX_PROFILES]) -> Result<u8, StoreError> {
+    check(buf)?;
+    let active = buf[7];
+    let body = &buf[HEADER_LEN..IMAGE_LEN - CRC_LEN];
+    if active as usize >= MAX_PROFILES
+        || body
+            .chunks_exact(PROFILE_LEN)
+            .any(|chunk| profile_from_bytes(chunk).is_none())
+    {
+        return Err(StoreError::BadEntry);
+    }
+
+    for (profile, chunk) in profiles.iter_mut().zip(body.chunks_exact(PROFILE_LEN)) {
+        // Checked above
+        *profile = profile_from_bytes(chunk).unwrap();
+    }
+    Ok(active)
+}
+
+/// Makes `active` the active profile of the image at the start of `buf` without
+/// touching the profiles, so switching doesn't also save unsaved changes. Returns
+/// whether the image changed, it is left alone if `active` was active already.
+pub fn set_active_profile(buf: &mut [u8], active: u8) -> Result<bool, StoreError> {
+    check(buf)?;
+    if active as usize >= MAX_PROFILES {
+        return Err(StoreError::BadEntry);
+    }
+    if buf[7] == active {
+        return Ok(false);
+    }
+    buf[7] = active;
+    let crc = crc32(&buf[..IMAGE_LEN - CRC_LEN]);
+    buf[IMAGE_LEN - CRC_LEN..IMAGE_LEN].copy_from_slice(&crc.to_le_bytes());
+    Ok(true)
+}
+
+/// CRC-32 (IEEE 802.3, reflected, poly 0xEDB88320).
+/// Table driven, a byte at a time: the image is a few KB and is checked on every
+/// boot and save. The table is built at compile time and takes 1 KB of flash.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc = (crc >> 8) ^ CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize];
+    }
+    !crc
+}
+
+// CRC of every byte value, the bitwise loop run once for each
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
@@ -0,0 +1,22 @@
+use crate::consumer;
+
+// 25ms debounce - initial default
+pub const DEBOUNCE_US: u32 = 25_000;
+
+pub const BUTTON_COUNT: usize = 6;
+pub const LAYER_COUNT: usize = 4;
+
+pub const KEYCODE_1: u8 = 0x69;
+pub const KEYCODE_2: u8 = 0x6A;
+pub const KEYCODE_3: u8 = 0x6B;
+pub const KEYCODE_4: u8 = 0x6C;
+pub const KEYCODE_5: u8 = 0x6D;
+pub const KEYCODE_6: u8 = 0x6E;
+
+pub const MEDIAKEY_PLAYPAUSE: u16 = consumer::PLAY_PAUSE;
+pub const MEDIAKEY_VOLUP: u16 = consumer::VOLUME_UP;
+pub const MEDIAKEY_VOLDOWN: u16 = consumer::VOLUME_DOWN;
+pub const MEDIAKEY_MUTE: u16 = consumer::MUTE;
+pub const MEDIAKEY_PREVTRACK: u16 = consumer::PREV_TRACK;
+pub const MEDIAKEY_NEXTTRACK: u16 = consumer::NEXT_TRACK;
+pub const MEDIAKEY_NONE: u16 = 0x00;
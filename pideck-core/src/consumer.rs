@@ -0,0 +1,68 @@
+// Consumer page (0x0C) usages, what media actions send. Any 16-bit usage can be
+// given as a number, the common ones also by name, e.g. `media calculator`.
+
+use crate::protocol::{self, ErrorCode};
+
+pub const BRIGHTNESS_UP: u16 = 0x006F;
+pub const BRIGHTNESS_DOWN: u16 = 0x0070;
+pub const NEXT_TRACK: u16 = 0x00B5;
+pub const PREV_TRACK: u16 = 0x00B6;
+pub const STOP: u16 = 0x00B7;
+pub const EJECT: u16 = 0x00B8;
+pub const PLAY_PAUSE: u16 = 0x00CD;
+pub const MUTE: u16 = 0x00E2;
+pub const VOLUME_UP: u16 = 0x00E9;
+pub const VOLUME_DOWN: u16 = 0x00EA;
+pub const MEDIA_SELECT: u16 = 0x0183;
+pub const MAIL: u16 = 0x018A;
+pub const CALCULATOR: u16 = 0x0192;
+pub const FILE_BROWSER: u16 = 0x0194;
+pub const SCREEN_SAVER: u16 = 0x019E;
+pub const BROWSER_SEARCH: u16 = 0x0221;
+pub const BROWSER_HOME: u16 = 0x0223;
+pub const BROWSER_BACK: u16 = 0x0224;
+pub const BROWSER_FORWARD: u16 = 0x0225;
+pub const BROWSER_STOP: u16 = 0x0226;
+pub const BROWSER_REFRESH: u16 = 0x0227;
+pub const BROWSER_BOOKMARKS: u16 = 0x022A;
+
+pub const NAMES: [(&str, u16); 22] = [
+    ("brightness-up", BRIGHTNESS_UP),
+    ("brightness-down", BRIGHTNESS_DOWN),
+    ("next-track", NEXT_TRACK),
+    ("prev-track", PREV_TRACK),
+    ("stop", STOP),
+    ("eject", EJECT),
+    ("play-pause", PLAY_PAUSE),
+    ("mute", MUTE),
+    ("volume-up", VOLUME_UP),
+    ("volume-down", VOLUME_DOWN),
+    ("media-select", MEDIA_SELECT),
+    ("mail", MAIL),
+    ("calculator", CALCULATOR),
+    ("file-browser", FILE_BROWSER),
+    ("screen-saver", SCREEN_SAVER),
+    ("browser-search", BROWSER_SEARCH),
+    ("browser-home", BROWSER_HOME),
+    ("browser-back", BROWSER_BACK),
+    ("browser-forward", BROWSER_FORWARD),
+    ("browser-stop", BROWSER_STOP),
+    ("browser-refresh", BROWSER_REFRESH),
+    ("browser-bookmarks", BROWSER_BOOKMARKS),
+];
+
+/// Parse a usage from its name in `NAMES` or as a decimal or 0x prefixed hex number.
+pub fn parse(word: &[u8]) -> Result<u16, ErrorCode> {
+    match NAMES.iter().find(|(name, _)| name.as_bytes() == word) {
+        Some((_, usage)) => Ok(*usage),
+        None => protocol::parse_u16(word),
+    }
+}
+
+/// Name of a usage in `NAMES`, if it has one.
+pub fn name(usage: u16) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|(_, known)| *known == usage)
+        .map(|(name, _)| *name)
+}
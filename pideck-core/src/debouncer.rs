@@ -0,0 +1,171 @@
+// Times are in microseconds from any free running counter, e.g. the low word of
+// the RP2040 timer. Wrap around of the counter is handled.
+
+use crate::constants::DEBOUNCE_US;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebounceStrategy {
+    // React to the first edge, then ignore the pin until the lockout has passed
+    Eager { lockout_us: u32 },
+    // Only report a state once the pin has held it for the whole period
+    Deferred { stable_us: u32 },
+    // Count samples up while pressed and down while released, report at either end
+    Integrator { samples: u8 },
+}
+
+impl DebounceStrategy {
+    pub const DEFAULT: DebounceStrategy = DebounceStrategy::Deferred {
+        stable_us: DEBOUNCE_US,
+    };
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebounceStrategy::Eager { .. } => "eager",
+            DebounceStrategy::Deferred { .. } => "deferred",
+            DebounceStrategy::Integrator { .. } => "integrator",
+        }
+    }
+
+    /// Milliseconds for the timed strategies, samples for the integrator.
+    /// This is what the config protocol and flash image carry.
+    pub fn value(&self) -> u8 {
+        let ms = match self {
+            DebounceStrategy::Eager { lockout_us } => *lockout_us / 1000,
+            DebounceStrategy::Deferred { stable_us } => *stable_us / 1000,
+            DebounceStrategy::Integrator { samples } => return *samples,
+        };
+        ms.min(u8::MAX as u32) as u8
+    }
+
+    /// Inverse of `name` and `value`. An integrator needs at least one sample.
+    pub fn from_parts(name: &[u8], value: u8) -> Option<Self> {
+        match name {
+            b"eager" => Some(DebounceStrategy::Eager {
+                lockout_us: value as u32 * 1000,
+            }),
+            b"deferred" => Some(DebounceStrategy::Deferred {
+                stable_us: value as u32 * 1000,
+            }),
+            b"integrator" if value > 0 => Some(DebounceStrategy::Integrator { samples: value }),
+            _ => None,
+        }
+    }
+}
+
+impl Default for DebounceStrategy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub struct Debouncer {
+    pub current_state: bool,
+    pub stabilised_state: bool,
+    strategy: DebounceStrategy,
+    last_transition_time: u32,
+    // Eager: still inside the lockout after the last reported edge
+    locked: bool,
+    // Integrator: samples towards pressed, 0..=samples
+    integrator: u8,
+}
+
+impl Debouncer {
+    pub fn new(stability_period: u32) -> Self {
+        Self::with_strategy(DebounceStrategy::Deferred {
+            stable_us: stability_period,
+        })
+    }
+
+    pub fn with_strategy(strategy: DebounceStrategy) -> Self {
+        Debouncer {
+            current_state: false,
+            stabilised_state: false,
+            strategy,
+            last_transition_time: 0,
+            locked: false,
+            integrator: 0,
+        }
+    }
+
+    pub fn strategy(&self) -> DebounceStrategy {
+        self.strategy
+    }
+
+    /// Switch algorithm. The debounced state is kept so no spurious edge is reported.
+    pub fn set_strategy(&mut self, strategy: DebounceStrategy) {
+        self.strategy = strategy;
+        self.locked = false;
+        self.integrator = match strategy {
+            DebounceStrategy::Integrator { samples } if self.stabilised_state => samples,
+            _ => 0,
+        };
+    }
+
+    /// Feed a raw sample, returns the new stabilised state when it changes.
+    pub fn update(&mut self, current_time: u32, raw_state: bool) -> Option<bool> {
+        match self.strategy {
+            DebounceStrategy::Eager { lockout_us } => {
+                self.update_eager(current_time, raw_state, lockout_us)
+            }
+            DebounceStrategy::Deferred { stable_us } => {
+                self.update_deferred(current_time, raw_state, stable_us)
+            }
+            DebounceStrategy::Integrator { samples } => self.update_integrator(raw_state, samples),
+        }
+    }
+
+    fn update_deferred(&mut self, current_time: u32, raw_state: bool, period: u32) -> Option<bool> {
+        if self.current_state != raw_state {
+            self.current_state = raw_state;
+            self.last_transition_time = current_time;
+        }
+
+        if current_time.wrapping_sub(self.last_transition_time) >= period
+            && self.stabilised_state != self.current_state
+        {
+            self.stabilised_state = self.current_state;
+            return Some(self.stabilised_state);
+        }
+        None
+    }
+
+    fn update_eager(&mut self, current_time: u32, raw_state: bool, lockout: u32) -> Option<bool> {
+        self.current_state = raw_state;
+
+        if self.locked {
+            if current_time.wrapping_sub(self.last_transition_time) < lockout {
+                return None;
+            }
+            self.locked = false;
+        }
+
+        if raw_state != self.stabilised_state {
+            self.stabilised_state = raw_state;
+            self.last_transition_time = current_time;
+            self.locked = true;
+            return Some(raw_state);
+        }
+        None
+    }
+
+    fn update_integrator(&mut self, raw_state: bool, samples: u8) -> Option<bool> {
+        self.current_state = raw_state;
+
+        if raw_state {
+            self.integrator = self.integrator.saturating_add(1).min(samples);
+        } else {
+            self.integrator = self.integrator.saturating_sub(1);
+        }
+
+        let state = match self.integrator {
+            0 => false,
+            count if count >= samples => true,
+            _ => return None,
+        };
+        if state != self.stabilised_state {
+            self.stabilised_state = state;
+            return Some(state);
+        }
+        None
+    }
+}
@@ -0,0 +1,83 @@
+// Gamepad emulation. Buttons bound to gamepad actions press one of the gamepad's
+// own buttons, so OBS, games and overlays can bind to the deck without taking
+// over a keyboard shortcut. Gamepad buttons are numbered from 1 like their HID
+// usages, e.g. `gamepad 3`.
+
+use crate::protocol::{self, ErrorCode};
+use crate::report::GamepadReport;
+
+// Buttons the report descriptor declares, one bit each
+pub const BUTTON_COUNT: u8 = 16;
+
+/// Parse a gamepad button number, 1 to `BUTTON_COUNT`.
+pub fn parse(word: &[u8]) -> Result<u8, ErrorCode> {
+    let button = protocol::parse_byte(word)?;
+    if !is_button(button) {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(button)
+}
+
+pub fn is_button(button: u8) -> bool {
+    (1..=BUTTON_COUNT).contains(&button)
+}
+
+pub struct Gamepad {
+    // How many held deck buttons want each gamepad button, so two deck buttons
+    // on the same gamepad button do not let go of it when one is released
+    held: [u8; BUTTON_COUNT as usize],
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Gamepad {
+            held: [0; BUTTON_COUNT as usize],
+        }
+    }
+
+    /// Hold `button`. Returns the report to send, `None` for a button the gamepad
+    /// does not have.
+    pub fn press(&mut self, button: u8) -> Option<GamepadReport> {
+        let count = self.held.get_mut(button.checked_sub(1)? as usize)?;
+        *count = count.saturating_add(1);
+        Some(self.report())
+    }
+
+    /// Let go of `button`. Returns the report to send, `None` if it was not held.
+    pub fn release(&mut self, button: u8) -> Option<GamepadReport> {
+        let count = self.held.get_mut(button.checked_sub(1)? as usize)?;
+        if *count == 0 {
+            return None;
+        }
+        *count -= 1;
+        Some(self.report())
+    }
+
+    /// Let go of everything, returning the report to send if anything was held.
+    pub fn release_all(&mut self) -> Option<GamepadReport> {
+        let was_held = self.held.iter().any(|count| *count > 0);
+        *self = Gamepad::new();
+        if was_held {
+            Some(GamepadReport::default())
+        } else {
+            None
+        }
+    }
+
+    /// Every held button, button 1 in bit 0.
+    pub fn report(&self) -> GamepadReport {
+        let buttons = self
+            .held
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .fold(0, |buttons, (index, _)| buttons | (1 << index));
+        GamepadReport { buttons }
+    }
+}
+
+impl Default for Gamepad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
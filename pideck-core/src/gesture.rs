@@ -0,0 +1,216 @@
+// Per-button gesture recognition on top of the debounced press/release edges.
+//
+// A button with any gesture bound stops sending its plain key and instead reports
+// one of:
+//   tap         released before the long press threshold, and no second press
+//               inside the double tap window
+//   double-tap  pressed again inside the double tap window, fires on that press
+//   long-press  released after the long press threshold but before hold starts
+//   hold        still down at the hold threshold, held until the button is released
+//
+// Gestures that are not bound are not waited for, e.g. without a double tap a tap
+// fires straight on release. Times use the same free running microsecond counter
+// as the debouncer.
+
+use enum_map::{Enum, EnumMap};
+
+use crate::action::Action;
+
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    DoubleTap,
+    LongPress,
+    Hold,
+}
+
+impl Gesture {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gesture::Tap => "tap",
+            Gesture::DoubleTap => "double-tap",
+            Gesture::LongPress => "long-press",
+            Gesture::Hold => "hold",
+        }
+    }
+
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        match word {
+            b"tap" => Some(Gesture::Tap),
+            b"double-tap" => Some(Gesture::DoubleTap),
+            b"long-press" => Some(Gesture::LongPress),
+            b"hold" => Some(Gesture::Hold),
+            _ => None,
+        }
+    }
+}
+
+/// Thresholds for one button. A threshold of 0 turns its gesture off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GestureTiming {
+    // Longest gap between a tap's release and the second press
+    pub double_tap_us: u32,
+    // Shortest press that counts as a long press rather than a tap
+    pub long_press_us: u32,
+    // How long the button is down before hold starts, should be above long_press_us
+    pub hold_us: u32,
+}
+
+impl GestureTiming {
+    pub const DEFAULT: GestureTiming = GestureTiming {
+        double_tap_us: 250_000,
+        long_press_us: 500_000,
+        hold_us: 1_000_000,
+    };
+
+    /// Thresholds in milliseconds, as the config protocol and flash image carry them.
+    pub fn to_ms(&self) -> [u16; 3] {
+        [self.double_tap_us, self.long_press_us, self.hold_us]
+            .map(|us| (us / 1000).min(u16::MAX as u32) as u16)
+    }
+
+    pub fn from_ms(ms: [u16; 3]) -> Self {
+        GestureTiming {
+            double_tap_us: ms[0] as u32 * 1000,
+            long_press_us: ms[1] as u32 * 1000,
+            hold_us: ms[2] as u32 * 1000,
+        }
+    }
+}
+
+impl Default for GestureTiming {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Gesture bindings of one button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GestureConfig {
+    pub timing: GestureTiming,
+    pub actions: EnumMap<Gesture, Action>,
+}
+
+impl GestureConfig {
+    /// Whether the button reports gestures instead of its plain key.
+    pub fn is_enabled(&self) -> bool {
+        self.actions.values().any(|action| !action.is_none())
+    }
+}
+
+/// What the recognizer reports. Hold is the only gesture with a length, so it
+/// has a start and an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureEvent {
+    Tap,
+    DoubleTap,
+    LongPress,
+    HoldStart,
+    HoldEnd,
+}
+
+impl GestureEvent {
+    pub fn gesture(&self) -> Gesture {
+        match self {
+            GestureEvent::Tap => Gesture::Tap,
+            GestureEvent::DoubleTap => Gesture::DoubleTap,
+            GestureEvent::LongPress => Gesture::LongPress,
+            GestureEvent::HoldStart | GestureEvent::HoldEnd => Gesture::Hold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Pressed { since: u32 },
+    // Released after a short press, waiting to see if a second press follows
+    Released { at: u32 },
+    Holding,
+    // Second press of a double tap, nothing more to report until it is released
+    DoubleTapped,
+}
+
+pub struct GestureRecognizer {
+    timing: GestureTiming,
+    state: State,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: &GestureConfig) -> Self {
+        let mut recognizer = GestureRecognizer {
+            timing: GestureTiming::DEFAULT,
+            state: State::Idle,
+        };
+        recognizer.configure(config);
+        recognizer
+    }
+
+    /// Take on new bindings. Thresholds of unbound gestures are dropped so they
+    /// are never waited for.
+    pub fn configure(&mut self, config: &GestureConfig) {
+        let bound = |gesture: Gesture, us: u32| {
+            if config.actions[gesture].is_none() {
+                0
+            } else {
+                us
+            }
+        };
+        self.timing = GestureTiming {
+            double_tap_us: bound(Gesture::DoubleTap, config.timing.double_tap_us),
+            long_press_us: bound(Gesture::LongPress, config.timing.long_press_us),
+            hold_us: bound(Gesture::Hold, config.timing.hold_us),
+        };
+        self.state = State::Idle;
+    }
+
+    /// Feed the debounced edge from this scan, if any. Called on every scan so
+    /// the time based gestures fire without waiting for another edge.
+    pub fn update(&mut self, current_time: u32, pressed: Option<bool>) -> Option<GestureEvent> {
+        let timing = self.timing;
+        let elapsed = |since: u32| current_time.wrapping_sub(since);
+
+        // A lone tap is only known once the double tap window has passed
+        let mut event = None;
+        if let State::Released { at } = self.state {
+            if elapsed(at) >= timing.double_tap_us {
+                self.state = State::Idle;
+                event = Some(GestureEvent::Tap);
+            }
+        }
+
+        let (state, edge_event) = match (self.state, pressed) {
+            (State::Idle, Some(true)) => (
+                State::Pressed {
+                    since: current_time,
+                },
+                None,
+            ),
+            (State::Pressed { since }, None)
+                if timing.hold_us > 0 && elapsed(since) >= timing.hold_us =>
+            {
+                (State::Holding, Some(GestureEvent::HoldStart))
+            }
+            (State::Pressed { since }, Some(false)) => {
+                if timing.long_press_us > 0 && elapsed(since) >= timing.long_press_us {
+                    (State::Idle, Some(GestureEvent::LongPress))
+                } else if timing.double_tap_us > 0 {
+                    (State::Released { at: current_time }, None)
+                } else {
+                    (State::Idle, Some(GestureEvent::Tap))
+                }
+            }
+            (State::Released { .. }, Some(true)) => {
+                (State::DoubleTapped, Some(GestureEvent::DoubleTap))
+            }
+            (State::Holding, Some(false)) => (State::Idle, Some(GestureEvent::HoldEnd)),
+            (State::DoubleTapped, Some(false)) => (State::Idle, None),
+            (state, _) => (state, None),
+        };
+        self.state = state;
+
+        // `event` is only set when the window ran out, the edge can then only
+        // start a new press which reports nothing itself
+        edge_event.or(event)
+    }
+}
@@ -0,0 +1,618 @@
+use enum_map::{Enum, EnumMap};
+
+use crate::action::Action;
+use crate::combo::ComboConfig;
+use crate::constants::*;
+use crate::debouncer::DebounceStrategy;
+use crate::gamepad::Gamepad;
+use crate::gesture::{GestureConfig, GestureEvent};
+use crate::key_config::KeyConfig;
+use crate::label::{self, Label, LabelText};
+use crate::layer::{self, Keymap, LayerStack};
+use crate::layout::Layout;
+use crate::lock::{Lock, LockLayers, LockLeds};
+use crate::macro_player::{MacroCode, MacroPlayer, MacroStep, MAX_MACROS};
+use crate::modifier;
+use crate::mouse::Mouse;
+use crate::profile::{Profile, ProfileName, MAX_PROFILES};
+use crate::report::{KeyboardReport, MediaReport, ReportQueue, ReportSink, SystemReport};
+use crate::text::{Text, TextConfig, Typist, UnicodeMethod};
+
+pub struct CustomKeycode {
+    // Held keys as the NKRO bitmap, the modifier byte is filled in from the counts
+    keys: KeyboardReport,
+    // How many held actions want each modifier bit, so a shortcut released while a
+    // Ctrl key is still held leaves Ctrl down
+    modifier_counts: [u8; 8],
+}
+
+impl CustomKeycode {
+    pub fn new() -> Self {
+        CustomKeycode {
+            keys: KeyboardReport::default(),
+            modifier_counts: [0; 8],
+        }
+    }
+
+    /// Hold `keycode`, modifier keycodes go to the modifier byte. Returns false for
+    /// reserved keycodes past the modifiers.
+    pub fn press(&mut self, keycode: u8) -> bool {
+        match modifier::modifier_bit(keycode) {
+            Some(bit) => {
+                self.press_modifiers(bit);
+                true
+            }
+            None => self.keys.press(keycode),
+        }
+    }
+
+    /// Let go of `keycode`. Returns false if it was not held.
+    pub fn release(&mut self, keycode: u8) -> bool {
+        match modifier::modifier_bit(keycode) {
+            Some(bit) => self.release_modifiers(bit),
+            None => self.keys.release(keycode),
+        }
+    }
+
+    pub fn press_modifiers(&mut self, modifiers: u8) {
+        for (index, count) in self.modifier_counts.iter_mut().enumerate() {
+            if modifiers & (1 << index) != 0 {
+                *count = count.saturating_add(1);
+            }
+        }
+    }
+
+    /// Returns false if none of `modifiers` was held.
+    pub fn release_modifiers(&mut self, modifiers: u8) -> bool {
+        let mut released = false;
+        for (index, count) in self.modifier_counts.iter_mut().enumerate() {
+            if modifiers & (1 << index) != 0 && *count > 0 {
+                *count -= 1;
+                released = true;
+            }
+        }
+        released
+    }
+
+    /// Modifier byte of the report, every modifier something holds.
+    pub fn modifier(&self) -> u8 {
+        self.modifier_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .fold(0, |modifier, (index, _)| modifier | (1 << index))
+    }
+
+    pub fn clear(&mut self) {
+        self.keys = KeyboardReport::default();
+        self.modifier_counts = [0; 8];
+    }
+
+    /// Everything held, however many keys that is.
+    pub fn report(&self) -> KeyboardReport {
+        KeyboardReport {
+            modifier: self.modifier(),
+            ..self.keys
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.pressed().next().is_none() && self.modifier() == 0
+    }
+}
+
+impl Default for CustomKeycode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct HIDUtil {
+    pub custom_keycode: CustomKeycode,
+    // Every profile, the active one is what the buttons send
+    profiles: [Profile; MAX_PROFILES],
+    active: u8,
+    layers: LayerStack,
+    // Layer switched on by a one-shot action, dropped after the next key press
+    one_shot: Option<u8>,
+    // What each held button pressed, so the release undoes that same action even
+    // if the layers have changed in between
+    pressed: EnumMap<KeyConfig, Option<Action>>,
+    player: MacroPlayer,
+    mouse: Mouse,
+    gamepad: Gamepad,
+    leds: LockLeds,
+    typist: Typist,
+    // Reports the endpoints had no room for yet
+    reports: ReportQueue,
+}
+
+impl HIDUtil {
+    /// The first profile sends `keymap`, the others start out with the defaults.
+    pub fn new(keymap: Keymap) -> Self {
+        let mut profiles = [Profile::default(); MAX_PROFILES];
+        profiles[0].keymap = keymap;
+        HIDUtil {
+            custom_keycode: CustomKeycode::new(),
+            profiles,
+            active: 0,
+            layers: LayerStack::new(),
+            one_shot: None,
+            pressed: EnumMap::default(),
+            player: MacroPlayer::new(),
+            mouse: Mouse::new(),
+            gamepad: Gamepad::new(),
+            leds: LockLeds::default(),
+            typist: Typist::new(),
+            reports: ReportQueue::new(),
+        }
+    }
+
+    /// Action `button_id` sends with the layers that are active now.
+    pub fn action(&self, button_id: KeyConfig) -> Action {
+        self.layers.resolve(&self.profile().keymap[button_id])
+    }
+
+    pub fn push_input(&mut self, sink: &mut impl ReportSink, button_id: KeyConfig) {
+        let action = self.action(button_id);
+        self.pressed[button_id] = Some(action);
+        self.press_action(sink, action);
+
+        if !action.is_layer() {
+            if let Some(layer) = self.one_shot.take() {
+                self.layers.deactivate(layer);
+            }
+        }
+    }
+
+    pub fn release_input(&mut self, sink: &mut impl ReportSink, button_id: KeyConfig) {
+        if let Some(action) = self.pressed[button_id].take() {
+            self.release_action(sink, action);
+        }
+    }
+
+    /// Send the action bound to a recognised gesture. Everything but hold is a
+    /// press immediately followed by its release.
+    pub fn gesture_input(
+        &mut self,
+        sink: &mut impl ReportSink,
+        button_id: KeyConfig,
+        event: GestureEvent,
+    ) {
+        let action = self.profile().gestures[button_id].actions[event.gesture()];
+        match event {
+            GestureEvent::HoldStart => self.press_action(sink, action),
+            GestureEvent::HoldEnd => self.release_action(sink, action),
+            _ => {
+                self.press_action(sink, action);
+                self.release_action(sink, action);
+            }
+        }
+    }
+
+    /// Press or release the action of a combo the combo engine recognised.
+    pub fn combo_input(&mut self, sink: &mut impl ReportSink, action: Action, pressed: bool) {
+        if pressed {
+            self.press_action(sink, action);
+        } else {
+            self.release_action(sink, action);
+        }
+    }
+
+    /// The active profile, everything below but the layers and locks is its config.
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active as usize]
+    }
+
+    fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.active as usize]
+    }
+
+    pub fn profiles(&self) -> &[Profile; MAX_PROFILES] {
+        &self.profiles
+    }
+
+    /// Number of the active profile.
+    pub fn active_profile(&self) -> u8 {
+        self.active
+    }
+
+    /// Replace a whole profile, e.g. with one loaded from flash. Layers already
+    /// switched on by lit locks stay as they are until the next switch.
+    pub fn set_profile(&mut self, index: u8, profile: Profile) {
+        self.profiles[index as usize] = profile;
+    }
+
+    pub fn set_profile_name(&mut self, index: u8, name: ProfileName) {
+        self.profiles[index as usize].name = name;
+    }
+
+    /// Number of the first profile named `name`.
+    pub fn find_profile(&self, name: &ProfileName) -> Option<u8> {
+        self.profiles
+            .iter()
+            .position(|profile| profile.name == *name)
+            .map(|index| index as u8)
+    }
+
+    /// Switch to another profile. Everything held is released and the layers start
+    /// over from the base layer, plus the layers of the new profile's lit locks.
+    /// Returns false if `index` is already active or out of range.
+    pub fn select_profile(&mut self, sink: &mut impl ReportSink, index: u8) -> bool {
+        if index == self.active || index as usize >= MAX_PROFILES {
+            return false;
+        }
+        self.release_all(sink);
+        self.layers = LayerStack::new();
+        self.one_shot = None;
+        self.active = index;
+        for (lock, layer) in self.profile().lock_layers {
+            if let Some(layer) = layer {
+                if self.leds.is_on(lock) {
+                    self.layers.activate(layer);
+                }
+            }
+        }
+        true
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.profile().keymap
+    }
+
+    pub fn set_action(&mut self, layer: u8, button_id: KeyConfig, action: Action) {
+        self.profile_mut().keymap[button_id][layer as usize] = action;
+    }
+
+    /// Set the base layer keycode and media layer media key of `button_id`.
+    pub fn set_keys(&mut self, button_id: KeyConfig, keys: (u8, u16)) {
+        layer::set_keys(&mut self.profile_mut().keymap[button_id], keys);
+    }
+
+    pub fn label(&self, layer: u8, button_id: KeyConfig) -> &Label {
+        &self.profile().labels[button_id][layer as usize]
+    }
+
+    pub fn set_label(&mut self, layer: u8, button_id: KeyConfig, label: Label) {
+        self.profile_mut().labels[button_id][layer as usize] = label;
+    }
+
+    /// What the display shows on every button with the layers that are active now:
+    /// the label on the layer its action comes from, or a name for the action.
+    pub fn label_texts(&self) -> EnumMap<KeyConfig, LabelText> {
+        let profile = self.profile();
+        EnumMap::from_fn(|button_id| {
+            let actions = &profile.keymap[button_id];
+            match self.layers.layer_of(actions) {
+                Some(layer) => label::text(
+                    &profile.labels[button_id][layer as usize],
+                    actions[layer as usize],
+                ),
+                None => LabelText::new(),
+            }
+        })
+    }
+
+    /// Buttons held down that pressed an action. A button with gestures isn't, it
+    /// only acts once its gesture is known.
+    pub fn held(&self) -> EnumMap<KeyConfig, bool> {
+        EnumMap::from_fn(|button_id| self.pressed[button_id].is_some())
+    }
+
+    pub fn gestures(&self, button_id: KeyConfig) -> &GestureConfig {
+        &self.profile().gestures[button_id]
+    }
+
+    pub fn set_gestures(&mut self, button_id: KeyConfig, gestures: GestureConfig) {
+        self.profile_mut().gestures[button_id] = gestures;
+    }
+
+    // Debounce and combos run outside, in the button scan, they are only kept here
+    // so they switch and save with the rest of the profile
+
+    pub fn debounce(&self, button_id: KeyConfig) -> DebounceStrategy {
+        self.profile().debounce[button_id]
+    }
+
+    pub fn set_debounce(&mut self, button_id: KeyConfig, strategy: DebounceStrategy) {
+        self.profile_mut().debounce[button_id] = strategy;
+    }
+
+    pub fn combos(&self) -> &ComboConfig {
+        &self.profile().combos
+    }
+
+    pub fn set_combos(&mut self, combos: ComboConfig) {
+        self.profile_mut().combos = combos;
+    }
+
+    pub fn layers(&self) -> &LayerStack {
+        &self.layers
+    }
+
+    // Held keys are released first so nothing gets stuck on the host
+    pub fn set_layer(&mut self, sink: &mut impl ReportSink, layer: u8, active: bool) {
+        if self.layers.is_active(layer) == active {
+            return;
+        }
+        self.release_all(sink);
+        if active {
+            self.layers.activate(layer);
+        } else {
+            self.layers.deactivate(layer);
+        }
+    }
+
+    /// Lock LEDs as the host last reported them.
+    pub fn leds(&self) -> LockLeds {
+        self.leds
+    }
+
+    /// Take the lock LEDs from a host output report, switching the layers bound to
+    /// the locks that changed. Returns true if any LED changed.
+    pub fn set_leds(&mut self, sink: &mut impl ReportSink, leds: LockLeds) -> bool {
+        if leds == self.leds {
+            return false;
+        }
+        let old = core::mem::replace(&mut self.leds, leds);
+        for (lock, layer) in self.profile().lock_layers {
+            match layer {
+                Some(layer) if leds.is_on(lock) != old.is_on(lock) => {
+                    self.set_layer(sink, layer, leds.is_on(lock))
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
+    pub fn lock_layers(&self) -> &LockLayers {
+        &self.profile().lock_layers
+    }
+
+    /// Bind `lock` to `layer`, or unbind it with `None`. The layer follows the lock
+    /// from now on, so it is switched on straight away if the lock is lit.
+    pub fn set_lock_layer(&mut self, sink: &mut impl ReportSink, lock: Lock, layer: Option<u8>) {
+        let old = core::mem::replace(&mut self.profile_mut().lock_layers[lock], layer);
+        if !self.leds.is_on(lock) || old == layer {
+            return;
+        }
+        if let Some(old) = old {
+            self.set_layer(sink, old, false);
+        }
+        if let Some(layer) = layer {
+            self.set_layer(sink, layer, true);
+        }
+    }
+
+    pub fn macros(&self) -> &[MacroCode; MAX_MACROS] {
+        &self.profile().macros
+    }
+
+    pub fn set_macro(&mut self, slot: u8, code: MacroCode) {
+        self.profile_mut().macros[slot as usize] = code;
+    }
+
+    pub fn text_config(&self) -> &TextConfig {
+        &self.profile().texts
+    }
+
+    pub fn set_text(&mut self, slot: u8, text: Text) {
+        self.profile_mut().texts.texts[slot as usize] = text;
+    }
+
+    pub fn set_unicode_method(&mut self, method: UnicodeMethod) {
+        self.profile_mut().texts.method = method;
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.profile_mut().texts.layout = layout;
+    }
+
+    /// Whether a macro plays or a text is being typed, they share the macro steps.
+    pub fn is_playing_macro(&self) -> bool {
+        self.player.is_playing() || self.typist.is_typing()
+    }
+
+    /// Send the next step of the playing macro or text, if it is due. Call once
+    /// per host poll interval while `is_playing_macro`, so each report is read
+    /// before the next one replaces it.
+    pub fn macro_step(&mut self, sink: &mut impl ReportSink, current_time: u32) {
+        let step = if self.player.is_playing() {
+            self.player.next_step(current_time)
+        } else {
+            self.typist.next_step()
+        };
+        match step {
+            Some(MacroStep::Press(keycode)) => self.press_action(sink, Action::Key(keycode)),
+            Some(MacroStep::Release(keycode)) => self.release_action(sink, Action::Key(keycode)),
+            Some(MacroStep::Media(usage_id)) => {
+                self.reports.send_media(sink, MediaReport { usage_id })
+            }
+            None => {}
+        }
+    }
+
+    /// Send the reports that are waiting for an endpoint the host has read since.
+    /// Call whenever the host may have polled one.
+    pub fn flush_reports(&mut self, sink: &mut impl ReportSink) {
+        self.reports.flush(sink);
+    }
+
+    /// Whether a mouse movement or wheel is held, so `mouse_tick` has reports to send.
+    pub fn is_moving_mouse(&self) -> bool {
+        self.mouse.is_active()
+    }
+
+    /// Send one tick of mouse movement and scrolling. Call every `mouse::TICK_MS`
+    /// while `is_moving_mouse`.
+    pub fn mouse_tick(&mut self, sink: &mut impl ReportSink) {
+        if let Some(report) = self.mouse.tick() {
+            self.reports.send_mouse(sink, report);
+        }
+    }
+
+    fn keyboard_report(&self) -> KeyboardReport {
+        self.custom_keycode.report()
+    }
+
+    // Keyboard actions share the held key set so everything held ends up in one report
+    fn press_action(&mut self, sink: &mut impl ReportSink, action: Action) {
+        match action {
+            Action::None | Action::Transparent => {}
+            Action::Key(keycode) => {
+                if self.custom_keycode.press(keycode) {
+                    self.reports.send_keyboard(sink, self.keyboard_report());
+                }
+            }
+            // Modifiers go down in the same report as the key
+            Action::Shortcut { modifiers, keycode } => {
+                self.custom_keycode.press_modifiers(modifiers);
+                self.custom_keycode.press(keycode);
+                self.reports.send_keyboard(sink, self.keyboard_report());
+            }
+            Action::Media(usage_id) => self.reports.send_media(sink, MediaReport { usage_id }),
+            Action::System(usage_id) => self.reports.send_system(sink, SystemReport { usage_id }),
+            // Movement starts on the next tick
+            Action::Mouse(action) => {
+                if let Some(report) = self.mouse.press(action) {
+                    self.reports.send_mouse(sink, report);
+                }
+            }
+            Action::Gamepad(button) => {
+                if let Some(report) = self.gamepad.press(button) {
+                    self.reports.send_gamepad(sink, report);
+                }
+            }
+            Action::Momentary(layer) => {
+                self.layers.activate(layer);
+            }
+            Action::Toggle(layer) => self.layers.toggle(layer),
+            Action::OneShot(layer) => {
+                if self.layers.activate(layer) {
+                    self.one_shot = Some(layer);
+                }
+            }
+            Action::Bootloader => {
+                // Let the host see everything released before the device drops off
+                self.release_all(sink);
+                sink.enter_bootloader();
+            }
+            // Ignored while another macro or text is still playing
+            Action::Macro(slot) => {
+                if let Some(code) = self.profiles[self.active as usize]
+                    .macros
+                    .get(slot as usize)
+                {
+                    if !self.typist.is_typing() {
+                        self.player.start(code);
+                    }
+                }
+            }
+            Action::Text(slot) => {
+                let texts = &self.profiles[self.active as usize].texts;
+                if let Some(text) = texts.texts.get(slot as usize) {
+                    if !self.player.is_playing() {
+                        self.typist.start(text, texts.layout, texts.method);
+                    }
+                }
+            }
+            Action::Profile(index) => {
+                self.select_profile(sink, index);
+            }
+            Action::NextProfile => {
+                let next = (self.active as usize + 1) % MAX_PROFILES;
+                self.select_profile(sink, next as u8);
+            }
+        }
+    }
+
+    fn release_action(&mut self, sink: &mut impl ReportSink, action: Action) {
+        match action {
+            Action::Key(keycode) => {
+                if self.custom_keycode.release(keycode) {
+                    self.reports.send_keyboard(sink, self.keyboard_report());
+                }
+            }
+            Action::Shortcut { modifiers, keycode } => {
+                let released = self.custom_keycode.release(keycode);
+                if self.custom_keycode.release_modifiers(modifiers) || released {
+                    self.reports.send_keyboard(sink, self.keyboard_report());
+                }
+            }
+            Action::Media(_) => self.reports.send_media(
+                sink,
+                MediaReport {
+                    usage_id: MEDIAKEY_NONE,
+                },
+            ),
+            Action::System(_) => self.reports.send_system(sink, SystemReport::default()),
+            Action::Mouse(action) => {
+                if let Some(report) = self.mouse.release(action) {
+                    self.reports.send_mouse(sink, report);
+                }
+            }
+            Action::Gamepad(button) => {
+                if let Some(report) = self.gamepad.release(button) {
+                    self.reports.send_gamepad(sink, report);
+                }
+            }
+            Action::Momentary(layer) => {
+                self.layers.deactivate(layer);
+            }
+            Action::None
+            | Action::Transparent
+            | Action::Toggle(_)
+            | Action::OneShot(_)
+            | Action::Bootloader
+            | Action::Macro(_)
+            | Action::Text(_)
+            | Action::Profile(_)
+            | Action::NextProfile => {}
+        }
+    }
+
+    // Everything goes up in one report per endpoint, the state it ends in
+    fn release_all(&mut self, sink: &mut impl ReportSink) {
+        let (mut media, mut system) = (false, false);
+        for button_id in 0..BUTTON_COUNT {
+            match self.pressed[KeyConfig::from_usize(button_id)].take() {
+                Some(Action::Media(_)) => media = true,
+                Some(Action::System(_)) => system = true,
+                Some(Action::Momentary(layer)) => {
+                    self.layers.deactivate(layer);
+                }
+                // Keys, mouse buttons and gamepad buttons are all let go below,
+                // along with anything a held gesture still has down
+                _ => {}
+            }
+        }
+        if !self.custom_keycode.is_empty() {
+            self.custom_keycode.clear();
+            self.reports.send_keyboard(sink, self.keyboard_report());
+        }
+        if media {
+            self.reports.send_media(
+                sink,
+                MediaReport {
+                    usage_id: MEDIAKEY_NONE,
+                },
+            );
+        }
+        if system {
+            self.reports.send_system(sink, SystemReport::default());
+        }
+        if let Some(report) = self.mouse.release_all() {
+            self.reports.send_mouse(sink, report);
+        }
+        if let Some(report) = self.gamepad.release_all() {
+            self.reports.send_gamepad(sink, report);
+        }
+    }
+}
+
+impl Default for HIDUtil {
+    fn default() -> Self {
+        Self::new(layer::default_keymap())
+    }
+}
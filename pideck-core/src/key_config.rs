@@ -0,0 +1,33 @@
+// TODO: Change all this to an enum map
+
+use enum_map::{enum_map, Enum, EnumMap};
+
+// use usbd_hid::descriptor::MediaKey;
+
+use crate::constants::*;
+
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum KeyConfig {
+    One,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+}
+
+impl KeyConfig {
+    // Returns the whole default map rather than a single key, kept as `new` as
+    // that is how the rest of the code has always built it
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> EnumMap<KeyConfig, (u8, u16)> {
+        enum_map! {
+            KeyConfig::One => (KEYCODE_1, MEDIAKEY_PLAYPAUSE),
+            KeyConfig::Two => (KEYCODE_2, MEDIAKEY_NONE),
+            KeyConfig::Three => (KEYCODE_3, MEDIAKEY_VOLUP),
+            KeyConfig::Four => (KEYCODE_4, MEDIAKEY_VOLDOWN),
+            KeyConfig::Five => (KEYCODE_5, MEDIAKEY_PREVTRACK),
+            KeyConfig::Six => (KEYCODE_6, MEDIAKEY_NEXTTRACK),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Key labels: what the display shows on each button. Every button has a label on
+// every layer, so e.g. a layer of media keys can say so, and the display shows the
+// one from the layer the button's action comes from.
+//
+// A label is printable ASCII without spaces like a profile name, `none` for no
+// label. A button without a label shows a short name for its action instead, e.g.
+// `0x68` for a key or `mo 1` for a momentary layer.
+
+use core::fmt::{self, Write};
+use core::str;
+
+use enum_map::EnumMap;
+use heapless::String;
+
+use crate::action::Action;
+use crate::constants::LAYER_COUNT;
+use crate::key_config::KeyConfig;
+use crate::mouse::MouseAction;
+use crate::profile;
+use crate::protocol::ErrorCode;
+
+// In bytes, padded with 0. As many 6 pixel wide characters as fit a third of the
+// display
+pub const LABEL_LEN: usize = 7;
+
+pub type Label = [u8; LABEL_LEN];
+
+/// Label of every button on every layer, indexed by layer like `Keymap`.
+pub type Labels = EnumMap<KeyConfig, [Label; LAYER_COUNT]>;
+
+/// What the display shows on a button.
+pub type LabelText = String<LABEL_LEN>;
+
+/// The label in `label`, empty if it has none.
+pub fn label_str(label: &Label) -> &str {
+    let len = profile::validate_name(label).unwrap_or(0);
+    str::from_utf8(&label[..len]).unwrap_or("")
+}
+
+/// Parse a label as the config protocol sends it, `none` clearing it.
+pub fn parse_label(word: &[u8]) -> Result<Label, ErrorCode> {
+    let mut label = [0; LABEL_LEN];
+    if word == b"none" {
+        return Ok(label);
+    }
+    if word.len() > LABEL_LEN {
+        return Err(ErrorCode::OutOfRange);
+    }
+    label[..word.len()].copy_from_slice(word);
+    match profile::validate_name(&label) {
+        Some(len) if len == word.len() && len > 0 => Ok(label),
+        _ => Err(ErrorCode::BadArgument),
+    }
+}
+
+/// A label as the config protocol sends it, the inverse of `parse_label`.
+pub struct Word<'a>(pub &'a Label);
+
+impl fmt::Display for Word<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match label_str(self.0) {
+            "" => f.write_str("none"),
+            label => f.write_str(label),
+        }
+    }
+}
+
+/// What the display shows on a button with `label` that sends `action`: the
+/// label, or a short name for the action when it has none.
+pub fn text(label: &Label, action: Action) -> LabelText {
+    let mut text = LabelText::new();
+    let label = label_str(label);
+    if !label.is_empty() {
+        let _ = text.push_str(label);
+        return text;
+    }
+
+    // Every one of these fits LABEL_LEN
+    let _ = match action {
+        Action::None | Action::Transparent => Ok(()),
+        Action::Key(code) => write!(text, "{:#04x}", code),
+        Action::Shortcut { keycode, .. } => write!(text, "^{:#04x}", keycode),
+        Action::Media(code) => write!(text, "m{:#04x}", code),
+        Action::System(code) => write!(text, "s{:#04x}", code),
+        Action::Mouse(action) => text.write_str(mouse_name(action)),
+        Action::Gamepad(button) => write!(text, "pad {}", button),
+        Action::Momentary(layer) => write!(text, "mo {}", layer),
+        Action::Toggle(layer) => write!(text, "tog {}", layer),
+        Action::OneShot(layer) => write!(text, "one {}", layer),
+        Action::Bootloader => text.write_str("boot"),
+        Action::Macro(slot) => write!(text, "mac {}", slot),
+        Action::Text(slot) => write!(text, "txt {}", slot),
+        Action::Profile(profile) => write!(text, "prof {}", profile),
+        Action::NextProfile => text.write_str("prof >"),
+    };
+    text
+}
+
+// Mouse actions named short enough for a label
+fn mouse_name(action: MouseAction) -> &'static str {
+    match action {
+        MouseAction::Up => "up",
+        MouseAction::Down => "down",
+        MouseAction::Left => "left",
+        MouseAction::Right => "right",
+        MouseAction::LeftClick => "click L",
+        MouseAction::RightClick => "click R",
+        MouseAction::MiddleClick => "click M",
+        MouseAction::WheelUp => "whl up",
+        MouseAction::WheelDown => "whl dn",
+    }
+}
@@ -0,0 +1,159 @@
+// Layers: every layer maps all buttons, and the active layers form a stack with
+// the base layer always at the bottom. A button takes the action of the topmost
+// active layer that is not transparent for it.
+//
+// Layers are switched by layer actions on keys or gestures:
+//   momentary  active while the key is held
+//   toggle     switched on or off on each press
+//   oneshot    active for the next non-layer key press only
+
+use enum_map::{enum_map, EnumMap};
+use heapless::Vec;
+
+use crate::action::Action;
+use crate::constants::*;
+use crate::key_config::KeyConfig;
+use crate::mouse::MouseAction;
+
+pub const BASE_LAYER: u8 = 0;
+// Layer the `get`/`set` media key and the default 1+2 combo use
+pub const MEDIA_LAYER: u8 = 1;
+// Topmost, so it covers every layer below while it is on. The default 2+3 combo
+// toggles it
+pub const MOUSE_LAYER: u8 = LAYER_COUNT as u8 - 1;
+
+/// Action of every button on every layer, indexed by layer.
+pub type Keymap = EnumMap<KeyConfig, [Action; LAYER_COUNT]>;
+
+/// Keycode on the base layer and media key on the media layer, 0 where the
+/// button has something else there. This is what `get` reports.
+pub fn keys_of(actions: &[Action; LAYER_COUNT]) -> (u8, u16) {
+    let keycode = match actions[BASE_LAYER as usize] {
+        Action::Key(keycode) => keycode,
+        _ => 0,
+    };
+    let media_key = match actions[MEDIA_LAYER as usize] {
+        Action::Media(media_key) => media_key,
+        _ => 0,
+    };
+    (keycode, media_key)
+}
+
+/// Inverse of `keys_of`, used by `set`. A media key of 0 leaves the button
+/// doing nothing on the media layer rather than falling through.
+pub fn set_keys(actions: &mut [Action; LAYER_COUNT], keys: (u8, u16)) {
+    actions[BASE_LAYER as usize] = Action::Key(keys.0);
+    actions[MEDIA_LAYER as usize] = match keys.1 {
+        MEDIAKEY_NONE => Action::None,
+        media_key => Action::Media(media_key),
+    };
+}
+
+/// The keyboard keys on the base layer and media keys on the media layer from
+/// `KeyConfig::new`, the mouse on the mouse layer and every other layer
+/// transparent.
+pub fn default_keymap() -> Keymap {
+    let key_config = KeyConfig::new();
+    let mouse = mouse_layer();
+    enum_map! {
+        id => {
+            let mut actions = [Action::Transparent; LAYER_COUNT];
+            set_keys(&mut actions, key_config[id]);
+            actions[MOUSE_LAYER as usize] = mouse[id];
+            actions
+        }
+    }
+}
+
+/// A layer that turns the buttons into a mouse, laid out like arrow keys: left
+/// click, up and right click on the top row, left, down and right below.
+pub fn mouse_layer() -> EnumMap<KeyConfig, Action> {
+    enum_map! {
+        KeyConfig::One => Action::Mouse(MouseAction::LeftClick),
+        KeyConfig::Two => Action::Mouse(MouseAction::Up),
+        KeyConfig::Three => Action::Mouse(MouseAction::RightClick),
+        KeyConfig::Four => Action::Mouse(MouseAction::Left),
+        KeyConfig::Five => Action::Mouse(MouseAction::Down),
+        KeyConfig::Six => Action::Mouse(MouseAction::Right),
+    }
+}
+
+pub struct LayerStack {
+    // Bottom to top, always starts with the base layer
+    layers: Vec<u8, LAYER_COUNT>,
+}
+
+impl LayerStack {
+    pub fn new() -> Self {
+        let mut layers = Vec::new();
+        let _ = layers.push(BASE_LAYER);
+        LayerStack { layers }
+    }
+
+    pub fn is_active(&self, layer: u8) -> bool {
+        self.layers.contains(&layer)
+    }
+
+    /// Put `layer` on top of the stack. Returns false when it was already active
+    /// or does not exist.
+    pub fn activate(&mut self, layer: u8) -> bool {
+        if layer as usize >= LAYER_COUNT || self.is_active(layer) {
+            return false;
+        }
+        // Can't fail, there is room for every layer once
+        self.layers.push(layer).is_ok()
+    }
+
+    /// Take `layer` off the stack wherever it is. The base layer stays.
+    pub fn deactivate(&mut self, layer: u8) -> bool {
+        if layer == BASE_LAYER {
+            return false;
+        }
+        match self.layers.iter().position(|active| *active == layer) {
+            Some(index) => {
+                self.layers.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn toggle(&mut self, layer: u8) {
+        if !self.deactivate(layer) {
+            self.activate(layer);
+        }
+    }
+
+    pub fn top(&self) -> u8 {
+        *self.layers.last().unwrap_or(&BASE_LAYER)
+    }
+
+    /// Active layers, bottom to top.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.layers
+    }
+
+    /// The action `actions` gives on the topmost layer that is not transparent.
+    pub fn resolve(&self, actions: &[Action; LAYER_COUNT]) -> Action {
+        match self.layer_of(actions) {
+            Some(layer) => actions[layer as usize],
+            None => Action::None,
+        }
+    }
+
+    /// The layer `resolve` takes the action from, `None` when every active layer
+    /// is transparent.
+    pub fn layer_of(&self, actions: &[Action; LAYER_COUNT]) -> Option<u8> {
+        self.layers
+            .iter()
+            .rev()
+            .copied()
+            .find(|layer| actions[*layer as usize] != Action::Transparent)
+    }
+}
+
+impl Default for LayerStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,136 @@
+// Host keyboard layouts. The host turns keycodes into characters with its own
+// layout, so typing a text needs the keycode and modifiers that give each
+// character on the layout the host has set, e.g. Y and Z swap places on a German
+// host.
+//
+// Every layout comes from the one table below: a row per key, a column per
+// layout, each column the characters the key types plain, with Shift and with
+// AltGr (Right Alt) in that order. `\0` skips a level. Dead keys are left out,
+// they type nothing until the next key, so their characters go through the
+// Unicode input method instead.
+
+use enum_map::Enum;
+
+use crate::modifier::{MOD_LSHIFT, MOD_RALT};
+
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Us,
+    Uk,
+    De,
+    Fr,
+    Dvorak,
+}
+
+// Modifiers of each level in a column
+const LEVELS: [u8; 3] = [0, MOD_LSHIFT, MOD_RALT];
+
+#[rustfmt::skip]
+const KEYS: [(u8, [&str; Layout::LENGTH]); 55] = [
+    //       us       uk       de        fr        dvorak
+    (0x04, ["aA",    "aA",    "aA",     "qQ",     "aA"]),
+    (0x05, ["bB",    "bB",    "bB",     "bB",     "xX"]),
+    (0x06, ["cC",    "cC",    "cC",     "cC",     "jJ"]),
+    (0x07, ["dD",    "dD",    "dD",     "dD",     "eE"]),
+    (0x08, ["eE",    "eE",    "eE€",    "eE€",    ".>"]),
+    (0x09, ["fF",    "fF",    "fF",     "fF",     "uU"]),
+    (0x0A, ["gG",    "gG",    "gG",     "gG",     "iI"]),
+    (0x0B, ["hH",    "hH",    "hH",     "hH",     "dD"]),
+    (0x0C, ["iI",    "iI",    "iI",     "iI",     "cC"]),
+    (0x0D, ["jJ",    "jJ",    "jJ",     "jJ",     "hH"]),
+    (0x0E, ["kK",    "kK",    "kK",     "kK",     "tT"]),
+    (0x0F, ["lL",    "lL",    "lL",     "lL",     "nN"]),
+    (0x10, ["mM",    "mM",    "mMµ",    ",?",     "mM"]),
+    (0x11, ["nN",    "nN",    "nN",     "nN",     "bB"]),
+    (0x12, ["oO",    "oO",    "oO",     "oO",     "rR"]),
+    (0x13, ["pP",    "pP",    "pP",     "pP",     "lL"]),
+    (0x14, ["qQ",    "qQ",    "qQ@",    "aA",     "'\""]),
+    (0x15, ["rR",    "rR",    "rR",     "rR",     "pP"]),
+    (0x16, ["sS",    "sS",    "sS",     "sS",     "oO"]),
+    (0x17, ["tT",    "tT",    "tT",     "tT",     "yY"]),
+    (0x18, ["uU",    "uU",    "uU",     "uU",     "gG"]),
+    (0x19, ["vV",    "vV",    "vV",     "vV",     "kK"]),
+    (0x1A, ["wW",    "wW",    "wW",     "zZ",     ",<"]),
+    (0x1B, ["xX",    "xX",    "xX",     "xX",     "qQ"]),
+    (0x1C, ["yY",    "yY",    "zZ",     "yY",     "fF"]),
+    (0x1D, ["zZ",    "zZ",    "yY",     "wW",     ";:"]),
+    (0x1E, ["1!",    "1!",    "1!",     "&1",     "1!"]),
+    (0x1F, ["2@",    "2\"",   "2\"²",   "é2",     "2@"]),
+    (0x20, ["3#",    "3£",    "3§³",    "\"3#",   "3#"]),
+    (0x21, ["4$",    "4$€",   "4$",     "'4{",    "4$"]),
+    (0x22, ["5%",    "5%",    "5%",     "(5[",    "5%"]),
+    (0x23, ["6^",    "6^",    "6&",     "-6|",    "6^"]),
+    (0x24, ["7&",    "7&",    "7/{",    "è7",     "7&"]),
+    (0x25, ["8*",    "8*",    "8([",    "_8\\",   "8*"]),
+    (0x26, ["9(",    "9(",    "9)]",    "ç9^",    "9("]),
+    (0x27, ["0)",    "0)",    "0=}",    "à0@",    "0)"]),
+    (0x28, ["\n",    "\n",    "\n",     "\n",     "\n"]),
+    (0x2B, ["\t",    "\t",    "\t",     "\t",     "\t"]),
+    (0x2C, [" ",     " ",     " ",      " ",      " "]),
+    (0x2D, ["-_",    "-_",    "ß?\\",   ")°]",    "[{"]),
+    (0x2E, ["=+",    "=+",    "",       "=+}",    "]}"]),
+    (0x2F, ["[{",    "[{",    "üÜ",     "",       "/?"]),
+    (0x30, ["]}",    "]}",    "+*~",    "$£¤",    "=+"]),
+    (0x31, ["\\|",   "",      "",       "",       "\\|"]),
+    // Non-US # and ~, next to Enter on ISO keyboards
+    (0x32, ["",      "#~",    "#'",     "*µ",     ""]),
+    (0x33, [";:",    ";:",    "öÖ",     "mM",     "sS"]),
+    (0x34, ["'\"",   "'@",    "äÄ",     "ù%",     "-_"]),
+    (0x35, ["`~",    "`¬",    "\0°",    "²",      "`~"]),
+    (0x36, [",<",    ",<",    ",;",     ";.",     "wW"]),
+    (0x37, [".>",    ".>",    ".:",     ":/",     "vV"]),
+    (0x38, ["/?",    "/?",    "-_",     "!§",     "zZ"]),
+    // Non-US \ and |, next to the left Shift on ISO keyboards
+    (0x64, ["",      "\\|",   "<>|",    "<>",     ""]),
+    // Keypad keys type the same everywhere
+    (0x54, ["/",     "/",     "/",      "/",      "/"]),
+    (0x55, ["*",     "*",     "*",      "*",      "*"]),
+    (0x57, ["+",     "+",     "+",      "+",      "+"]),
+];
+
+impl Layout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::De => "de",
+            Layout::Fr => "fr",
+            Layout::Dvorak => "dvorak",
+        }
+    }
+
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        match word {
+            b"us" => Some(Layout::Us),
+            b"uk" => Some(Layout::Uk),
+            b"de" => Some(Layout::De),
+            b"fr" => Some(Layout::Fr),
+            b"dvorak" => Some(Layout::Dvorak),
+            _ => None,
+        }
+    }
+
+    /// Keycode and modifier bits that type `c`, `None` if no key does.
+    pub fn key(&self, c: char) -> Option<(u8, u8)> {
+        if c == '\0' {
+            return None;
+        }
+        KEYS.iter().find_map(|(keycode, columns)| {
+            let level = columns[self.into_usize()]
+                .chars()
+                .position(|typed| typed == c)?;
+            Some((*keycode, LEVELS[level]))
+        })
+    }
+
+    /// Character a key types with `modifiers` held, the inverse of `key`.
+    pub fn char_at(&self, keycode: u8, modifiers: u8) -> Option<char> {
+        let level = LEVELS.iter().position(|level| *level == modifiers)?;
+        let (_, columns) = KEYS.iter().find(|(key, _)| *key == keycode)?;
+        columns[self.into_usize()]
+            .chars()
+            .nth(level)
+            .filter(|c| *c != '\0')
+    }
+}
@@ -0,0 +1,27 @@
+#![no_std]
+
+pub mod action;
+pub mod button;
+pub mod combo;
+pub mod config_store;
+pub mod constants;
+pub mod consumer;
+pub mod debouncer;
+pub mod gamepad;
+pub mod gesture;
+pub mod hid_util;
+pub mod key_config;
+pub mod label;
+pub mod layer;
+pub mod layout;
+pub mod lock;
+pub mod macro_player;
+pub mod modifier;
+pub mod mouse;
+pub mod profile;
+pub mod protocol;
+pub mod raw_hid;
+pub mod report;
+pub mod screen;
+pub mod system_control;
+pub mod text;
@@ -0,0 +1,80 @@
+// Lock key state the host sends its keyboards in output reports. Hosts keep Caps,
+// Num and Scroll Lock the same across every keyboard, so the deck lights up
+// whatever the main keyboard does, and any lock can switch a layer on while it
+// is lit, e.g. other actions while Caps Lock is on.
+
+use core::fmt;
+
+use enum_map::{Enum, EnumMap};
+
+// Bits of the keyboard output report, LED usages 1-3
+pub const NUM_LOCK: u8 = 1 << 0;
+pub const CAPS_LOCK: u8 = 1 << 1;
+pub const SCROLL_LOCK: u8 = 1 << 2;
+
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum Lock {
+    Num,
+    Caps,
+    Scroll,
+}
+
+impl Lock {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lock::Num => "num",
+            Lock::Caps => "caps",
+            Lock::Scroll => "scroll",
+        }
+    }
+
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        match word {
+            b"num" => Some(Lock::Num),
+            b"caps" => Some(Lock::Caps),
+            b"scroll" => Some(Lock::Scroll),
+            _ => None,
+        }
+    }
+
+    pub fn bit(&self) -> u8 {
+        match self {
+            Lock::Num => NUM_LOCK,
+            Lock::Caps => CAPS_LOCK,
+            Lock::Scroll => SCROLL_LOCK,
+        }
+    }
+}
+
+/// Layer each lock switches on while it is lit, if any.
+pub type LockLayers = EnumMap<Lock, Option<u8>>;
+
+/// LED bits of the last keyboard output report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockLeds(pub u8);
+
+impl LockLeds {
+    pub fn is_on(&self, lock: Lock) -> bool {
+        self.0 & lock.bit() != 0
+    }
+}
+
+// The lit locks, e.g. `caps num`, or `none`
+impl fmt::Display for LockLeds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for lock in [Lock::Caps, Lock::Num, Lock::Scroll] {
+            if self.is_on(lock) {
+                if !first {
+                    f.write_str(" ")?;
+                }
+                f.write_str(lock.name())?;
+                first = false;
+            }
+        }
+        if first {
+            f.write_str("none")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+// Keystroke macros: a short bytecode program per macro slot, played back one
+// report at a time so the host sees every step.
+//
+// Each instruction is an opcode byte, most followed by an argument:
+//   0x00           end, also pads the rest of the slot
+//   0x01 <key>     press a keyboard key and keep it held
+//   0x02 <key>     release a held key
+//   0x03 <key>     tap a key, pressed on one step and released on the next
+//   0x04 <lo> <hi> tap a media key, its 16-bit usage little endian
+//   0x05 <ms>      wait before the next step, several in a row for over 255 ms
+//   0x06           release every key the macro holds
+//
+// Keys still held at the end are released. E.g. Ctrl+Shift+P is
+// `01 e0 01 e1 03 13 06`.
+//
+// The same program has a text form for people, the instruction names with their
+// argument: `press 0xe0 press 0xe1 tap 0x13 release-all`. The config protocol
+// carries the bytecode itself as hex, `none` for an empty slot.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::consumer;
+use crate::protocol::{self, ErrorCode};
+
+pub const MAX_MACROS: usize = 8;
+pub const MACRO_LEN: usize = 32;
+
+pub type MacroCode = [u8; MACRO_LEN];
+
+pub const OP_END: u8 = 0x00;
+pub const OP_PRESS: u8 = 0x01;
+pub const OP_RELEASE: u8 = 0x02;
+pub const OP_TAP: u8 = 0x03;
+pub const OP_MEDIA: u8 = 0x04;
+pub const OP_WAIT: u8 = 0x05;
+pub const OP_RELEASE_ALL: u8 = 0x06;
+
+// Name in the text form and how many argument bytes the instruction takes
+fn op_info(op: u8) -> Option<(&'static str, usize)> {
+    match op {
+        OP_END => Some(("end", 0)),
+        OP_PRESS => Some(("press", 1)),
+        OP_RELEASE => Some(("release", 1)),
+        OP_TAP => Some(("tap", 1)),
+        OP_MEDIA => Some(("media", 2)),
+        OP_WAIT => Some(("wait", 1)),
+        OP_RELEASE_ALL => Some(("release-all", 0)),
+        _ => None,
+    }
+}
+
+/// Length of the program in `code` up to its end, or `None` if it holds an
+/// unknown opcode or an instruction cut short.
+pub fn validate(code: &[u8]) -> Option<usize> {
+    let mut pc = 0;
+    while pc < code.len() {
+        if code[pc] == OP_END {
+            return Some(pc);
+        }
+        let (_, arg_len) = op_info(code[pc])?;
+        if pc + arg_len >= code.len() {
+            return None;
+        }
+        pc += 1 + arg_len;
+    }
+    Some(pc)
+}
+
+/// Build a program from its text form. A lone `none` is the empty program.
+pub fn assemble<'a>(mut words: impl Iterator<Item = &'a [u8]>) -> Result<MacroCode, ErrorCode> {
+    let mut code: Vec<u8, MACRO_LEN> = Vec::new();
+    while let Some(name) = words.next() {
+        if name == b"none" && code.is_empty() {
+            return match words.next() {
+                Some(_) => Err(ErrorCode::BadArgument),
+                None => Ok([OP_END; MACRO_LEN]),
+            };
+        }
+        let op = (OP_PRESS..=OP_RELEASE_ALL)
+            .find(|op| op_info(*op).map(|(op_name, _)| op_name.as_bytes()) == Some(name))
+            .ok_or(ErrorCode::BadArgument)?;
+        code.push(op).map_err(|_| ErrorCode::OutOfRange)?;
+        let arg = match op_info(op) {
+            Some((_, 0)) | None => continue,
+            Some(_) => words.next().ok_or(ErrorCode::BadArgument)?,
+        };
+        // Media keys take a usage name as well as a number
+        let pushed = match op {
+            OP_MEDIA => code.extend_from_slice(&consumer::parse(arg)?.to_le_bytes()),
+            _ => code.push(protocol::parse_byte(arg)?).map_err(|_| ()),
+        };
+        pushed.map_err(|_| ErrorCode::OutOfRange)?;
+    }
+
+    let mut padded = [OP_END; MACRO_LEN];
+    padded[..code.len()].copy_from_slice(&code);
+    Ok(padded)
+}
+
+/// Parse the hex form used by the config protocol, checking it is a valid program.
+pub fn parse_hex(word: &[u8]) -> Result<MacroCode, ErrorCode> {
+    let mut code = [OP_END; MACRO_LEN];
+    if word == b"none" {
+        return Ok(code);
+    }
+    protocol::parse_hex_bytes(word, &mut code)?;
+    validate(&code).ok_or(ErrorCode::BadArgument)?;
+    Ok(code)
+}
+
+/// Hex form of a program up to its end, the inverse of `parse_hex`.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = validate(self.0).ok_or(fmt::Error)?;
+        if len == 0 {
+            return f.write_str("none");
+        }
+        for byte in &self.0[..len] {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Text form of a program, e.g. `tap 0x0f wait 50 tap 0x28`, or `none` for a
+/// macro that does nothing.
+pub struct Disassembly<'a>(pub &'a [u8]);
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0;
+        let len = validate(code).ok_or(fmt::Error)?;
+        if len == 0 {
+            return f.write_str("none");
+        }
+        let mut pc = 0;
+        let mut separator = "";
+        while pc < len {
+            // Checked by `validate`
+            let (name, arg_len) = op_info(code[pc]).ok_or(fmt::Error)?;
+            write!(f, "{}{}", separator, name)?;
+            match (code[pc], arg_len) {
+                (_, 0) => {}
+                (OP_WAIT, _) => write!(f, " {}", code[pc + 1])?,
+                (OP_MEDIA, _) => write!(f, " {:#04x}", media_usage(code, pc))?,
+                _ => write!(f, " {:#04x}", code[pc + 1])?,
+            }
+            pc += 1 + arg_len;
+            separator = " ";
+        }
+        Ok(())
+    }
+}
+
+// Usage of the media instruction at `pc`
+fn media_usage(code: &[u8], pc: usize) -> u16 {
+    u16::from_le_bytes([code[pc + 1], code[pc + 2]])
+}
+
+/// One report's worth of change for the HID side to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroStep {
+    Press(u8),
+    Release(u8),
+    // Media usage id, 0 releases
+    Media(u16),
+}
+
+pub struct MacroPlayer {
+    code: MacroCode,
+    pc: usize,
+    playing: bool,
+    // Second half of a tap, sent on the next step
+    follow_up: Option<MacroStep>,
+    // Start and length of a running wait
+    wait: Option<(u32, u32)>,
+    held: Vec<u8, MACRO_LEN>,
+}
+
+impl MacroPlayer {
+    pub fn new() -> Self {
+        MacroPlayer {
+            code: [OP_END; MACRO_LEN],
+            pc: 0,
+            playing: false,
+            follow_up: None,
+            wait: None,
+            held: Vec::new(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Start playing `code` from the top. Returns false and carries on with the
+    /// current macro if one is still playing.
+    pub fn start(&mut self, code: &MacroCode) -> bool {
+        if self.playing || validate(code).is_none() {
+            return false;
+        }
+        self.code = *code;
+        self.pc = 0;
+        self.playing = true;
+        self.follow_up = None;
+        self.wait = None;
+        self.held.clear();
+        true
+    }
+
+    /// The next change to send. Each call gives at most one, so calling this once
+    /// per host poll interval lets the host see every report. `None` while
+    /// waiting or once the macro has finished.
+    pub fn next_step(&mut self, current_time: u32) -> Option<MacroStep> {
+        if !self.playing {
+            return None;
+        }
+        if let Some(step) = self.follow_up.take() {
+            return Some(step);
+        }
+        if let Some((since, duration)) = self.wait {
+            if current_time.wrapping_sub(since) < duration {
+                return None;
+            }
+            self.wait = None;
+        }
+
+        loop {
+            let op = self.code.get(self.pc).copied().unwrap_or(OP_END);
+            let arg = self.code.get(self.pc + 1).copied().unwrap_or(0);
+            match op {
+                OP_PRESS => {
+                    self.pc += 2;
+                    if !self.held.contains(&arg) {
+                        let _ = self.held.push(arg);
+                    }
+                    return Some(MacroStep::Press(arg));
+                }
+                OP_RELEASE => {
+                    self.pc += 2;
+                    self.held.retain(|key| *key != arg);
+                    return Some(MacroStep::Release(arg));
+                }
+                OP_TAP => {
+                    self.pc += 2;
+                    self.follow_up = Some(MacroStep::Release(arg));
+                    return Some(MacroStep::Press(arg));
+                }
+                OP_MEDIA => {
+                    let usage = media_usage(&self.code, self.pc);
+                    self.pc += 3;
+                    self.follow_up = Some(MacroStep::Media(0));
+                    return Some(MacroStep::Media(usage));
+                }
+                OP_WAIT => {
+                    self.pc += 2;
+                    self.wait = Some((current_time, arg as u32 * 1000));
+                    return None;
+                }
+                OP_RELEASE_ALL => match self.held.pop() {
+                    Some(key) => return Some(MacroStep::Release(key)),
+                    None => self.pc += 1,
+                },
+                // The end, release whatever is still held one key at a time
+                _ => match self.held.pop() {
+                    Some(key) => return Some(MacroStep::Release(key)),
+                    None => {
+                        self.playing = false;
+                        return None;
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Default for MacroPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,61 @@
+// Keyboard modifiers: the bits of a keyboard report's modifier byte, their names in
+// the config protocol and the modifier keycodes (0xE0-0xE7) they stand for.
+
+use core::fmt;
+
+pub const MOD_LCTRL: u8 = 1 << 0;
+pub const MOD_LSHIFT: u8 = 1 << 1;
+pub const MOD_LALT: u8 = 1 << 2;
+pub const MOD_LGUI: u8 = 1 << 3;
+pub const MOD_RCTRL: u8 = 1 << 4;
+pub const MOD_RSHIFT: u8 = 1 << 5;
+pub const MOD_RALT: u8 = 1 << 6;
+pub const MOD_RGUI: u8 = 1 << 7;
+
+// Names in bit order, the left hand keys go by the plain name
+const NAMES: [&str; 8] = [
+    "ctrl", "shift", "alt", "gui", "rctrl", "rshift", "ralt", "rgui",
+];
+
+const KEYCODE_LCTRL: u8 = 0xE0;
+const KEYCODE_RGUI: u8 = 0xE7;
+
+/// Modifier bit of a modifier keycode, `None` for any other key.
+pub fn modifier_bit(keycode: u8) -> Option<u8> {
+    match keycode {
+        KEYCODE_LCTRL..=KEYCODE_RGUI => Some(1 << (keycode - KEYCODE_LCTRL)),
+        _ => None,
+    }
+}
+
+/// Parse modifier names joined with `+`, e.g. `ctrl+alt`, or `none`.
+pub fn parse(word: &[u8]) -> Option<u8> {
+    if word == b"none" {
+        return Some(0);
+    }
+    let mut modifiers = 0;
+    for name in word.split(|byte| *byte == b'+') {
+        let index = NAMES.iter().position(|known| known.as_bytes() == name)?;
+        modifiers |= 1 << index;
+    }
+    Some(modifiers)
+}
+
+/// Modifier names joined with `+`, or `none`, the inverse of `parse`.
+pub struct Modifiers(pub u8);
+
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("none");
+        }
+        let mut separator = "";
+        for (index, name) in NAMES.iter().enumerate() {
+            if self.0 & (1 << index) != 0 {
+                write!(f, "{}{}", separator, name)?;
+                separator = "+";
+            }
+        }
+        Ok(())
+    }
+}
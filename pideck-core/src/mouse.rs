@@ -0,0 +1,221 @@
+// Mouse emulation. Buttons bound to mouse actions move the cursor, click and
+// scroll. Clicks are sent as soon as they change, movement and scrolling go out on
+// a periodic tick for as long as their buttons are held.
+//
+// The cursor starts slowly for fine positioning and speeds up the longer a
+// direction is held, up to `MAX_SPEED`. Opposite directions cancel out.
+
+use enum_map::Enum;
+
+use crate::report::MouseReport;
+
+// Ticks are this far apart, the firmware drives `Mouse::tick` at this rate
+pub const TICK_MS: u32 = 10;
+
+// Pixels per tick to start with and at most
+pub const START_SPEED: u8 = 1;
+pub const MAX_SPEED: u8 = 16;
+// Ticks per extra pixel of speed, so full speed is reached after 750 ms
+pub const ACCEL_TICKS: u16 = 5;
+// Ticks between wheel notches while a wheel button is held
+pub const WHEEL_TICKS: u16 = 10;
+
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    LeftClick,
+    RightClick,
+    MiddleClick,
+    WheelUp,
+    WheelDown,
+}
+
+impl MouseAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MouseAction::Up => "up",
+            MouseAction::Down => "down",
+            MouseAction::Left => "left",
+            MouseAction::Right => "right",
+            MouseAction::LeftClick => "left-click",
+            MouseAction::RightClick => "right-click",
+            MouseAction::MiddleClick => "middle-click",
+            MouseAction::WheelUp => "wheel-up",
+            MouseAction::WheelDown => "wheel-down",
+        }
+    }
+
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        match word {
+            b"up" => Some(MouseAction::Up),
+            b"down" => Some(MouseAction::Down),
+            b"left" => Some(MouseAction::Left),
+            b"right" => Some(MouseAction::Right),
+            b"left-click" => Some(MouseAction::LeftClick),
+            b"right-click" => Some(MouseAction::RightClick),
+            b"middle-click" => Some(MouseAction::MiddleClick),
+            b"wheel-up" => Some(MouseAction::WheelUp),
+            b"wheel-down" => Some(MouseAction::WheelDown),
+            _ => None,
+        }
+    }
+
+    // Report button bit of a click, 0 for the rest
+    fn button(&self) -> u8 {
+        match self {
+            MouseAction::LeftClick => BUTTON_LEFT,
+            MouseAction::RightClick => BUTTON_RIGHT,
+            MouseAction::MiddleClick => BUTTON_MIDDLE,
+            _ => 0,
+        }
+    }
+}
+
+pub struct Mouse {
+    // How many held buttons want each action, so two buttons on the same
+    // direction do not stop it when one is released
+    held: [u8; MouseAction::LENGTH],
+    // Ticks since movement started, drives the acceleration
+    move_ticks: u16,
+    wheel_ticks: u16,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Mouse {
+            held: [0; MouseAction::LENGTH],
+            move_ticks: 0,
+            wheel_ticks: 0,
+        }
+    }
+
+    /// Start `action`. Returns the report to send straight away if it clicked.
+    pub fn press(&mut self, action: MouseAction) -> Option<MouseReport> {
+        let count = &mut self.held[action.into_usize()];
+        *count = count.saturating_add(1);
+        self.click_report(action)
+    }
+
+    /// Stop `action`. Returns the report to send straight away if it let go of a
+    /// mouse button.
+    pub fn release(&mut self, action: MouseAction) -> Option<MouseReport> {
+        let count = &mut self.held[action.into_usize()];
+        if *count == 0 {
+            return None;
+        }
+        *count -= 1;
+        if !self.is_moving() {
+            self.move_ticks = 0;
+        }
+        if !self.is_scrolling() {
+            self.wheel_ticks = 0;
+        }
+        self.click_report(action)
+    }
+
+    /// Let go of everything, returning the report to send if anything was held.
+    pub fn release_all(&mut self) -> Option<MouseReport> {
+        let was_active = self.held.iter().any(|count| *count > 0);
+        *self = Mouse::new();
+        if was_active {
+            Some(MouseReport::default())
+        } else {
+            None
+        }
+    }
+
+    /// Whether movement or scrolling is held, i.e. `tick` has something to send.
+    pub fn is_active(&self) -> bool {
+        self.is_moving() || self.is_scrolling()
+    }
+
+    /// Movement and scrolling for one tick, `None` if neither is held.
+    pub fn tick(&mut self) -> Option<MouseReport> {
+        if !self.is_active() {
+            return None;
+        }
+
+        let mut report = MouseReport {
+            buttons: self.buttons(),
+            ..MouseReport::default()
+        };
+        if self.is_moving() {
+            let speed = self.speed() as i8;
+            report.x = self.axis(MouseAction::Left, MouseAction::Right) * speed;
+            report.y = self.axis(MouseAction::Up, MouseAction::Down) * speed;
+            self.move_ticks = self.move_ticks.saturating_add(1);
+        }
+        if self.is_scrolling() {
+            // A notch on the first tick, then one every WHEEL_TICKS
+            if self.wheel_ticks.is_multiple_of(WHEEL_TICKS) {
+                report.wheel = self.axis(MouseAction::WheelDown, MouseAction::WheelUp);
+            }
+            self.wheel_ticks = self.wheel_ticks.wrapping_add(1);
+        }
+        Some(report)
+    }
+
+    /// Mouse buttons held down, the report's button bits.
+    pub fn buttons(&self) -> u8 {
+        [
+            MouseAction::LeftClick,
+            MouseAction::RightClick,
+            MouseAction::MiddleClick,
+        ]
+        .iter()
+        .filter(|action| self.is_held(**action))
+        .fold(0, |buttons, action| buttons | action.button())
+    }
+
+    fn click_report(&self, action: MouseAction) -> Option<MouseReport> {
+        if action.button() == 0 {
+            return None;
+        }
+        Some(MouseReport {
+            buttons: self.buttons(),
+            ..MouseReport::default()
+        })
+    }
+
+    fn speed(&self) -> u8 {
+        let extra = (self.move_ticks / ACCEL_TICKS).min(MAX_SPEED as u16) as u8;
+        START_SPEED.saturating_add(extra).min(MAX_SPEED)
+    }
+
+    // -1, 0 or 1 from the held pair of opposite actions
+    fn axis(&self, negative: MouseAction, positive: MouseAction) -> i8 {
+        self.is_held(positive) as i8 - self.is_held(negative) as i8
+    }
+
+    fn is_held(&self, action: MouseAction) -> bool {
+        self.held[action.into_usize()] > 0
+    }
+
+    fn is_moving(&self) -> bool {
+        [
+            MouseAction::Up,
+            MouseAction::Down,
+            MouseAction::Left,
+            MouseAction::Right,
+        ]
+        .iter()
+        .any(|action| self.is_held(*action))
+    }
+
+    fn is_scrolling(&self) -> bool {
+        self.is_held(MouseAction::WheelUp) || self.is_held(MouseAction::WheelDown)
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
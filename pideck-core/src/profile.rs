@@ -0,0 +1,109 @@
+// Profiles: complete configurations to switch between, e.g. one for video calls
+// and one for the IDE. Each has its own key map with all its layers, gestures,
+// debounce, combos, macros, lock layers, texts and key labels, and a name to show
+// on the display. The deck holds `MAX_PROFILES` of them with one active at a time, and
+// the config image stores them all along with which one is active.
+//
+// A profile name is printable ASCII without spaces so it fits one word of the
+// config protocol, `none` for an unnamed profile.
+
+use core::{fmt, str};
+
+use enum_map::{enum_map, EnumMap};
+
+use crate::combo::ComboConfig;
+use crate::constants::LAYER_COUNT;
+use crate::debouncer::DebounceStrategy;
+use crate::gesture::GestureConfig;
+use crate::key_config::KeyConfig;
+use crate::label::{Labels, LABEL_LEN};
+use crate::layer::{self, Keymap};
+use crate::lock::LockLayers;
+use crate::macro_player::{MacroCode, MACRO_LEN, MAX_MACROS};
+use crate::protocol::ErrorCode;
+use crate::text::TextConfig;
+
+pub const MAX_PROFILES: usize = 4;
+// In bytes, padded with 0. Leaves room for the profile number on one line of the
+// display
+pub const PROFILE_NAME_LEN: usize = 12;
+
+pub type ProfileName = [u8; PROFILE_NAME_LEN];
+
+/// Everything one profile configures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub name: ProfileName,
+    pub keymap: Keymap,
+    pub debounce: EnumMap<KeyConfig, DebounceStrategy>,
+    pub gestures: EnumMap<KeyConfig, GestureConfig>,
+    pub combos: ComboConfig,
+    pub macros: [MacroCode; MAX_MACROS],
+    pub lock_layers: LockLayers,
+    pub texts: TextConfig,
+    pub labels: Labels,
+}
+
+// The compiled-in config, unnamed
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: [0; PROFILE_NAME_LEN],
+            keymap: layer::default_keymap(),
+            debounce: enum_map! { _ => DebounceStrategy::DEFAULT },
+            gestures: EnumMap::default(),
+            combos: ComboConfig::DEFAULT,
+            macros: [[0; MACRO_LEN]; MAX_MACROS],
+            lock_layers: LockLayers::default(),
+            texts: TextConfig::default(),
+            labels: enum_map! { _ => [[0; LABEL_LEN]; LAYER_COUNT] },
+        }
+    }
+}
+
+/// Length of the name in `name` up to its padding, or `None` if it has anything
+/// but printable ASCII before the padding or anything after it.
+pub fn validate_name(name: &[u8]) -> Option<usize> {
+    let len = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    if !name[..len].iter().all(u8::is_ascii_graphic) || name[len..].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    Some(len)
+}
+
+/// The name of a profile, empty if it has none.
+pub fn name_str(name: &ProfileName) -> &str {
+    let len = validate_name(name).unwrap_or(0);
+    str::from_utf8(&name[..len]).unwrap_or("")
+}
+
+/// Parse a name as the config protocol sends it, `none` clearing it.
+pub fn parse_name(word: &[u8]) -> Result<ProfileName, ErrorCode> {
+    let mut name = [0; PROFILE_NAME_LEN];
+    if word == b"none" {
+        return Ok(name);
+    }
+    if word.len() > PROFILE_NAME_LEN {
+        return Err(ErrorCode::OutOfRange);
+    }
+    name[..word.len()].copy_from_slice(word);
+    match validate_name(&name) {
+        Some(len) if len == word.len() && len > 0 => Ok(name),
+        _ => Err(ErrorCode::BadArgument),
+    }
+}
+
+/// A profile name as the config protocol sends it, the inverse of `parse_name`.
+pub struct Name<'a>(pub &'a ProfileName);
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match name_str(self.0) {
+            "" => f.write_str("none"),
+            name => f.write_str(name),
+        }
+    }
+}
@@ -0,0 +1,1086 @@
+// Line based configuration protocol. It is spoken over two transports, both
+// carrying the same byte stream:
+//   - the CDC serial port, bytes as they are
+//   - the vendor-defined raw HID interface, in 64-byte reports of a length byte,
+//     up to 63 bytes of text and 0 padding, see `raw_hid`. A line may span
+//     several reports
+// Each transport has its own `LineBuffer`, so a line half sent over one is not
+// finished by the other, and replies go back over the transport the request came
+// from.
+//
+// Requests are single ASCII lines terminated by '\n' ('\r' is ignored):
+//   version                         -> OK <firmware version>
+//   get <button>                    -> OK <button> <keycode> <media key>
+//   set <button> <keycode> <media>  -> OK
+//   map <layer> <button>            -> OK <layer> <button> <action>
+//   map <layer> <button> <action>   -> OK <layer> <button> <action>
+//   label <layer> <button>          -> OK <layer> <button> <label>
+//   label <layer> <button> <label|none>
+//                                   -> OK <layer> <button> <label>
+//   layer                           -> OK <active layers, bottom to top>
+//   layer <layer> <on|off>          -> OK <active layers, bottom to top>
+//   state                           -> OK <one 0/1 digit per button>
+//   debounce <button>               -> OK <button> <strategy> <value>
+//   debounce <button> <strategy> <value>
+//                                   -> OK <button> <strategy> <value>
+//   gesture <button> <gesture>      -> OK <button> <gesture> <action>
+//   gesture <button> <gesture> <action>
+//                                   -> OK <button> <gesture> <action>
+//   timing <button>                 -> OK <button> <double tap> <long press> <hold>
+//   timing <button> <double tap> <long press> <hold>
+//                                   -> OK <button> <double tap> <long press> <hold>
+//   combo <slot>                    -> OK <slot> <buttons> <action>
+//   combo <slot> <buttons> <action> -> OK <slot> <buttons> <action>
+//   combo-window                    -> OK <ms>
+//   combo-window <ms>               -> OK <ms>
+//   macro <slot>                    -> OK <slot> <bytecode>
+//   macro <slot> <bytecode>         -> OK <slot> <bytecode>
+//   leds                            -> OK <lit locks, or none>
+//   lock <lock>                     -> OK <lock> <layer or none>
+//   lock <lock> <layer|none>        -> OK <lock> <layer or none>
+//   text <slot>                     -> OK <slot> <utf-8 hex>
+//   text <slot> <utf-8 hex>         -> OK <slot> <utf-8 hex>
+//   unicode                         -> OK <method>
+//   unicode <method>                -> OK <method>
+//   layout                          -> OK <layout>
+//   layout <layout>                 -> OK <layout>
+//   profile                         -> OK <profile> <name>
+//   profile <profile>               -> OK <profile> <name>
+//   profile-name <profile>          -> OK <profile> <name>
+//   profile-name <profile> <name|none>
+//                                   -> OK <profile> <name>
+//   profile-select <name>           -> OK <profile> <name>
+//   save                            -> OK
+//
+// `get` and `set` are a shorthand for the keycode on the base layer and the media
+// key on the media layer.
+//
+// Debounce strategies are `eager` and `deferred` with a value in ms, or
+// `integrator` with a value in samples. Gestures are `tap`, `double-tap`,
+// `long-press` and `hold`. Actions are `none`, `trans`, `bootloader`,
+// `key <keycode>`, `shortcut <modifiers> <keycode>`, `media <media key>`,
+// `system <usage>`, `mouse <mouse action>`, `gamepad <button>`, `macro <slot>`,
+// `text <slot>`, `profile <profile>`, `profile next`, or `momentary`, `toggle` and
+// `oneshot` with a layer. Modifiers are `ctrl`, `shift`, `alt`,
+// `gui` and their right hand `r` versions joined with `+`, e.g.
+// `shortcut ctrl+alt 0x17` for Ctrl+Alt+T. Gesture timings are in ms, 0 turns
+// that gesture off.
+//
+// Media keys are 16-bit Consumer page usages, a number or one of the names in
+// `consumer::NAMES`, e.g. `media calculator`. `system <usage>` sends a System
+// Control usage, `power-down`, `sleep`, `wake-up` or a number from 0x81 to 0xb7.
+// Replies always give the number. Mouse actions are `up`, `down`, `left`, `right`,
+// `left-click`, `right-click`, `middle-click`, `wheel-up` and `wheel-down`.
+// Gamepad buttons are numbered from 1 to 16.
+//
+// A label is what the display shows on a button while its action comes from that
+// layer, up to 7 printable ASCII characters without spaces, or `none` to show a
+// name for the action (see `label`).
+//
+// Locks are `caps`, `num` and `scroll`. `leds` gives the ones the host has lit,
+// `lock` binds a lock to a layer that is active while the lock is lit.
+//
+// Combo slots are numbered from 0. A combo's buttons are written `1+2`, or `none`
+// for an unused slot.
+//
+// Macro slots are numbered from 0 too. A macro is its bytecode as hex, e.g.
+// `01e001e1031306` for Ctrl+Shift+P, or `none` for an empty slot (see
+// `macro_player` for the instructions).
+//
+// Text slots are numbered from 0 as well. A text is its UTF-8 bytes as hex, e.g.
+// `68c3a9` for "hé", or `none`. `unicode` picks how characters without a key are
+// typed, `linux`, `windows` or `wincompose` (see `text`). `layout` is the host's
+// keyboard layout texts are typed for, `us`, `uk`, `de`, `fr` or `dvorak`.
+//
+// Profiles are numbered from 0. Every command above but `version`, `layer`,
+// `state` and `leds` reads or changes the active profile, `profile` switches to
+// another one and `save` stores them all. A profile's name is up to 12 printable
+// ASCII characters without spaces, or `none` (see `profile`). `profile-select`
+// switches to the first profile with that name, for host tools that know profiles
+// by name rather than number, and fails with `unknown profile` if none has it.
+//
+// Buttons are numbered from 1. Numbers may be decimal or 0x prefixed hex and are
+// always replied as hex. Failures reply with `ERR <code> <message>`.
+//
+// Both directions are implemented here so the firmware and host tools agree on
+// the wire format.
+
+use core::fmt;
+
+use crate::action::Action;
+use crate::combo::{Combo, MAX_COMBOS};
+use crate::constants::LAYER_COUNT;
+use crate::consumer;
+use crate::debouncer::DebounceStrategy;
+use crate::gamepad;
+use crate::gesture::{Gesture, GestureTiming};
+use crate::label::{self, Label};
+use crate::layout::Layout;
+use crate::lock::{Lock, LockLeds};
+use crate::macro_player::{self, MacroCode, MACRO_LEN, MAX_MACROS};
+use crate::modifier;
+use crate::mouse::MouseAction;
+use crate::profile::{self, ProfileName, MAX_PROFILES};
+use crate::system_control;
+use crate::text::{self, Text, UnicodeMethod, MAX_TEXTS};
+
+// Room for a full macro or text as hex and then some
+pub const MAX_LINE_LEN: usize = 32 + 2 * MACRO_LEN;
+
+// Test VID/PID the deck enumerates with, used by host tools to find it
+pub const USB_VID: u16 = 0x16c0;
+pub const USB_PID: u16 = 0x27dd;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Version,
+    Get {
+        button: u8,
+    },
+    Set {
+        button: u8,
+        keycode: u8,
+        media_key: u16,
+    },
+    Map {
+        layer: u8,
+        button: u8,
+        action: Option<Action>,
+    },
+    Label {
+        layer: u8,
+        button: u8,
+        label: Option<Label>,
+    },
+    // Switch a layer on or off, or just report the active ones
+    Layer(Option<(u8, bool)>),
+    State,
+    Debounce {
+        button: u8,
+        strategy: Option<DebounceStrategy>,
+    },
+    Gesture {
+        button: u8,
+        gesture: Gesture,
+        action: Option<Action>,
+    },
+    Timing {
+        button: u8,
+        timing: Option<GestureTiming>,
+    },
+    Combo {
+        slot: u8,
+        combo: Option<Combo>,
+    },
+    // Combo window in ms
+    ComboWindow(Option<u16>),
+    Macro {
+        slot: u8,
+        code: Option<MacroCode>,
+    },
+    Leds,
+    // Layer a lock switches on, `Some(None)` unbinds it
+    LockLayer {
+        lock: Lock,
+        layer: Option<Option<u8>>,
+    },
+    Text {
+        slot: u8,
+        text: Option<Text>,
+    },
+    Unicode(Option<UnicodeMethod>),
+    Layout(Option<Layout>),
+    // Switch to a profile, or just report the active one
+    Profile(Option<u8>),
+    ProfileName {
+        profile: u8,
+        name: Option<ProfileName>,
+    },
+    // Switch to the profile with this name
+    SelectProfile(ProfileName),
+    Save,
+}
+
+// Formats the request line sent by host tools, the inverse of `parse`
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Version => f.write_str("version"),
+            Command::Get { button } => write!(f, "get {}", button),
+            Command::Set {
+                button,
+                keycode,
+                media_key,
+            } => write!(f, "set {} {:#04x} {:#04x}", button, keycode, media_key),
+            Command::Map {
+                layer,
+                button,
+                action: None,
+            } => write!(f, "map {} {}", layer, button),
+            Command::Map {
+                layer,
+                button,
+                action: Some(action),
+            } => write!(f, "map {} {} {}", layer, button, action),
+            Command::Label {
+                layer,
+                button,
+                label: None,
+            } => write!(f, "label {} {}", layer, button),
+            Command::Label {
+                layer,
+                button,
+                label: Some(label),
+            } => write!(f, "label {} {} {}", layer, button, label::Word(label)),
+            Command::Layer(None) => f.write_str("layer"),
+            Command::Layer(Some((layer, active))) => {
+                write!(f, "layer {} {}", layer, if *active { "on" } else { "off" })
+            }
+            Command::State => f.write_str("state"),
+            Command::Debounce {
+                button,
+                strategy: None,
+            } => write!(f, "debounce {}", button),
+            Command::Debounce {
+                button,
+                strategy: Some(strategy),
+            } => write!(
+                f,
+                "debounce {} {} {}",
+                button,
+                strategy.name(),
+                strategy.value()
+            ),
+            Command::Gesture {
+                button,
+                gesture,
+                action: None,
+            } => write!(f, "gesture {} {}", button, gesture.name()),
+            Command::Gesture {
+                button,
+                gesture,
+                action: Some(action),
+            } => write!(f, "gesture {} {} {}", button, gesture.name(), action),
+            Command::Timing {
+                button,
+                timing: None,
+            } => write!(f, "timing {}", button),
+            Command::Timing {
+                button,
+                timing: Some(timing),
+            } => {
+                let [double_tap, long_press, hold] = timing.to_ms();
+                write!(
+                    f,
+                    "timing {} {} {} {}",
+                    button, double_tap, long_press, hold
+                )
+            }
+            Command::Combo { slot, combo: None } => write!(f, "combo {}", slot),
+            Command::Combo {
+                slot,
+                combo: Some(combo),
+            } => write!(f, "combo {} {}", slot, combo),
+            Command::ComboWindow(None) => f.write_str("combo-window"),
+            Command::ComboWindow(Some(ms)) => write!(f, "combo-window {}", ms),
+            Command::Macro { slot, code: None } => write!(f, "macro {}", slot),
+            Command::Macro {
+                slot,
+                code: Some(code),
+            } => write!(f, "macro {} {}", slot, macro_player::Hex(code)),
+            Command::Leds => f.write_str("leds"),
+            Command::LockLayer { lock, layer: None } => write!(f, "lock {}", lock.name()),
+            Command::LockLayer {
+                lock,
+                layer: Some(layer),
+            } => write!(f, "lock {} {}", lock.name(), LayerOrNone(*layer)),
+            Command::Text { slot, text: None } => write!(f, "text {}", slot),
+            Command::Text {
+                slot,
+                text: Some(text),
+            } => write!(f, "text {} {}", slot, text::Hex(text)),
+            Command::Unicode(None) => f.write_str("unicode"),
+            Command::Unicode(Some(method)) => write!(f, "unicode {}", method.name()),
+            Command::Layout(None) => f.write_str("layout"),
+            Command::Layout(Some(layout)) => write!(f, "layout {}", layout.name()),
+            Command::Profile(None) => f.write_str("profile"),
+            Command::Profile(Some(profile)) => write!(f, "profile {}", profile),
+            Command::ProfileName {
+                profile,
+                name: None,
+            } => write!(f, "profile-name {}", profile),
+            Command::ProfileName {
+                profile,
+                name: Some(name),
+            } => write!(f, "profile-name {} {}", profile, profile::Name(name)),
+            Command::SelectProfile(name) => write!(f, "profile-select {}", profile::Name(name)),
+            Command::Save => f.write_str("save"),
+        }?;
+        f.write_str("\n")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownCommand = 1,
+    BadArgument = 2,
+    OutOfRange = 3,
+    LineTooLong = 4,
+    StorageFailed = 5,
+    UnknownProfile = 6,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "unknown command",
+            ErrorCode::BadArgument => "bad argument",
+            ErrorCode::OutOfRange => "out of range",
+            ErrorCode::LineTooLong => "line too long",
+            ErrorCode::StorageFailed => "storage failed",
+            ErrorCode::UnknownProfile => "unknown profile",
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(ErrorCode::UnknownCommand),
+            2 => Some(ErrorCode::BadArgument),
+            3 => Some(ErrorCode::OutOfRange),
+            4 => Some(ErrorCode::LineTooLong),
+            5 => Some(ErrorCode::StorageFailed),
+            6 => Some(ErrorCode::UnknownProfile),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response<'a> {
+    Ok,
+    Version(&'a str),
+    Key {
+        button: u8,
+        keycode: u8,
+        media_key: u16,
+    },
+    Map {
+        layer: u8,
+        button: u8,
+        action: Action,
+    },
+    Label {
+        layer: u8,
+        button: u8,
+        label: Label,
+    },
+    // The first `count` entries of `active` are the active layers, bottom to top
+    Layers {
+        active: [u8; LAYER_COUNT],
+        count: u8,
+    },
+    // Bit n set when button n + 1 is held
+    State {
+        pressed: u8,
+        count: u8,
+    },
+    Debounce {
+        button: u8,
+        strategy: DebounceStrategy,
+    },
+    Gesture {
+        button: u8,
+        gesture: Gesture,
+        action: Action,
+    },
+    Timing {
+        button: u8,
+        timing: GestureTiming,
+    },
+    Combo {
+        slot: u8,
+        combo: Combo,
+    },
+    ComboWindow(u16),
+    Macro {
+        slot: u8,
+        code: MacroCode,
+    },
+    Leds(LockLeds),
+    LockLayer {
+        lock: Lock,
+        layer: Option<u8>,
+    },
+    Text {
+        slot: u8,
+        text: Text,
+    },
+    Unicode(UnicodeMethod),
+    Layout(Layout),
+    Profile {
+        profile: u8,
+        name: ProfileName,
+    },
+    Err(ErrorCode),
+}
+
+impl fmt::Display for Response<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ok => f.write_str("OK"),
+            Response::Version(version) => write!(f, "OK {}", version),
+            Response::Key {
+                button,
+                keycode,
+                media_key,
+            } => write!(f, "OK {} {:#04x} {:#04x}", button, keycode, media_key),
+            Response::Map {
+                layer,
+                button,
+                action,
+            } => write!(f, "OK {} {} {}", layer, button, action),
+            Response::Label {
+                layer,
+                button,
+                label,
+            } => write!(f, "OK {} {} {}", layer, button, label::Word(label)),
+            Response::Layers { active, count } => {
+                f.write_str("OK")?;
+                for layer in &active[..*count as usize] {
+                    write!(f, " {}", layer)?;
+                }
+                Ok(())
+            }
+            Response::State { pressed, count } => {
+                f.write_str("OK ")?;
+                for index in 0..*count {
+                    f.write_str(if pressed & (1 << index) != 0 {
+                        "1"
+                    } else {
+                        "0"
+                    })?;
+                }
+                Ok(())
+            }
+            Response::Debounce { button, strategy } => {
+                write!(f, "OK {} {} {}", button, strategy.name(), strategy.value())
+            }
+            Response::Gesture {
+                button,
+                gesture,
+                action,
+            } => write!(f, "OK {} {} {}", button, gesture.name(), action),
+            Response::Timing { button, timing } => {
+                let [double_tap, long_press, hold] = timing.to_ms();
+                write!(f, "OK {} {} {} {}", button, double_tap, long_press, hold)
+            }
+            Response::Combo { slot, combo } => write!(f, "OK {} {}", slot, combo),
+            Response::ComboWindow(ms) => write!(f, "OK {}", ms),
+            Response::Macro { slot, code } => {
+                write!(f, "OK {} {}", slot, macro_player::Hex(code))
+            }
+            Response::Leds(leds) => write!(f, "OK {}", leds),
+            Response::LockLayer { lock, layer } => {
+                write!(f, "OK {} {}", lock.name(), LayerOrNone(*layer))
+            }
+            Response::Text { slot, text } => write!(f, "OK {} {}", slot, text::Hex(text)),
+            Response::Unicode(method) => write!(f, "OK {}", method.name()),
+            Response::Layout(layout) => write!(f, "OK {}", layout.name()),
+            Response::Profile { profile, name } => {
+                write!(f, "OK {} {}", profile, profile::Name(name))
+            }
+            Response::Err(code) => write!(f, "ERR {} {}", code.code(), code.message()),
+        }?;
+        f.write_str("\n")
+    }
+}
+
+// A lock's layer, `none` when it has none
+struct LayerOrNone(Option<u8>);
+
+impl fmt::Display for LayerOrNone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(layer) => write!(f, "{}", layer),
+            None => f.write_str("none"),
+        }
+    }
+}
+
+fn parse_lock_layer(word: Option<&[u8]>) -> Result<Option<u8>, ErrorCode> {
+    match word {
+        Some(b"none") => Ok(None),
+        word => parse_layer(word).map(Some),
+    }
+}
+
+/// Parse the reply line to `command` (without its terminator).
+/// Returns `None` when the line is not a well formed reply to that command.
+pub fn parse_response<'a>(command: &Command, line: &'a str) -> Option<Response<'a>> {
+    let line = line.trim_end();
+    if let Some(rest) = line.strip_prefix("ERR ") {
+        let code = rest.split(' ').next()?.parse().ok()?;
+        return ErrorCode::from_code(code).map(Response::Err);
+    }
+
+    if line == "OK" {
+        return match command {
+            Command::Set { .. } | Command::Save => Some(Response::Ok),
+            _ => None,
+        };
+    }
+
+    let rest = line.strip_prefix("OK ")?;
+    let mut words = rest.split(' ');
+    let response = match command {
+        Command::Version => Response::Version(rest),
+        Command::Get { .. } => Response::Key {
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            keycode: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            media_key: parse_usage(words.next().map(str::as_bytes)).ok()?,
+        },
+        Command::Map { .. } => Response::Map {
+            layer: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            action: parse_action(&mut words.by_ref().map(str::as_bytes)).ok()?,
+        },
+        Command::Label { .. } => Response::Label {
+            layer: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            label: label::parse_label(words.next()?.as_bytes()).ok()?,
+        },
+        Command::Layer(_) => {
+            let mut active = [0u8; LAYER_COUNT];
+            let mut count = 0;
+            for word in words.by_ref() {
+                *active.get_mut(count)? = parse_layer(Some(word.as_bytes())).ok()?;
+                count += 1;
+            }
+            Response::Layers {
+                active,
+                count: count as u8,
+            }
+        }
+        Command::State => {
+            let digits = words.next()?;
+            if digits.len() > 8 {
+                return None;
+            }
+            let mut pressed = 0u8;
+            for (index, digit) in digits.bytes().enumerate() {
+                match digit {
+                    b'1' => pressed |= 1 << index,
+                    b'0' => {}
+                    _ => return None,
+                }
+            }
+            Response::State {
+                pressed,
+                count: digits.len() as u8,
+            }
+        }
+        Command::Debounce { .. } => Response::Debounce {
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            strategy: parse_strategy(
+                words.next().map(str::as_bytes),
+                words.next().map(str::as_bytes),
+            )
+            .ok()?,
+        },
+        Command::Gesture { .. } => Response::Gesture {
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            gesture: Gesture::parse(words.next()?.as_bytes())?,
+            action: parse_action(&mut words.by_ref().map(str::as_bytes)).ok()?,
+        },
+        Command::Timing { .. } => Response::Timing {
+            button: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            timing: parse_timing(&mut words.by_ref().map(str::as_bytes)).ok()?,
+        },
+        Command::Combo { .. } => Response::Combo {
+            slot: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            combo: Combo {
+                buttons: parse_buttons(words.next().map(str::as_bytes), 8).ok()?,
+                action: parse_action(&mut words.by_ref().map(str::as_bytes)).ok()?,
+            },
+        },
+        Command::ComboWindow(_) => {
+            Response::ComboWindow(parse_ms(words.next().map(str::as_bytes)).ok()?)
+        }
+        Command::Macro { .. } => Response::Macro {
+            slot: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            code: macro_player::parse_hex(words.next()?.as_bytes()).ok()?,
+        },
+        Command::Leds => {
+            let mut leds = 0;
+            for word in words.by_ref() {
+                match Lock::parse(word.as_bytes()) {
+                    Some(lock) => leds |= lock.bit(),
+                    None if word == "none" => {}
+                    None => return None,
+                }
+            }
+            Response::Leds(LockLeds(leds))
+        }
+        Command::LockLayer { .. } => Response::LockLayer {
+            lock: Lock::parse(words.next()?.as_bytes())?,
+            layer: parse_lock_layer(words.next().map(str::as_bytes)).ok()?,
+        },
+        Command::Text { .. } => Response::Text {
+            slot: parse_u8(words.next().map(str::as_bytes)).ok()?,
+            text: text::parse_hex(words.next()?.as_bytes()).ok()?,
+        },
+        Command::Unicode(_) => Response::Unicode(UnicodeMethod::parse(words.next()?.as_bytes())?),
+        Command::Layout(_) => Response::Layout(Layout::parse(words.next()?.as_bytes())?),
+        Command::Profile(_) | Command::ProfileName { .. } | Command::SelectProfile(_) => {
+            Response::Profile {
+                profile: parse_profile(words.next().map(str::as_bytes)).ok()?,
+                name: profile::parse_name(words.next()?.as_bytes()).ok()?,
+            }
+        }
+        Command::Set { .. } | Command::Save => return None,
+    };
+
+    if !matches!(response, Response::Version(_)) && words.next().is_some() {
+        return None;
+    }
+    Some(response)
+}
+
+/// Parse one request line (without its terminator).
+/// `button_count` bounds the accepted button numbers.
+pub fn parse(line: &[u8], button_count: u8) -> Result<Command, ErrorCode> {
+    let mut words = line
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|word| !word.is_empty());
+
+    let command = match words.next() {
+        Some(word) => word,
+        None => return Err(ErrorCode::UnknownCommand),
+    };
+
+    let parsed = match command {
+        b"version" => Command::Version,
+        b"get" => Command::Get {
+            button: parse_button(words.next(), button_count)?,
+        },
+        b"set" => Command::Set {
+            button: parse_button(words.next(), button_count)?,
+            keycode: parse_u8(words.next())?,
+            media_key: parse_usage(words.next())?,
+        },
+        b"map" => {
+            let layer = parse_layer(words.next())?;
+            let button = parse_button(words.next(), button_count)?;
+            let mut words = words.by_ref().peekable();
+            let action = match words.peek() {
+                Some(_) => Some(parse_action(&mut words)?),
+                None => None,
+            };
+            Command::Map {
+                layer,
+                button,
+                action,
+            }
+        }
+        b"label" => {
+            let layer = parse_layer(words.next())?;
+            let button = parse_button(words.next(), button_count)?;
+            let label = match words.next() {
+                Some(word) => Some(label::parse_label(word)?),
+                None => None,
+            };
+            Command::Label {
+                layer,
+                button,
+                label,
+            }
+        }
+        b"layer" => match words.next() {
+            Some(word) => {
+                let layer = parse_layer(Some(word))?;
+                let active = match words.next() {
+                    Some(b"on") => true,
+                    Some(b"off") => false,
+                    _ => return Err(ErrorCode::BadArgument),
+                };
+                Command::Layer(Some((layer, active)))
+            }
+            None => Command::Layer(None),
+        },
+        b"state" => Command::State,
+        b"debounce" => {
+            let button = parse_button(words.next(), button_count)?;
+            let strategy = match words.next() {
+                Some(name) => Some(parse_strategy(Some(name), words.next())?),
+                None => None,
+            };
+            Command::Debounce { button, strategy }
+        }
+        b"gesture" => {
+            let button = parse_button(words.next(), button_count)?;
+            let gesture = words
+                .next()
+                .and_then(Gesture::parse)
+                .ok_or(ErrorCode::BadArgument)?;
+            let mut words = words.by_ref().peekable();
+            let action = match words.peek() {
+                Some(_) => Some(parse_action(&mut words)?),
+                None => None,
+            };
+            Command::Gesture {
+                button,
+                gesture,
+                action,
+            }
+        }
+        b"timing" => {
+            let button = parse_button(words.next(), button_count)?;
+            let mut words = words.by_ref().peekable();
+            let timing = match words.peek() {
+                Some(_) => Some(parse_timing(&mut words)?),
+                None => None,
+            };
+            Command::Timing { button, timing }
+        }
+        b"combo" => {
+            let slot = parse_u8(words.next())?;
+            if slot as usize >= MAX_COMBOS {
+                return Err(ErrorCode::OutOfRange);
+            }
+            let combo = match words.next() {
+                Some(buttons) => Some(Combo {
+                    buttons: parse_buttons(Some(buttons), button_count)?,
+                    action: parse_action(&mut words)?,
+                }),
+                None => None,
+            };
+            Command::Combo { slot, combo }
+        }
+        b"combo-window" => match words.next() {
+            Some(ms) => Command::ComboWindow(Some(parse_ms(Some(ms))?)),
+            None => Command::ComboWindow(None),
+        },
+        b"macro" => {
+            let slot = parse_macro_slot(words.next())?;
+            let code = match words.next() {
+                Some(word) => Some(macro_player::parse_hex(word)?),
+                None => None,
+            };
+            Command::Macro { slot, code }
+        }
+        b"leds" => Command::Leds,
+        b"lock" => {
+            let lock = words
+                .next()
+                .and_then(Lock::parse)
+                .ok_or(ErrorCode::BadArgument)?;
+            let layer = match words.next() {
+                Some(word) => Some(parse_lock_layer(Some(word))?),
+                None => None,
+            };
+            Command::LockLayer { lock, layer }
+        }
+        b"text" => {
+            let slot = parse_text_slot(words.next())?;
+            let text = match words.next() {
+                Some(word) => Some(text::parse_hex(word)?),
+                None => None,
+            };
+            Command::Text { slot, text }
+        }
+        b"unicode" => match words.next() {
+            Some(word) => Command::Unicode(Some(
+                UnicodeMethod::parse(word).ok_or(ErrorCode::BadArgument)?,
+            )),
+            None => Command::Unicode(None),
+        },
+        b"layout" => match words.next() {
+            Some(word) => Command::Layout(Some(Layout::parse(word).ok_or(ErrorCode::BadArgument)?)),
+            None => Command::Layout(None),
+        },
+        b"profile" => match words.next() {
+            Some(word) => Command::Profile(Some(parse_profile(Some(word))?)),
+            None => Command::Profile(None),
+        },
+        b"profile-name" => {
+            let profile = parse_profile(words.next())?;
+            let name = match words.next() {
+                Some(word) => Some(profile::parse_name(word)?),
+                None => None,
+            };
+            Command::ProfileName { profile, name }
+        }
+        b"profile-select" => {
+            let name = profile::parse_name(words.next().ok_or(ErrorCode::BadArgument)?)?;
+            // Unnamed profiles can't be picked by name
+            if profile::name_str(&name).is_empty() {
+                return Err(ErrorCode::BadArgument);
+            }
+            Command::SelectProfile(name)
+        }
+        b"save" => Command::Save,
+        _ => return Err(ErrorCode::UnknownCommand),
+    };
+
+    // Trailing words are a typo rather than something to silently ignore
+    if words.next().is_some() {
+        return Err(ErrorCode::BadArgument);
+    }
+
+    Ok(parsed)
+}
+
+fn parse_button(word: Option<&[u8]>, button_count: u8) -> Result<u8, ErrorCode> {
+    let button = parse_u8(word)?;
+    if button == 0 || button > button_count {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(button)
+}
+
+/// Parse a combo's buttons, `none` or at least two different buttons joined with
+/// `+`, into its button bits.
+pub fn parse_buttons(word: Option<&[u8]>, button_count: u8) -> Result<u8, ErrorCode> {
+    let word = word.ok_or(ErrorCode::BadArgument)?;
+    if word == b"none" {
+        return Ok(0);
+    }
+
+    let mut buttons = 0u8;
+    for button in word.split(|byte| *byte == b'+') {
+        let bit = 1 << (parse_button(Some(button), button_count)? - 1);
+        if buttons & bit != 0 {
+            return Err(ErrorCode::BadArgument);
+        }
+        buttons |= bit;
+    }
+    if buttons.count_ones() < 2 {
+        return Err(ErrorCode::BadArgument);
+    }
+    Ok(buttons)
+}
+
+fn parse_strategy(
+    name: Option<&[u8]>,
+    value: Option<&[u8]>,
+) -> Result<DebounceStrategy, ErrorCode> {
+    let name = name.ok_or(ErrorCode::BadArgument)?;
+    if !matches!(name, b"eager" | b"deferred" | b"integrator") {
+        return Err(ErrorCode::BadArgument);
+    }
+    // Only an integrator with no samples gets here
+    DebounceStrategy::from_parts(name, parse_u8(value)?).ok_or(ErrorCode::OutOfRange)
+}
+
+// `none`, `trans` and `bootloader` on their own, `shortcut` followed by its modifiers
+// and keycode, anything else followed by a code, layer, slot or profile
+fn parse_action<'a>(words: &mut impl Iterator<Item = &'a [u8]>) -> Result<Action, ErrorCode> {
+    let name = words.next().ok_or(ErrorCode::BadArgument)?;
+    let code = match name {
+        b"shortcut" => {
+            let modifiers = words
+                .next()
+                .and_then(modifier::parse)
+                .ok_or(ErrorCode::BadArgument)?;
+            let keycode = parse_u8(words.next())?;
+            return Ok(Action::Shortcut { modifiers, keycode });
+        }
+        b"media" => return Ok(Action::Media(parse_usage(words.next())?)),
+        b"mouse" => {
+            let action = words
+                .next()
+                .and_then(MouseAction::parse)
+                .ok_or(ErrorCode::BadArgument)?;
+            return Ok(Action::Mouse(action));
+        }
+        b"system" => {
+            let usage = system_control::parse(words.next().ok_or(ErrorCode::BadArgument)?)?;
+            return Ok(Action::System(usage));
+        }
+        b"gamepad" => {
+            let button = gamepad::parse(words.next().ok_or(ErrorCode::BadArgument)?)?;
+            return Ok(Action::Gamepad(button));
+        }
+        b"none" | b"trans" | b"bootloader" => None,
+        b"key" => Some(parse_u8(words.next())?),
+        b"momentary" | b"toggle" | b"oneshot" => Some(parse_layer(words.next())?),
+        b"macro" => Some(parse_macro_slot(words.next())?),
+        b"text" => Some(parse_text_slot(words.next())?),
+        b"profile" => match words.next() {
+            Some(b"next") => return Ok(Action::NextProfile),
+            word => Some(parse_profile(word)?),
+        },
+        _ => return Err(ErrorCode::BadArgument),
+    };
+    Action::from_parts(name, code).ok_or(ErrorCode::BadArgument)
+}
+
+fn parse_layer(word: Option<&[u8]>) -> Result<u8, ErrorCode> {
+    let layer = parse_u8(word)?;
+    if layer as usize >= LAYER_COUNT {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(layer)
+}
+
+fn parse_profile(word: Option<&[u8]>) -> Result<u8, ErrorCode> {
+    let profile = parse_u8(word)?;
+    if profile as usize >= MAX_PROFILES {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(profile)
+}
+
+fn parse_macro_slot(word: Option<&[u8]>) -> Result<u8, ErrorCode> {
+    let slot = parse_u8(word)?;
+    if slot as usize >= MAX_MACROS {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(slot)
+}
+
+fn parse_text_slot(word: Option<&[u8]>) -> Result<u8, ErrorCode> {
+    let slot = parse_u8(word)?;
+    if slot as usize >= MAX_TEXTS {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(slot)
+}
+
+fn parse_timing<'a>(
+    words: &mut impl Iterator<Item = &'a [u8]>,
+) -> Result<GestureTiming, ErrorCode> {
+    let mut ms = [0u16; 3];
+    for ms in ms.iter_mut() {
+        *ms = parse_ms(words.next())?;
+    }
+    Ok(GestureTiming::from_ms(ms))
+}
+
+fn parse_ms(word: Option<&[u8]>) -> Result<u16, ErrorCode> {
+    let value = parse_number(word.ok_or(ErrorCode::BadArgument)?)?;
+    u16::try_from(value).map_err(|_| ErrorCode::OutOfRange)
+}
+
+fn parse_usage(word: Option<&[u8]>) -> Result<u16, ErrorCode> {
+    consumer::parse(word.ok_or(ErrorCode::BadArgument)?)
+}
+
+fn parse_u8(word: Option<&[u8]>) -> Result<u8, ErrorCode> {
+    parse_byte(word.ok_or(ErrorCode::BadArgument)?)
+}
+
+/// Parse a decimal or 0x prefixed hex byte the same way request arguments are.
+pub fn parse_byte(word: &[u8]) -> Result<u8, ErrorCode> {
+    let value = parse_number(word)?;
+    u8::try_from(value).map_err(|_| ErrorCode::OutOfRange)
+}
+
+/// Decode the hex digit pairs of `word` into the start of `buf`, leaving the rest
+/// of it alone.
+pub fn parse_hex_bytes(word: &[u8], buf: &mut [u8]) -> Result<(), ErrorCode> {
+    if word.is_empty() || !word.len().is_multiple_of(2) {
+        return Err(ErrorCode::BadArgument);
+    }
+    if word.len() > 2 * buf.len() {
+        return Err(ErrorCode::OutOfRange);
+    }
+
+    for (byte, digits) in buf.iter_mut().zip(word.chunks_exact(2)) {
+        let mut value = 0;
+        for digit in digits {
+            value = value * 16
+                + (*digit as char)
+                    .to_digit(16)
+                    .ok_or(ErrorCode::BadArgument)? as u8;
+        }
+        *byte = value;
+    }
+    Ok(())
+}
+
+/// Same as `parse_byte` for 16-bit values.
+pub fn parse_u16(word: &[u8]) -> Result<u16, ErrorCode> {
+    let value = parse_number(word)?;
+    u16::try_from(value).map_err(|_| ErrorCode::OutOfRange)
+}
+
+fn parse_number(word: &[u8]) -> Result<u32, ErrorCode> {
+    let (digits, radix) = match word {
+        [b'0', b'x' | b'X', rest @ ..] => (rest, 16),
+        _ => (word, 10),
+    };
+    if digits.is_empty() {
+        return Err(ErrorCode::BadArgument);
+    }
+
+    let mut value: u32 = 0;
+    for byte in digits {
+        let digit = (*byte as char)
+            .to_digit(radix)
+            .ok_or(ErrorCode::BadArgument)?;
+        value = value
+            .checked_mul(radix)
+            .and_then(|value| value.checked_add(digit))
+            .ok_or(ErrorCode::OutOfRange)?;
+    }
+    Ok(value)
+}
+
+/// Accumulates bytes from one transport, serial or the payloads of raw HID
+/// reports, into request lines.
+pub struct LineBuffer {
+    buf: [u8; MAX_LINE_LEN],
+    len: usize,
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; MAX_LINE_LEN],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Add a byte, returns true once a full line is ready for `take_line`.
+    pub fn push(&mut self, byte: u8) -> bool {
+        match byte {
+            b'\n' => true,
+            b'\r' => false,
+            _ => {
+                if self.len < MAX_LINE_LEN {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                false
+            }
+        }
+    }
+
+    /// The completed line, clearing the buffer for the next one.
+    pub fn take_line(&mut self) -> Result<&[u8], ErrorCode> {
+        let len = self.len;
+        let overflow = self.overflow;
+        self.len = 0;
+        self.overflow = false;
+
+        if overflow {
+            return Err(ErrorCode::LineTooLong);
+        }
+        Ok(&self.buf[..len])
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
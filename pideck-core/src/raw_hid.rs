@@ -0,0 +1,40 @@
+// Framing of the config protocol over the vendor-defined raw HID interface. Raw
+// HID needs no driver on any host and no modem manager goes near it, unlike the
+// CDC serial port.
+//
+// Both directions use 64-byte reports. The first byte is how many bytes of text
+// the report carries, the text follows and the rest is padded with 0. Joined up,
+// the texts are exactly the byte stream the serial port carries, so a line may
+// span several reports and both ends split lines the same way they do on serial.
+
+pub const USAGE_PAGE: u16 = 0xFF00;
+pub const USAGE: u8 = 0x01;
+
+pub const REPORT_LEN: usize = 64;
+pub const MAX_PAYLOAD: usize = REPORT_LEN - 1;
+
+pub type Report = [u8; REPORT_LEN];
+
+/// Split `data` into as many reports as it needs, none for no data.
+pub fn frames(data: &[u8]) -> impl Iterator<Item = Report> + '_ {
+    data.chunks(MAX_PAYLOAD).map(|chunk| {
+        let mut report = [0; REPORT_LEN];
+        report[0] = chunk.len() as u8;
+        report[1..=chunk.len()].copy_from_slice(chunk);
+        report
+    })
+}
+
+/// Text a report carries, `None` if its length byte runs past the end of it.
+pub fn payload(report: &[u8]) -> Option<&[u8]> {
+    let len = *report.first()? as usize;
+    report.get(1..1 + len)
+}
+
+/// Whether a HID report descriptor is the raw HID interface's, i.e. starts with
+/// its vendor usage page. Lets hosts pick it out from the deck's other interfaces.
+pub fn is_raw_descriptor(descriptor: &[u8]) -> bool {
+    let [low, high] = USAGE_PAGE.to_le_bytes();
+    // Usage Page item with a 2 byte value
+    descriptor.starts_with(&[0x06, low, high])
+}
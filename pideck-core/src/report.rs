@@ -0,0 +1,197 @@
+// Hardware-independent HID reports. The firmware turns these into the usbd-hid
+// report structs, tests just record them.
+//
+// An endpoint holds one report until the host polls it, every 60 ms for most of
+// them, and refuses another until then. So a tap that presses and releases in one
+// go would lose its release. Reports an endpoint refuses wait in a `ReportQueue`
+// and go out one per poll, in order, as the firmware flushes it from the USB
+// interrupt.
+
+use heapless::Deque;
+
+// One bit per keyboard usage 0x00-0xDF, the modifiers 0xE0-0xE7 are the modifier byte
+pub const NKRO_KEY_BYTES: usize = 28;
+pub const NKRO_MAX_KEYCODE: u8 = 0xDF;
+
+pub const BOOT_KEYCODE_SLOTS: usize = 6;
+// Fills every slot of a boot report when more keys are held than fit
+pub const KEYCODE_ERROR_ROLLOVER: u8 = 0x01;
+
+/// Every held key. Sent as a bitmap to hosts using the report protocol, boot
+/// protocol hosts get `boot_keycodes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyboardReport {
+    pub modifier: u8,
+    pub keys: [u8; NKRO_KEY_BYTES],
+}
+
+impl KeyboardReport {
+    /// Mark `keycode` as held. Returns false for keycodes past the bitmap.
+    pub fn press(&mut self, keycode: u8) -> bool {
+        if keycode > NKRO_MAX_KEYCODE {
+            return false;
+        }
+        self.keys[keycode as usize / 8] |= 1 << (keycode % 8);
+        true
+    }
+
+    /// Returns false if `keycode` was not held.
+    pub fn release(&mut self, keycode: u8) -> bool {
+        let held = self.is_pressed(keycode);
+        if held {
+            self.keys[keycode as usize / 8] &= !(1 << (keycode % 8));
+        }
+        held
+    }
+
+    pub fn is_pressed(&self, keycode: u8) -> bool {
+        keycode <= NKRO_MAX_KEYCODE && self.keys[keycode as usize / 8] & (1 << (keycode % 8)) != 0
+    }
+
+    pub fn pressed(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=NKRO_MAX_KEYCODE).filter(|keycode| self.is_pressed(*keycode))
+    }
+
+    /// The six keycode slots of a boot protocol report. With more keys held than
+    /// that every slot is ErrorRollOver, which tells the host to keep the keys it
+    /// last saw rather than guess.
+    pub fn boot_keycodes(&self) -> [u8; BOOT_KEYCODE_SLOTS] {
+        let mut keycodes = [0; BOOT_KEYCODE_SLOTS];
+        for (index, keycode) in self.pressed().enumerate() {
+            if index == BOOT_KEYCODE_SLOTS {
+                return [KEYCODE_ERROR_ROLLOVER; BOOT_KEYCODE_SLOTS];
+            }
+            keycodes[index] = keycode;
+        }
+        keycodes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MediaReport {
+    pub usage_id: u16,
+}
+
+// System Control usage id, 0 releases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SystemReport {
+    pub usage_id: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseReport {
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+    // Notches up (positive) or down (negative)
+    pub wheel: i8,
+}
+
+// Held gamepad buttons, button 1 in bit 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GamepadReport {
+    pub buttons: u16,
+}
+
+/// Where `HIDUtil` sends its reports - the USB HID classes on the device. Each
+/// send returns false if the endpoint still holds a report the host hasn't read,
+/// and the report wasn't taken.
+pub trait ReportSink {
+    fn send_keyboard(&mut self, report: KeyboardReport) -> bool;
+    fn send_media(&mut self, report: MediaReport) -> bool;
+    fn send_system(&mut self, report: SystemReport) -> bool;
+    fn send_mouse(&mut self, report: MouseReport) -> bool;
+    fn send_gamepad(&mut self, report: GamepadReport) -> bool;
+    /// Reboot into the USB bootloader. Only returns on the host.
+    fn enter_bootloader(&mut self);
+}
+
+// Reports waiting for each endpoint. A tap is two, a few taps in a row fit
+pub const REPORT_QUEUE_LEN: usize = 8;
+
+/// Reports the endpoints refused so far, waiting for the host to poll.
+#[derive(Debug, Default)]
+pub struct ReportQueue {
+    keyboard: Deque<KeyboardReport, REPORT_QUEUE_LEN>,
+    media: Deque<MediaReport, REPORT_QUEUE_LEN>,
+    system: Deque<SystemReport, REPORT_QUEUE_LEN>,
+    mouse: Deque<MouseReport, REPORT_QUEUE_LEN>,
+    gamepad: Deque<GamepadReport, REPORT_QUEUE_LEN>,
+}
+
+impl ReportQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn send_keyboard(&mut self, sink: &mut impl ReportSink, report: KeyboardReport) {
+        send(&mut self.keyboard, report, |report| {
+            sink.send_keyboard(report)
+        });
+    }
+
+    pub fn send_media(&mut self, sink: &mut impl ReportSink, report: MediaReport) {
+        send(&mut self.media, report, |report| sink.send_media(report));
+    }
+
+    pub fn send_system(&mut self, sink: &mut impl ReportSink, report: SystemReport) {
+        send(&mut self.system, report, |report| sink.send_system(report));
+    }
+
+    pub fn send_mouse(&mut self, sink: &mut impl ReportSink, report: MouseReport) {
+        send(&mut self.mouse, report, |report| sink.send_mouse(report));
+    }
+
+    pub fn send_gamepad(&mut self, sink: &mut impl ReportSink, report: GamepadReport) {
+        send(&mut self.gamepad, report, |report| {
+            sink.send_gamepad(report)
+        });
+    }
+
+    /// Send what the endpoints take now, the next report of each that has one
+    /// waiting.
+    pub fn flush(&mut self, sink: &mut impl ReportSink) {
+        flush(&mut self.keyboard, |report| sink.send_keyboard(report));
+        flush(&mut self.media, |report| sink.send_media(report));
+        flush(&mut self.system, |report| sink.send_system(report));
+        flush(&mut self.mouse, |report| sink.send_mouse(report));
+        flush(&mut self.gamepad, |report| sink.send_gamepad(report));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyboard.is_empty()
+            && self.media.is_empty()
+            && self.system.is_empty()
+            && self.mouse.is_empty()
+            && self.gamepad.is_empty()
+    }
+}
+
+// Send `report` straight away unless earlier ones are still waiting, it must not
+// overtake them. With the queue full the last report waiting is replaced, the
+// host misses a step in between but ends up in the right state.
+fn send<R, const N: usize>(queue: &mut Deque<R, N>, report: R, mut send: impl FnMut(R) -> bool)
+where
+    R: Copy,
+{
+    if queue.is_empty() && send(report) {
+        return;
+    }
+    if let Err(report) = queue.push_back(report) {
+        if let Some(last) = queue.back_mut() {
+            *last = report;
+        }
+    }
+}
+
+fn flush<R, const N: usize>(queue: &mut Deque<R, N>, mut send: impl FnMut(R) -> bool)
+where
+    R: Copy,
+{
+    while let Some(report) = queue.front() {
+        if !send(*report) {
+            break;
+        }
+        queue.pop_front();
+    }
+}
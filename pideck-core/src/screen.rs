@@ -0,0 +1,260 @@
+// What the display shows, drawn onto any embedded-graphics `DrawTarget` so it can
+// be drawn into a mock display on the host as well as the SSD1306.
+//
+// The 128x32 panel has a status bar along the top and the buttons below it. The
+// status bar shows the active profile, the top layer, Caps Lock, the USB state
+// and whether a terminal has the serial port open. The buttons are laid out as
+// they sit on the deck, two rows of three with button 1 top left, and each shows
+// its label centred in its cell. A held button is drawn inverted.
+//
+// Each part draws over the whole of the target it is given, cropped to the part's
+// area, so one can be redrawn without the other.
+//
+// Drawing and sending the frame over I2C takes milliseconds, too long to wait for
+// in the middle of a key press. So the key handling only updates a `Screen`, which
+// remembers which parts changed, and the firmware draws it from a low priority
+// task once the presses are through. Changes made in between are drawn together.
+
+use core::fmt::Write;
+
+use embedded_graphics::mono_font::ascii::{FONT_4X6, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+use enum_map::{Enum, EnumMap};
+use heapless::String;
+
+use crate::key_config::KeyConfig;
+use crate::label::LabelText;
+use crate::profile::{self, ProfileName, PROFILE_NAME_LEN};
+
+pub const COLUMNS: u32 = 3;
+pub const ROWS: u32 = 2;
+// One line of the 6 pixel high font and a pixel to keep it off the buttons
+pub const STATUS_HEIGHT: u32 = 7;
+
+/// Where the deck is with the USB host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UsbState {
+    // Powered, waiting for the host to finish setting it up
+    #[default]
+    Enumerating,
+    Configured,
+    // The host is asleep, or the cable is out and only power is left
+    Suspended,
+}
+
+impl UsbState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UsbState::Enumerating => "ENUM",
+            UsbState::Configured => "USB",
+            UsbState::Suspended => "SUSP",
+        }
+    }
+}
+
+/// What the status bar shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status {
+    pub profile: u8,
+    pub profile_name: ProfileName,
+    // Top active layer
+    pub layer: u8,
+    pub caps_lock: bool,
+    pub usb: UsbState,
+    // A terminal has the serial port open (DTR set)
+    pub serial: bool,
+}
+
+/// The status bar's part of `area`, along the top.
+pub fn status_area(area: &Rectangle) -> Rectangle {
+    Rectangle::new(
+        area.top_left,
+        Size::new(area.size.width, STATUS_HEIGHT.min(area.size.height)),
+    )
+}
+
+/// The buttons' part of `area`, everything below the status bar.
+pub fn keys_area(area: &Rectangle) -> Rectangle {
+    let status = STATUS_HEIGHT.min(area.size.height);
+    Rectangle::new(
+        area.top_left + Point::new(0, status as i32),
+        Size::new(area.size.width, area.size.height - status),
+    )
+}
+
+/// Draw `status` over the whole of `target`. Every field keeps its column, so
+/// nothing shifts when another one changes.
+pub fn draw_status<D>(target: &mut D, status: &Status) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    // The name, or the number for a profile without one
+    let mut name: String<PROFILE_NAME_LEN> = String::new();
+    let _ = match profile::name_str(&status.profile_name) {
+        "" => write!(name, "P{}", status.profile),
+        profile_name => name.write_str(profile_name),
+    };
+    let mut line: String<32> = String::new();
+    let _ = write!(
+        line,
+        "{:<12} L{} {:<4} {:<4} {}",
+        name,
+        status.layer,
+        if status.caps_lock { "CAPS" } else { "" },
+        status.usb.name(),
+        if status.serial { "SER" } else { "" },
+    );
+
+    target.clear(BinaryColor::Off)?;
+    Text::with_baseline(
+        line.as_str(),
+        Point::zero(),
+        MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+        Baseline::Top,
+    )
+    .draw(target)?;
+    Ok(())
+}
+
+/// The area of `area` button `button_id` takes up.
+pub fn key_cell(area: &Rectangle, button_id: KeyConfig) -> Rectangle {
+    let index = button_id.into_usize() as u32;
+    let (column, row) = (index % COLUMNS, index / COLUMNS);
+    // Spread the pixels that don't divide evenly over the cells
+    let left = column * area.size.width / COLUMNS;
+    let right = (column + 1) * area.size.width / COLUMNS;
+    let top = row * area.size.height / ROWS;
+    let bottom = (row + 1) * area.size.height / ROWS;
+    Rectangle::new(
+        area.top_left + Point::new(left as i32, top as i32),
+        Size::new(right - left, bottom - top),
+    )
+}
+
+/// Draw every button's label over the whole of `target`, the `pressed` ones
+/// highlighted.
+pub fn draw_keys<D>(
+    target: &mut D,
+    labels: &EnumMap<KeyConfig, LabelText>,
+    pressed: &EnumMap<KeyConfig, bool>,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let area = target.bounding_box();
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+
+    for (button_id, label) in labels.iter() {
+        let (background, foreground) = match pressed[button_id] {
+            true => (BinaryColor::On, BinaryColor::Off),
+            false => (BinaryColor::Off, BinaryColor::On),
+        };
+        let cell = key_cell(&area, button_id);
+        cell.into_styled(PrimitiveStyle::with_fill(background))
+            .draw(target)?;
+        Text::with_text_style(
+            label.as_str(),
+            cell.center(),
+            MonoTextStyle::new(&FONT_6X10, foreground),
+            text_style,
+        )
+        .draw(target)?;
+    }
+    Ok(())
+}
+
+/// What the display should show, and which parts changed since it was last
+/// drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    labels: EnumMap<KeyConfig, LabelText>,
+    pressed: EnumMap<KeyConfig, bool>,
+    status: Status,
+    keys_dirty: bool,
+    status_dirty: bool,
+}
+
+impl Screen {
+    /// Nothing drawn yet, so the first draw covers whatever the display shows.
+    pub fn new() -> Self {
+        Screen {
+            labels: EnumMap::default(),
+            pressed: EnumMap::default(),
+            status: Status::default(),
+            keys_dirty: true,
+            status_dirty: true,
+        }
+    }
+
+    pub fn set_keys(
+        &mut self,
+        labels: EnumMap<KeyConfig, LabelText>,
+        pressed: EnumMap<KeyConfig, bool>,
+    ) {
+        if labels != self.labels || pressed != self.pressed {
+            self.labels = labels;
+            self.pressed = pressed;
+            self.keys_dirty = true;
+        }
+    }
+
+    pub fn set_status(&mut self, status: Status) {
+        if status != self.status {
+            self.status = status;
+            self.status_dirty = true;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.keys_dirty || self.status_dirty
+    }
+
+    /// A copy to draw, and mark everything drawn. The copy keeps the dirty parts
+    /// so it can be drawn without holding on to this one.
+    pub fn take(&mut self) -> Screen {
+        let frame = self.clone();
+        self.keys_dirty = false;
+        self.status_dirty = false;
+        frame
+    }
+
+    /// Mark everything to be drawn again, for when a frame taken never made it
+    /// to the display.
+    pub fn redraw(&mut self) {
+        self.keys_dirty = true;
+        self.status_dirty = true;
+    }
+
+    /// Draw the parts that changed onto `target`. Returns whether anything was
+    /// drawn, i.e. whether the display needs sending.
+    pub fn draw<D>(&self, target: &mut D) -> Result<bool, D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let area = target.bounding_box();
+        if self.keys_dirty {
+            draw_keys(
+                &mut target.cropped(&keys_area(&area)),
+                &self.labels,
+                &self.pressed,
+            )?;
+        }
+        if self.status_dirty {
+            draw_status(&mut target.cropped(&status_area(&area)), &self.status)?;
+        }
+        Ok(self.is_dirty())
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,30 @@
+// Generic Desktop System Control usages, what system actions send. The report
+// covers 0x81-0xB7, the three every host acts on have names, e.g. `system sleep`.
+
+use crate::protocol::{self, ErrorCode};
+
+pub const POWER_DOWN: u8 = 0x81;
+pub const SLEEP: u8 = 0x82;
+pub const WAKE_UP: u8 = 0x83;
+
+// Range the System Control report descriptor declares
+pub const FIRST_USAGE: u8 = 0x81;
+pub const LAST_USAGE: u8 = 0xB7;
+
+pub const NAMES: [(&str, u8); 3] = [
+    ("power-down", POWER_DOWN),
+    ("sleep", SLEEP),
+    ("wake-up", WAKE_UP),
+];
+
+/// Parse a usage from its name in `NAMES` or as a number in the report's range.
+pub fn parse(word: &[u8]) -> Result<u8, ErrorCode> {
+    if let Some((_, usage)) = NAMES.iter().find(|(name, _)| name.as_bytes() == word) {
+        return Ok(*usage);
+    }
+    let usage = protocol::parse_byte(word)?;
+    if !(FIRST_USAGE..=LAST_USAGE).contains(&usage) {
+        return Err(ErrorCode::OutOfRange);
+    }
+    Ok(usage)
+}
@@ -0,0 +1,332 @@
+// Typing text. A text action types the UTF-8 string stored in one of the text
+// slots, one report at a time like a macro.
+//
+// Characters the host's keyboard layout has a key for are typed on that key, with
+// Shift or AltGr where it needs them (see `layout`). Anything else is entered by
+// its code point in hex with the host's Unicode input method. The host cannot
+// tell the deck which one it has, so that is a setting:
+//   linux       Ctrl+Shift+U, the code point, Space (IBus and GTK)
+//   windows     Alt held over keypad +, the code point (needs the EnableHexNumpad
+//               registry value)
+//   wincompose  the compose key (Right Alt by default), u, the code point, Enter
+//
+// The config protocol carries a text as its UTF-8 bytes in hex, `none` for an
+// empty slot, so any text fits in one line of words.
+
+use core::fmt::{self, Write};
+use core::str;
+
+use heapless::{String, Vec};
+
+use crate::layout::Layout;
+use crate::macro_player::{MacroStep, MACRO_LEN};
+use crate::modifier::{MOD_LCTRL, MOD_LSHIFT};
+use crate::protocol::{self, ErrorCode};
+
+pub const MAX_TEXTS: usize = 8;
+// In bytes of UTF-8, padded with 0. As long as a macro so it fits a protocol line
+// as hex the same way
+pub const TEXT_LEN: usize = MACRO_LEN;
+
+pub type Text = [u8; TEXT_LEN];
+
+// Most steps one character takes: Ctrl+Shift+U, six digits that may each need
+// a modifier, and Space
+pub const MAX_CHAR_STEPS: usize = 6 + 6 * 4 + 2;
+
+type Steps = Vec<MacroStep, MAX_CHAR_STEPS>;
+
+const KEY_ENTER: u8 = 0x28;
+const KEY_SPACE: u8 = 0x2C;
+const KEY_KEYPAD_PLUS: u8 = 0x57;
+const KEY_KEYPAD_1: u8 = 0x59;
+const KEY_KEYPAD_0: u8 = 0x62;
+const KEY_LCTRL: u8 = 0xE0;
+const KEY_LALT: u8 = 0xE2;
+const KEY_RALT: u8 = 0xE6;
+
+/// How the host takes characters no key types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnicodeMethod {
+    #[default]
+    Linux,
+    Windows,
+    WinCompose,
+}
+
+impl UnicodeMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnicodeMethod::Linux => "linux",
+            UnicodeMethod::Windows => "windows",
+            UnicodeMethod::WinCompose => "wincompose",
+        }
+    }
+
+    pub fn parse(word: &[u8]) -> Option<Self> {
+        match word {
+            b"linux" => Some(UnicodeMethod::Linux),
+            b"windows" => Some(UnicodeMethod::Windows),
+            b"wincompose" => Some(UnicodeMethod::WinCompose),
+            _ => None,
+        }
+    }
+}
+
+/// Every text slot, the input method they are typed with and the host's layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextConfig {
+    pub texts: [Text; MAX_TEXTS],
+    pub method: UnicodeMethod,
+    pub layout: Layout,
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        TextConfig {
+            texts: [[0; TEXT_LEN]; MAX_TEXTS],
+            method: UnicodeMethod::default(),
+            layout: Layout::default(),
+        }
+    }
+}
+
+/// Presses and releases that type `c` on a host with `layout` and `method`, none
+/// if the layout lacks a key the method needs.
+pub fn char_steps(
+    c: char,
+    layout: Layout,
+    method: UnicodeMethod,
+) -> Vec<MacroStep, MAX_CHAR_STEPS> {
+    let mut steps = Steps::new();
+    let typed = match layout.key(c) {
+        Some(key) => {
+            tap(&mut steps, key);
+            Some(())
+        }
+        None => unicode_steps(&mut steps, c, layout, method),
+    };
+    if typed.is_none() {
+        steps.clear();
+    }
+    steps
+}
+
+fn unicode_steps(steps: &mut Steps, c: char, layout: Layout, method: UnicodeMethod) -> Option<()> {
+    let mut digits: String<8> = String::new();
+    write!(digits, "{:x}", c as u32).ok()?;
+
+    match method {
+        UnicodeMethod::Linux => {
+            // U wherever the layout has it, e.g. on F with Dvorak
+            let (u, modifiers) = layout.key('u')?;
+            press_modifiers(steps, modifiers | MOD_LCTRL | MOD_LSHIFT);
+            chord(steps, &[u]);
+            release_modifiers(steps, modifiers | MOD_LCTRL | MOD_LSHIFT);
+            for digit in digits.chars() {
+                tap(steps, layout.key(digit)?);
+            }
+            chord(steps, &[KEY_SPACE]);
+        }
+        // Alt stays down until the last digit, the number pad takes the digits
+        UnicodeMethod::Windows => {
+            let _ = steps.push(MacroStep::Press(KEY_LALT));
+            chord(steps, &[KEY_KEYPAD_PLUS]);
+            for digit in digits.bytes() {
+                match digit {
+                    b'0' => chord(steps, &[KEY_KEYPAD_0]),
+                    b'1'..=b'9' => chord(steps, &[KEY_KEYPAD_1 + digit - b'1']),
+                    _ => tap(steps, layout.key(digit as char)?),
+                }
+            }
+            let _ = steps.push(MacroStep::Release(KEY_LALT));
+        }
+        UnicodeMethod::WinCompose => {
+            chord(steps, &[KEY_RALT]);
+            tap(steps, layout.key('u')?);
+            for digit in digits.chars() {
+                tap(steps, layout.key(digit)?);
+            }
+            chord(steps, &[KEY_ENTER]);
+        }
+    }
+    Some(())
+}
+
+fn tap(steps: &mut Steps, (keycode, modifiers): (u8, u8)) {
+    press_modifiers(steps, modifiers);
+    chord(steps, &[keycode]);
+    release_modifiers(steps, modifiers);
+}
+
+// Modifier bits line up with the modifier keycodes from Left Ctrl on
+fn press_modifiers(steps: &mut Steps, modifiers: u8) {
+    for bit in 0..8 {
+        if modifiers & (1 << bit) != 0 {
+            let _ = steps.push(MacroStep::Press(KEY_LCTRL + bit));
+        }
+    }
+}
+
+fn release_modifiers(steps: &mut Steps, modifiers: u8) {
+    for bit in (0..8).rev() {
+        if modifiers & (1 << bit) != 0 {
+            let _ = steps.push(MacroStep::Release(KEY_LCTRL + bit));
+        }
+    }
+}
+
+// Press `keys` in order, then release them the other way round. `Steps` has room
+// for the longest character, so nothing is dropped
+fn chord(steps: &mut Steps, keys: &[u8]) {
+    for key in keys {
+        let _ = steps.push(MacroStep::Press(*key));
+    }
+    for key in keys.iter().rev() {
+        let _ = steps.push(MacroStep::Release(*key));
+    }
+}
+
+/// Length of the text in `text` up to its padding, or `None` if it is not UTF-8
+/// or has anything after the padding starts.
+pub fn validate(text: &[u8]) -> Option<usize> {
+    let len = text
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(text.len());
+    if text[len..].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    str::from_utf8(&text[..len]).ok()?;
+    Some(len)
+}
+
+/// The text in a slot, `None` if it does not check out.
+pub fn as_str(text: &Text) -> Option<&str> {
+    str::from_utf8(&text[..validate(text)?]).ok()
+}
+
+/// Pad `text` out to a slot, checking it fits.
+pub fn from_str(text: &str) -> Result<Text, ErrorCode> {
+    if text.len() > TEXT_LEN {
+        return Err(ErrorCode::OutOfRange);
+    }
+    if text.contains('\0') {
+        return Err(ErrorCode::BadArgument);
+    }
+    let mut padded = [0; TEXT_LEN];
+    padded[..text.len()].copy_from_slice(text.as_bytes());
+    Ok(padded)
+}
+
+/// Parse the hex form used by the config protocol, checking it is UTF-8.
+pub fn parse_hex(word: &[u8]) -> Result<Text, ErrorCode> {
+    let mut text = [0; TEXT_LEN];
+    if word == b"none" {
+        return Ok(text);
+    }
+    protocol::parse_hex_bytes(word, &mut text)?;
+    validate(&text).ok_or(ErrorCode::BadArgument)?;
+    Ok(text)
+}
+
+/// Hex form of a text up to its padding, the inverse of `parse_hex`.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = validate(self.0).ok_or(fmt::Error)?;
+        if len == 0 {
+            return f.write_str("none");
+        }
+        for byte in &self.0[..len] {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Types a text a step at a time, the same way `MacroPlayer` plays a macro.
+pub struct Typist {
+    text: Text,
+    len: usize,
+    // Byte offset of the next character
+    pos: usize,
+    layout: Layout,
+    method: UnicodeMethod,
+    // The character being typed and how far it has got
+    steps: Steps,
+    next: usize,
+    typing: bool,
+}
+
+impl Typist {
+    pub fn new() -> Self {
+        Typist {
+            text: [0; TEXT_LEN],
+            len: 0,
+            pos: 0,
+            layout: Layout::default(),
+            method: UnicodeMethod::default(),
+            steps: Steps::new(),
+            next: 0,
+            typing: false,
+        }
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.typing
+    }
+
+    /// Start typing `text` from the top. Returns false and carries on with the
+    /// current text if one is still being typed.
+    pub fn start(&mut self, text: &Text, layout: Layout, method: UnicodeMethod) -> bool {
+        let len = match validate(text) {
+            Some(len) if !self.typing => len,
+            _ => return false,
+        };
+        self.text = *text;
+        self.len = len;
+        self.pos = 0;
+        self.layout = layout;
+        self.method = method;
+        self.steps.clear();
+        self.next = 0;
+        self.typing = true;
+        true
+    }
+
+    /// The next change to send, one per call like `MacroPlayer::next_step`. Every
+    /// character ends with its keys released. `None` once the text is typed.
+    pub fn next_step(&mut self) -> Option<MacroStep> {
+        if !self.typing {
+            return None;
+        }
+        loop {
+            if let Some(step) = self.steps.get(self.next) {
+                self.next += 1;
+                return Some(*step);
+            }
+            // Checked by `start`
+            let c = match str::from_utf8(&self.text[self.pos..self.len])
+                .ok()
+                .and_then(|rest| rest.chars().next())
+            {
+                Some(c) => c,
+                None => {
+                    self.typing = false;
+                    return None;
+                }
+            };
+            self.pos += c.len_utf8();
+            self.steps = char_steps(c, self.layout, self.method);
+            self.next = 0;
+        }
+    }
+}
+
+impl Default for Typist {
+    fn default() -> Self {
+        Self::new()
+    }
+}
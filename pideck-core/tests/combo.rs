@@ -0,0 +1,242 @@
+use pideck_core::action::Action;
+use pideck_core::button::ButtonEvent;
+use pideck_core::combo::*;
+use pideck_core::key_config::KeyConfig;
+use pideck_core::layer::MEDIA_LAYER;
+
+const MS: u32 = 1_000;
+
+fn press(id: KeyConfig) -> ButtonEvent {
+    ButtonEvent { id, pressed: true }
+}
+
+fn release(id: KeyConfig) -> ButtonEvent {
+    ButtonEvent { id, pressed: false }
+}
+
+fn combo(buttons: &[KeyConfig], action: Action) -> Combo {
+    Combo {
+        buttons: buttons.iter().fold(0, |bits, id| bits | button_bit(*id)),
+        action,
+    }
+}
+
+fn action(action: Action, pressed: bool) -> ComboOutput {
+    ComboOutput::Action { action, pressed }
+}
+
+// Runs a 1 ms scan over `edges`, given as (ms, event), until `end` ms
+fn outputs(config: ComboConfig, edges: &[(u32, ButtonEvent)], end: u32) -> Vec<(u32, ComboOutput)> {
+    let mut engine = ComboEngine::new(config);
+    let mut outputs = Vec::new();
+    for ms in 0..=end {
+        let mut edges = edges.iter().filter(|(time, _)| *time == ms).peekable();
+        if edges.peek().is_none() {
+            engine.update(ms * MS, None, |output| outputs.push((ms, output)));
+        }
+        for (_, event) in edges {
+            engine.update(ms * MS, Some(*event), |output| outputs.push((ms, output)));
+        }
+    }
+    outputs
+}
+
+const TOGGLE_MEDIA: Action = Action::Toggle(MEDIA_LAYER);
+
+#[test]
+fn default_combo_toggles_the_media_layer() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[
+            (0, press(KeyConfig::One)),
+            (20, press(KeyConfig::Two)),
+            (100, release(KeyConfig::One)),
+            (110, release(KeyConfig::Two)),
+        ],
+        200,
+    );
+    // Neither button leaks through, the action is released with the first button
+    assert_eq!(
+        out,
+        vec![
+            (20, action(TOGGLE_MEDIA, true)),
+            (100, action(TOGGLE_MEDIA, false)),
+        ]
+    );
+}
+
+#[test]
+fn buttons_in_no_combo_pass_straight_through() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[(0, press(KeyConfig::Four)), (30, release(KeyConfig::Four))],
+        100,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (0, ComboOutput::Button(press(KeyConfig::Four))),
+            (30, ComboOutput::Button(release(KeyConfig::Four))),
+        ]
+    );
+}
+
+#[test]
+fn lone_press_is_let_through_when_the_window_runs_out() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[(0, press(KeyConfig::One)), (200, release(KeyConfig::One))],
+        300,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (50, ComboOutput::Button(press(KeyConfig::One))),
+            (200, ComboOutput::Button(release(KeyConfig::One))),
+        ]
+    );
+}
+
+#[test]
+fn quick_tap_is_let_through_on_release() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[(0, press(KeyConfig::Two)), (10, release(KeyConfig::Two))],
+        100,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (10, ComboOutput::Button(press(KeyConfig::Two))),
+            (10, ComboOutput::Button(release(KeyConfig::Two))),
+        ]
+    );
+}
+
+#[test]
+fn second_press_after_the_window_is_not_a_combo() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[(0, press(KeyConfig::One)), (60, press(KeyConfig::Two))],
+        200,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (50, ComboOutput::Button(press(KeyConfig::One))),
+            (110, ComboOutput::Button(press(KeyConfig::Two))),
+        ]
+    );
+}
+
+#[test]
+fn press_outside_the_combo_lets_held_presses_through_in_order() {
+    let out = outputs(
+        ComboConfig::DEFAULT,
+        &[(0, press(KeyConfig::One)), (10, press(KeyConfig::Five))],
+        100,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (10, ComboOutput::Button(press(KeyConfig::One))),
+            (10, ComboOutput::Button(press(KeyConfig::Five))),
+        ]
+    );
+}
+
+#[test]
+fn bigger_combo_is_waited_for() {
+    let mut config = ComboConfig::DEFAULT;
+    config.combos[1] = combo(
+        &[KeyConfig::One, KeyConfig::Two, KeyConfig::Three],
+        Action::Bootloader,
+    );
+
+    // All three inside the window
+    let out = outputs(
+        config,
+        &[
+            (0, press(KeyConfig::One)),
+            (10, press(KeyConfig::Two)),
+            (20, press(KeyConfig::Three)),
+        ],
+        100,
+    );
+    assert_eq!(out, vec![(20, action(Action::Bootloader, true))]);
+
+    // Only two, the smaller combo fires once the window runs out
+    let out = outputs(
+        config,
+        &[(0, press(KeyConfig::One)), (10, press(KeyConfig::Two))],
+        100,
+    );
+    assert_eq!(out, vec![(50, action(TOGGLE_MEDIA, true))]);
+}
+
+#[test]
+fn releasing_a_held_button_settles_the_combo() {
+    let mut config = ComboConfig::DEFAULT;
+    config.combos[1] = combo(
+        &[KeyConfig::One, KeyConfig::Two, KeyConfig::Three],
+        Action::Bootloader,
+    );
+
+    let out = outputs(
+        config,
+        &[
+            (0, press(KeyConfig::One)),
+            (10, press(KeyConfig::Two)),
+            (20, release(KeyConfig::Two)),
+            (30, release(KeyConfig::One)),
+        ],
+        100,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (20, action(TOGGLE_MEDIA, true)),
+            (20, action(TOGGLE_MEDIA, false)),
+        ]
+    );
+}
+
+#[test]
+fn disabled_slots_are_ignored() {
+    let mut config = ComboConfig::DEFAULT;
+    config.combos[0].action = Action::None;
+    config.combos[1] = combo(&[KeyConfig::Three], Action::Key(0x04));
+
+    let out = outputs(
+        config,
+        &[(0, press(KeyConfig::One)), (0, press(KeyConfig::Three))],
+        10,
+    );
+    assert_eq!(
+        out,
+        vec![
+            (0, ComboOutput::Button(press(KeyConfig::One))),
+            (0, ComboOutput::Button(press(KeyConfig::Three))),
+        ]
+    );
+}
+
+#[test]
+fn release_uses_the_action_that_was_pressed() {
+    let mut engine = ComboEngine::default();
+    let mut out = Vec::new();
+    engine.update(0, Some(press(KeyConfig::One)), |output| out.push(output));
+    engine.update(0, Some(press(KeyConfig::Two)), |output| out.push(output));
+
+    let mut config = ComboConfig::DEFAULT;
+    config.combos[0].action = Action::Key(0x04);
+    engine.configure(config);
+    assert_eq!(engine.config(), &config);
+
+    engine.update(MS, Some(release(KeyConfig::Two)), |output| out.push(output));
+    engine.update(MS, Some(release(KeyConfig::One)), |output| out.push(output));
+    assert_eq!(
+        out,
+        vec![action(TOGGLE_MEDIA, true), action(TOGGLE_MEDIA, false)]
+    );
+}
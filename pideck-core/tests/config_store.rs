@@ -0,0 +1,398 @@
+use pideck_core::action::Action;
+use pideck_core::combo::Combo;
+use pideck_core::config_store::*;
+use pideck_core::constants::*;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gesture::{Gesture, GestureTiming};
+use pideck_core::key_config::KeyConfig;
+use pideck_core::label;
+use pideck_core::layout::Layout;
+use pideck_core::lock::Lock;
+use pideck_core::macro_player::assemble;
+use pideck_core::mouse::MouseAction;
+use pideck_core::profile::{self, Profile, MAX_PROFILES};
+use pideck_core::text::{self, UnicodeMethod};
+
+fn defaults() -> [Profile; MAX_PROFILES] {
+    [Profile::default(); MAX_PROFILES]
+}
+
+// Image of the defaults with `profile` as profile 1
+fn encoded(profile: &Profile) -> ([u8; IMAGE_LEN], usize) {
+    let mut profiles = defaults();
+    profiles[1] = *profile;
+    let mut buf = [0xFFu8; IMAGE_LEN];
+    let len = encode(&profiles, 0, &mut buf).unwrap();
+    (buf, len)
+}
+
+// Profile 1 of the image in `buf`
+fn decoded(buf: &[u8]) -> Result<Profile, StoreError> {
+    let mut profiles = defaults();
+    decode(buf, &mut profiles)?;
+    Ok(profiles[1])
+}
+
+fn patch_crc(buf: &mut [u8]) {
+    let crc = crc32(&buf[..IMAGE_LEN - CRC_LEN]);
+    buf[IMAGE_LEN - CRC_LEN..IMAGE_LEN].copy_from_slice(&crc.to_le_bytes());
+}
+
+// Offset of the first entry of profile 1
+const ENTRIES: usize = HEADER_LEN + PROFILE_LEN + profile::PROFILE_NAME_LEN;
+
+#[test]
+fn crc32_matches_reference_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(&[]), 0);
+}
+
+#[test]
+fn defaults_follow_key_config() {
+    let profile = Profile::default();
+    assert_eq!(
+        profile.keymap[KeyConfig::One],
+        [
+            Action::Key(KEYCODE_1),
+            Action::Media(MEDIAKEY_PLAYPAUSE),
+            Action::Transparent,
+            Action::Mouse(MouseAction::LeftClick)
+        ]
+    );
+    // Unmapped media key does nothing rather than falling through
+    assert_eq!(profile.keymap[KeyConfig::Two][1], Action::None);
+    assert_eq!(profile.debounce[KeyConfig::Six], DebounceStrategy::DEFAULT);
+}
+
+#[test]
+fn round_trip() {
+    let (buf, len) = encoded(&Profile::default());
+    assert_eq!(len, IMAGE_LEN);
+    assert_eq!(&buf[0..4], b"PDCK");
+
+    let mut profiles = defaults();
+    profiles[3].name = profile::parse_name(b"Zoom").unwrap();
+    assert_eq!(decode(&buf, &mut profiles), Ok(0));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn round_trip_keeps_every_profile_and_the_active_one() {
+    let mut profiles = defaults();
+    for (index, profile) in profiles.iter_mut().enumerate() {
+        profile.keymap[KeyConfig::Two][0] = Action::Key(0x04 + index as u8);
+    }
+    profiles[2].name = profile::parse_name(b"Photo-edit").unwrap();
+    profiles[3].keymap[KeyConfig::One][0] = Action::NextProfile;
+    profiles[3].keymap[KeyConfig::Six][0] = Action::Profile(1);
+    let mut buf = [0xFFu8; IMAGE_LEN];
+    encode(&profiles, 2, &mut buf).unwrap();
+
+    let mut decoded = defaults();
+    assert_eq!(decode(&buf, &mut decoded), Ok(2));
+    assert_eq!(decoded, profiles);
+}
+
+#[test]
+fn active_profile_is_switched_in_place() {
+    let mut profiles = defaults();
+    profiles[1].name = profile::parse_name(b"IDE").unwrap();
+    let mut buf = [0xFFu8; IMAGE_LEN];
+    encode(&profiles, 0, &mut buf).unwrap();
+
+    assert_eq!(set_active_profile(&mut buf, 3), Ok(true));
+    let mut decoded = defaults();
+    assert_eq!(decode(&buf, &mut decoded), Ok(3));
+    assert_eq!(decoded, profiles);
+    assert_eq!(set_active_profile(&mut buf, 3), Ok(false));
+
+    assert_eq!(
+        set_active_profile(&mut buf, MAX_PROFILES as u8),
+        Err(StoreError::BadEntry)
+    );
+    let mut erased = [0xFFu8; IMAGE_LEN];
+    assert_eq!(
+        set_active_profile(&mut erased, 1),
+        Err(StoreError::BadMagic)
+    );
+}
+
+#[test]
+fn active_profile_past_the_last_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[7] = MAX_PROFILES as u8;
+    patch_crc(&mut buf);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+}
+
+#[test]
+fn bad_profile_name_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[HEADER_LEN + PROFILE_LEN] = b' ';
+    patch_crc(&mut buf);
+
+    assert_eq!(decoded(&buf), Err(StoreError::BadEntry));
+}
+
+#[test]
+fn round_trip_keeps_debounce_strategies() {
+    let mut profile = Profile::default();
+    profile.debounce[KeyConfig::One] = DebounceStrategy::Eager { lockout_us: 40_000 };
+    profile.debounce[KeyConfig::Two] = DebounceStrategy::Integrator { samples: 7 };
+    profile.debounce[KeyConfig::Three] = DebounceStrategy::Deferred { stable_us: 5_000 };
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn round_trip_keeps_layers() {
+    let mut profile = Profile::default();
+    let keymap = &mut profile.keymap;
+    keymap[KeyConfig::One] = [
+        Action::Momentary(2),
+        Action::Toggle(3),
+        Action::OneShot(1),
+        Action::None,
+    ];
+    keymap[KeyConfig::Six][3] = Action::Key(0x04);
+    keymap[KeyConfig::Five][1] = Action::Media(0x225);
+    keymap[KeyConfig::Four][2] = Action::System(0x81);
+    keymap[KeyConfig::Four][3] = Action::Mouse(MouseAction::MiddleClick);
+    keymap[KeyConfig::Five][3] = Action::Gamepad(12);
+    keymap[KeyConfig::Six][2] = Action::Shortcut {
+        modifiers: 0b0000_0101,
+        keycode: 0x17,
+    };
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn layer_action_past_the_last_layer_is_rejected() {
+    let mut profile = Profile::default();
+    profile.keymap[KeyConfig::One][0] = Action::Toggle(LAYER_COUNT as u8);
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn profile_action_past_the_last_profile_is_rejected() {
+    let mut profile = Profile::default();
+    profile.keymap[KeyConfig::One][0] = Action::Profile(MAX_PROFILES as u8);
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Err(StoreError::BadEntry));
+}
+
+#[test]
+fn round_trip_keeps_gestures() {
+    let mut profile = Profile::default();
+    let gestures = &mut profile.gestures[KeyConfig::Four];
+    gestures.timing = GestureTiming {
+        double_tap_us: 300_000,
+        long_press_us: 0,
+        hold_us: 2_000_000,
+    };
+    gestures.actions[Gesture::Tap] = Action::Key(0x68);
+    gestures.actions[Gesture::DoubleTap] = Action::Media(0xCD);
+    gestures.actions[Gesture::Hold] = Action::Key(0x04);
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn round_trip_keeps_combos() {
+    let mut profile = Profile::default();
+    profile.combos.window_us = 80_000;
+    profile.combos.combos[3] = Combo {
+        buttons: 0b11_0000,
+        action: Action::Bootloader,
+    };
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn combo_past_the_last_button_is_rejected() {
+    let mut profile = Profile::default();
+    profile.combos.combos[1] = Combo {
+        buttons: 0b1000_0001,
+        action: Action::Key(0x04),
+    };
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn round_trip_keeps_macros() {
+    let mut profile = Profile::default();
+    profile.macros[2] =
+        assemble(b"press 0xe0 tap 0x06 wait 20 media 0xcd".split(|byte| *byte == b' ')).unwrap();
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn bad_macro_is_rejected() {
+    let mut profile = Profile::default();
+    // Unknown opcode
+    profile.macros[0][0] = 0x7f;
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn round_trip_keeps_lock_layers() {
+    let mut profile = Profile::default();
+    profile.lock_layers[Lock::Caps] = Some(2);
+    profile.lock_layers[Lock::Scroll] = Some(0);
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn lock_layer_past_the_last_layer_is_rejected() {
+    let mut profile = Profile::default();
+    profile.lock_layers[Lock::Num] = Some(LAYER_COUNT as u8);
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn round_trip_keeps_texts() {
+    let mut profile = Profile::default();
+    profile.texts.texts[1] = text::from_str("Grüße\n").unwrap();
+    profile.texts.method = UnicodeMethod::WinCompose;
+    profile.texts.layout = Layout::Fr;
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn text_that_is_not_utf8_is_rejected() {
+    let mut profile = Profile::default();
+    // A lone continuation byte
+    profile.texts.texts[0][0] = 0x80;
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn round_trip_keeps_labels() {
+    let mut profile = Profile::default();
+    profile.labels[KeyConfig::Two][1] = label::parse_label(b"Vol+").unwrap();
+    profile.labels[KeyConfig::Six][3] = label::parse_label(b"Mute").unwrap();
+    let (buf, _) = encoded(&profile);
+
+    assert_eq!(decoded(&buf), Ok(profile));
+}
+
+#[test]
+fn label_with_a_space_is_rejected() {
+    let mut profile = Profile::default();
+    profile.labels[KeyConfig::One][0] = *b"a b\0\0\0\0";
+    let (buf, _) = encoded(&profile);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn encode_rejects_small_buffer() {
+    let mut buf = [0u8; 10];
+    assert_eq!(
+        encode(&defaults(), 0, &mut buf),
+        Err(StoreError::BufferTooSmall)
+    );
+}
+
+#[test]
+fn erased_flash_is_bad_magic() {
+    let erased = [0xFFu8; IMAGE_LEN];
+    let mut profiles = defaults();
+    assert_eq!(decode(&erased, &mut profiles), Err(StoreError::BadMagic));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn corrupt_entry_fails_crc_and_keeps_defaults() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[ENTRIES] ^= 0x01;
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadCrc));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn unknown_debounce_kind_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[ENTRIES + 3 * LAYER_COUNT] = 9;
+    patch_crc(&mut buf);
+
+    let mut profiles = defaults();
+    assert_eq!(decode(&buf, &mut profiles), Err(StoreError::BadEntry));
+    assert_eq!(profiles, defaults());
+}
+
+#[test]
+fn unknown_action_kind_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    // Hold action kind of the last button
+    buf[ENTRIES + 6 * ENTRY_LEN - 3] = 0x7f;
+    patch_crc(&mut buf);
+
+    assert_eq!(decoded(&buf), Err(StoreError::BadEntry));
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[4] = IMAGE_VERSION + 1;
+
+    assert_eq!(
+        decoded(&buf),
+        Err(StoreError::UnsupportedVersion(IMAGE_VERSION + 1))
+    );
+}
+
+#[test]
+fn entry_count_mismatch_is_rejected() {
+    let (mut buf, _) = encoded(&Profile::default());
+    buf[5] = 4;
+    patch_crc(&mut buf);
+
+    assert_eq!(decoded(&buf), Err(StoreError::BadLength));
+}
+
+#[test]
+fn truncated_image_is_rejected() {
+    let (buf, len) = encoded(&Profile::default());
+
+    assert_eq!(decoded(&buf[..len - 1]), Err(StoreError::BufferTooSmall));
+}
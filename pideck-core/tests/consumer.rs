@@ -0,0 +1,24 @@
+use pideck_core::consumer::*;
+use pideck_core::protocol::ErrorCode;
+
+#[test]
+fn names_and_numbers_parse() {
+    assert_eq!(parse(b"calculator"), Ok(0x192));
+    assert_eq!(parse(b"browser-back"), Ok(0x224));
+    assert_eq!(parse(b"browser-forward"), Ok(0x225));
+    assert_eq!(parse(b"brightness-up"), Ok(0x6F));
+    assert_eq!(parse(b"brightness-down"), Ok(0x70));
+    assert_eq!(parse(b"0x29f"), Ok(0x29F));
+    assert_eq!(parse(b"205"), Ok(PLAY_PAUSE));
+    assert_eq!(parse(b"0x10000"), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"Calculator"), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn every_name_round_trips() {
+    for (known, usage) in NAMES {
+        assert_eq!(parse(known.as_bytes()), Ok(usage));
+        assert_eq!(name(usage), Some(known));
+    }
+    assert_eq!(name(0x29F), None);
+}
@@ -0,0 +1,186 @@
+use pideck_core::debouncer::{DebounceStrategy, Debouncer};
+
+const PERIOD: u32 = 25_000;
+
+#[test]
+fn starts_released() {
+    let debouncer = Debouncer::new(PERIOD);
+    assert!(!debouncer.stabilised_state);
+    assert!(!debouncer.current_state);
+}
+
+#[test]
+fn press_is_reported_once_stable_for_the_period() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    debouncer.update(1_000, true);
+    assert!(!debouncer.stabilised_state);
+
+    debouncer.update(1_000 + PERIOD - 1, true);
+    assert!(!debouncer.stabilised_state);
+
+    debouncer.update(1_000 + PERIOD, true);
+    assert!(debouncer.stabilised_state);
+}
+
+#[test]
+fn bounces_restart_the_period() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    // Contact chatter for the first few milliseconds of a press
+    for (time, state) in [
+        (0, true),
+        (800, false),
+        (1_500, true),
+        (2_000, false),
+        (4_000, true),
+    ] {
+        debouncer.update(time, state);
+    }
+    debouncer.update(4_000 + PERIOD - 1, true);
+    assert!(!debouncer.stabilised_state);
+
+    debouncer.update(4_000 + PERIOD, true);
+    assert!(debouncer.stabilised_state);
+}
+
+#[test]
+fn release_is_debounced_too() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    debouncer.update(0, true);
+    debouncer.update(PERIOD, true);
+    assert!(debouncer.stabilised_state);
+
+    debouncer.update(100_000, false);
+    debouncer.update(100_500, true);
+    debouncer.update(101_000, false);
+    debouncer.update(101_000 + PERIOD - 1, false);
+    assert!(debouncer.stabilised_state);
+
+    debouncer.update(101_000 + PERIOD, false);
+    assert!(!debouncer.stabilised_state);
+}
+
+#[test]
+fn handles_timer_wrap_around() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    let start = u32::MAX - 10_000;
+    debouncer.update(start, true);
+    debouncer.update(start.wrapping_add(PERIOD - 1), true);
+    assert!(!debouncer.stabilised_state);
+
+    debouncer.update(start.wrapping_add(PERIOD), true);
+    assert!(debouncer.stabilised_state);
+}
+
+#[test]
+fn each_stable_edge_is_reported_once() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    let mut edges = Vec::new();
+
+    // 1 ms scan over a bouncy press, a hold and a bouncy release
+    for time in (0..200_000).step_by(1_000) {
+        let raw = match time {
+            0..=2_999 => time % 2_000 == 0,
+            3_000..=99_999 => true,
+            100_000..=102_999 => time % 2_000 != 0,
+            _ => false,
+        };
+        if let Some(state) = debouncer.update(time, raw) {
+            edges.push((time, state));
+        }
+    }
+
+    assert_eq!(
+        edges,
+        vec![(2_000 + PERIOD, true), (102_000 + PERIOD, false)]
+    );
+}
+
+// A 1 ms scan over a bouncy press, a hold and a bouncy release with a single
+// noise spike in the middle of the hold
+fn bouncy_trace(time: u32) -> bool {
+    match time {
+        0..=2_999 => time.is_multiple_of(2_000),
+        50_000 => false,
+        3_000..=99_999 => true,
+        100_000..=102_999 => !time.is_multiple_of(2_000),
+        _ => false,
+    }
+}
+
+fn edges(strategy: DebounceStrategy) -> Vec<(u32, bool)> {
+    let mut debouncer = Debouncer::with_strategy(strategy);
+    (0..200_000)
+        .step_by(1_000)
+        .filter_map(|time| {
+            debouncer
+                .update(time, bouncy_trace(time))
+                .map(|state| (time, state))
+        })
+        .collect()
+}
+
+#[test]
+fn deferred_waits_out_bounces_and_spikes() {
+    let edges = edges(DebounceStrategy::Deferred { stable_us: 5_000 });
+    assert_eq!(edges, vec![(7_000, true), (107_000, false)]);
+}
+
+#[test]
+fn eager_reports_the_first_edge_immediately() {
+    let edges = edges(DebounceStrategy::Eager { lockout_us: 5_000 });
+    // The spike is long after the lockout so it comes through as a short release,
+    // the re-press is held back until the lockout it started has passed
+    assert_eq!(
+        edges,
+        vec![(0, true), (50_000, false), (55_000, true), (100_000, false)]
+    );
+}
+
+#[test]
+fn eager_catches_up_after_the_lockout() {
+    let mut debouncer = Debouncer::with_strategy(DebounceStrategy::Eager { lockout_us: 5_000 });
+    assert_eq!(debouncer.update(0, true), Some(true));
+    // Released inside the lockout, noticed on the first sample after it
+    assert_eq!(debouncer.update(2_000, false), None);
+    assert_eq!(debouncer.update(4_999, false), None);
+    assert_eq!(debouncer.update(5_000, false), Some(false));
+}
+
+#[test]
+fn integrator_filters_short_noise() {
+    let edges = edges(DebounceStrategy::Integrator { samples: 4 });
+    assert_eq!(edges, vec![(5_000, true), (105_000, false)]);
+}
+
+#[test]
+fn switching_strategy_keeps_the_debounced_state() {
+    let mut debouncer = Debouncer::new(PERIOD);
+    debouncer.update(0, true);
+    assert_eq!(debouncer.update(PERIOD, true), Some(true));
+
+    debouncer.set_strategy(DebounceStrategy::Integrator { samples: 3 });
+    assert_eq!(
+        debouncer.strategy(),
+        DebounceStrategy::Integrator { samples: 3 }
+    );
+    // Still held, so no new edge and one released sample is not enough
+    assert_eq!(debouncer.update(PERIOD + 1_000, true), None);
+    assert_eq!(debouncer.update(PERIOD + 2_000, false), None);
+    assert!(debouncer.stabilised_state);
+}
+
+#[test]
+fn strategy_parts_round_trip() {
+    for strategy in [
+        DebounceStrategy::Eager { lockout_us: 10_000 },
+        DebounceStrategy::Deferred { stable_us: 25_000 },
+        DebounceStrategy::Integrator { samples: 5 },
+    ] {
+        assert_eq!(
+            DebounceStrategy::from_parts(strategy.name().as_bytes(), strategy.value()),
+            Some(strategy)
+        );
+    }
+    assert_eq!(DebounceStrategy::from_parts(b"integrator", 0), None);
+    assert_eq!(DebounceStrategy::from_parts(b"schmitt", 5), None);
+}
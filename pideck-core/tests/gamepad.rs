@@ -0,0 +1,48 @@
+use pideck_core::gamepad::*;
+use pideck_core::protocol::ErrorCode;
+use pideck_core::report::GamepadReport;
+
+fn buttons(report: Option<GamepadReport>) -> Option<u16> {
+    report.map(|report| report.buttons)
+}
+
+#[test]
+fn parses_buttons_in_range() {
+    assert_eq!(parse(b"1"), Ok(1));
+    assert_eq!(parse(b"0x10"), Ok(16));
+    assert_eq!(parse(b"0"), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"17"), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"start"), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn press_and_release_report_every_held_button() {
+    let mut gamepad = Gamepad::new();
+
+    assert_eq!(buttons(gamepad.press(1)), Some(0b0001));
+    assert_eq!(buttons(gamepad.press(3)), Some(0b0101));
+    assert_eq!(buttons(gamepad.release(1)), Some(0b0100));
+    assert_eq!(buttons(gamepad.release(1)), None);
+    assert_eq!(buttons(gamepad.release(3)), Some(0));
+}
+
+#[test]
+fn ignores_buttons_it_does_not_have() {
+    let mut gamepad = Gamepad::new();
+
+    assert_eq!(gamepad.press(0), None);
+    assert_eq!(gamepad.press(BUTTON_COUNT + 1), None);
+    assert_eq!(gamepad.release(0), None);
+    assert_eq!(gamepad.report(), GamepadReport::default());
+}
+
+#[test]
+fn release_all_only_reports_when_something_was_held() {
+    let mut gamepad = Gamepad::new();
+    assert_eq!(gamepad.release_all(), None);
+
+    gamepad.press(5);
+    gamepad.press(5);
+    assert_eq!(gamepad.release_all(), Some(GamepadReport::default()));
+    assert_eq!(gamepad.release(5), None);
+}
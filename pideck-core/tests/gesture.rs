@@ -0,0 +1,120 @@
+use pideck_core::action::Action;
+use pideck_core::gesture::*;
+
+const MS: u32 = 1_000;
+
+fn bind(gestures: &[Gesture]) -> GestureConfig {
+    let mut config = GestureConfig::default();
+    for gesture in gestures {
+        config.actions[*gesture] = Action::Key(0x68);
+    }
+    config
+}
+
+// Runs a 1 ms scan over `edges`, given as (ms, pressed), until `end` ms
+fn events(config: &GestureConfig, edges: &[(u32, bool)], end: u32) -> Vec<(u32, GestureEvent)> {
+    let mut recognizer = GestureRecognizer::new(config);
+    (0..=end)
+        .filter_map(|ms| {
+            let edge = edges
+                .iter()
+                .find(|(time, _)| *time == ms)
+                .map(|(_, pressed)| *pressed);
+            recognizer.update(ms * MS, edge).map(|event| (ms, event))
+        })
+        .collect()
+}
+
+const ALL: [Gesture; 4] = [
+    Gesture::Tap,
+    Gesture::DoubleTap,
+    Gesture::LongPress,
+    Gesture::Hold,
+];
+
+#[test]
+fn unbound_buttons_are_disabled() {
+    assert!(!GestureConfig::default().is_enabled());
+    assert!(bind(&[Gesture::Hold]).is_enabled());
+}
+
+#[test]
+fn tap_waits_out_the_double_tap_window() {
+    let events = events(&bind(&ALL), &[(10, true), (60, false)], 1_000);
+    assert_eq!(events, vec![(310, GestureEvent::Tap)]);
+}
+
+#[test]
+fn tap_fires_on_release_without_double_tap() {
+    let config = bind(&[Gesture::Tap, Gesture::Hold]);
+    let events = events(&config, &[(10, true), (60, false)], 1_000);
+    assert_eq!(events, vec![(60, GestureEvent::Tap)]);
+}
+
+#[test]
+fn second_press_in_the_window_is_a_double_tap() {
+    let edges = [(10, true), (60, false), (200, true), (260, false)];
+    let events = events(&bind(&ALL), &edges, 1_000);
+    assert_eq!(events, vec![(200, GestureEvent::DoubleTap)]);
+}
+
+#[test]
+fn second_press_after_the_window_is_two_taps() {
+    let edges = [(10, true), (60, false), (310, true), (360, false)];
+    let events = events(&bind(&ALL), &edges, 1_000);
+    assert_eq!(
+        events,
+        vec![(310, GestureEvent::Tap), (610, GestureEvent::Tap)]
+    );
+}
+
+#[test]
+fn release_between_thresholds_is_a_long_press() {
+    let events = events(&bind(&ALL), &[(0, true), (700, false)], 2_000);
+    assert_eq!(events, vec![(700, GestureEvent::LongPress)]);
+}
+
+#[test]
+fn hold_starts_at_its_threshold_and_ends_on_release() {
+    let events = events(&bind(&ALL), &[(0, true), (1_500, false)], 2_000);
+    assert_eq!(
+        events,
+        vec![
+            (1_000, GestureEvent::HoldStart),
+            (1_500, GestureEvent::HoldEnd)
+        ]
+    );
+}
+
+#[test]
+fn without_long_press_a_slow_release_is_a_tap() {
+    let config = bind(&[Gesture::Tap]);
+    let events = events(&config, &[(0, true), (1_500, false)], 2_000);
+    assert_eq!(events, vec![(1_500, GestureEvent::Tap)]);
+}
+
+#[test]
+fn custom_thresholds_are_used() {
+    let mut config = bind(&ALL);
+    config.timing = GestureTiming::from_ms([100, 200, 300]);
+    assert_eq!(config.timing.to_ms(), [100, 200, 300]);
+
+    let edges = [(0, true), (50, false), (400, true), (650, false)];
+    let events = events(&config, &edges, 1_000);
+    assert_eq!(
+        events,
+        vec![(150, GestureEvent::Tap), (650, GestureEvent::LongPress)]
+    );
+}
+
+#[test]
+fn handles_timer_wrap_around() {
+    let mut recognizer = GestureRecognizer::new(&bind(&ALL));
+    let start = u32::MAX - 100 * MS;
+    assert_eq!(recognizer.update(start, Some(true)), None);
+    assert_eq!(recognizer.update(start.wrapping_add(999 * MS), None), None);
+    assert_eq!(
+        recognizer.update(start.wrapping_add(1_000 * MS), None),
+        Some(GestureEvent::HoldStart)
+    );
+}
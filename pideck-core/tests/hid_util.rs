@@ -0,0 +1,897 @@
+use pideck_core::action::Action;
+use pideck_core::constants::*;
+use pideck_core::gesture::{Gesture, GestureConfig, GestureEvent};
+use pideck_core::hid_util::HIDUtil;
+use pideck_core::key_config::KeyConfig;
+use pideck_core::label;
+use pideck_core::layer::{self, BASE_LAYER, MEDIA_LAYER};
+use pideck_core::lock::{Lock, LockLeds, CAPS_LOCK, NUM_LOCK, SCROLL_LOCK};
+use pideck_core::macro_player::assemble;
+use pideck_core::modifier::*;
+use pideck_core::mouse::BUTTON_LEFT;
+use pideck_core::profile::{self, Profile, MAX_PROFILES};
+use pideck_core::report::{
+    GamepadReport, KeyboardReport, MediaReport, MouseReport, ReportSink, SystemReport,
+    KEYCODE_ERROR_ROLLOVER,
+};
+use pideck_core::system_control::SLEEP;
+use pideck_core::text::{self, UnicodeMethod};
+
+#[derive(Debug, PartialEq)]
+enum Sent {
+    Keyboard(KeyboardReport),
+    Media(MediaReport),
+    System(SystemReport),
+    Mouse(MouseReport),
+    Gamepad(GamepadReport),
+    Bootloader,
+}
+
+#[derive(Default)]
+struct Recorder {
+    sent: Vec<Sent>,
+}
+
+impl ReportSink for Recorder {
+    fn send_keyboard(&mut self, report: KeyboardReport) -> bool {
+        self.sent.push(Sent::Keyboard(report));
+        true
+    }
+
+    fn send_media(&mut self, report: MediaReport) -> bool {
+        self.sent.push(Sent::Media(report));
+        true
+    }
+
+    fn send_system(&mut self, report: SystemReport) -> bool {
+        self.sent.push(Sent::System(report));
+        true
+    }
+
+    fn send_mouse(&mut self, report: MouseReport) -> bool {
+        self.sent.push(Sent::Mouse(report));
+        true
+    }
+
+    fn send_gamepad(&mut self, report: GamepadReport) -> bool {
+        self.sent.push(Sent::Gamepad(report));
+        true
+    }
+
+    fn enter_bootloader(&mut self) {
+        self.sent.push(Sent::Bootloader);
+    }
+}
+
+// Takes one report per endpoint until the host polls, like the USB endpoints do
+#[derive(Default)]
+struct OnePerPoll {
+    sent: Vec<Sent>,
+    full: [bool; 5],
+}
+
+impl OnePerPoll {
+    fn take(&mut self, endpoint: usize, report: Sent) -> bool {
+        if self.full[endpoint] {
+            return false;
+        }
+        self.full[endpoint] = true;
+        self.sent.push(report);
+        true
+    }
+
+    fn poll(&mut self) {
+        self.full = [false; 5];
+    }
+}
+
+impl ReportSink for OnePerPoll {
+    fn send_keyboard(&mut self, report: KeyboardReport) -> bool {
+        self.take(0, Sent::Keyboard(report))
+    }
+
+    fn send_media(&mut self, report: MediaReport) -> bool {
+        self.take(1, Sent::Media(report))
+    }
+
+    fn send_system(&mut self, report: SystemReport) -> bool {
+        self.take(2, Sent::System(report))
+    }
+
+    fn send_mouse(&mut self, report: MouseReport) -> bool {
+        self.take(3, Sent::Mouse(report))
+    }
+
+    fn send_gamepad(&mut self, report: GamepadReport) -> bool {
+        self.take(4, Sent::Gamepad(report))
+    }
+
+    fn enter_bootloader(&mut self) {
+        self.sent.push(Sent::Bootloader);
+    }
+}
+
+fn keys(keycodes: &[u8]) -> Sent {
+    keys_with(0, keycodes)
+}
+
+fn keys_with(modifier: u8, keycodes: &[u8]) -> Sent {
+    let mut report = KeyboardReport {
+        modifier,
+        ..KeyboardReport::default()
+    };
+    for keycode in keycodes {
+        assert!(report.press(*keycode));
+    }
+    Sent::Keyboard(report)
+}
+
+fn media(usage_id: u16) -> Sent {
+    Sent::Media(MediaReport { usage_id })
+}
+
+#[test]
+fn press_and_release_send_keyboard_reports() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.release_input(&mut sink, KeyConfig::Three);
+
+    assert_eq!(sink.sent, vec![keys(&[KEYCODE_3]), keys(&[])]);
+}
+
+#[test]
+fn held_keys_share_one_report() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.push_input(&mut sink, KeyConfig::Five);
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Five);
+
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys(&[KEYCODE_3]),
+            keys(&[KEYCODE_3, KEYCODE_5]),
+            keys(&[KEYCODE_3, KEYCODE_5, KEYCODE_6]),
+            keys(&[KEYCODE_3, KEYCODE_6]),
+        ]
+    );
+}
+
+#[test]
+fn release_without_press_sends_nothing() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.release_input(&mut sink, KeyConfig::One);
+    assert!(sink.sent.is_empty());
+}
+
+#[test]
+fn combo_actions_press_and_release() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.combo_input(&mut sink, Action::Toggle(MEDIA_LAYER), true);
+    hid.combo_input(&mut sink, Action::Toggle(MEDIA_LAYER), false);
+    assert_eq!(hid.layers().top(), MEDIA_LAYER);
+
+    hid.combo_input(&mut sink, Action::Key(0x04), true);
+    hid.combo_input(&mut sink, Action::Key(0x04), false);
+    assert_eq!(sink.sent, vec![keys(&[0x04]), keys(&[])]);
+}
+
+#[test]
+fn both_chord_buttons_send_their_keys() {
+    // Chords are up to the combo engine now, HIDUtil presses whatever it is given
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.push_input(&mut sink, KeyConfig::Two);
+
+    assert_eq!(hid.layers().as_slice(), &[BASE_LAYER]);
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[KEYCODE_1]), keys(&[KEYCODE_1, KEYCODE_2])]
+    );
+}
+
+#[test]
+fn bootloader_releases_everything_first() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(BASE_LAYER, KeyConfig::Six, Action::Bootloader);
+
+    hid.push_input(&mut sink, KeyConfig::Four);
+    hid.push_input(&mut sink, KeyConfig::Six);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[KEYCODE_4]), keys(&[]), Sent::Bootloader]
+    );
+}
+
+#[test]
+fn tap_releases_on_the_next_poll() {
+    let mut hid = HIDUtil::default();
+    let mut sink = OnePerPoll::default();
+    let mut gestures = GestureConfig::default();
+    gestures.actions[Gesture::Tap] = Action::Key(0x68);
+    gestures.actions[Gesture::DoubleTap] = Action::Media(MEDIAKEY_MUTE);
+    hid.set_gestures(KeyConfig::Two, gestures);
+
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::Tap);
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::DoubleTap);
+    assert_eq!(sink.sent, vec![keys(&[0x68]), media(MEDIAKEY_MUTE)]);
+
+    // Flushing before the host has read anything sends nothing
+    hid.flush_reports(&mut sink);
+    assert_eq!(sink.sent.len(), 2);
+
+    sink.poll();
+    hid.flush_reports(&mut sink);
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys(&[0x68]),
+            media(MEDIAKEY_MUTE),
+            keys(&[]),
+            media(MEDIAKEY_NONE)
+        ]
+    );
+}
+
+#[test]
+fn reports_wait_their_turn() {
+    let mut hid = HIDUtil::default();
+    let mut sink = OnePerPoll::default();
+
+    // The endpoint is busy with the first press, the rest queue up behind it
+    hid.push_input(&mut sink, KeyConfig::Four);
+    hid.release_input(&mut sink, KeyConfig::Four);
+    hid.push_input(&mut sink, KeyConfig::Five);
+    assert_eq!(sink.sent, vec![keys(&[KEYCODE_4])]);
+
+    // Nor does one sent once the endpoint is free again but others still wait
+    sink.poll();
+    hid.release_input(&mut sink, KeyConfig::Five);
+    assert_eq!(sink.sent, vec![keys(&[KEYCODE_4])]);
+    for _ in 0..3 {
+        hid.flush_reports(&mut sink);
+        sink.poll();
+    }
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[KEYCODE_4]), keys(&[]), keys(&[KEYCODE_5]), keys(&[])]
+    );
+}
+
+#[test]
+fn media_layer_sends_media_reports_and_switches_back() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_layer(&mut sink, MEDIA_LAYER, true);
+    // Nothing was held so there was nothing to release
+    assert!(sink.sent.is_empty());
+
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.release_input(&mut sink, KeyConfig::Three);
+    assert_eq!(sink.sent, vec![media(MEDIAKEY_VOLUP), media(MEDIAKEY_NONE)]);
+
+    hid.set_layer(&mut sink, MEDIA_LAYER, false);
+    assert_eq!(hid.layers().top(), BASE_LAYER);
+}
+
+#[test]
+fn set_layer_releases_held_keys() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.push_input(&mut sink, KeyConfig::Four);
+    hid.set_layer(&mut sink, MEDIA_LAYER, true);
+    hid.set_layer(&mut sink, MEDIA_LAYER, true);
+
+    assert_eq!(sink.sent, vec![keys(&[KEYCODE_4]), keys(&[])]);
+    assert_eq!(hid.action(KeyConfig::Four), Action::Media(MEDIAKEY_VOLDOWN));
+}
+
+#[test]
+fn remapped_key_is_used() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_keys(KeyConfig::Six, (0x04, MEDIAKEY_MUTE));
+    hid.push_input(&mut sink, KeyConfig::Six);
+
+    assert_eq!(sink.sent, vec![keys(&[0x04])]);
+    assert_eq!(
+        layer::keys_of(&hid.keymap()[KeyConfig::Six]),
+        (0x04, MEDIAKEY_MUTE)
+    );
+}
+
+#[test]
+fn momentary_layer_is_active_while_held() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(BASE_LAYER, KeyConfig::Six, Action::Momentary(2));
+    hid.set_action(2, KeyConfig::Three, Action::Key(0x04));
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.layers().as_slice(), &[BASE_LAYER, 2]);
+    hid.push_input(&mut sink, KeyConfig::Three);
+    // Transparent on layer 2, falls through to the base layer
+    hid.push_input(&mut sink, KeyConfig::Four);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.layers().as_slice(), &[BASE_LAYER]);
+    // Released as what it pressed, not what the base layer has now
+    hid.release_input(&mut sink, KeyConfig::Three);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[0x04]), keys(&[0x04, KEYCODE_4]), keys(&[KEYCODE_4])]
+    );
+}
+
+#[test]
+fn toggle_layer_stays_until_pressed_again() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(BASE_LAYER, KeyConfig::Six, Action::Toggle(3));
+    hid.set_action(3, KeyConfig::Six, Action::Toggle(3));
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.layers().top(), 3);
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.layers().top(), BASE_LAYER);
+    assert!(sink.sent.is_empty());
+}
+
+#[test]
+fn one_shot_layer_covers_the_next_key_only() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(BASE_LAYER, KeyConfig::Six, Action::OneShot(2));
+    hid.set_action(2, KeyConfig::Three, Action::Key(0x04));
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.layers().top(), 2);
+
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.release_input(&mut sink, KeyConfig::Three);
+    assert_eq!(hid.layers().top(), BASE_LAYER);
+    hid.push_input(&mut sink, KeyConfig::Three);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[0x04]), keys(&[]), keys(&[KEYCODE_3])]
+    );
+}
+
+#[test]
+fn gestures_send_their_bound_actions() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    let mut gestures = GestureConfig::default();
+    gestures.actions[Gesture::Tap] = Action::Key(0x68);
+    gestures.actions[Gesture::DoubleTap] = Action::Media(MEDIAKEY_MUTE);
+    gestures.actions[Gesture::Hold] = Action::Key(0x04);
+    hid.set_gestures(KeyConfig::Two, gestures);
+    assert_eq!(hid.gestures(KeyConfig::Two), &gestures);
+
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::Tap);
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::DoubleTap);
+    // Long press is unbound
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::LongPress);
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::HoldStart);
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.gesture_input(&mut sink, KeyConfig::Two, GestureEvent::HoldEnd);
+
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys(&[0x68]),
+            keys(&[]),
+            media(MEDIAKEY_MUTE),
+            media(MEDIAKEY_NONE),
+            keys(&[0x04]),
+            keys(&[0x04, KEYCODE_3]),
+            keys(&[KEYCODE_3]),
+        ]
+    );
+}
+
+#[test]
+fn macro_plays_one_report_per_step() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    let ctrl_shift_p = assemble(
+        "press 0xe0 press 0xe1 tap 0x13 release-all media 0xcd"
+            .split(' ')
+            .map(str::as_bytes),
+    )
+    .unwrap();
+    hid.set_macro(2, ctrl_shift_p);
+    assert_eq!(hid.macros()[2], ctrl_shift_p);
+    hid.set_action(BASE_LAYER, KeyConfig::One, Action::Macro(2));
+
+    // Nothing is sent until the first step
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::One);
+    assert!(sink.sent.is_empty());
+    assert!(hid.is_playing_macro());
+
+    let mut now = 0;
+    while hid.is_playing_macro() {
+        hid.macro_step(&mut sink, now);
+        now += 10_000;
+    }
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys_with(MOD_LCTRL, &[]),
+            keys_with(MOD_LCTRL | MOD_LSHIFT, &[]),
+            keys_with(MOD_LCTRL | MOD_LSHIFT, &[0x13]),
+            keys_with(MOD_LCTRL | MOD_LSHIFT, &[]),
+            keys_with(MOD_LCTRL, &[]),
+            keys(&[]),
+            media(0xCD),
+            media(MEDIAKEY_NONE),
+        ]
+    );
+}
+
+#[test]
+fn text_types_through_the_macro_steps() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_text(5, text::from_str("A€").unwrap());
+    hid.set_unicode_method(UnicodeMethod::Windows);
+    assert_eq!(hid.text_config().method, UnicodeMethod::Windows);
+    hid.set_action(BASE_LAYER, KeyConfig::One, Action::Text(5));
+    hid.set_action(BASE_LAYER, KeyConfig::Two, Action::Macro(0));
+
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::One);
+    assert!(hid.is_playing_macro());
+    // No macro starts while the text is typed
+    hid.push_input(&mut sink, KeyConfig::Two);
+    hid.release_input(&mut sink, KeyConfig::Two);
+    assert!(sink.sent.is_empty());
+
+    let mut now = 0;
+    while hid.is_playing_macro() {
+        hid.macro_step(&mut sink, now);
+        now += 10_000;
+    }
+    let mut expected = vec![
+        keys_with(MOD_LSHIFT, &[]),
+        keys_with(MOD_LSHIFT, &[0x04]),
+        keys_with(MOD_LSHIFT, &[]),
+        keys(&[]),
+        keys_with(MOD_LALT, &[]),
+    ];
+    // Keypad +, 2, 0, then A and C on the letter keys
+    for keycode in [0x57, 0x5A, 0x62, 0x04, 0x06] {
+        expected.push(keys_with(MOD_LALT, &[keycode]));
+        expected.push(keys_with(MOD_LALT, &[]));
+    }
+    expected.push(keys(&[]));
+    assert_eq!(sink.sent, expected);
+}
+
+#[test]
+fn macro_keys_share_the_report_with_held_buttons() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_macro(
+        0,
+        assemble([b"tap".as_slice(), b"0x04"].into_iter()).unwrap(),
+    );
+    hid.set_action(BASE_LAYER, KeyConfig::One, Action::Macro(0));
+
+    hid.push_input(&mut sink, KeyConfig::Three);
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.macro_step(&mut sink, 0);
+    hid.macro_step(&mut sink, 10_000);
+    hid.macro_step(&mut sink, 20_000);
+
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys(&[KEYCODE_3]),
+            keys(&[KEYCODE_3, 0x04]),
+            keys(&[KEYCODE_3]),
+        ]
+    );
+    assert!(!hid.is_playing_macro());
+}
+
+#[test]
+fn shortcut_sends_modifiers_with_its_key() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    let ctrl_alt_t = Action::Shortcut {
+        modifiers: MOD_LCTRL | MOD_LALT,
+        keycode: 0x17,
+    };
+    hid.set_action(BASE_LAYER, KeyConfig::One, ctrl_alt_t);
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::One);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys_with(MOD_LCTRL | MOD_LALT, &[0x17]), keys(&[])]
+    );
+}
+
+#[test]
+fn modifiers_stay_down_while_anything_holds_them() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    // A plain Ctrl key and a Ctrl+C shortcut
+    hid.set_action(BASE_LAYER, KeyConfig::One, Action::Key(0xE0));
+    hid.set_action(
+        BASE_LAYER,
+        KeyConfig::Two,
+        Action::Shortcut {
+            modifiers: MOD_LCTRL,
+            keycode: 0x06,
+        },
+    );
+
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.push_input(&mut sink, KeyConfig::Two);
+    hid.release_input(&mut sink, KeyConfig::Two);
+    hid.release_input(&mut sink, KeyConfig::One);
+
+    assert_eq!(
+        sink.sent,
+        vec![
+            keys_with(MOD_LCTRL, &[]),
+            keys_with(MOD_LCTRL, &[0x06]),
+            keys_with(MOD_LCTRL, &[]),
+            keys(&[]),
+        ]
+    );
+}
+
+#[test]
+fn every_held_key_is_in_the_report() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_macro(
+        0,
+        assemble(
+            "press 0x04 press 0x05 press 0x06 press 0x07 press 0x08 press 0x09 press 0x0a \
+             press 0xe1"
+                .split_whitespace()
+                .map(str::as_bytes),
+        )
+        .unwrap(),
+    );
+    hid.set_action(BASE_LAYER, KeyConfig::One, Action::Macro(0));
+    hid.push_input(&mut sink, KeyConfig::One);
+    for step in 0..8 {
+        hid.macro_step(&mut sink, step * 10_000);
+    }
+    // Along with a key from a button
+    hid.push_input(&mut sink, KeyConfig::Three);
+
+    let all = [0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, KEYCODE_3];
+    assert_eq!(sink.sent.last(), Some(&keys_with(MOD_LSHIFT, &all)));
+}
+
+#[test]
+fn boot_report_rolls_over_past_six_keys() {
+    let mut report = KeyboardReport::default();
+    for keycode in [0x1E, 0x04, 0x2C, 0x05, 0x06, 0x07] {
+        assert!(report.press(keycode));
+    }
+    assert_eq!(report.boot_keycodes(), [0x04, 0x05, 0x06, 0x07, 0x1E, 0x2C]);
+
+    assert!(report.press(0x08));
+    assert_eq!(report.boot_keycodes(), [KEYCODE_ERROR_ROLLOVER; 6]);
+
+    // Back to six and the keys are listed again
+    assert!(report.release(0x1E));
+    assert!(!report.release(0x1E));
+    assert_eq!(report.boot_keycodes(), [0x04, 0x05, 0x06, 0x07, 0x08, 0x2C]);
+
+    // Reserved usages past the bitmap are refused
+    assert!(!report.press(0xE8));
+    assert!(!report.is_pressed(0xE8));
+}
+
+#[test]
+fn system_action_presses_and_releases_its_usage() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_action(BASE_LAYER, KeyConfig::Four, Action::System(SLEEP));
+    hid.push_input(&mut sink, KeyConfig::Four);
+    hid.release_input(&mut sink, KeyConfig::Four);
+
+    assert_eq!(
+        sink.sent,
+        vec![
+            Sent::System(SystemReport { usage_id: SLEEP }),
+            Sent::System(SystemReport { usage_id: 0 }),
+        ]
+    );
+}
+
+#[test]
+fn default_keymap_has_a_mouse_layer() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    assert_eq!(
+        hid.combos().combos[1].action,
+        Action::Toggle(layer::MOUSE_LAYER)
+    );
+
+    hid.set_layer(&mut sink, layer::MOUSE_LAYER, true);
+    for (id, action) in layer::mouse_layer() {
+        assert_eq!(hid.action(id), action);
+    }
+}
+
+#[test]
+fn mouse_layer_clicks_at_once_and_moves_on_ticks() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    for (id, action) in layer::mouse_layer() {
+        hid.set_action(3, id, action);
+    }
+    hid.set_layer(&mut sink, 3, true);
+
+    hid.push_input(&mut sink, KeyConfig::One);
+    assert!(!hid.is_moving_mouse());
+    hid.push_input(&mut sink, KeyConfig::Six);
+    assert!(hid.is_moving_mouse());
+    hid.mouse_tick(&mut sink);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert!(!hid.is_moving_mouse());
+    // Nothing held to move, so nothing to send
+    hid.mouse_tick(&mut sink);
+    hid.release_input(&mut sink, KeyConfig::One);
+
+    let mouse = |buttons, x| {
+        Sent::Mouse(MouseReport {
+            buttons,
+            x,
+            ..MouseReport::default()
+        })
+    };
+    assert_eq!(
+        sink.sent,
+        vec![mouse(BUTTON_LEFT, 0), mouse(BUTTON_LEFT, 1), mouse(0, 0)]
+    );
+}
+
+#[test]
+fn gamepad_buttons_are_held_together_and_per_layer() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+
+    hid.set_action(2, KeyConfig::One, Action::Gamepad(1));
+    hid.set_action(2, KeyConfig::Two, Action::Gamepad(16));
+    hid.set_action(2, KeyConfig::Three, Action::Gamepad(16));
+    hid.set_layer(&mut sink, 2, true);
+
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.push_input(&mut sink, KeyConfig::Two);
+    hid.push_input(&mut sink, KeyConfig::Three);
+    // Button 16 stays down while button three still holds it
+    hid.release_input(&mut sink, KeyConfig::Two);
+    hid.release_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::Three);
+    // Layer 0 still has its keyboard key
+    hid.set_layer(&mut sink, 2, false);
+    hid.push_input(&mut sink, KeyConfig::One);
+
+    let gamepad = |buttons| Sent::Gamepad(GamepadReport { buttons });
+    assert_eq!(
+        sink.sent,
+        vec![
+            gamepad(0x0001),
+            gamepad(0x8001),
+            gamepad(0x8001),
+            gamepad(0x8001),
+            gamepad(0x8000),
+            gamepad(0x0000),
+            keys(&[KEYCODE_1]),
+        ]
+    );
+}
+
+#[test]
+fn lock_layer_follows_the_host_leds() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(2, KeyConfig::One, Action::Key(0x04));
+    hid.set_lock_layer(&mut sink, Lock::Caps, Some(2));
+
+    assert!(hid.set_leds(&mut sink, LockLeds(CAPS_LOCK)));
+    assert!(hid.layers().is_active(2));
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::One);
+
+    // Num Lock has no layer, Caps Lock is still lit
+    assert!(hid.set_leds(&mut sink, LockLeds(CAPS_LOCK | NUM_LOCK)));
+    assert!(!hid.set_leds(&mut sink, LockLeds(CAPS_LOCK | NUM_LOCK)));
+    assert!(hid.layers().is_active(2));
+
+    hid.set_leds(&mut sink, LockLeds(NUM_LOCK));
+    assert!(!hid.layers().is_active(2));
+    hid.push_input(&mut sink, KeyConfig::One);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[0x04]), keys(&[]), keys(&[KEYCODE_1])]
+    );
+    assert_eq!(hid.leds(), LockLeds(NUM_LOCK));
+}
+
+#[test]
+fn binding_a_lit_lock_switches_its_layer_at_once() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_leds(&mut sink, LockLeds(SCROLL_LOCK));
+
+    hid.set_lock_layer(&mut sink, Lock::Scroll, Some(3));
+    assert!(hid.layers().is_active(3));
+    hid.set_lock_layer(&mut sink, Lock::Scroll, Some(1));
+    assert!(!hid.layers().is_active(3));
+    assert!(hid.layers().is_active(1));
+    hid.set_lock_layer(&mut sink, Lock::Scroll, None);
+    assert!(!hid.layers().is_active(1));
+    assert_eq!(hid.lock_layers()[Lock::Scroll], None);
+}
+
+#[test]
+fn profile_actions_switch_the_whole_config() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_action(BASE_LAYER, KeyConfig::Six, Action::NextProfile);
+    let mut zoom = Profile::default();
+    zoom.keymap[KeyConfig::One][BASE_LAYER as usize] = Action::Key(0x04);
+    zoom.keymap[KeyConfig::Six][BASE_LAYER as usize] = Action::Profile(0);
+    hid.set_profile(1, zoom);
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.active_profile(), 1);
+    hid.push_input(&mut sink, KeyConfig::One);
+    hid.release_input(&mut sink, KeyConfig::One);
+
+    hid.push_input(&mut sink, KeyConfig::Six);
+    hid.release_input(&mut sink, KeyConfig::Six);
+    assert_eq!(hid.active_profile(), 0);
+    hid.push_input(&mut sink, KeyConfig::One);
+
+    assert_eq!(
+        sink.sent,
+        vec![keys(&[0x04]), keys(&[]), keys(&[KEYCODE_1])]
+    );
+    // Edits go to the active profile only
+    hid.set_action(BASE_LAYER, KeyConfig::Two, Action::Key(0x05));
+    assert_eq!(
+        hid.profiles()[1].keymap[KeyConfig::Two],
+        layer::default_keymap()[KeyConfig::Two]
+    );
+}
+
+#[test]
+fn switching_profiles_releases_keys_and_starts_from_the_base_layer() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    let mut ide = Profile::default();
+    ide.lock_layers[Lock::Num] = Some(3);
+    hid.set_profile(2, ide);
+    hid.set_leds(&mut sink, LockLeds(NUM_LOCK));
+    hid.set_layer(&mut sink, 2, true);
+    hid.push_input(&mut sink, KeyConfig::One);
+    sink.sent.clear();
+
+    assert!(hid.select_profile(&mut sink, 2));
+    assert_eq!(sink.sent, vec![keys(&[])]);
+    assert!(!hid.layers().is_active(2));
+    assert!(hid.layers().is_active(3));
+
+    assert!(!hid.select_profile(&mut sink, 2));
+    assert!(!hid.select_profile(&mut sink, MAX_PROFILES as u8));
+    assert_eq!(hid.active_profile(), 2);
+}
+
+#[test]
+fn switching_profiles_releases_everything_in_one_report() {
+    let mut hid = HIDUtil::default();
+    let mut sink = OnePerPoll::default();
+    hid.push_input(&mut sink, KeyConfig::Four);
+    sink.poll();
+    hid.push_input(&mut sink, KeyConfig::Five);
+    sink.poll();
+    sink.sent.clear();
+
+    hid.select_profile(&mut sink, 1);
+    assert_eq!(sink.sent, vec![keys(&[])]);
+
+    // Nothing left over for the next poll
+    sink.poll();
+    hid.flush_reports(&mut sink);
+    assert_eq!(sink.sent, vec![keys(&[])]);
+}
+
+#[test]
+fn profiles_are_found_by_name() {
+    let mut hid = HIDUtil::default();
+    let ide = profile::parse_name(b"IDE").unwrap();
+    hid.set_profile_name(3, ide);
+    hid.set_profile_name(1, profile::parse_name(b"Zoom").unwrap());
+
+    assert_eq!(hid.find_profile(&ide), Some(3));
+    assert_eq!(
+        hid.find_profile(&profile::parse_name(b"ide").unwrap()),
+        None
+    );
+}
+
+#[test]
+fn labels_come_from_the_layer_the_action_does() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.set_label(
+        BASE_LAYER,
+        KeyConfig::One,
+        label::parse_label(b"F13").unwrap(),
+    );
+    hid.set_label(
+        MEDIA_LAYER,
+        KeyConfig::One,
+        label::parse_label(b"Play").unwrap(),
+    );
+    hid.set_action(MEDIA_LAYER, KeyConfig::Two, Action::Transparent);
+
+    let texts = hid.label_texts();
+    assert_eq!(texts[KeyConfig::One], "F13");
+    assert_eq!(texts[KeyConfig::Two], "0x6a");
+
+    hid.set_layer(&mut sink, MEDIA_LAYER, true);
+    let texts = hid.label_texts();
+    assert_eq!(texts[KeyConfig::One], "Play");
+    // Falls through to the base layer, and its action
+    assert_eq!(texts[KeyConfig::Two], "0x6a");
+    assert_eq!(texts[KeyConfig::Three], "m0xe9");
+}
+
+#[test]
+fn held_buttons_are_the_ones_that_pressed_something() {
+    let mut hid = HIDUtil::default();
+    let mut sink = Recorder::default();
+    hid.push_input(&mut sink, KeyConfig::Two);
+    hid.push_input(&mut sink, KeyConfig::Five);
+    hid.release_input(&mut sink, KeyConfig::Two);
+
+    let held = hid.held();
+    assert!(held[KeyConfig::Five]);
+    assert!(!held[KeyConfig::Two]);
+    assert!(!held[KeyConfig::One]);
+}
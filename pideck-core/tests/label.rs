@@ -0,0 +1,60 @@
+use pideck_core::action::Action;
+use pideck_core::label::*;
+use pideck_core::modifier::MOD_LCTRL;
+use pideck_core::mouse::MouseAction;
+use pideck_core::protocol::ErrorCode;
+
+#[test]
+fn labels_round_trip() {
+    let label = parse_label(b"Vol+").unwrap();
+    assert_eq!(label_str(&label), "Vol+");
+    assert_eq!(Word(&label).to_string(), "Vol+");
+
+    let none = parse_label(b"none").unwrap();
+    assert_eq!(none, [0; LABEL_LEN]);
+    assert_eq!(Word(&none).to_string(), "none");
+}
+
+#[test]
+fn bad_labels_are_rejected() {
+    assert_eq!(parse_label(b"Play/Pse"), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse_label(b""), Err(ErrorCode::BadArgument));
+    assert_eq!(parse_label(b"\xc3\xa9"), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn a_label_wins_over_the_action() {
+    let label = parse_label(b"Mute").unwrap();
+    assert_eq!(text(&label, Action::Media(0xe2)), "Mute");
+}
+
+#[test]
+fn unlabelled_buttons_show_their_action() {
+    let none = [0; LABEL_LEN];
+    for (action, shown) in [
+        (Action::None, ""),
+        (Action::Key(0x68), "0x68"),
+        (
+            Action::Shortcut {
+                modifiers: MOD_LCTRL,
+                keycode: 0x17,
+            },
+            "^0x17",
+        ),
+        (Action::Media(0xcd), "m0xcd"),
+        (Action::Media(0xffff), "m0xffff"),
+        (Action::System(0x82), "s0x82"),
+        (Action::Mouse(MouseAction::MiddleClick), "click M"),
+        (Action::Gamepad(16), "pad 16"),
+        (Action::Momentary(3), "mo 3"),
+        (Action::Toggle(1), "tog 1"),
+        (Action::OneShot(2), "one 2"),
+        (Action::Bootloader, "boot"),
+        (Action::Macro(15), "mac 15"),
+        (Action::Text(7), "txt 7"),
+        (Action::Profile(3), "prof 3"),
+        (Action::NextProfile, "prof >"),
+    ] {
+        assert_eq!(text(&none, action), shown, "{:?}", action);
+    }
+}
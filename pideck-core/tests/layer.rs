@@ -0,0 +1,87 @@
+use pideck_core::action::Action;
+use pideck_core::constants::*;
+use pideck_core::key_config::KeyConfig;
+use pideck_core::layer::*;
+
+#[test]
+fn starts_on_the_base_layer() {
+    let stack = LayerStack::new();
+    assert_eq!(stack.as_slice(), &[BASE_LAYER]);
+    assert_eq!(stack.top(), BASE_LAYER);
+}
+
+#[test]
+fn layers_stack_in_activation_order() {
+    let mut stack = LayerStack::new();
+    assert!(stack.activate(3));
+    assert!(stack.activate(1));
+    assert!(!stack.activate(3));
+    assert_eq!(stack.as_slice(), &[0, 3, 1]);
+
+    assert!(stack.deactivate(3));
+    assert_eq!(stack.as_slice(), &[0, 1]);
+}
+
+#[test]
+fn base_layer_and_missing_layers_are_left_alone() {
+    let mut stack = LayerStack::new();
+    assert!(!stack.deactivate(BASE_LAYER));
+    assert!(!stack.activate(LAYER_COUNT as u8));
+    assert!(!stack.deactivate(2));
+    assert_eq!(stack.as_slice(), &[BASE_LAYER]);
+}
+
+#[test]
+fn toggle_flips_a_layer() {
+    let mut stack = LayerStack::new();
+    stack.toggle(2);
+    assert!(stack.is_active(2));
+    stack.toggle(2);
+    assert!(!stack.is_active(2));
+}
+
+#[test]
+fn transparent_falls_through_to_lower_layers() {
+    let actions = [
+        Action::Key(0x04),
+        Action::Media(MEDIAKEY_MUTE),
+        Action::Transparent,
+        Action::Key(0x05),
+    ];
+    let mut stack = LayerStack::new();
+    stack.activate(1);
+    stack.activate(2);
+    assert_eq!(stack.resolve(&actions), Action::Media(MEDIAKEY_MUTE));
+
+    // Order of activation decides, not the layer number
+    stack.deactivate(1);
+    stack.activate(3);
+    stack.activate(1);
+    assert_eq!(stack.resolve(&actions), Action::Media(MEDIAKEY_MUTE));
+    stack.deactivate(1);
+    assert_eq!(stack.resolve(&actions), Action::Key(0x05));
+}
+
+#[test]
+fn all_transparent_does_nothing() {
+    let stack = LayerStack::new();
+    assert_eq!(
+        stack.resolve(&[Action::Transparent; LAYER_COUNT]),
+        Action::None
+    );
+}
+
+#[test]
+fn keys_shorthand_round_trips() {
+    let keymap = default_keymap();
+    assert_eq!(
+        keys_of(&keymap[KeyConfig::Three]),
+        KeyConfig::new()[KeyConfig::Three]
+    );
+
+    let mut actions = [Action::Transparent; LAYER_COUNT];
+    set_keys(&mut actions, (0x68, MEDIAKEY_NONE));
+    assert_eq!(actions[BASE_LAYER as usize], Action::Key(0x68));
+    assert_eq!(actions[MEDIA_LAYER as usize], Action::None);
+    assert_eq!(keys_of(&actions), (0x68, MEDIAKEY_NONE));
+}
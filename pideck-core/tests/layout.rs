@@ -0,0 +1,74 @@
+use enum_map::Enum;
+use pideck_core::layout::Layout;
+use pideck_core::modifier::{MOD_LCTRL, MOD_LSHIFT, MOD_RALT};
+
+const KEYCODES: core::ops::RangeInclusive<u8> = 0x04..=0x65;
+
+fn layouts() -> impl Iterator<Item = Layout> {
+    (0..Layout::LENGTH).map(Layout::from_usize)
+}
+
+#[test]
+fn every_key_round_trips_through_its_character() {
+    for layout in layouts() {
+        for keycode in KEYCODES {
+            for modifiers in [0, MOD_LSHIFT, MOD_RALT] {
+                let c = match layout.char_at(keycode, modifiers) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                // A character on two keys comes back as the first one, which
+                // still types it
+                let (key, held) = layout.key(c).unwrap();
+                assert_eq!(layout.char_at(key, held), Some(c), "{:?} {:?}", layout, c);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_layout_types_ascii() {
+    for layout in layouts() {
+        // Dead keys on the layout, typed with the Unicode input method
+        let dead = match layout {
+            Layout::De => "^`",
+            Layout::Fr => "`~",
+            _ => "",
+        };
+        for c in (' '..='~').chain(['\n', '\t']) {
+            let key = layout.key(c);
+            assert_eq!(key.is_none(), dead.contains(c), "{:?} {:?}", layout, c);
+            if let Some((keycode, modifiers)) = key {
+                assert_eq!(layout.char_at(keycode, modifiers), Some(c));
+            }
+        }
+    }
+}
+
+#[test]
+fn keys_move_between_layouts() {
+    assert_eq!(Layout::Us.key('y'), Some((0x1C, 0)));
+    assert_eq!(Layout::De.key('y'), Some((0x1D, 0)));
+    assert_eq!(Layout::De.key('@'), Some((0x14, MOD_RALT)));
+    assert_eq!(Layout::De.key('Ä'), Some((0x34, MOD_LSHIFT)));
+    assert_eq!(Layout::Uk.key('£'), Some((0x20, MOD_LSHIFT)));
+    assert_eq!(Layout::Uk.key('#'), Some((0x32, 0)));
+    assert_eq!(Layout::Fr.key('a'), Some((0x14, 0)));
+    assert_eq!(Layout::Fr.key('0'), Some((0x27, MOD_LSHIFT)));
+    assert_eq!(Layout::Dvorak.key('s'), Some((0x33, 0)));
+    assert_eq!(Layout::Us.key('é'), None);
+    assert_eq!(Layout::Us.key('\0'), None);
+
+    // Only the levels the table has
+    assert_eq!(Layout::Us.char_at(0x04, MOD_LCTRL), None);
+    assert_eq!(Layout::Us.char_at(0x04, MOD_RALT), None);
+    assert_eq!(Layout::De.char_at(0x35, 0), None);
+}
+
+#[test]
+fn names_parse_back() {
+    for layout in layouts() {
+        assert_eq!(Layout::parse(layout.name().as_bytes()), Some(layout));
+    }
+    assert_eq!(Layout::parse(b"qwertz"), None);
+}
@@ -0,0 +1,29 @@
+use pideck_core::lock::*;
+
+#[test]
+fn names_round_trip() {
+    for lock in [Lock::Num, Lock::Caps, Lock::Scroll] {
+        assert_eq!(Lock::parse(lock.name().as_bytes()), Some(lock));
+    }
+    assert_eq!(Lock::parse(b"shift"), None);
+}
+
+#[test]
+fn leds_follow_the_output_report_bits() {
+    let leds = LockLeds(NUM_LOCK | CAPS_LOCK);
+    assert!(leds.is_on(Lock::Num));
+    assert!(leds.is_on(Lock::Caps));
+    assert!(!leds.is_on(Lock::Scroll));
+    // Kana and Compose are not locks the deck knows about
+    assert!(!LockLeds(0b1_1000).is_on(Lock::Scroll));
+}
+
+#[test]
+fn formats_the_lit_locks() {
+    assert_eq!(LockLeds(0).to_string(), "none");
+    assert_eq!(LockLeds(SCROLL_LOCK).to_string(), "scroll");
+    assert_eq!(
+        LockLeds(NUM_LOCK | CAPS_LOCK | SCROLL_LOCK).to_string(),
+        "caps num scroll"
+    );
+}
@@ -0,0 +1,157 @@
+use pideck_core::macro_player::*;
+use pideck_core::protocol::ErrorCode;
+
+const MS: u32 = 1_000;
+
+fn asm(text: &str) -> Result<MacroCode, ErrorCode> {
+    assemble(text.split_whitespace().map(str::as_bytes))
+}
+
+// Plays `code` with a step every `interval` ms, returning (ms, step) pairs
+fn play(code: &MacroCode, interval: u32) -> Vec<(u32, MacroStep)> {
+    let mut player = MacroPlayer::new();
+    assert!(player.start(code));
+    let mut steps = Vec::new();
+    let mut ms = 0;
+    while player.is_playing() {
+        if let Some(step) = player.next_step(ms * MS) {
+            steps.push((ms, step));
+        }
+        ms += interval;
+        assert!(ms < 10_000, "macro never finished");
+    }
+    steps
+}
+
+#[test]
+fn assembles_to_bytecode() {
+    let code = asm("press 0xe0 press 0xe1 tap 0x13 release-all").unwrap();
+    assert_eq!(
+        code[..8],
+        [
+            OP_PRESS,
+            0xE0,
+            OP_PRESS,
+            0xE1,
+            OP_TAP,
+            0x13,
+            OP_RELEASE_ALL,
+            OP_END
+        ]
+    );
+    assert!(code[8..].iter().all(|byte| *byte == OP_END));
+    assert_eq!(asm("").unwrap(), [OP_END; MACRO_LEN]);
+}
+
+#[test]
+fn rejects_bad_assembly() {
+    assert_eq!(asm("jump 0x04"), Err(ErrorCode::BadArgument));
+    assert_eq!(asm("tap"), Err(ErrorCode::BadArgument));
+    assert_eq!(asm("wait 300"), Err(ErrorCode::OutOfRange));
+    // 33 bytes
+    let too_long = ["tap 4"; 16].join(" ") + " release-all";
+    assert_eq!(asm(&too_long), Err(ErrorCode::OutOfRange));
+}
+
+#[test]
+fn disassembly_and_hex_round_trip() {
+    let text = "press 0xe0 tap 0x06 wait 20 media 0xcd release 0xe0";
+    let code = asm(text).unwrap();
+    assert_eq!(Disassembly(&code).to_string(), text);
+
+    let hex = Hex(&code).to_string();
+    assert_eq!(hex, "01e00306051404cd0002e0");
+    assert_eq!(parse_hex(hex.as_bytes()), Ok(code));
+
+    let empty = [OP_END; MACRO_LEN];
+    assert_eq!(Hex(&empty).to_string(), "none");
+    assert_eq!(parse_hex(b"none"), Ok(empty));
+    assert_eq!(Disassembly(&empty).to_string(), "none");
+    assert_eq!(asm("none"), Ok(empty));
+    assert_eq!(asm("none tap 0x04"), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn media_takes_a_16_bit_usage() {
+    let code = asm("media calculator media 0xe9").unwrap();
+    assert_eq!(code[..6], [OP_MEDIA, 0x92, 0x01, OP_MEDIA, 0xE9, 0x00]);
+    assert_eq!(Disassembly(&code).to_string(), "media 0x192 media 0xe9");
+    assert_eq!(
+        play(&code, 10),
+        vec![
+            (0, MacroStep::Media(0x192)),
+            (10, MacroStep::Media(0)),
+            (20, MacroStep::Media(0xE9)),
+            (30, MacroStep::Media(0)),
+        ]
+    );
+    // Usage cut short by the end of the slot
+    assert_eq!(validate(&[OP_MEDIA, 0x92]), None);
+    assert_eq!(asm("media 0x10000"), Err(ErrorCode::OutOfRange));
+}
+
+#[test]
+fn validate_finds_the_end() {
+    assert_eq!(validate(&[OP_TAP, 0x04, OP_END, 0xFF]), Some(2));
+    assert_eq!(validate(&[OP_RELEASE_ALL]), Some(1));
+    assert_eq!(validate(&[0x07]), None);
+    // Argument cut off by the end of the slot
+    assert_eq!(validate(&[OP_TAP]), None);
+}
+
+#[test]
+fn one_step_per_call() {
+    let code = asm("press 0xe0 press 0xe1 tap 0x13 release-all").unwrap();
+    assert_eq!(
+        play(&code, 10),
+        vec![
+            (0, MacroStep::Press(0xE0)),
+            (10, MacroStep::Press(0xE1)),
+            (20, MacroStep::Press(0x13)),
+            (30, MacroStep::Release(0x13)),
+            (40, MacroStep::Release(0xE1)),
+            (50, MacroStep::Release(0xE0)),
+        ]
+    );
+}
+
+#[test]
+fn waits_before_the_next_step() {
+    let code = asm("tap 0x04 wait 25 media 0xcd").unwrap();
+    assert_eq!(
+        play(&code, 10),
+        vec![
+            (0, MacroStep::Press(0x04)),
+            (10, MacroStep::Release(0x04)),
+            // Wait starts at 20 ms and is over by the 50 ms poll
+            (50, MacroStep::Media(0xCD)),
+            (60, MacroStep::Media(0)),
+        ]
+    );
+}
+
+#[test]
+fn held_keys_are_released_at_the_end() {
+    let code = asm("press 0x04 press 0x05 release 0x04").unwrap();
+    assert_eq!(
+        play(&code, 1),
+        vec![
+            (0, MacroStep::Press(0x04)),
+            (1, MacroStep::Press(0x05)),
+            (2, MacroStep::Release(0x04)),
+            (3, MacroStep::Release(0x05)),
+        ]
+    );
+}
+
+#[test]
+fn start_is_ignored_while_playing() {
+    let mut player = MacroPlayer::new();
+    assert!(player.start(&asm("tap 0x04").unwrap()));
+    assert!(!player.start(&asm("tap 0x05").unwrap()));
+    assert_eq!(player.next_step(0), Some(MacroStep::Press(0x04)));
+    assert_eq!(player.next_step(0), Some(MacroStep::Release(0x04)));
+    assert_eq!(player.next_step(0), None);
+    assert!(!player.is_playing());
+    assert!(player.start(&asm("tap 0x05").unwrap()));
+}
@@ -0,0 +1,109 @@
+use pideck_core::mouse::*;
+use pideck_core::report::MouseReport;
+
+fn moves(mouse: &mut Mouse, ticks: usize) -> Vec<(i8, i8)> {
+    (0..ticks)
+        .map(|_| {
+            let report = mouse.tick().unwrap();
+            (report.x, report.y)
+        })
+        .collect()
+}
+
+#[test]
+fn names_round_trip() {
+    for action in [
+        MouseAction::Up,
+        MouseAction::Down,
+        MouseAction::Left,
+        MouseAction::Right,
+        MouseAction::LeftClick,
+        MouseAction::RightClick,
+        MouseAction::MiddleClick,
+        MouseAction::WheelUp,
+        MouseAction::WheelDown,
+    ] {
+        assert_eq!(MouseAction::parse(action.name().as_bytes()), Some(action));
+    }
+    assert_eq!(MouseAction::parse(b"sideways"), None);
+}
+
+#[test]
+fn movement_speeds_up_while_held() {
+    let mut mouse = Mouse::new();
+    assert_eq!(mouse.tick(), None);
+    assert_eq!(mouse.press(MouseAction::Right), None);
+
+    let steps = moves(&mut mouse, 1000);
+    assert_eq!(steps[0], (START_SPEED as i8, 0));
+    assert_eq!(steps[ACCEL_TICKS as usize], (START_SPEED as i8 + 1, 0));
+    assert!(steps.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    assert_eq!(steps[999], (MAX_SPEED as i8, 0));
+
+    // Starts slow again once let go
+    mouse.release(MouseAction::Right);
+    assert_eq!(mouse.tick(), None);
+    mouse.press(MouseAction::Up);
+    assert_eq!(moves(&mut mouse, 1), [(0, -(START_SPEED as i8))]);
+}
+
+#[test]
+fn diagonals_and_opposites() {
+    let mut mouse = Mouse::new();
+    mouse.press(MouseAction::Down);
+    mouse.press(MouseAction::Left);
+    assert_eq!(moves(&mut mouse, 1), [(-1, 1)]);
+
+    mouse.press(MouseAction::Up);
+    assert_eq!(moves(&mut mouse, 1), [(-1, 0)]);
+}
+
+#[test]
+fn clicks_are_reported_straight_away() {
+    let mut mouse = Mouse::new();
+    let buttons = |buttons| {
+        Some(MouseReport {
+            buttons,
+            ..MouseReport::default()
+        })
+    };
+    assert_eq!(mouse.press(MouseAction::LeftClick), buttons(BUTTON_LEFT));
+    assert_eq!(
+        mouse.press(MouseAction::MiddleClick),
+        buttons(BUTTON_LEFT | BUTTON_MIDDLE)
+    );
+    assert!(!mouse.is_active());
+
+    // Dragging keeps the button down
+    mouse.press(MouseAction::Up);
+    assert_eq!(mouse.tick().unwrap().buttons, BUTTON_LEFT | BUTTON_MIDDLE);
+
+    assert_eq!(
+        mouse.release(MouseAction::LeftClick),
+        buttons(BUTTON_MIDDLE)
+    );
+    assert_eq!(mouse.release(MouseAction::LeftClick), None);
+    assert_eq!(mouse.release_all(), buttons(0));
+    assert_eq!(mouse.release_all(), None);
+}
+
+#[test]
+fn wheel_notches_at_its_own_rate() {
+    let mut mouse = Mouse::new();
+    mouse.press(MouseAction::WheelDown);
+    let wheel: Vec<i8> = (0..WHEEL_TICKS * 2 + 1)
+        .map(|_| mouse.tick().unwrap().wheel)
+        .collect();
+    let notches: Vec<usize> = wheel
+        .iter()
+        .enumerate()
+        .filter(|(_, wheel)| **wheel != 0)
+        .map(|(tick, _)| tick)
+        .collect();
+    assert_eq!(notches, [0, WHEEL_TICKS as usize, 2 * WHEEL_TICKS as usize]);
+    assert_eq!(wheel[0], -1);
+
+    mouse.release(MouseAction::WheelDown);
+    mouse.press(MouseAction::WheelUp);
+    assert_eq!(mouse.tick().unwrap().wheel, 1);
+}
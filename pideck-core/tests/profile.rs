@@ -0,0 +1,32 @@
+use pideck_core::profile::*;
+use pideck_core::protocol::ErrorCode;
+
+#[test]
+fn names_round_trip() {
+    let name = parse_name(b"Zoom").unwrap();
+    assert_eq!(name_str(&name), "Zoom");
+    assert_eq!(Name(&name).to_string(), "Zoom");
+
+    let full = parse_name(b"Photo-edit12").unwrap();
+    assert_eq!(name_str(&full), "Photo-edit12");
+}
+
+#[test]
+fn none_is_no_name() {
+    let name = parse_name(b"none").unwrap();
+    assert_eq!(name, [0; PROFILE_NAME_LEN]);
+    assert_eq!(name_str(&name), "");
+    assert_eq!(Name(&name).to_string(), "none");
+}
+
+#[test]
+fn bad_names_are_rejected() {
+    assert_eq!(parse_name(b"Photo-edit123"), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse_name(b""), Err(ErrorCode::BadArgument));
+    assert_eq!(parse_name(b"caf\xc3\xa9"), Err(ErrorCode::BadArgument));
+    assert_eq!(parse_name(b"a\0b"), Err(ErrorCode::BadArgument));
+
+    // Anything after the padding
+    assert_eq!(validate_name(b"ab\0c"), None);
+    assert_eq!(validate_name(b"ab\0\0"), Some(2));
+}
@@ -0,0 +1,993 @@
+use core::fmt::Write;
+
+use pideck_core::action::Action;
+use pideck_core::combo::Combo;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gesture::{Gesture, GestureTiming};
+use pideck_core::label::{self, LABEL_LEN};
+use pideck_core::layout::Layout;
+use pideck_core::lock::{Lock, LockLeds, CAPS_LOCK, SCROLL_LOCK};
+use pideck_core::macro_player::{MACRO_LEN, OP_PRESS, OP_RELEASE_ALL, OP_TAP};
+use pideck_core::mouse::MouseAction;
+use pideck_core::profile::{self, MAX_PROFILES};
+use pideck_core::protocol::*;
+use pideck_core::text::{UnicodeMethod, TEXT_LEN};
+
+const BUTTONS: u8 = 6;
+
+fn feed(buffer: &mut LineBuffer, bytes: &[u8]) -> Vec<Result<Vec<u8>, ErrorCode>> {
+    let mut lines = Vec::new();
+    for byte in bytes {
+        if buffer.push(*byte) {
+            lines.push(buffer.take_line().map(|line| line.to_vec()));
+        }
+    }
+    lines
+}
+
+fn render(response: Response) -> String {
+    let mut out = String::new();
+    write!(out, "{}", response).unwrap();
+    out
+}
+
+// Taps filling a whole macro slot, the longest line there is
+fn full_macro() -> [u8; MACRO_LEN] {
+    let mut code = [0u8; MACRO_LEN];
+    for tap in code.chunks_exact_mut(2) {
+        tap.copy_from_slice(&[OP_TAP, 0x04]);
+    }
+    code
+}
+
+#[test]
+fn parses_simple_commands() {
+    assert_eq!(parse(b"version", BUTTONS), Ok(Command::Version));
+    assert_eq!(parse(b"state", BUTTONS), Ok(Command::State));
+    assert_eq!(parse(b"save", BUTTONS), Ok(Command::Save));
+    assert_eq!(parse(b"layer", BUTTONS), Ok(Command::Layer(None)));
+    assert_eq!(
+        parse(b"layer 1 on", BUTTONS),
+        Ok(Command::Layer(Some((1, true))))
+    );
+    assert_eq!(
+        parse(b"layer 3 off", BUTTONS),
+        Ok(Command::Layer(Some((3, false))))
+    );
+}
+
+#[test]
+fn parses_lock_layers() {
+    assert_eq!(parse(b"leds", BUTTONS), Ok(Command::Leds));
+    assert_eq!(
+        parse(b"lock caps", BUTTONS),
+        Ok(Command::LockLayer {
+            lock: Lock::Caps,
+            layer: None
+        })
+    );
+    assert_eq!(
+        parse(b"lock num 2", BUTTONS),
+        Ok(Command::LockLayer {
+            lock: Lock::Num,
+            layer: Some(Some(2))
+        })
+    );
+    assert_eq!(
+        parse(b"lock scroll none", BUTTONS),
+        Ok(Command::LockLayer {
+            lock: Lock::Scroll,
+            layer: Some(None)
+        })
+    );
+    assert_eq!(parse(b"lock shift 1", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"lock caps 4", BUTTONS), Err(ErrorCode::OutOfRange));
+}
+
+#[test]
+fn parses_get_and_set_with_decimal_and_hex() {
+    assert_eq!(parse(b"get 3", BUTTONS), Ok(Command::Get { button: 3 }));
+    assert_eq!(
+        parse(b"  set 1 0x68\t205 ", BUTTONS),
+        Ok(Command::Set {
+            button: 1,
+            keycode: 0x68,
+            media_key: 0xCD
+        })
+    );
+    assert_eq!(
+        parse(b"set 2 0x04 brightness-up", BUTTONS),
+        Ok(Command::Set {
+            button: 2,
+            keycode: 0x04,
+            media_key: 0x6F
+        })
+    );
+    assert_eq!(
+        parse(b"set 2 0x04 0x10000", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"map 0 1 media volume-sideways", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"map 0 1 system 0x80", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"map 0 1 gamepad 0", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"map 0 1 gamepad 17", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+}
+
+#[test]
+fn rejects_bad_input() {
+    assert_eq!(parse(b"", BUTTONS), Err(ErrorCode::UnknownCommand));
+    assert_eq!(parse(b"reboot", BUTTONS), Err(ErrorCode::UnknownCommand));
+    assert_eq!(parse(b"get", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"get one", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"get 0x", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"get 0", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"get 7", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"set 1 256 0", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(
+        parse(b"set 1 99999999999 0", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(parse(b"set 1 0x68", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"layer 1", BUTTONS), Err(ErrorCode::BadArgument));
+    assert_eq!(parse(b"layer 4 on", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(parse(b"state now", BUTTONS), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn parses_debounce() {
+    assert_eq!(
+        parse(b"debounce 2", BUTTONS),
+        Ok(Command::Debounce {
+            button: 2,
+            strategy: None
+        })
+    );
+    assert_eq!(
+        parse(b"debounce 2 eager 10", BUTTONS),
+        Ok(Command::Debounce {
+            button: 2,
+            strategy: Some(DebounceStrategy::Eager { lockout_us: 10_000 })
+        })
+    );
+    assert_eq!(
+        parse(b"debounce 6 integrator 0x05", BUTTONS),
+        Ok(Command::Debounce {
+            button: 6,
+            strategy: Some(DebounceStrategy::Integrator { samples: 5 })
+        })
+    );
+    assert_eq!(parse(b"debounce 7", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(
+        parse(b"debounce 1 schmitt 5", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"debounce 1 deferred", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"debounce 1 integrator 0", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+}
+
+#[test]
+fn parses_layer_maps() {
+    assert_eq!(
+        parse(b"map 2 5", BUTTONS),
+        Ok(Command::Map {
+            layer: 2,
+            button: 5,
+            action: None
+        })
+    );
+    for (line, action) in [
+        (&b"map 0 1 trans"[..], Action::Transparent),
+        (b"map 0 1 momentary 1", Action::Momentary(1)),
+        (b"map 0 1 toggle 3", Action::Toggle(3)),
+        (b"map 0 1 oneshot 2", Action::OneShot(2)),
+        (b"map 0 1 media 0xe9", Action::Media(0xE9)),
+        (b"map 0 1 media 0x192", Action::Media(0x192)),
+        (b"map 0 1 media browser-back", Action::Media(0x224)),
+        (b"map 0 1 system sleep", Action::System(0x82)),
+        (
+            b"map 0 1 mouse wheel-up",
+            Action::Mouse(MouseAction::WheelUp),
+        ),
+        (b"map 0 1 system 0xa8", Action::System(0xA8)),
+        (b"map 0 1 gamepad 16", Action::Gamepad(16)),
+        (
+            b"map 0 1 shortcut ctrl+alt 0x17",
+            Action::Shortcut {
+                modifiers: 0b0000_0101,
+                keycode: 0x17,
+            },
+        ),
+        (
+            b"map 0 1 shortcut rgui+shift 4",
+            Action::Shortcut {
+                modifiers: 0b1000_0010,
+                keycode: 0x04,
+            },
+        ),
+    ] {
+        assert_eq!(
+            parse(line, BUTTONS),
+            Ok(Command::Map {
+                layer: 0,
+                button: 1,
+                action: Some(action)
+            })
+        );
+    }
+    assert_eq!(parse(b"map 4 1", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(
+        parse(b"map 0 1 toggle 4", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"map 0 1 trans 1", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"map 0 1 shortcut ctrl+meta 0x17", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"map 0 1 shortcut ctrl", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+}
+
+#[test]
+fn parses_gesture_bindings() {
+    assert_eq!(
+        parse(b"gesture 1 double-tap", BUTTONS),
+        Ok(Command::Gesture {
+            button: 1,
+            gesture: Gesture::DoubleTap,
+            action: None
+        })
+    );
+    assert_eq!(
+        parse(b"gesture 1 hold key 0x04", BUTTONS),
+        Ok(Command::Gesture {
+            button: 1,
+            gesture: Gesture::Hold,
+            action: Some(Action::Key(0x04))
+        })
+    );
+    assert_eq!(
+        parse(b"gesture 2 tap none", BUTTONS),
+        Ok(Command::Gesture {
+            button: 2,
+            gesture: Gesture::Tap,
+            action: Some(Action::None)
+        })
+    );
+    assert_eq!(
+        parse(b"gesture 2 swipe", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"gesture 2 tap media", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"gesture 2 tap none 0x04", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+}
+
+#[test]
+fn parses_gesture_timing() {
+    assert_eq!(
+        parse(b"timing 3", BUTTONS),
+        Ok(Command::Timing {
+            button: 3,
+            timing: None
+        })
+    );
+    assert_eq!(
+        parse(b"timing 3 200 0 1500", BUTTONS),
+        Ok(Command::Timing {
+            button: 3,
+            timing: Some(GestureTiming::from_ms([200, 0, 1500]))
+        })
+    );
+    assert_eq!(
+        parse(b"timing 3 200 0", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"timing 3 200 0 70000", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+}
+
+#[test]
+fn parses_combos() {
+    assert_eq!(
+        parse(b"combo 0", BUTTONS),
+        Ok(Command::Combo {
+            slot: 0,
+            combo: None
+        })
+    );
+    assert_eq!(
+        parse(b"combo 7 5+6 bootloader", BUTTONS),
+        Ok(Command::Combo {
+            slot: 7,
+            combo: Some(Combo {
+                buttons: 0b11_0000,
+                action: Action::Bootloader
+            })
+        })
+    );
+    assert_eq!(
+        parse(b"combo 1 none none", BUTTONS),
+        Ok(Command::Combo {
+            slot: 1,
+            combo: Some(Combo::default())
+        })
+    );
+    assert_eq!(
+        parse(b"combo-window 80", BUTTONS),
+        Ok(Command::ComboWindow(Some(80)))
+    );
+    assert_eq!(parse(b"combo 8", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(
+        parse(b"combo 0 1+7 key 4", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    // A combo needs two different buttons
+    assert_eq!(
+        parse(b"combo 0 3 key 4", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"combo 0 3+3 key 4", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(parse(b"combo 0 1+2", BUTTONS), Err(ErrorCode::BadArgument));
+}
+
+#[test]
+fn parses_macros() {
+    let mut ctrl_shift_p = [0u8; MACRO_LEN];
+    ctrl_shift_p[..7].copy_from_slice(&[
+        OP_PRESS,
+        0xE0,
+        OP_PRESS,
+        0xE1,
+        OP_TAP,
+        0x13,
+        OP_RELEASE_ALL,
+    ]);
+    assert_eq!(
+        parse(b"macro 3", BUTTONS),
+        Ok(Command::Macro {
+            slot: 3,
+            code: None
+        })
+    );
+    assert_eq!(
+        parse(b"macro 0 01E001e1031306", BUTTONS),
+        Ok(Command::Macro {
+            slot: 0,
+            code: Some(ctrl_shift_p)
+        })
+    );
+    assert_eq!(
+        parse(b"macro 7 none", BUTTONS),
+        Ok(Command::Macro {
+            slot: 7,
+            code: Some([0; MACRO_LEN])
+        })
+    );
+    assert_eq!(
+        parse(b"map 0 1 macro 2", BUTTONS),
+        Ok(Command::Map {
+            layer: 0,
+            button: 1,
+            action: Some(Action::Macro(2))
+        })
+    );
+    assert_eq!(parse(b"macro 8", BUTTONS), Err(ErrorCode::OutOfRange));
+    assert_eq!(
+        parse(b"map 0 1 macro 8", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    // Odd length, not hex, unknown opcode and a tap missing its key
+    for bad in [
+        "macro 0 031",
+        "macro 0 03zz",
+        "macro 0 7f",
+        "macro 0 0313 03",
+    ] {
+        assert_eq!(parse(bad.as_bytes(), BUTTONS), Err(ErrorCode::BadArgument));
+    }
+    let mut too_long = b"macro 0 ".to_vec();
+    too_long.extend_from_slice(&[b'0'; 2 * MACRO_LEN + 2]);
+    assert_eq!(parse(&too_long, BUTTONS), Err(ErrorCode::OutOfRange));
+}
+
+#[test]
+fn parses_texts() {
+    let mut hi = [0u8; TEXT_LEN];
+    hi[..3].copy_from_slice("hé".as_bytes());
+    assert_eq!(
+        parse(b"text 2", BUTTONS),
+        Ok(Command::Text {
+            slot: 2,
+            text: None
+        })
+    );
+    assert_eq!(
+        parse(b"text 0 68C3a9", BUTTONS),
+        Ok(Command::Text {
+            slot: 0,
+            text: Some(hi)
+        })
+    );
+    assert_eq!(
+        parse(b"map 1 2 text 7", BUTTONS),
+        Ok(Command::Map {
+            layer: 1,
+            button: 2,
+            action: Some(Action::Text(7))
+        })
+    );
+    assert_eq!(parse(b"text 8", BUTTONS), Err(ErrorCode::OutOfRange));
+    // Not UTF-8
+    assert_eq!(parse(b"text 0 ff", BUTTONS), Err(ErrorCode::BadArgument));
+
+    assert_eq!(parse(b"unicode", BUTTONS), Ok(Command::Unicode(None)));
+    assert_eq!(
+        parse(b"unicode wincompose", BUTTONS),
+        Ok(Command::Unicode(Some(UnicodeMethod::WinCompose)))
+    );
+    assert_eq!(parse(b"unicode mac", BUTTONS), Err(ErrorCode::BadArgument));
+
+    assert_eq!(parse(b"layout", BUTTONS), Ok(Command::Layout(None)));
+    assert_eq!(
+        parse(b"layout de", BUTTONS),
+        Ok(Command::Layout(Some(Layout::De)))
+    );
+    assert_eq!(
+        parse(b"layout azerty", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+}
+
+#[test]
+fn parses_labels() {
+    assert_eq!(
+        parse(b"label 1 3", BUTTONS),
+        Ok(Command::Label {
+            layer: 1,
+            button: 3,
+            label: None
+        })
+    );
+    assert_eq!(
+        parse(b"label 1 3 Vol+", BUTTONS),
+        Ok(Command::Label {
+            layer: 1,
+            button: 3,
+            label: Some(label::parse_label(b"Vol+").unwrap())
+        })
+    );
+    assert_eq!(
+        parse(b"label 0 1 none", BUTTONS),
+        Ok(Command::Label {
+            layer: 0,
+            button: 1,
+            label: Some([0; LABEL_LEN])
+        })
+    );
+    assert_eq!(
+        parse(b"label 4 1 Mute", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"label 0 7 Mute", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"label 0 1 Longer-than", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"label 0 1 Vol up", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+}
+
+#[test]
+fn parses_profiles() {
+    assert_eq!(parse(b"profile", BUTTONS), Ok(Command::Profile(None)));
+    assert_eq!(parse(b"profile 2", BUTTONS), Ok(Command::Profile(Some(2))));
+    assert_eq!(parse(b"profile 4", BUTTONS), Err(ErrorCode::OutOfRange));
+
+    assert_eq!(
+        parse(b"profile-name 1 Zoom", BUTTONS),
+        Ok(Command::ProfileName {
+            profile: 1,
+            name: Some(profile::parse_name(b"Zoom").unwrap())
+        })
+    );
+    assert_eq!(
+        parse(b"profile-name 1 none", BUTTONS),
+        Ok(Command::ProfileName {
+            profile: 1,
+            name: Some([0; profile::PROFILE_NAME_LEN])
+        })
+    );
+    assert_eq!(
+        parse(b"profile-name 0 a-much-too-long-name", BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"profile-name 0 Photo edit", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+
+    assert_eq!(
+        parse(b"map 0 6 profile next", BUTTONS),
+        Ok(Command::Map {
+            layer: 0,
+            button: 6,
+            action: Some(Action::NextProfile)
+        })
+    );
+    assert_eq!(
+        parse(b"gesture 1 hold profile 3", BUTTONS),
+        Ok(Command::Gesture {
+            button: 1,
+            gesture: Gesture::Hold,
+            action: Some(Action::Profile(3))
+        })
+    );
+    let too_far = format!("map 0 6 profile {}", MAX_PROFILES);
+    assert_eq!(
+        parse(too_far.as_bytes(), BUTTONS),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        parse(b"map 0 6 profile last", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+
+    assert_eq!(
+        parse(b"profile-select Zoom", BUTTONS),
+        Ok(Command::SelectProfile(
+            profile::parse_name(b"Zoom").unwrap()
+        ))
+    );
+    assert_eq!(
+        parse(b"profile-select none", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+    assert_eq!(
+        parse(b"profile-select", BUTTONS),
+        Err(ErrorCode::BadArgument)
+    );
+}
+
+#[test]
+fn line_buffer_splits_chunked_input() {
+    let mut buffer = LineBuffer::new();
+    assert!(feed(&mut buffer, b"get ").is_empty());
+
+    let lines = feed(&mut buffer, b"2\r\nversion\nsta");
+    assert_eq!(lines, vec![Ok(b"get 2".to_vec()), Ok(b"version".to_vec())]);
+
+    let lines = feed(&mut buffer, b"te\n");
+    assert_eq!(lines, vec![Ok(b"state".to_vec())]);
+}
+
+#[test]
+fn line_buffer_reports_overflow_once_and_recovers() {
+    let mut buffer = LineBuffer::new();
+    let long = [b'a'; MAX_LINE_LEN + 10];
+
+    assert!(feed(&mut buffer, &long).is_empty());
+    let lines = feed(&mut buffer, b"\nsave\n");
+    assert_eq!(
+        lines,
+        vec![Err(ErrorCode::LineTooLong), Ok(b"save".to_vec())]
+    );
+}
+
+#[test]
+fn formats_responses() {
+    assert_eq!(render(Response::Ok), "OK\n");
+    assert_eq!(render(Response::Version("0.1.0")), "OK 0.1.0\n");
+    assert_eq!(
+        render(Response::Key {
+            button: 1,
+            keycode: 0x69,
+            media_key: 0x00
+        }),
+        "OK 1 0x69 0x00\n"
+    );
+    assert_eq!(
+        render(Response::Layers {
+            active: [0, 2, 1, 0],
+            count: 3
+        }),
+        "OK 0 2 1\n"
+    );
+    assert_eq!(
+        render(Response::State {
+            pressed: 0b10_0001,
+            count: 6
+        }),
+        "OK 100001\n"
+    );
+    assert_eq!(
+        render(Response::Combo {
+            slot: 1,
+            combo: Combo {
+                buttons: 0b11_0000,
+                action: Action::Bootloader
+            }
+        }),
+        "OK 1 5+6 bootloader\n"
+    );
+    assert_eq!(
+        render(Response::Err(ErrorCode::OutOfRange)),
+        "ERR 3 out of range\n"
+    );
+}
+
+#[test]
+fn formatted_commands_parse_back() {
+    let commands = [
+        Command::Version,
+        Command::Get { button: 6 },
+        Command::Set {
+            button: 2,
+            keycode: 0x68,
+            media_key: 0xB5,
+        },
+        Command::Layer(None),
+        Command::Layer(Some((2, false))),
+        Command::Map {
+            layer: 0,
+            button: 6,
+            action: None,
+        },
+        Command::Map {
+            layer: 3,
+            button: 1,
+            action: Some(Action::OneShot(2)),
+        },
+        Command::Map {
+            layer: 1,
+            button: 3,
+            action: Some(Action::Shortcut {
+                modifiers: 0b0011_0000,
+                keycode: 0x2C,
+            }),
+        },
+        Command::State,
+        Command::Debounce {
+            button: 1,
+            strategy: None,
+        },
+        Command::Debounce {
+            button: 3,
+            strategy: Some(DebounceStrategy::Deferred { stable_us: 5_000 }),
+        },
+        Command::Gesture {
+            button: 4,
+            gesture: Gesture::LongPress,
+            action: Some(Action::Media(0xE9)),
+        },
+        Command::Timing {
+            button: 5,
+            timing: Some(GestureTiming::DEFAULT),
+        },
+        Command::Combo {
+            slot: 2,
+            combo: Some(Combo {
+                buttons: 0b10_0101,
+                action: Action::Momentary(3),
+            }),
+        },
+        Command::ComboWindow(None),
+        Command::ComboWindow(Some(30)),
+        Command::Macro {
+            slot: 1,
+            code: Some(full_macro()),
+        },
+        Command::Leds,
+        Command::LockLayer {
+            lock: Lock::Caps,
+            layer: Some(Some(3)),
+        },
+        Command::LockLayer {
+            lock: Lock::Num,
+            layer: Some(None),
+        },
+        Command::Text {
+            slot: 4,
+            text: Some([b'x'; TEXT_LEN]),
+        },
+        Command::Unicode(Some(UnicodeMethod::Windows)),
+        Command::Layout(Some(Layout::Dvorak)),
+        Command::Profile(Some(3)),
+        Command::ProfileName {
+            profile: 2,
+            name: Some(profile::parse_name(b"IDE").unwrap()),
+        },
+        Command::Map {
+            layer: 2,
+            button: 1,
+            action: Some(Action::NextProfile),
+        },
+        Command::SelectProfile(profile::parse_name(b"Photo-edit").unwrap()),
+        Command::Label {
+            layer: 0,
+            button: 2,
+            label: None,
+        },
+        Command::Label {
+            layer: 3,
+            button: 6,
+            label: Some(label::parse_label(b"Leave").unwrap()),
+        },
+        Command::Save,
+    ];
+
+    for command in commands {
+        let line = command.to_string();
+        assert!(line.ends_with('\n'));
+        assert_eq!(parse(line.trim_end().as_bytes(), BUTTONS), Ok(command));
+    }
+}
+
+#[test]
+fn formatted_responses_parse_back() {
+    let pairs = [
+        (Command::Version, Response::Version("0.1.0")),
+        (
+            Command::Get { button: 4 },
+            Response::Key {
+                button: 4,
+                keycode: 0x6C,
+                media_key: 0xEA,
+            },
+        ),
+        (Command::Save, Response::Ok),
+        (
+            Command::Layer(None),
+            Response::Layers {
+                active: [0, 1, 0, 0],
+                count: 2,
+            },
+        ),
+        (
+            Command::Map {
+                layer: 1,
+                button: 2,
+                action: None,
+            },
+            Response::Map {
+                layer: 1,
+                button: 2,
+                action: Action::Transparent,
+            },
+        ),
+        (
+            Command::State,
+            Response::State {
+                pressed: 0b00_0110,
+                count: 6,
+            },
+        ),
+        (
+            Command::Debounce {
+                button: 5,
+                strategy: None,
+            },
+            Response::Debounce {
+                button: 5,
+                strategy: DebounceStrategy::Integrator { samples: 8 },
+            },
+        ),
+        (
+            Command::Gesture {
+                button: 2,
+                gesture: Gesture::Tap,
+                action: None,
+            },
+            Response::Gesture {
+                button: 2,
+                gesture: Gesture::Tap,
+                action: Action::Key(0x68),
+            },
+        ),
+        (
+            Command::Timing {
+                button: 2,
+                timing: None,
+            },
+            Response::Timing {
+                button: 2,
+                timing: GestureTiming::from_ms([300, 0, 2000]),
+            },
+        ),
+        (
+            Command::Combo {
+                slot: 0,
+                combo: None,
+            },
+            Response::Combo {
+                slot: 0,
+                combo: Combo {
+                    buttons: 0b00_0011,
+                    action: Action::Toggle(1),
+                },
+            },
+        ),
+        (
+            Command::Combo {
+                slot: 5,
+                combo: None,
+            },
+            Response::Combo {
+                slot: 5,
+                combo: Combo::default(),
+            },
+        ),
+        (Command::ComboWindow(None), Response::ComboWindow(50)),
+        (
+            Command::Macro {
+                slot: 1,
+                code: None,
+            },
+            Response::Macro {
+                slot: 1,
+                code: full_macro(),
+            },
+        ),
+        (
+            Command::Macro {
+                slot: 6,
+                code: None,
+            },
+            Response::Macro {
+                slot: 6,
+                code: [0; MACRO_LEN],
+            },
+        ),
+        (
+            Command::Leds,
+            Response::Leds(LockLeds(CAPS_LOCK | SCROLL_LOCK)),
+        ),
+        (Command::Leds, Response::Leds(LockLeds(0))),
+        (
+            Command::LockLayer {
+                lock: Lock::Caps,
+                layer: None,
+            },
+            Response::LockLayer {
+                lock: Lock::Caps,
+                layer: Some(2),
+            },
+        ),
+        (
+            Command::LockLayer {
+                lock: Lock::Scroll,
+                layer: Some(None),
+            },
+            Response::LockLayer {
+                lock: Lock::Scroll,
+                layer: None,
+            },
+        ),
+        (
+            Command::Text {
+                slot: 3,
+                text: None,
+            },
+            Response::Text {
+                slot: 3,
+                text: [b'x'; TEXT_LEN],
+            },
+        ),
+        (
+            Command::Unicode(None),
+            Response::Unicode(UnicodeMethod::Linux),
+        ),
+        (Command::Layout(None), Response::Layout(Layout::Uk)),
+        (
+            Command::Profile(None),
+            Response::Profile {
+                profile: 1,
+                name: profile::parse_name(b"Photo-edit").unwrap(),
+            },
+        ),
+        (
+            Command::ProfileName {
+                profile: 3,
+                name: None,
+            },
+            Response::Profile {
+                profile: 3,
+                name: [0; profile::PROFILE_NAME_LEN],
+            },
+        ),
+        (
+            Command::SelectProfile(profile::parse_name(b"IDE").unwrap()),
+            Response::Profile {
+                profile: 2,
+                name: profile::parse_name(b"IDE").unwrap(),
+            },
+        ),
+        (
+            Command::Label {
+                layer: 1,
+                button: 5,
+                label: None,
+            },
+            Response::Label {
+                layer: 1,
+                button: 5,
+                label: label::parse_label(b"Vol+").unwrap(),
+            },
+        ),
+        (
+            Command::Label {
+                layer: 0,
+                button: 2,
+                label: Some([0; LABEL_LEN]),
+            },
+            Response::Label {
+                layer: 0,
+                button: 2,
+                label: [0; LABEL_LEN],
+            },
+        ),
+        (Command::State, Response::Err(ErrorCode::StorageFailed)),
+        (
+            Command::SelectProfile(profile::parse_name(b"Zoom").unwrap()),
+            Response::Err(ErrorCode::UnknownProfile),
+        ),
+    ];
+
+    for (command, response) in pairs {
+        let line = render(response);
+        assert_eq!(parse_response(&command, &line), Some(response));
+    }
+}
+
+#[test]
+fn rejects_malformed_responses() {
+    assert_eq!(parse_response(&Command::Version, "OK"), None);
+    assert_eq!(
+        parse_response(&Command::Get { button: 1 }, "OK 1 0x69"),
+        None
+    );
+    assert_eq!(parse_response(&Command::Layer(None), "OK 0 9"), None);
+    assert_eq!(parse_response(&Command::Layer(None), "OK 0 1 2 3 0"), None);
+    assert_eq!(parse_response(&Command::State, "OK 10x"), None);
+    assert_eq!(parse_response(&Command::Save, "ERR 99 what"), None);
+    assert_eq!(parse_response(&Command::Save, "I'm here"), None);
+    assert_eq!(parse_response(&Command::Leds, "OK caps shift"), None);
+}
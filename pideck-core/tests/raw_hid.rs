@@ -0,0 +1,77 @@
+use pideck_core::protocol::{self, LineBuffer, Response};
+use pideck_core::raw_hid::*;
+
+fn unframe(reports: &[Report]) -> Vec<u8> {
+    reports
+        .iter()
+        .flat_map(|report| payload(report).unwrap().to_vec())
+        .collect()
+}
+
+#[test]
+fn short_text_fits_one_padded_report() {
+    let reports: Vec<Report> = frames(b"version\n").collect();
+
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0][0], 8);
+    assert_eq!(&reports[0][1..9], b"version\n");
+    assert!(reports[0][9..].iter().all(|byte| *byte == 0));
+    assert_eq!(frames(b"").count(), 0);
+}
+
+#[test]
+fn long_lines_span_reports_and_join_back_up() {
+    // The longest reply there is, a full macro slot in hex
+    let line = format!("MACRO 0 {}\r\n", "ab".repeat(protocol::MAX_LINE_LEN));
+    let reports: Vec<Report> = frames(line.as_bytes()).collect();
+
+    assert_eq!(reports.len(), line.len().div_ceil(MAX_PAYLOAD));
+    assert!(reports[..reports.len() - 1]
+        .iter()
+        .all(|report| report[0] as usize == MAX_PAYLOAD));
+    assert_eq!(unframe(&reports), line.as_bytes());
+}
+
+#[test]
+fn payloads_feed_the_serial_line_buffer() {
+    let mut reports: Vec<Report> = frames(b"map 0 1 ").collect();
+    reports.extend(frames(b"key 0x68\nlayer\n"));
+
+    let mut buffer = LineBuffer::new();
+    let mut lines = Vec::new();
+    for byte in unframe(&reports) {
+        if buffer.push(byte) {
+            lines.push(buffer.take_line().unwrap().to_vec());
+        }
+    }
+    assert_eq!(lines, [b"map 0 1 key 0x68".to_vec(), b"layer".to_vec()]);
+
+    let reply = Response::Ok.to_string();
+    assert_eq!(
+        unframe(&frames(reply.as_bytes()).collect::<Vec<_>>()),
+        reply.as_bytes()
+    );
+}
+
+#[test]
+fn rejects_lengths_past_the_report() {
+    let mut report = [0; REPORT_LEN];
+    assert_eq!(payload(&report), Some(&[][..]));
+    report[0] = MAX_PAYLOAD as u8;
+    assert_eq!(payload(&report).map(<[u8]>::len), Some(MAX_PAYLOAD));
+    report[0] = REPORT_LEN as u8;
+    assert_eq!(payload(&report), None);
+    assert_eq!(payload(&[]), None);
+    // hidraw may hand back a report cut short
+    assert_eq!(payload(&[3, b'O', b'K']), None);
+}
+
+#[test]
+fn recognises_the_vendor_descriptor() {
+    assert!(is_raw_descriptor(&[
+        0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01
+    ]));
+    // Generic Desktop keyboard
+    assert!(!is_raw_descriptor(&[0x05, 0x01, 0x09, 0x06]));
+    assert!(!is_raw_descriptor(&[0x06]));
+}
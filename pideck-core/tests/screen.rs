@@ -0,0 +1,354 @@
+use std::{env, fmt, fs, path::Path};
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use enum_map::EnumMap;
+use pideck_core::hid_util::HIDUtil;
+use pideck_core::key_config::KeyConfig;
+use pideck_core::label::{self, LabelText};
+use pideck_core::layer::MEDIA_LAYER;
+use pideck_core::profile;
+use pideck_core::report::ReportSink;
+use pideck_core::screen::{
+    draw_keys, draw_status, key_cell, keys_area, status_area, Screen, Status, UsbState,
+    STATUS_HEIGHT,
+};
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 32;
+
+// The SSD1306's 128x32 pixels. Drawing outside them is a bug, so it panics.
+struct MockDisplay {
+    pixels: [[bool; WIDTH]; HEIGHT],
+}
+
+impl MockDisplay {
+    fn new() -> Self {
+        MockDisplay {
+            pixels: [[false; WIDTH]; HEIGHT],
+        }
+    }
+}
+
+impl OriginDimensions for MockDisplay {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for MockDisplay {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (x, y) = (point.x as usize, point.y as usize);
+            assert!(x < WIDTH && y < HEIGHT, "drew outside at {:?}", point);
+            self.pixels[y][x] = color.is_on();
+        }
+        Ok(())
+    }
+}
+
+// One line per row, `#` for a lit pixel
+impl fmt::Display for MockDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.pixels {
+            for pixel in row {
+                f.write_str(if *pixel { "#" } else { "." })?;
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+// Compares with tests/snapshots/<name>.txt, or writes it with UPDATE_SNAPSHOTS
+// set after a deliberate change to the drawing
+fn assert_snapshot(name: &str, display: &MockDisplay) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.txt", name));
+    let drawn = display.to_string();
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &drawn).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(expected == drawn, "{} drew\n{}", name, drawn);
+}
+
+struct NoReports;
+
+impl ReportSink for NoReports {
+    fn send_keyboard(&mut self, _: pideck_core::report::KeyboardReport) -> bool {
+        true
+    }
+    fn send_media(&mut self, _: pideck_core::report::MediaReport) -> bool {
+        true
+    }
+    fn send_system(&mut self, _: pideck_core::report::SystemReport) -> bool {
+        true
+    }
+    fn send_mouse(&mut self, _: pideck_core::report::MouseReport) -> bool {
+        true
+    }
+    fn send_gamepad(&mut self, _: pideck_core::report::GamepadReport) -> bool {
+        true
+    }
+    fn enter_bootloader(&mut self) {}
+}
+
+fn labels(texts: [&str; 6]) -> EnumMap<KeyConfig, LabelText> {
+    EnumMap::from_fn(|button_id: KeyConfig| LabelText::from(texts[button_id as usize]))
+}
+
+#[test]
+fn cells_cover_the_display_in_two_rows_of_three() {
+    let area = MockDisplay::new().bounding_box();
+    assert_eq!(
+        key_cell(&area, KeyConfig::One),
+        Rectangle::new(Point::new(0, 0), Size::new(42, 16))
+    );
+    assert_eq!(
+        key_cell(&area, KeyConfig::Three),
+        Rectangle::new(Point::new(85, 0), Size::new(43, 16))
+    );
+    assert_eq!(
+        key_cell(&area, KeyConfig::Five),
+        Rectangle::new(Point::new(42, 16), Size::new(43, 16))
+    );
+    let covered: u32 = EnumMap::<KeyConfig, ()>::default()
+        .iter()
+        .map(|(button_id, _)| key_cell(&area, button_id).size)
+        .map(|size| size.width * size.height)
+        .sum();
+    assert_eq!(covered, (WIDTH * HEIGHT) as u32);
+}
+
+#[test]
+fn draws_the_labels() {
+    let mut display = MockDisplay::new();
+    draw_keys(
+        &mut display,
+        &labels(["Mute", "Cam", "Share", "Vol-", "Vol+", "Leave"]),
+        &EnumMap::default(),
+    )
+    .unwrap();
+    assert_snapshot("labels", &display);
+}
+
+#[test]
+fn highlights_held_buttons() {
+    let mut display = MockDisplay::new();
+    let mut pressed = EnumMap::default();
+    pressed[KeyConfig::Two] = true;
+    pressed[KeyConfig::Six] = true;
+    draw_keys(
+        &mut display,
+        &labels(["Mute", "Cam", "Share", "Vol-", "Vol+", "Leave"]),
+        &pressed,
+    )
+    .unwrap();
+    assert_snapshot("held", &display);
+}
+
+#[test]
+fn full_labels_fit_their_cell() {
+    let mut display = MockDisplay::new();
+    let mut pressed = EnumMap::default();
+    pressed[KeyConfig::One] = true;
+    draw_keys(
+        &mut display,
+        &labels(["click L", "m0xffff", "whl dn", "", "WWWWWWW", "prof >"]),
+        &pressed,
+    )
+    .unwrap();
+    assert_snapshot("full", &display);
+}
+
+#[test]
+fn draws_what_the_deck_has_on_the_active_layers() {
+    let mut hid = HIDUtil::default();
+    hid.set_label(
+        MEDIA_LAYER,
+        KeyConfig::One,
+        label::parse_label(b"Play").unwrap(),
+    );
+    hid.set_layer(&mut NoReports, MEDIA_LAYER, true);
+    hid.push_input(&mut NoReports, KeyConfig::Three);
+
+    let mut display = MockDisplay::new();
+    draw_keys(&mut display, &hid.label_texts(), &hid.held()).unwrap();
+    assert_snapshot("media_layer", &display);
+}
+
+#[test]
+fn status_bar_sits_above_the_keys() {
+    let area = MockDisplay::new().bounding_box();
+    assert_eq!(
+        status_area(&area),
+        Rectangle::new(Point::new(0, 0), Size::new(128, 7))
+    );
+    assert_eq!(
+        keys_area(&area),
+        Rectangle::new(Point::new(0, 7), Size::new(128, 25))
+    );
+}
+
+#[test]
+fn draws_the_status() {
+    let mut display = MockDisplay::new();
+    draw_status(
+        &mut display,
+        &Status {
+            profile: 2,
+            profile_name: profile::parse_name(b"Photo-edit12").unwrap(),
+            layer: 3,
+            caps_lock: true,
+            usb: UsbState::Configured,
+            serial: true,
+        },
+    )
+    .unwrap();
+    assert_snapshot("status", &display);
+}
+
+#[test]
+fn unnamed_profiles_show_their_number() {
+    let mut display = MockDisplay::new();
+    draw_status(
+        &mut display,
+        &Status {
+            profile: 1,
+            usb: UsbState::Suspended,
+            ..Status::default()
+        },
+    )
+    .unwrap();
+    assert_snapshot("status_unnamed", &display);
+}
+
+#[test]
+fn draws_status_and_keys_each_in_their_own_area() {
+    let mut display = MockDisplay::new();
+    let area = display.bounding_box();
+    let mut pressed = EnumMap::default();
+    pressed[KeyConfig::Four] = true;
+    draw_keys(
+        &mut display.cropped(&keys_area(&area)),
+        &labels(["Mute", "Cam", "Share", "Vol-", "Vol+", "Leave"]),
+        &pressed,
+    )
+    .unwrap();
+    draw_status(
+        &mut display.cropped(&status_area(&area)),
+        &Status {
+            profile_name: profile::parse_name(b"Zoom").unwrap(),
+            usb: UsbState::Configured,
+            ..Status::default()
+        },
+    )
+    .unwrap();
+    assert_snapshot("screen", &display);
+}
+
+// What `draws_status_and_keys_each_in_their_own_area` draws
+fn zoom_screen() -> Screen {
+    let mut screen = Screen::new();
+    let mut pressed = EnumMap::default();
+    pressed[KeyConfig::Four] = true;
+    screen.set_keys(
+        labels(["Mute", "Cam", "Share", "Vol-", "Vol+", "Leave"]),
+        pressed,
+    );
+    screen.set_status(Status {
+        profile_name: profile::parse_name(b"Zoom").unwrap(),
+        usb: UsbState::Configured,
+        ..Status::default()
+    });
+    screen
+}
+
+#[test]
+fn first_draw_covers_everything() {
+    let mut display = MockDisplay::new();
+    // Nothing set yet still clears what was shown before, e.g. the logo
+    assert!(Screen::new().is_dirty());
+
+    assert!(zoom_screen().take().draw(&mut display).unwrap());
+    assert_snapshot("screen", &display);
+}
+
+#[test]
+fn nothing_to_draw_after_take() {
+    let mut screen = zoom_screen();
+    screen.take();
+    assert!(!screen.is_dirty());
+
+    let mut display = MockDisplay::new();
+    assert!(!screen.take().draw(&mut display).unwrap());
+    assert_eq!(display.to_string(), MockDisplay::new().to_string());
+}
+
+#[test]
+fn redraw_covers_everything_again() {
+    let mut screen = zoom_screen();
+    screen.take();
+    screen.redraw();
+
+    let mut display = MockDisplay::new();
+    assert!(screen.take().draw(&mut display).unwrap());
+    assert_snapshot("screen", &display);
+}
+
+#[test]
+fn setting_the_same_state_is_no_change() {
+    let mut screen = zoom_screen();
+    let frame = screen.take();
+    let mut pressed = EnumMap::default();
+    pressed[KeyConfig::Four] = true;
+    screen.set_keys(
+        labels(["Mute", "Cam", "Share", "Vol-", "Vol+", "Leave"]),
+        pressed,
+    );
+    screen.set_status(Status {
+        profile_name: profile::parse_name(b"Zoom").unwrap(),
+        usb: UsbState::Configured,
+        ..Status::default()
+    });
+    assert!(!screen.is_dirty());
+
+    // Changes made before the take are drawn once, by the frame taken
+    assert!(frame.is_dirty());
+}
+
+#[test]
+fn redraws_only_the_parts_that_changed() {
+    let mut screen = zoom_screen();
+    screen.take();
+    screen.set_status(Status {
+        caps_lock: true,
+        ..Status::default()
+    });
+
+    let mut display = MockDisplay::new();
+    assert!(screen.take().draw(&mut display).unwrap());
+    let drawn = display.to_string();
+    let (status, keys) = drawn.split_at((WIDTH + 1) * STATUS_HEIGHT as usize);
+    assert!(status.contains('#'));
+    assert!(!keys.contains('#'));
+
+    // Then the keys alone
+    screen.set_keys(labels(["1", "2", "3", "4", "5", "6"]), EnumMap::default());
+    let mut display = MockDisplay::new();
+    assert!(screen.take().draw(&mut display).unwrap());
+    let drawn = display.to_string();
+    let (status, keys) = drawn.split_at((WIDTH + 1) * STATUS_HEIGHT as usize);
+    assert!(!status.contains('#'));
+    assert!(keys.contains('#'));
+}
@@ -0,0 +1,196 @@
+use pideck_core::layout::Layout;
+use pideck_core::macro_player::MacroStep::{self, Press, Release};
+use pideck_core::protocol::ErrorCode;
+use pideck_core::text::*;
+
+const LCTRL: u8 = 0xE0;
+const LSHIFT: u8 = 0xE1;
+const LALT: u8 = 0xE2;
+const RALT: u8 = 0xE6;
+
+fn tap(keycode: u8) -> [MacroStep; 2] {
+    [Press(keycode), Release(keycode)]
+}
+
+fn shifted(keycode: u8) -> [MacroStep; 4] {
+    [
+        Press(LSHIFT),
+        Press(keycode),
+        Release(keycode),
+        Release(LSHIFT),
+    ]
+}
+
+// Every step of typing `text`
+fn type_out(text: &str, layout: Layout, method: UnicodeMethod) -> Vec<MacroStep> {
+    let mut typist = Typist::new();
+    assert!(typist.start(&from_str(text).unwrap(), layout, method));
+    let mut steps = Vec::new();
+    while let Some(step) = typist.next_step() {
+        steps.push(step);
+        assert!(steps.len() < 1000, "text never finished");
+    }
+    assert!(!typist.is_typing());
+    steps
+}
+
+#[test]
+fn ascii_is_typed_on_its_key() {
+    assert_eq!(
+        char_steps('!', Layout::Us, UnicodeMethod::Linux)[..],
+        shifted(0x1E)
+    );
+    assert_eq!(
+        type_out("Hi", Layout::Us, UnicodeMethod::Linux),
+        [&shifted(0x0B)[..], &tap(0x0C)].concat()
+    );
+}
+
+#[test]
+fn characters_are_typed_for_the_hosts_layout() {
+    // Z is where US has Y, @ takes AltGr, ß has its own key
+    let expected = [
+        &tap(0x1C)[..],
+        &[Press(RALT), Press(0x14), Release(0x14), Release(RALT)],
+        &tap(0x2D),
+    ]
+    .concat();
+    assert_eq!(type_out("z@ß", Layout::De, UnicodeMethod::Linux), expected);
+
+    // The AZERTY number row needs Shift for digits
+    assert_eq!(
+        char_steps('1', Layout::Fr, UnicodeMethod::Linux)[..],
+        shifted(0x1E)
+    );
+    // A dead key on the layout goes through the input method
+    assert_eq!(
+        char_steps('^', Layout::De, UnicodeMethod::Linux)[..3],
+        [Press(LCTRL), Press(LSHIFT), Press(0x18)]
+    );
+}
+
+#[test]
+fn code_points_use_the_layouts_letters() {
+    // é is U+E9, Dvorak has U on F and E on D
+    let expected = [
+        &[Press(LCTRL), Press(LSHIFT), Press(0x09)][..],
+        &[Release(0x09), Release(LSHIFT), Release(LCTRL)],
+        &tap(0x07),
+        &tap(0x26),
+        &tap(0x2C),
+    ]
+    .concat();
+    assert_eq!(
+        char_steps('é', Layout::Dvorak, UnicodeMethod::Linux)[..],
+        expected
+    );
+}
+
+#[test]
+fn linux_enters_code_points_after_ctrl_shift_u() {
+    // é is U+E9
+    let expected = [
+        &[Press(LCTRL), Press(LSHIFT), Press(0x18)][..],
+        &[Release(0x18), Release(LSHIFT), Release(LCTRL)],
+        &tap(0x08),
+        &tap(0x26),
+        &tap(0x2C),
+    ]
+    .concat();
+    assert_eq!(
+        char_steps('é', Layout::Us, UnicodeMethod::Linux)[..],
+        expected
+    );
+}
+
+#[test]
+fn windows_holds_alt_over_the_keypad() {
+    // € is U+20AC, digits on the keypad and letters on the letter keys
+    let expected = [
+        &[Press(LALT)][..],
+        &tap(0x57),
+        &tap(0x5A),
+        &tap(0x62),
+        &tap(0x04),
+        &tap(0x06),
+        &[Release(LALT)],
+    ]
+    .concat();
+    assert_eq!(
+        char_steps('€', Layout::Us, UnicodeMethod::Windows)[..],
+        expected
+    );
+}
+
+#[test]
+fn wincompose_types_u_and_the_code_point_after_compose() {
+    // 😀 is U+1F600, past the 16 bit range
+    let expected = [
+        &tap(RALT)[..],
+        &tap(0x18),
+        &tap(0x1E),
+        &tap(0x09),
+        &tap(0x23),
+        &tap(0x27),
+        &tap(0x27),
+        &tap(0x28),
+    ]
+    .concat();
+    assert_eq!(
+        char_steps('😀', Layout::Us, UnicodeMethod::WinCompose)[..],
+        expected
+    );
+}
+
+#[test]
+fn typist_mixes_keys_and_code_points_and_stops() {
+    let steps = type_out("aé b", Layout::Us, UnicodeMethod::Linux);
+    let expected = [
+        &tap(0x04)[..],
+        &char_steps('é', Layout::Us, UnicodeMethod::Linux),
+        &tap(0x2C),
+        &tap(0x05),
+    ]
+    .concat();
+    assert_eq!(steps, expected);
+
+    // Nothing is left held
+    let held = steps.iter().fold(0i32, |held, step| match step {
+        Press(_) => held + 1,
+        Release(_) => held - 1,
+        MacroStep::Media(_) => held,
+    });
+    assert_eq!(held, 0);
+    assert!(type_out("", Layout::Us, UnicodeMethod::Windows).is_empty());
+}
+
+#[test]
+fn typist_carries_on_with_the_current_text() {
+    let mut typist = Typist::new();
+    assert!(typist.start(&from_str("ab").unwrap(), Layout::Us, UnicodeMethod::Linux));
+    assert_eq!(typist.next_step(), Some(Press(0x04)));
+    assert!(!typist.start(&from_str("c").unwrap(), Layout::Us, UnicodeMethod::Linux));
+    assert_eq!(typist.next_step(), Some(Release(0x04)));
+    assert_eq!(typist.next_step(), Some(Press(0x05)));
+}
+
+#[test]
+fn texts_go_over_the_protocol_as_utf8_hex() {
+    let text = parse_hex(b"68c3a9").unwrap();
+    assert_eq!(as_str(&text), Some("hé"));
+    assert_eq!(Hex(&text).to_string(), "68c3a9");
+    assert_eq!(Hex(&parse_hex(b"none").unwrap()).to_string(), "none");
+
+    // Cut off in the middle of é
+    assert_eq!(parse_hex(b"68c3"), Err(ErrorCode::BadArgument));
+    assert_eq!(parse_hex(b"6"), Err(ErrorCode::BadArgument));
+    assert_eq!(
+        parse_hex("61".repeat(TEXT_LEN + 1).as_bytes()),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(
+        from_str(&"a".repeat(TEXT_LEN + 1)),
+        Err(ErrorCode::OutOfRange)
+    );
+    assert_eq!(from_str("a\0b"), Err(ErrorCode::BadArgument));
+}
@@ -0,0 +1,80 @@
+// Backups are plain request lines, a `map` per button and layer, a `combo` per
+// slot, the `combo-window` and a `macro` per slot, so they can be read and edited
+// by hand and are checked with the same parser the firmware uses. `set` lines from backups taken
+// before layers existed are still accepted.
+
+use std::io::{BufRead, Write};
+
+use pideck_core::action::Action;
+use pideck_core::combo::ComboConfig;
+use pideck_core::constants::LAYER_COUNT;
+use pideck_core::macro_player::MacroCode;
+use pideck_core::protocol::{self, Command, ErrorCode};
+
+use crate::client::{Error, Result};
+
+pub fn write_backup(
+    keymap: &[[Action; LAYER_COUNT]],
+    combos: &ComboConfig,
+    macros: &[MacroCode],
+    mut out: impl Write,
+) -> Result<()> {
+    writeln!(out, "# pideck key config backup")?;
+    for layer in 0..LAYER_COUNT {
+        for (index, actions) in keymap.iter().enumerate() {
+            let command = Command::Map {
+                layer: layer as u8,
+                button: index as u8 + 1,
+                action: Some(actions[layer]),
+            };
+            write!(out, "{}", command)?;
+        }
+    }
+    for (slot, combo) in combos.combos.iter().enumerate() {
+        let command = Command::Combo {
+            slot: slot as u8,
+            combo: Some(*combo),
+        };
+        write!(out, "{}", command)?;
+    }
+    let window_ms = (combos.window_us / 1000) as u16;
+    write!(out, "{}", Command::ComboWindow(Some(window_ms)))?;
+    for (slot, code) in macros.iter().enumerate() {
+        let command = Command::Macro {
+            slot: slot as u8,
+            code: Some(*code),
+        };
+        write!(out, "{}", command)?;
+    }
+    Ok(())
+}
+
+/// Read a backup back as the `set`, `map`, `combo`, `combo-window` and `macro`
+/// commands to replay.
+/// Blank lines and `#` comments are skipped.
+pub fn read_backup(input: impl BufRead, button_count: u8) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let backup_error = |code| Error::Backup {
+            line: index + 1,
+            code,
+        };
+        match protocol::parse(line.as_bytes(), button_count).map_err(backup_error)? {
+            command @ (Command::Set { .. }
+            | Command::Map {
+                action: Some(_), ..
+            }
+            | Command::Combo { combo: Some(_), .. }
+            | Command::ComboWindow(Some(_))
+            | Command::Macro { code: Some(_), .. }) => commands.push(command),
+            _ => return Err(backup_error(ErrorCode::UnknownCommand)),
+        }
+    }
+    Ok(commands)
+}
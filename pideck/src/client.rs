@@ -0,0 +1,517 @@
+// Typed requests to a deck on top of a `Transport`.
+
+use std::fmt;
+use std::io;
+
+use pideck_core::action::Action;
+use pideck_core::combo::{Combo, ComboConfig};
+use pideck_core::constants::LAYER_COUNT;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gesture::{Gesture, GestureTiming};
+use pideck_core::label::Label;
+use pideck_core::layout::Layout;
+use pideck_core::lock::{Lock, LockLeds};
+use pideck_core::macro_player::{MacroCode, MAX_MACROS};
+use pideck_core::profile::ProfileName;
+use pideck_core::protocol::{self, Command, ErrorCode, Response};
+use pideck_core::text::{Text, UnicodeMethod, MAX_TEXTS};
+
+use crate::transport::Transport;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The deck answered with `ERR`
+    Device(ErrorCode),
+    // The deck answered with something that is not a reply to the request
+    BadReply(String),
+    NotFound,
+    Backup { line: usize, code: ErrorCode },
+    Rules { line: usize, code: ErrorCode },
+    // Asking the desktop for the focused window failed, the deck is fine
+    Window(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::Device(code) => write!(f, "deck error {}: {}", code.code(), code.message()),
+            Error::BadReply(line) => write!(f, "unexpected reply from deck: {:?}", line),
+            Error::NotFound => f.write_str("no deck found, pass --port"),
+            Error::Backup { line, code } => {
+                write!(f, "backup line {}: {}", line, code.message())
+            }
+            Error::Rules { line, code } => {
+                write!(f, "rules line {}: {}", line, code.message())
+            }
+            Error::Window(err) => write!(f, "focused window: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(err: serialport::Error) -> Self {
+        Error::Io(err.into())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub struct Deck<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Deck<T> {
+    pub fn new(transport: T) -> Self {
+        Deck { transport }
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    // Sends `command` and hands its reply to `accept`, which picks out the
+    // expected variant. `ERR` replies and anything unexpected become errors.
+    fn request<R>(
+        &mut self,
+        command: Command,
+        accept: impl FnOnce(Response) -> Option<R>,
+    ) -> Result<R> {
+        let line = self.transport.transact(&command.to_string())?;
+        let accepted = match protocol::parse_response(&command, &line) {
+            Some(Response::Err(code)) => return Err(Error::Device(code)),
+            Some(response) => accept(response),
+            None => None,
+        };
+        accepted.ok_or(Error::BadReply(line))
+    }
+
+    pub fn version(&mut self) -> Result<String> {
+        self.request(Command::Version, |response| match response {
+            Response::Version(version) => Some(version.to_string()),
+            _ => None,
+        })
+    }
+
+    pub fn get(&mut self, button: u8) -> Result<(u8, u16)> {
+        self.request(Command::Get { button }, |response| match response {
+            Response::Key {
+                keycode, media_key, ..
+            } => Some((keycode, media_key)),
+            _ => None,
+        })
+    }
+
+    pub fn set(&mut self, button: u8, keys: (u8, u16)) -> Result<()> {
+        let command = Command::Set {
+            button,
+            keycode: keys.0,
+            media_key: keys.1,
+        };
+        self.request(command, |response| match response {
+            Response::Ok => Some(()),
+            _ => None,
+        })
+    }
+
+    /// Pressed state of every button, which also tells us how many there are.
+    pub fn state(&mut self) -> Result<Vec<bool>> {
+        self.request(Command::State, |response| match response {
+            Response::State { pressed, count } => Some(
+                (0..count)
+                    .map(|index| pressed & (1 << index) != 0)
+                    .collect(),
+            ),
+            _ => None,
+        })
+    }
+
+    /// Action of every button on every layer, in button order.
+    pub fn list(&mut self) -> Result<Vec<[Action; LAYER_COUNT]>> {
+        let count = self.state()?.len() as u8;
+        let mut keymap = Vec::new();
+        for button in 1..=count {
+            let mut actions = [Action::None; LAYER_COUNT];
+            for (layer, action) in actions.iter_mut().enumerate() {
+                *action = self.map(layer as u8, button)?;
+            }
+            keymap.push(actions);
+        }
+        Ok(keymap)
+    }
+
+    pub fn map(&mut self, layer: u8, button: u8) -> Result<Action> {
+        self.remap(layer, button, None)
+    }
+
+    pub fn set_map(&mut self, layer: u8, button: u8, action: Action) -> Result<Action> {
+        self.remap(layer, button, Some(action))
+    }
+
+    fn remap(&mut self, layer: u8, button: u8, action: Option<Action>) -> Result<Action> {
+        let command = Command::Map {
+            layer,
+            button,
+            action,
+        };
+        self.request(command, |response| match response {
+            Response::Map {
+                layer: replied_layer,
+                button: replied,
+                action,
+            } if replied_layer == layer && replied == button => Some(action),
+            _ => None,
+        })
+    }
+
+    pub fn label(&mut self, layer: u8, button: u8) -> Result<Label> {
+        self.relabel(layer, button, None)
+    }
+
+    pub fn set_label(&mut self, layer: u8, button: u8, label: Label) -> Result<Label> {
+        self.relabel(layer, button, Some(label))
+    }
+
+    fn relabel(&mut self, layer: u8, button: u8, label: Option<Label>) -> Result<Label> {
+        let command = Command::Label {
+            layer,
+            button,
+            label,
+        };
+        self.request(command, |response| match response {
+            Response::Label {
+                layer: replied_layer,
+                button: replied,
+                label,
+            } if replied_layer == layer && replied == button => Some(label),
+            _ => None,
+        })
+    }
+
+    /// Active layers, bottom to top.
+    pub fn layers(&mut self) -> Result<Vec<u8>> {
+        self.switch_layer(None)
+    }
+
+    pub fn set_layer(&mut self, layer: u8, active: bool) -> Result<Vec<u8>> {
+        self.switch_layer(Some((layer, active)))
+    }
+
+    fn switch_layer(&mut self, change: Option<(u8, bool)>) -> Result<Vec<u8>> {
+        self.request(Command::Layer(change), |response| match response {
+            Response::Layers { active, count } => Some(active[..count as usize].to_vec()),
+            _ => None,
+        })
+    }
+
+    pub fn debounce(&mut self, button: u8) -> Result<DebounceStrategy> {
+        self.switch_debounce(button, None)
+    }
+
+    pub fn set_debounce(
+        &mut self,
+        button: u8,
+        strategy: DebounceStrategy,
+    ) -> Result<DebounceStrategy> {
+        self.switch_debounce(button, Some(strategy))
+    }
+
+    fn switch_debounce(
+        &mut self,
+        button: u8,
+        strategy: Option<DebounceStrategy>,
+    ) -> Result<DebounceStrategy> {
+        self.request(
+            Command::Debounce { button, strategy },
+            |response| match response {
+                Response::Debounce {
+                    button: replied,
+                    strategy,
+                } if replied == button => Some(strategy),
+                _ => None,
+            },
+        )
+    }
+
+    /// Action bound to `gesture` on `button`, `Action::None` when unbound.
+    pub fn gesture(&mut self, button: u8, gesture: Gesture) -> Result<Action> {
+        self.bind_gesture(button, gesture, None)
+    }
+
+    pub fn set_gesture(&mut self, button: u8, gesture: Gesture, action: Action) -> Result<Action> {
+        self.bind_gesture(button, gesture, Some(action))
+    }
+
+    fn bind_gesture(
+        &mut self,
+        button: u8,
+        gesture: Gesture,
+        action: Option<Action>,
+    ) -> Result<Action> {
+        let command = Command::Gesture {
+            button,
+            gesture,
+            action,
+        };
+        self.request(command, |response| match response {
+            Response::Gesture {
+                button: replied,
+                gesture: replied_gesture,
+                action,
+            } if replied == button && replied_gesture == gesture => Some(action),
+            _ => None,
+        })
+    }
+
+    pub fn timing(&mut self, button: u8) -> Result<GestureTiming> {
+        self.switch_timing(button, None)
+    }
+
+    pub fn set_timing(&mut self, button: u8, timing: GestureTiming) -> Result<GestureTiming> {
+        self.switch_timing(button, Some(timing))
+    }
+
+    fn switch_timing(
+        &mut self,
+        button: u8,
+        timing: Option<GestureTiming>,
+    ) -> Result<GestureTiming> {
+        self.request(
+            Command::Timing { button, timing },
+            |response| match response {
+                Response::Timing {
+                    button: replied,
+                    timing,
+                } if replied == button => Some(timing),
+                _ => None,
+            },
+        )
+    }
+
+    pub fn combo(&mut self, slot: u8) -> Result<Combo> {
+        self.switch_combo(slot, None)
+    }
+
+    pub fn set_combo(&mut self, slot: u8, combo: Combo) -> Result<Combo> {
+        self.switch_combo(slot, Some(combo))
+    }
+
+    fn switch_combo(&mut self, slot: u8, combo: Option<Combo>) -> Result<Combo> {
+        self.request(Command::Combo { slot, combo }, |response| match response {
+            Response::Combo {
+                slot: replied,
+                combo,
+            } if replied == slot => Some(combo),
+            _ => None,
+        })
+    }
+
+    /// Every combo slot, unused ones included, and the combo window.
+    pub fn combos(&mut self) -> Result<ComboConfig> {
+        let mut config = ComboConfig {
+            window_us: self.combo_window()? as u32 * 1000,
+            ..ComboConfig::default()
+        };
+        for (slot, combo) in config.combos.iter_mut().enumerate() {
+            *combo = self.combo(slot as u8)?;
+        }
+        Ok(config)
+    }
+
+    /// Combo window in ms.
+    pub fn combo_window(&mut self) -> Result<u16> {
+        self.switch_combo_window(None)
+    }
+
+    pub fn set_combo_window(&mut self, ms: u16) -> Result<u16> {
+        self.switch_combo_window(Some(ms))
+    }
+
+    fn switch_combo_window(&mut self, ms: Option<u16>) -> Result<u16> {
+        self.request(Command::ComboWindow(ms), |response| match response {
+            Response::ComboWindow(ms) => Some(ms),
+            _ => None,
+        })
+    }
+
+    /// Bytecode in macro slot `slot`, all zeroes when empty.
+    pub fn macro_code(&mut self, slot: u8) -> Result<MacroCode> {
+        self.switch_macro(slot, None)
+    }
+
+    pub fn set_macro(&mut self, slot: u8, code: MacroCode) -> Result<MacroCode> {
+        self.switch_macro(slot, Some(code))
+    }
+
+    fn switch_macro(&mut self, slot: u8, code: Option<MacroCode>) -> Result<MacroCode> {
+        self.request(Command::Macro { slot, code }, |response| match response {
+            Response::Macro {
+                slot: replied,
+                code,
+            } if replied == slot => Some(code),
+            _ => None,
+        })
+    }
+
+    /// Every macro slot, empty ones included.
+    pub fn macros(&mut self) -> Result<Vec<MacroCode>> {
+        (0..MAX_MACROS as u8)
+            .map(|slot| self.macro_code(slot))
+            .collect()
+    }
+
+    /// Lock LEDs the host has lit.
+    pub fn leds(&mut self) -> Result<LockLeds> {
+        self.request(Command::Leds, |response| match response {
+            Response::Leds(leds) => Some(leds),
+            _ => None,
+        })
+    }
+
+    pub fn lock_layer(&mut self, lock: Lock) -> Result<Option<u8>> {
+        self.switch_lock_layer(lock, None)
+    }
+
+    pub fn set_lock_layer(&mut self, lock: Lock, layer: Option<u8>) -> Result<Option<u8>> {
+        self.switch_lock_layer(lock, Some(layer))
+    }
+
+    fn switch_lock_layer(&mut self, lock: Lock, layer: Option<Option<u8>>) -> Result<Option<u8>> {
+        self.request(
+            Command::LockLayer { lock, layer },
+            |response| match response {
+                Response::LockLayer {
+                    lock: replied,
+                    layer,
+                } if replied == lock => Some(layer),
+                _ => None,
+            },
+        )
+    }
+
+    /// Text in slot `slot`, all zeroes when empty.
+    pub fn text(&mut self, slot: u8) -> Result<Text> {
+        self.switch_text(slot, None)
+    }
+
+    pub fn set_text(&mut self, slot: u8, text: Text) -> Result<Text> {
+        self.switch_text(slot, Some(text))
+    }
+
+    fn switch_text(&mut self, slot: u8, text: Option<Text>) -> Result<Text> {
+        self.request(Command::Text { slot, text }, |response| match response {
+            Response::Text {
+                slot: replied,
+                text,
+            } if replied == slot => Some(text),
+            _ => None,
+        })
+    }
+
+    /// Every text slot, empty ones included.
+    pub fn texts(&mut self) -> Result<Vec<Text>> {
+        (0..MAX_TEXTS as u8).map(|slot| self.text(slot)).collect()
+    }
+
+    /// How characters without a key are typed.
+    pub fn unicode_method(&mut self) -> Result<UnicodeMethod> {
+        self.switch_unicode_method(None)
+    }
+
+    pub fn set_unicode_method(&mut self, method: UnicodeMethod) -> Result<UnicodeMethod> {
+        self.switch_unicode_method(Some(method))
+    }
+
+    fn switch_unicode_method(&mut self, method: Option<UnicodeMethod>) -> Result<UnicodeMethod> {
+        self.request(Command::Unicode(method), |response| match response {
+            Response::Unicode(method) => Some(method),
+            _ => None,
+        })
+    }
+
+    /// The host keyboard layout texts are typed for.
+    pub fn layout(&mut self) -> Result<Layout> {
+        self.switch_layout(None)
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) -> Result<Layout> {
+        self.switch_layout(Some(layout))
+    }
+
+    fn switch_layout(&mut self, layout: Option<Layout>) -> Result<Layout> {
+        self.request(Command::Layout(layout), |response| match response {
+            Response::Layout(layout) => Some(layout),
+            _ => None,
+        })
+    }
+
+    /// Number and name of the active profile.
+    pub fn profile(&mut self) -> Result<(u8, ProfileName)> {
+        self.switch_profile(None)
+    }
+
+    /// Switch to another profile, the deck then answers every other request for it.
+    pub fn select_profile(&mut self, profile: u8) -> Result<(u8, ProfileName)> {
+        self.switch_profile(Some(profile))
+    }
+
+    /// Switch to the profile named `name`, fails with `UnknownProfile` if the deck
+    /// has none by that name.
+    pub fn select_profile_named(&mut self, name: ProfileName) -> Result<(u8, ProfileName)> {
+        self.request(Command::SelectProfile(name), |response| match response {
+            Response::Profile {
+                profile,
+                name: replied,
+            } if replied == name => Some((profile, name)),
+            _ => None,
+        })
+    }
+
+    fn switch_profile(&mut self, profile: Option<u8>) -> Result<(u8, ProfileName)> {
+        self.request(Command::Profile(profile), |response| match response {
+            Response::Profile { profile, name } => Some((profile, name)),
+            _ => None,
+        })
+    }
+
+    pub fn profile_name(&mut self, profile: u8) -> Result<ProfileName> {
+        self.switch_profile_name(profile, None)
+    }
+
+    pub fn set_profile_name(&mut self, profile: u8, name: ProfileName) -> Result<ProfileName> {
+        self.switch_profile_name(profile, Some(name))
+    }
+
+    fn switch_profile_name(
+        &mut self,
+        profile: u8,
+        name: Option<ProfileName>,
+    ) -> Result<ProfileName> {
+        self.request(
+            Command::ProfileName { profile, name },
+            |response| match response {
+                Response::Profile {
+                    profile: replied,
+                    name,
+                } if replied == profile => Some(name),
+                _ => None,
+            },
+        )
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        self.request(Command::Save, |response| match response {
+            Response::Ok => Some(()),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,154 @@
+// Switches the deck's profile to follow the focused application. Rules are read
+// from a text file, one per line, the profile name first and then what it
+// matches:
+//
+//   # profile  match
+//   Zoom        class zoom
+//   IDE         class code
+//   IDE         title - Visual Studio Code
+//   Web         default
+//
+// `class` is compared with the whole window class, `title` looked for anywhere in
+// the title, both ignoring case. The first rule that matches wins, `default`
+// matches any window and so belongs last. When nothing matches the deck keeps
+// the profile it has.
+//
+// The deck is only told when the profile the rules pick changes, so switching by
+// hand on the deck sticks until another application gets the focus.
+
+use std::io::BufRead;
+
+use pideck_core::profile::{self, ProfileName};
+use pideck_core::protocol::ErrorCode;
+
+use crate::client::{Deck, Error, Result};
+use crate::transport::Transport;
+use crate::window::{FocusedWindow, WindowSource};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Match {
+    Class(String),
+    Title(String),
+    Default,
+}
+
+impl Match {
+    pub fn matches(&self, window: &FocusedWindow) -> bool {
+        match self {
+            Match::Class(class) => window.class.eq_ignore_ascii_case(class),
+            Match::Title(text) => window.title.to_lowercase().contains(&text.to_lowercase()),
+            Match::Default => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub profile: ProfileName,
+    pub matches: Match,
+}
+
+/// Read a rules file. Blank lines and `#` comments are skipped.
+pub fn read_rules(input: impl BufRead) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let rule = parse_rule(line).map_err(|code| Error::Rules {
+            line: index + 1,
+            code,
+        })?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+fn parse_rule(line: &str) -> std::result::Result<Rule, ErrorCode> {
+    // Words may be lined up with any run of spaces, the match text is the rest of
+    // the line
+    let (profile, rest) = split_word(line);
+    let profile = profile::parse_name(profile.as_bytes())?;
+    if profile::name_str(&profile).is_empty() {
+        return Err(ErrorCode::BadArgument);
+    }
+    let matches = match split_word(rest) {
+        ("default", "") => Match::Default,
+        ("class", class) if !class.is_empty() => Match::Class(class.to_string()),
+        ("title", text) if !text.is_empty() => Match::Title(text.to_string()),
+        _ => return Err(ErrorCode::BadArgument),
+    };
+    Ok(Rule { profile, matches })
+}
+
+// The first word of `text` and the rest, both trimmed
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Profile the rules pick for `window`, `None` if no rule matches or nothing has
+/// the focus.
+pub fn pick(rules: &[Rule], window: Option<&FocusedWindow>) -> Option<ProfileName> {
+    let window = window?;
+    rules
+        .iter()
+        .find(|rule| rule.matches.matches(window))
+        .map(|rule| rule.profile)
+}
+
+/// Keeps the deck on the profile the rules pick for the focused window.
+pub struct Switcher {
+    rules: Vec<Rule>,
+    // What the deck was last told, whether or not it had that profile
+    picked: Option<ProfileName>,
+}
+
+impl Switcher {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Switcher {
+            rules,
+            picked: None,
+        }
+    }
+
+    /// Look at the focused window once and switch the deck if the rules now pick
+    /// another profile. Returns the profile switched to.
+    ///
+    /// A profile the deck has no such name for fails with
+    /// `Error::Device(ErrorCode::UnknownProfile)` once, and is not asked for again
+    /// until the rules have picked something else in between. Any other failure
+    /// is tried again on the next poll. The window source failing is
+    /// `Error::Window`, which leaves the deck alone.
+    pub fn poll<S, T>(
+        &mut self,
+        source: &mut S,
+        deck: &mut Deck<T>,
+    ) -> Result<Option<(u8, ProfileName)>>
+    where
+        S: WindowSource + ?Sized,
+        T: Transport,
+    {
+        let window = source.focused().map_err(Error::Window)?;
+        let picked = match pick(&self.rules, window.as_ref()) {
+            Some(picked) if Some(picked) != self.picked => picked,
+            _ => return Ok(None),
+        };
+        match deck.select_profile_named(picked) {
+            Ok(selected) => {
+                self.picked = Some(picked);
+                Ok(Some(selected))
+            }
+            Err(err @ Error::Device(ErrorCode::UnknownProfile)) => {
+                self.picked = Some(picked);
+                Err(err)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
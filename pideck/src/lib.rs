@@ -0,0 +1,5 @@
+pub mod backup;
+pub mod client;
+pub mod daemon;
+pub mod transport;
+pub mod window;
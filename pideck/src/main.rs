@@ -0,0 +1,883 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use pideck_core::action::Action;
+use pideck_core::combo::Combo;
+use pideck_core::constants::LAYER_COUNT;
+use pideck_core::consumer;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gamepad;
+use pideck_core::gesture::{Gesture, GestureTiming};
+use pideck_core::label::{self, Label};
+use pideck_core::layer;
+use pideck_core::layout::Layout;
+use pideck_core::lock::Lock;
+use pideck_core::macro_player::{self, Disassembly};
+use pideck_core::modifier;
+use pideck_core::mouse::MouseAction;
+use pideck_core::profile::{self, Name, ProfileName, MAX_PROFILES};
+use pideck_core::protocol::{self, Command, ErrorCode};
+use pideck_core::system_control;
+use pideck_core::text::{self, UnicodeMethod};
+
+use pideck::backup;
+use pideck::client::{Deck, Error, Result};
+use pideck::daemon::{self, Switcher};
+use pideck::transport::{self, HidTransport, SerialTransport, Transport};
+use pideck::window::{self, WindowSource};
+
+const ACTION_NAMES: [&str; 15] = [
+    "none",
+    "trans",
+    "key",
+    "shortcut",
+    "media",
+    "system",
+    "mouse",
+    "gamepad",
+    "momentary",
+    "toggle",
+    "oneshot",
+    "bootloader",
+    "macro",
+    "text",
+    "profile",
+];
+
+// Every action but none, trans and bootloader needs a code
+const ACTIONS_WITH_CODE: [(&str, &str); 12] = [
+    ("action", "key"),
+    ("action", "shortcut"),
+    ("action", "media"),
+    ("action", "system"),
+    ("action", "mouse"),
+    ("action", "gamepad"),
+    ("action", "momentary"),
+    ("action", "toggle"),
+    ("action", "oneshot"),
+    ("action", "macro"),
+    ("action", "text"),
+    ("action", "profile"),
+];
+
+/// Configure a Pi Deck Pico over its serial port
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Serial port of the deck, found by USB VID/PID when left out. The hidraw
+    /// device with --hid
+    #[arg(short, long, global = true)]
+    port: Option<String>,
+
+    /// Talk to the deck over its raw HID interface instead of serial
+    #[arg(long, global = true)]
+    hid: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List serial ports and hidraw devices that look like a deck
+    Ports,
+    /// Print the firmware version
+    Version,
+    /// Print the action of every button on every layer
+    List,
+    /// Print the base layer keycode and media layer media key of one button
+    Get {
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+    },
+    /// Change the base layer keycode and media layer media key of one button
+    Set {
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        #[arg(value_parser = parse_byte)]
+        keycode: u8,
+        /// Consumer page usage, a number or a name like play-pause
+        #[arg(value_parser = parse_usage)]
+        media_key: u16,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change the action of one button on one layer
+    Map {
+        #[arg(value_parser = parse_byte)]
+        layer: u8,
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        #[arg(value_parser = ACTION_NAMES)]
+        action: Option<String>,
+        /// Keycode, media key, system usage, mouse action, gamepad button, layer,
+        /// macro slot, text slot or profile the action needs. Media keys and system
+        /// usages can also be given by name, e.g. calculator or sleep, and profile
+        /// takes next for the one after the active profile
+        #[arg(required_if_eq_any = ACTIONS_WITH_CODE)]
+        code: Option<String>,
+        /// Modifiers a shortcut holds with its key, e.g. ctrl+alt
+        #[arg(long, value_parser = parse_modifiers, required_if_eq("action", "shortcut"))]
+        modifiers: Option<u8>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change what the display shows on one button on one layer
+    Label {
+        #[arg(value_parser = parse_byte)]
+        layer: u8,
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        /// Up to 7 characters without spaces, or none to show the action
+        #[arg(value_parser = parse_label)]
+        label: Option<Label>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Turn a layer into a mouse: movement on 2, 4, 5 and 6, clicks on 1 and 3
+    MouseLayer {
+        #[arg(value_parser = parse_byte)]
+        layer: u8,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print the active layers, or switch one on or off
+    Layer {
+        #[arg(value_parser = parse_byte, requires = "state")]
+        layer: Option<u8>,
+        #[arg(value_parser = ["on", "off"])]
+        state: Option<String>,
+    },
+    /// Print or change the debounce strategy of one button
+    Debounce {
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        /// Algorithm to switch to
+        #[arg(value_parser = ["eager", "deferred", "integrator"], requires = "value")]
+        strategy: Option<String>,
+        /// Milliseconds for eager and deferred, samples for integrator
+        #[arg(value_parser = parse_byte)]
+        value: Option<u8>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or bind the action a gesture on one button sends
+    Gesture {
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        /// tap, double-tap, long-press or hold
+        #[arg(value_parser = parse_gesture)]
+        gesture: Gesture,
+        /// What to send, none unbinds the gesture
+        #[arg(value_parser = ACTION_NAMES)]
+        action: Option<String>,
+        /// Keycode, media key, system usage, mouse action, gamepad button, layer,
+        /// macro slot, text slot or profile the action needs. Media keys and system
+        /// usages can also be given by name, e.g. calculator or sleep, and profile
+        /// takes next for the one after the active profile
+        #[arg(required_if_eq_any = ACTIONS_WITH_CODE)]
+        code: Option<String>,
+        /// Modifiers a shortcut holds with its key, e.g. ctrl+alt
+        #[arg(long, value_parser = parse_modifiers, required_if_eq("action", "shortcut"))]
+        modifiers: Option<u8>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change the gesture thresholds of one button, in ms
+    Timing {
+        #[arg(value_parser = parse_byte)]
+        button: u8,
+        /// Longest gap between the presses of a double tap
+        #[arg(requires_all = ["long_press", "hold"])]
+        double_tap: Option<u16>,
+        /// Shortest press that counts as a long press
+        long_press: Option<u16>,
+        /// How long a press lasts before hold starts
+        hold: Option<u16>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print every combo slot, or change one
+    Combo {
+        /// Slot to change, from 0
+        #[arg(value_parser = parse_byte, requires = "buttons")]
+        slot: Option<u8>,
+        /// Buttons joined with +, e.g. 1+2, or none to clear the slot
+        #[arg(value_parser = parse_buttons, requires = "action")]
+        buttons: Option<u8>,
+        /// What the combo sends
+        #[arg(value_parser = ACTION_NAMES)]
+        action: Option<String>,
+        /// Keycode, media key, system usage, mouse action, gamepad button, layer,
+        /// macro slot, text slot or profile the action needs. Media keys and system
+        /// usages can also be given by name, e.g. calculator or sleep, and profile
+        /// takes next for the one after the active profile
+        #[arg(required_if_eq_any = ACTIONS_WITH_CODE)]
+        code: Option<String>,
+        /// Modifiers a shortcut holds with its key, e.g. ctrl+alt
+        #[arg(long, value_parser = parse_modifiers, required_if_eq("action", "shortcut"))]
+        modifiers: Option<u8>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change how close together the presses of a combo must be, in ms
+    ComboWindow {
+        ms: Option<u16>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print every macro, or print or change one
+    Macro {
+        /// Slot to print or change, from 0
+        #[arg(value_parser = parse_byte)]
+        slot: Option<u8>,
+        /// Steps to play, e.g. `press 0xe0 tap 0x06 release-all`, or none to clear
+        /// the slot. Steps are press, release and tap with a keycode, media with a
+        /// media key, wait with ms, and release-all.
+        #[arg(requires = "slot", num_args = 1..)]
+        steps: Vec<String>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print every text, or print or change one
+    Text {
+        /// Slot to print or change, from 0
+        #[arg(value_parser = parse_byte)]
+        slot: Option<u8>,
+        /// What a text action in the slot types, up to 32 bytes of UTF-8. An
+        /// empty string clears the slot
+        #[arg(requires = "slot")]
+        text: Option<String>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change how texts type characters no key types
+    Unicode {
+        /// linux (Ctrl+Shift+U), windows (Alt with keypad +) or wincompose
+        #[arg(value_parser = parse_unicode_method)]
+        method: Option<UnicodeMethod>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print or change the host keyboard layout texts are typed for
+    Layout {
+        /// us, uk, de, fr or dvorak
+        #[arg(value_parser = parse_layout)]
+        layout: Option<Layout>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Print every profile, or switch to one. The deck remembers the active
+    /// profile across power cycles, every other command reads or changes it
+    Profile {
+        /// Profile to switch to, from 0
+        #[arg(value_parser = parse_byte)]
+        profile: Option<u8>,
+    },
+    /// Print or change the name of a profile, shown on the display
+    ProfileName {
+        #[arg(value_parser = parse_byte)]
+        profile: u8,
+        /// Up to 12 characters without spaces, or none to clear it
+        #[arg(value_parser = parse_profile_name)]
+        name: Option<ProfileName>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Switch to the profile with this name
+    ProfileSelect {
+        #[arg(value_parser = parse_profile_name)]
+        name: ProfileName,
+    },
+    /// Keep running and switch profiles to follow the focused application, by the
+    /// rules in a file of `<profile> class <class>`, `<profile> title <text>` and
+    /// `<profile> default` lines
+    Daemon {
+        rules: String,
+        /// How to find the focused window: x11 (xprop, XWayland too) or hyprland
+        #[arg(long, value_parser = ["x11", "hyprland"], default_value = "x11")]
+        backend: String,
+        /// Shell command printing the focused window's class, and its title on a
+        /// second line, for desktops without a backend
+        #[arg(long, conflicts_with = "backend")]
+        command: Option<String>,
+        /// How often to look at the focused window, in ms
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+    },
+    /// Print the lock LEDs the host has lit
+    Leds,
+    /// Print or change the layer a lock switches on while it is lit
+    Lock {
+        /// caps, num or scroll
+        #[arg(value_parser = parse_lock)]
+        lock: Lock,
+        /// Layer to switch on, or none to unbind the lock
+        #[arg(value_parser = parse_lock_layer)]
+        layer: Option<LockLayerArg>,
+        /// Also write the config to flash
+        #[arg(long)]
+        save: bool,
+    },
+    /// Write the current config to flash
+    Save,
+    /// Write every button mapping, combo and macro of the active profile to a file
+    Backup { file: String },
+    /// Load button mappings, combos and macros from a backup file into the active
+    /// profile and save them to flash
+    Restore {
+        file: String,
+        /// Apply the backup without writing it to flash
+        #[arg(long)]
+        no_save: bool,
+    },
+}
+
+fn parse_byte(value: &str) -> std::result::Result<u8, String> {
+    protocol::parse_byte(value.as_bytes()).map_err(|code| code.message().to_string())
+}
+
+// Any button number is let through, the deck checks it against its own count
+fn parse_buttons(value: &str) -> std::result::Result<u8, String> {
+    protocol::parse_buttons(Some(value.as_bytes()), 8).map_err(|code| code.message().to_string())
+}
+
+fn parse_usage(value: &str) -> std::result::Result<u16, String> {
+    consumer::parse(value.as_bytes()).map_err(|code| code.message().to_string())
+}
+
+fn parse_modifiers(value: &str) -> std::result::Result<u8, String> {
+    modifier::parse(value.as_bytes())
+        .ok_or_else(|| "expected modifiers like ctrl+alt, from ctrl, shift, alt, gui, rctrl, rshift, ralt and rgui".to_string())
+}
+
+fn parse_gesture(value: &str) -> std::result::Result<Gesture, String> {
+    Gesture::parse(value.as_bytes())
+        .ok_or_else(|| "expected tap, double-tap, long-press or hold".to_string())
+}
+
+fn parse_unicode_method(value: &str) -> std::result::Result<UnicodeMethod, String> {
+    UnicodeMethod::parse(value.as_bytes())
+        .ok_or_else(|| "expected linux, windows or wincompose".to_string())
+}
+
+fn parse_layout(value: &str) -> std::result::Result<Layout, String> {
+    Layout::parse(value.as_bytes()).ok_or_else(|| "expected us, uk, de, fr or dvorak".to_string())
+}
+
+fn parse_profile_name(value: &str) -> std::result::Result<ProfileName, String> {
+    profile::parse_name(value.as_bytes()).map_err(|code| code.message().to_string())
+}
+
+fn parse_label(value: &str) -> std::result::Result<Label, String> {
+    label::parse_label(value.as_bytes()).map_err(|code| code.message().to_string())
+}
+
+fn parse_lock(value: &str) -> std::result::Result<Lock, String> {
+    Lock::parse(value.as_bytes()).ok_or_else(|| "expected caps, num or scroll".to_string())
+}
+
+// A layer or none, wrapped as clap takes a bare Option<Option<_>> to mean a flag
+// with an optional value
+#[derive(Clone)]
+struct LockLayerArg(Option<u8>);
+
+fn parse_lock_layer(value: &str) -> std::result::Result<LockLayerArg, String> {
+    match value {
+        "none" => Ok(LockLayerArg(None)),
+        value => parse_byte(value).map(|layer| LockLayerArg(Some(layer))),
+    }
+}
+
+// clap has already checked the name and that a code is there when needed. The
+// code is parsed here as what it can be depends on the action
+fn action_from_args(name: &str, code: Option<String>, modifiers: Option<u8>) -> Result<Action> {
+    let code = code.as_deref().map(str::as_bytes);
+    match (name, code, modifiers) {
+        ("shortcut", Some(keycode), Some(modifiers)) => {
+            let keycode = protocol::parse_byte(keycode).map_err(Error::Device)?;
+            return Ok(Action::Shortcut { modifiers, keycode });
+        }
+        ("media", Some(usage), _) => {
+            return consumer::parse(usage)
+                .map(Action::Media)
+                .map_err(Error::Device);
+        }
+        ("system", Some(usage), _) => {
+            return system_control::parse(usage)
+                .map(Action::System)
+                .map_err(Error::Device);
+        }
+        ("gamepad", Some(button), _) => {
+            return gamepad::parse(button)
+                .map(Action::Gamepad)
+                .map_err(Error::Device);
+        }
+        ("profile", Some(b"next"), _) => return Ok(Action::NextProfile),
+        ("mouse", Some(name), _) => {
+            return MouseAction::parse(name)
+                .map(Action::Mouse)
+                .ok_or(Error::Device(ErrorCode::BadArgument));
+        }
+        _ => {}
+    }
+    let code = match name {
+        "none" | "trans" | "bootloader" => None,
+        _ => code
+            .map(protocol::parse_byte)
+            .transpose()
+            .map_err(Error::Device)?,
+    };
+    Action::from_parts(name.as_bytes(), code).ok_or(Error::Device(ErrorCode::BadArgument))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Commands::Ports => list_ports(),
+        command if cli.hid => {
+            open_hid(cli.port).and_then(|transport| run(Deck::new(transport), command))
+        }
+        command => open(cli.port).and_then(|transport| run(Deck::new(transport), command)),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("pideck: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list_ports() -> Result<()> {
+    let serial = transport::find_deck()?;
+    let hid = transport::find_hid_deck()?;
+    if serial.is_none() && hid.is_none() {
+        return Err(Error::NotFound);
+    }
+    for port in serial.iter().chain(hid.iter()) {
+        println!("{}", port);
+    }
+    Ok(())
+}
+
+fn open(port: Option<String>) -> Result<SerialTransport> {
+    let port = match port {
+        Some(port) => port,
+        None => transport::find_deck()?.ok_or(Error::NotFound)?,
+    };
+    Ok(SerialTransport::open(&port)?)
+}
+
+fn open_hid(port: Option<String>) -> Result<HidTransport<File>> {
+    let port = match port {
+        Some(port) => port,
+        None => transport::find_hid_deck()?.ok_or(Error::NotFound)?,
+    };
+    Ok(HidTransport::open(&port)?)
+}
+
+fn run<T: Transport>(mut deck: Deck<T>, command: Commands) -> Result<()> {
+    match command {
+        Commands::Ports => unreachable!("handled before opening the deck"),
+        Commands::Version => println!("{}", deck.version()?),
+        Commands::List => {
+            for (index, actions) in deck.list()?.iter().enumerate() {
+                print_actions(index as u8 + 1, actions);
+            }
+        }
+        Commands::Get { button } => print_keys(button, deck.get(button)?),
+        Commands::Set {
+            button,
+            keycode,
+            media_key,
+            save,
+        } => {
+            deck.set(button, (keycode, media_key))?;
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Map {
+            layer,
+            button,
+            action,
+            code,
+            modifiers,
+            save,
+        } => {
+            let action = match action {
+                Some(name) => {
+                    deck.set_map(layer, button, action_from_args(&name, code, modifiers)?)?
+                }
+                None => deck.map(layer, button)?,
+            };
+            println!("{}  layer {}  {}", button, layer, action);
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Label {
+            layer,
+            button,
+            label,
+            save,
+        } => {
+            let label = match label {
+                Some(label) => deck.set_label(layer, button, label)?,
+                None => deck.label(layer, button)?,
+            };
+            println!("{}  layer {}  {}", button, layer, label::Word(&label));
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::MouseLayer { layer, save } => {
+            for (index, action) in layer::mouse_layer().values().enumerate() {
+                let button = index as u8 + 1;
+                let action = deck.set_map(layer, button, *action)?;
+                println!("{}  layer {}  {}", button, layer, action);
+            }
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Layer { layer, state } => {
+            let active = match (layer, state) {
+                (Some(layer), Some(state)) => deck.set_layer(layer, state == "on")?,
+                _ => deck.layers()?,
+            };
+            let active: Vec<String> = active.iter().map(u8::to_string).collect();
+            println!("{}", active.join(" "));
+        }
+        Commands::Debounce {
+            button,
+            strategy,
+            value,
+            save,
+        } => {
+            let strategy = match (strategy, value) {
+                (Some(name), Some(value)) => {
+                    // Same check the deck does, an integrator needs at least one sample
+                    let strategy = DebounceStrategy::from_parts(name.as_bytes(), value)
+                        .ok_or(Error::Device(ErrorCode::OutOfRange))?;
+                    deck.set_debounce(button, strategy)?
+                }
+                _ => deck.debounce(button)?,
+            };
+            print_debounce(button, strategy);
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Gesture {
+            button,
+            gesture,
+            action,
+            code,
+            modifiers,
+            save,
+        } => {
+            let action = match action {
+                Some(name) => {
+                    deck.set_gesture(button, gesture, action_from_args(&name, code, modifiers)?)?
+                }
+                None => deck.gesture(button, gesture)?,
+            };
+            println!("{}  {}  {}", button, gesture.name(), action);
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Timing {
+            button,
+            double_tap,
+            long_press,
+            hold,
+            save,
+        } => {
+            let timing = match (double_tap, long_press, hold) {
+                (Some(double_tap), Some(long_press), Some(hold)) => deck.set_timing(
+                    button,
+                    GestureTiming::from_ms([double_tap, long_press, hold]),
+                )?,
+                _ => deck.timing(button)?,
+            };
+            let [double_tap, long_press, hold] = timing.to_ms();
+            println!(
+                "{}  double-tap {} ms  long-press {} ms  hold {} ms",
+                button, double_tap, long_press, hold
+            );
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Combo {
+            slot,
+            buttons,
+            action,
+            code,
+            modifiers,
+            save,
+        } => {
+            match (slot, buttons, action) {
+                (Some(slot), Some(buttons), Some(name)) => {
+                    let combo = Combo {
+                        buttons,
+                        action: action_from_args(&name, code, modifiers)?,
+                    };
+                    println!("{}  {}", slot, deck.set_combo(slot, combo)?);
+                }
+                _ => {
+                    for (slot, combo) in deck.combos()?.combos.iter().enumerate() {
+                        println!("{}  {}", slot, combo);
+                    }
+                }
+            }
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::ComboWindow { ms, save } => {
+            let ms = match ms {
+                Some(ms) => deck.set_combo_window(ms)?,
+                None => deck.combo_window()?,
+            };
+            println!("{} ms", ms);
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Macro { slot, steps, save } => {
+            match slot {
+                Some(slot) if !steps.is_empty() => {
+                    let text = steps.join(" ");
+                    let code = macro_player::assemble(text.split_whitespace().map(str::as_bytes))
+                        .map_err(Error::Device)?;
+                    println!("{}  {}", slot, Disassembly(&deck.set_macro(slot, code)?));
+                }
+                Some(slot) => println!("{}  {}", slot, Disassembly(&deck.macro_code(slot)?)),
+                None => {
+                    for (slot, code) in deck.macros()?.iter().enumerate() {
+                        println!("{}  {}", slot, Disassembly(code));
+                    }
+                }
+            }
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Text { slot, text, save } => {
+            match (slot, text) {
+                (Some(slot), Some(text)) => {
+                    let text = text::from_str(&text).map_err(Error::Device)?;
+                    print_text(slot, &deck.set_text(slot, text)?);
+                }
+                (Some(slot), None) => print_text(slot, &deck.text(slot)?),
+                _ => {
+                    for (slot, text) in deck.texts()?.iter().enumerate() {
+                        print_text(slot as u8, text);
+                    }
+                }
+            }
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Unicode { method, save } => {
+            let method = match method {
+                Some(method) => deck.set_unicode_method(method)?,
+                None => deck.unicode_method()?,
+            };
+            println!("{}", method.name());
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Layout { layout, save } => {
+            let layout = match layout {
+                Some(layout) => deck.set_layout(layout)?,
+                None => deck.layout()?,
+            };
+            println!("{}", layout.name());
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Profile { profile } => match profile {
+            Some(profile) => {
+                let (profile, name) = deck.select_profile(profile)?;
+                println!("{}  {}", profile, Name(&name));
+            }
+            None => {
+                let (active, _) = deck.profile()?;
+                for profile in 0..MAX_PROFILES as u8 {
+                    let name = deck.profile_name(profile)?;
+                    let marker = if profile == active { "  active" } else { "" };
+                    println!("{}  {}{}", profile, Name(&name), marker);
+                }
+            }
+        },
+        Commands::ProfileName {
+            profile,
+            name,
+            save,
+        } => {
+            let name = match name {
+                Some(name) => deck.set_profile_name(profile, name)?,
+                None => deck.profile_name(profile)?,
+            };
+            println!("{}  {}", profile, Name(&name));
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::ProfileSelect { name } => {
+            let (profile, name) = deck.select_profile_named(name)?;
+            println!("{}  {}", profile, Name(&name));
+        }
+        Commands::Daemon {
+            rules,
+            backend,
+            command,
+            interval,
+        } => {
+            let rules = daemon::read_rules(BufReader::new(File::open(rules)?))?;
+            let mut source: Box<dyn WindowSource> = match (command, backend.as_str()) {
+                (Some(command), _) => Box::new(window::Shell(command)),
+                (None, "hyprland") => Box::new(window::Hyprland),
+                (None, _) => Box::new(window::X11),
+            };
+            let mut switcher = Switcher::new(rules);
+            loop {
+                match switcher.poll(source.as_mut(), &mut deck) {
+                    Ok(Some((profile, name))) => println!("{}  {}", profile, Name(&name)),
+                    Ok(None) => {}
+                    // A rule naming a profile the deck doesn't have is worth a
+                    // warning, not stopping for, and so is the desktop failing to
+                    // answer, e.g. the focused window closing while it is asked
+                    // about or the compositor not being up yet. The deck going
+                    // away ends the daemon
+                    Err(err @ (Error::Device(ErrorCode::UnknownProfile) | Error::Window(_))) => {
+                        eprintln!("pideck: {}", err)
+                    }
+                    Err(err) => return Err(err),
+                }
+                thread::sleep(Duration::from_millis(interval));
+            }
+        }
+        Commands::Leds => println!("{}", deck.leds()?),
+        Commands::Lock { lock, layer, save } => {
+            let layer = match layer {
+                Some(LockLayerArg(layer)) => deck.set_lock_layer(lock, layer)?,
+                None => deck.lock_layer(lock)?,
+            };
+            match layer {
+                Some(layer) => println!("{}  layer {}", lock.name(), layer),
+                None => println!("{}  none", lock.name()),
+            }
+            if save {
+                deck.save()?;
+            }
+        }
+        Commands::Save => deck.save()?,
+        Commands::Backup { file } => {
+            let keymap = deck.list()?;
+            let combos = deck.combos()?;
+            let macros = deck.macros()?;
+            backup::write_backup(&keymap, &combos, &macros, File::create(file)?)?;
+        }
+        Commands::Restore { file, no_save } => {
+            let count = deck.state()?.len() as u8;
+            let commands = backup::read_backup(BufReader::new(File::open(file)?), count)?;
+            for command in commands {
+                match command {
+                    Command::Set {
+                        button,
+                        keycode,
+                        media_key,
+                    } => deck.set(button, (keycode, media_key))?,
+                    Command::Map {
+                        layer,
+                        button,
+                        action: Some(action),
+                    } => {
+                        deck.set_map(layer, button, action)?;
+                    }
+                    Command::Combo {
+                        slot,
+                        combo: Some(combo),
+                    } => {
+                        deck.set_combo(slot, combo)?;
+                    }
+                    Command::ComboWindow(Some(ms)) => {
+                        deck.set_combo_window(ms)?;
+                    }
+                    Command::Macro {
+                        slot,
+                        code: Some(code),
+                    } => {
+                        deck.set_macro(slot, code)?;
+                    }
+                    _ => unreachable!("read_backup only returns changes"),
+                }
+            }
+            if !no_save {
+                deck.save()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_keys(button: u8, keys: (u8, u16)) {
+    println!("{}  keycode {:#04x}  media {:#04x}", button, keys.0, keys.1);
+}
+
+fn print_actions(button: u8, actions: &[Action; LAYER_COUNT]) {
+    let actions: Vec<String> = actions.iter().map(Action::to_string).collect();
+    println!("{}  {}", button, actions.join(" | "));
+}
+
+// Quoted so spaces and newlines show
+fn print_text(slot: u8, text: &text::Text) {
+    match text::as_str(text) {
+        Some("") => println!("{}  none", slot),
+        Some(text) => println!("{}  {:?}", slot, text),
+        None => println!("{}  invalid", slot),
+    }
+}
+
+fn print_debounce(button: u8, strategy: DebounceStrategy) {
+    let unit = match strategy {
+        DebounceStrategy::Integrator { .. } => "samples",
+        _ => "ms",
+    };
+    println!(
+        "{}  {} {} {}",
+        button,
+        strategy.name(),
+        strategy.value(),
+        unit
+    );
+}
@@ -0,0 +1,468 @@
+// Ways of exchanging protocol lines with a deck.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use pideck_core::action::Action;
+use pideck_core::combo::ComboConfig;
+use pideck_core::constants::LAYER_COUNT;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gesture::GestureConfig;
+use pideck_core::label::{Label, LABEL_LEN};
+use pideck_core::layer::{self, LayerStack};
+use pideck_core::lock::{LockLayers, LockLeds};
+use pideck_core::macro_player::{MacroCode, MACRO_LEN, MAX_MACROS};
+use pideck_core::profile::{ProfileName, MAX_PROFILES, PROFILE_NAME_LEN};
+use pideck_core::protocol::{self, Command, ErrorCode, Response};
+use pideck_core::raw_hid;
+use pideck_core::text::TextConfig;
+
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub trait Transport {
+    /// Send one request line and wait for the single reply line, returned without its terminator.
+    fn transact(&mut self, request: &str) -> io::Result<String>;
+}
+
+/// A deck attached over its CDC serial port.
+pub struct SerialTransport {
+    port: Box<dyn serialport::SerialPort>,
+    pending: Vec<u8>,
+}
+
+impl SerialTransport {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let port = serialport::new(path, 115_200).timeout(TIMEOUT).open()?;
+        // Anything left over from an earlier session would be taken as our reply
+        port.clear(serialport::ClearBuffer::All)?;
+        Ok(SerialTransport {
+            port,
+            pending: Vec::new(),
+        })
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(line) = take_line(&mut self.pending) {
+                return Ok(line);
+            }
+
+            let mut buf = [0u8; 64];
+            let count = self.port.read(&mut buf)?;
+            if count == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.pending.extend_from_slice(&buf[..count]);
+        }
+    }
+}
+
+impl Transport for SerialTransport {
+    fn transact(&mut self, request: &str) -> io::Result<String> {
+        self.port.write_all(request.as_bytes())?;
+        self.port.flush()?;
+        self.read_line()
+    }
+}
+
+// The first complete line in `pending`, without its terminator
+fn take_line(pending: &mut Vec<u8>) -> Option<String> {
+    let end = pending.iter().position(|byte| *byte == b'\n')?;
+    let line: Vec<u8> = pending.drain(..=end).collect();
+    let line = String::from_utf8_lossy(&line[..end]);
+    Some(line.trim_end_matches('\r').to_string())
+}
+
+/// A deck attached over its raw HID interface, e.g. a Linux hidraw device. Works
+/// where the serial port needs a driver or a modem manager grabs it.
+pub struct HidTransport<W: Write> {
+    device: W,
+    reports: Receiver<io::Result<Vec<u8>>>,
+    pending: Vec<u8>,
+}
+
+impl HidTransport<File> {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let device = OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = device.try_clone()?;
+        Ok(HidTransport::new(device, reader))
+    }
+}
+
+impl<W: Write> HidTransport<W> {
+    /// Talk to a deck writing reports to `device` and reading them from `reader`.
+    pub fn new(device: W, mut reader: impl Read + Send + 'static) -> Self {
+        // hidraw reads block with no timeout, so they are waited for on a thread
+        let (sender, reports) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut report = [0u8; raw_hid::REPORT_LEN];
+            let result = match reader.read(&mut report) {
+                Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+                result => result.map(|count| report[..count].to_vec()),
+            };
+            let failed = result.is_err();
+            if sender.send(result).is_err() || failed {
+                break;
+            }
+        });
+        HidTransport {
+            device,
+            reports,
+            pending: Vec::new(),
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(line) = take_line(&mut self.pending) {
+                return Ok(line);
+            }
+
+            let report = match self.reports.recv_timeout(TIMEOUT) {
+                Ok(report) => report?,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::ErrorKind::UnexpectedEof.into())
+                }
+            };
+            let payload = raw_hid::payload(&report).ok_or(io::ErrorKind::InvalidData)?;
+            self.pending.extend_from_slice(payload);
+        }
+    }
+}
+
+impl<W: Write> Transport for HidTransport<W> {
+    fn transact(&mut self, request: &str) -> io::Result<String> {
+        for report in raw_hid::frames(request.as_bytes()) {
+            // hidraw wants the report id first, 0 as the deck does not number its reports
+            let mut buf = [0u8; raw_hid::REPORT_LEN + 1];
+            buf[1..].copy_from_slice(&report);
+            self.device.write_all(&buf)?;
+        }
+        self.device.flush()?;
+        self.read_line()
+    }
+}
+
+/// Path of the first serial port that belongs to a deck.
+pub fn find_deck() -> io::Result<Option<String>> {
+    let ports = serialport::available_ports()?;
+    Ok(ports
+        .into_iter()
+        .find(|port| {
+            matches!(&port.port_type, serialport::SerialPortType::UsbPort(info)
+                if info.vid == protocol::USB_VID && info.pid == protocol::USB_PID)
+        })
+        .map(|port| port.port_name))
+}
+
+/// Path of the hidraw device of a deck's raw HID interface. The deck has several
+/// HID interfaces, the raw one is told apart by its report descriptor.
+pub fn find_hid_deck() -> io::Result<Option<String>> {
+    let entries = match fs::read_dir("/sys/class/hidraw") {
+        Ok(entries) => entries,
+        // No hidraw support on this host
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let device = entry.path().join("device");
+        let uevent = fs::read_to_string(device.join("uevent")).unwrap_or_default();
+        if !is_deck_uevent(&uevent) {
+            continue;
+        }
+        let descriptor = fs::read(device.join("report_descriptor")).unwrap_or_default();
+        if raw_hid::is_raw_descriptor(&descriptor) {
+            let dev = Path::new("/dev").join(entry.file_name());
+            return Ok(Some(dev.to_string_lossy().into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether a hidraw device's uevent is a deck's, from its `HID_ID=bus:vid:pid` line.
+pub fn is_deck_uevent(uevent: &str) -> bool {
+    uevent
+        .lines()
+        .filter_map(|line| line.strip_prefix("HID_ID="))
+        .any(|id| {
+            let ids: Vec<Option<u32>> = id
+                .split(':')
+                .map(|part| u32::from_str_radix(part, 16).ok())
+                .collect();
+            ids[..]
+                == [
+                    Some(0x03),
+                    Some(protocol::USB_VID as u32),
+                    Some(protocol::USB_PID as u32),
+                ]
+        })
+}
+
+/// Stand-in for a deck that answers requests with the firmware's own parser.
+/// Lets the CLI be exercised without hardware attached. It keeps one config that
+/// every profile shares, switching only changes which profile is reported.
+pub struct Loopback {
+    pub keymap: Vec<[Action; LAYER_COUNT]>,
+    pub debounce: Vec<DebounceStrategy>,
+    pub gestures: Vec<GestureConfig>,
+    pub labels: Vec<[Label; LAYER_COUNT]>,
+    pub layers: LayerStack,
+    pub combos: ComboConfig,
+    pub macros: [MacroCode; MAX_MACROS],
+    pub leds: LockLeds,
+    pub lock_layers: LockLayers,
+    pub texts: TextConfig,
+    pub profile: u8,
+    pub profile_names: [ProfileName; MAX_PROFILES],
+    pub pressed: u8,
+    pub saved: Option<Vec<[Action; LAYER_COUNT]>>,
+    pub requests: Vec<String>,
+}
+
+impl Loopback {
+    pub fn new(keymap: Vec<[Action; LAYER_COUNT]>) -> Self {
+        Loopback {
+            debounce: vec![DebounceStrategy::DEFAULT; keymap.len()],
+            gestures: vec![GestureConfig::default(); keymap.len()],
+            labels: vec![[[0; LABEL_LEN]; LAYER_COUNT]; keymap.len()],
+            keymap,
+            layers: LayerStack::new(),
+            combos: ComboConfig::DEFAULT,
+            macros: [[0; MACRO_LEN]; MAX_MACROS],
+            leds: LockLeds::default(),
+            lock_layers: LockLayers::default(),
+            texts: TextConfig::default(),
+            profile: 0,
+            profile_names: [[0; PROFILE_NAME_LEN]; MAX_PROFILES],
+            pressed: 0,
+            saved: None,
+            requests: Vec::new(),
+        }
+    }
+
+    fn respond(&mut self, command: Command) -> Response<'static> {
+        match command {
+            Command::Version => Response::Version("loopback"),
+            Command::Get { button } => {
+                let keys = layer::keys_of(&self.keymap[button as usize - 1]);
+                Response::Key {
+                    button,
+                    keycode: keys.0,
+                    media_key: keys.1,
+                }
+            }
+            Command::Set {
+                button,
+                keycode,
+                media_key,
+            } => {
+                layer::set_keys(&mut self.keymap[button as usize - 1], (keycode, media_key));
+                Response::Ok
+            }
+            Command::Map {
+                layer,
+                button,
+                action,
+            } => {
+                let current = &mut self.keymap[button as usize - 1][layer as usize];
+                if let Some(action) = action {
+                    *current = action;
+                }
+                Response::Map {
+                    layer,
+                    button,
+                    action: *current,
+                }
+            }
+            Command::Label {
+                layer,
+                button,
+                label,
+            } => {
+                let current = &mut self.labels[button as usize - 1][layer as usize];
+                if let Some(label) = label {
+                    *current = label;
+                }
+                Response::Label {
+                    layer,
+                    button,
+                    label: *current,
+                }
+            }
+            Command::Layer(change) => {
+                match change {
+                    Some((layer, true)) => {
+                        self.layers.activate(layer);
+                    }
+                    Some((layer, false)) => {
+                        self.layers.deactivate(layer);
+                    }
+                    None => {}
+                }
+                let mut active = [0u8; LAYER_COUNT];
+                let stack = self.layers.as_slice();
+                active[..stack.len()].copy_from_slice(stack);
+                Response::Layers {
+                    active,
+                    count: stack.len() as u8,
+                }
+            }
+            Command::State => Response::State {
+                pressed: self.pressed,
+                count: self.keymap.len() as u8,
+            },
+            Command::Debounce { button, strategy } => {
+                let current = &mut self.debounce[button as usize - 1];
+                if let Some(strategy) = strategy {
+                    *current = strategy;
+                }
+                Response::Debounce {
+                    button,
+                    strategy: *current,
+                }
+            }
+            Command::Gesture {
+                button,
+                gesture,
+                action,
+            } => {
+                let actions = &mut self.gestures[button as usize - 1].actions;
+                if let Some(action) = action {
+                    actions[gesture] = action;
+                }
+                Response::Gesture {
+                    button,
+                    gesture,
+                    action: actions[gesture],
+                }
+            }
+            Command::Timing { button, timing } => {
+                let current = &mut self.gestures[button as usize - 1].timing;
+                if let Some(timing) = timing {
+                    *current = timing;
+                }
+                Response::Timing {
+                    button,
+                    timing: *current,
+                }
+            }
+            Command::Combo { slot, combo } => {
+                let current = &mut self.combos.combos[slot as usize];
+                if let Some(combo) = combo {
+                    *current = combo;
+                }
+                Response::Combo {
+                    slot,
+                    combo: *current,
+                }
+            }
+            Command::ComboWindow(ms) => {
+                if let Some(ms) = ms {
+                    self.combos.window_us = ms as u32 * 1000;
+                }
+                Response::ComboWindow((self.combos.window_us / 1000) as u16)
+            }
+            Command::Macro { slot, code } => {
+                let current = &mut self.macros[slot as usize];
+                if let Some(code) = code {
+                    *current = code;
+                }
+                Response::Macro {
+                    slot,
+                    code: *current,
+                }
+            }
+            Command::Leds => Response::Leds(self.leds),
+            Command::LockLayer { lock, layer } => {
+                if let Some(layer) = layer {
+                    self.lock_layers[lock] = layer;
+                }
+                Response::LockLayer {
+                    lock,
+                    layer: self.lock_layers[lock],
+                }
+            }
+            Command::Text { slot, text } => {
+                let current = &mut self.texts.texts[slot as usize];
+                if let Some(text) = text {
+                    *current = text;
+                }
+                Response::Text {
+                    slot,
+                    text: *current,
+                }
+            }
+            Command::Unicode(method) => {
+                if let Some(method) = method {
+                    self.texts.method = method;
+                }
+                Response::Unicode(self.texts.method)
+            }
+            Command::Layout(layout) => {
+                if let Some(layout) = layout {
+                    self.texts.layout = layout;
+                }
+                Response::Layout(self.texts.layout)
+            }
+            Command::Profile(profile) => {
+                if let Some(profile) = profile {
+                    self.profile = profile;
+                }
+                Response::Profile {
+                    profile: self.profile,
+                    name: self.profile_names[self.profile as usize],
+                }
+            }
+            Command::ProfileName { profile, name } => {
+                let current = &mut self.profile_names[profile as usize];
+                if let Some(name) = name {
+                    *current = name;
+                }
+                Response::Profile {
+                    profile,
+                    name: *current,
+                }
+            }
+            Command::SelectProfile(name) => {
+                match self.profile_names.iter().position(|stored| *stored == name) {
+                    Some(profile) => {
+                        self.profile = profile as u8;
+                        Response::Profile {
+                            profile: self.profile,
+                            name,
+                        }
+                    }
+                    None => Response::Err(ErrorCode::UnknownProfile),
+                }
+            }
+            Command::Save => {
+                self.saved = Some(self.keymap.clone());
+                Response::Ok
+            }
+        }
+    }
+}
+
+impl Transport for Loopback {
+    fn transact(&mut self, request: &str) -> io::Result<String> {
+        self.requests.push(request.to_string());
+
+        let line = request.trim_end_matches(['\r', '\n']);
+        let response = if line.len() > protocol::MAX_LINE_LEN {
+            Response::Err(ErrorCode::LineTooLong)
+        } else {
+            match protocol::parse(line.as_bytes(), self.keymap.len() as u8) {
+                Ok(command) => self.respond(command),
+                Err(code) => Response::Err(code),
+            }
+        };
+
+        Ok(response.to_string().trim_end().to_string())
+    }
+}
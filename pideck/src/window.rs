@@ -0,0 +1,143 @@
+// Where the daemon learns which application has the focus. Each desktop has its
+// own way to ask, so every backend is a `WindowSource` and the daemon only sees
+// the trait. The backends run the desktop's own command line tools rather than
+// linking its libraries:
+//
+// - X11 asks `xprop` for the root window's _NET_ACTIVE_WINDOW and then for that
+//   window's WM_CLASS and _NET_WM_NAME
+// - Hyprland asks `hyprctl activewindow`
+// - any other Wayland compositor can be covered by a shell command printing the
+//   class on its first line and the title on its second, e.g. `swaymsg` and `jq`
+//   on sway
+
+use std::io;
+use std::process;
+
+/// The focused window, as far as a backend can tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FocusedWindow {
+    /// Application class, or app id on Wayland, e.g. `firefox`
+    pub class: String,
+    pub title: String,
+}
+
+pub trait WindowSource {
+    /// The focused window, `None` when nothing has the focus.
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>>;
+}
+
+/// Focus on an X11 desktop, or of X11 windows under XWayland.
+pub struct X11;
+
+impl WindowSource for X11 {
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>> {
+        let root = run("xprop", &["-root", "_NET_ACTIVE_WINDOW"])?;
+        let id = match parse_xprop_active(&root) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let window = run("xprop", &["-id", &id, "WM_CLASS", "_NET_WM_NAME"])?;
+        Ok(Some(parse_xprop_window(&window)))
+    }
+}
+
+/// Focus on Hyprland.
+pub struct Hyprland;
+
+impl WindowSource for Hyprland {
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>> {
+        Ok(parse_hyprctl(&run("hyprctl", &["activewindow"])?))
+    }
+}
+
+/// Focus as printed by a shell command, the class on the first line and the
+/// title on the second. No output is no focus.
+pub struct Shell(pub String);
+
+impl WindowSource for Shell {
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>> {
+        let output = run("sh", &["-c", &self.0])?;
+        let mut lines = output.lines();
+        match lines.next().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(class) => Ok(Some(FocusedWindow {
+                class: class.to_string(),
+                title: lines.next().unwrap_or("").trim().to_string(),
+            })),
+        }
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = process::Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program, output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Window id from `xprop -root _NET_ACTIVE_WINDOW`, e.g.
+/// `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007`. Id 0 is no window.
+pub fn parse_xprop_active(output: &str) -> Option<String> {
+    let id = output.split('#').nth(1)?.split(',').next()?.trim();
+    match u32::from_str_radix(id.trim_start_matches("0x"), 16) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(id.to_string()),
+    }
+}
+
+/// Class and title from `xprop -id <id> WM_CLASS _NET_WM_NAME`. WM_CLASS holds
+/// the instance and then the class, the class is the one kept.
+pub fn parse_xprop_window(output: &str) -> FocusedWindow {
+    let mut window = FocusedWindow::default();
+    for line in output.lines() {
+        let (name, value) = match line.split_once(" = ") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let strings = quoted(value);
+        if name.starts_with("WM_CLASS") {
+            window.class = strings.last().cloned().unwrap_or_default();
+        } else if name.starts_with("_NET_WM_NAME") {
+            window.title = strings.first().cloned().unwrap_or_default();
+        }
+    }
+    window
+}
+
+// The quoted strings in an xprop value, e.g. `"Navigator", "firefox"`
+fn quoted(value: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = value.chars();
+    while chars.any(|c| c == '"') {
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => string.extend(chars.next()),
+                c => string.push(c),
+            }
+        }
+        strings.push(string);
+    }
+    strings
+}
+
+/// Class and title from `hyprctl activewindow`, which prints `Invalid` when no
+/// window has the focus.
+pub fn parse_hyprctl(output: &str) -> Option<FocusedWindow> {
+    let mut window = FocusedWindow::default();
+    let mut found = false;
+    for line in output.lines().map(str::trim) {
+        if let Some(class) = line.strip_prefix("class: ") {
+            window.class = class.to_string();
+            found = true;
+        } else if let Some(title) = line.strip_prefix("title: ") {
+            window.title = title.to_string();
+        }
+    }
+    found.then_some(window)
+}
@@ -0,0 +1,287 @@
+use std::collections::VecDeque;
+use std::io::{self, Cursor};
+
+use pideck::client::{Deck, Error};
+use pideck::daemon::{pick, read_rules, Match, Rule, Switcher};
+use pideck::transport::{Loopback, Transport};
+use pideck::window::{
+    parse_hyprctl, parse_xprop_active, parse_xprop_window, FocusedWindow, WindowSource,
+};
+use pideck_core::layer;
+use pideck_core::profile::{self, ProfileName};
+use pideck_core::protocol::ErrorCode;
+
+const RULES: &str = "\
+# profile  match
+Zoom        class zoom
+IDE         class code
+IDE         title - Visual Studio Code
+
+Web         default
+";
+
+fn name(name: &str) -> ProfileName {
+    profile::parse_name(name.as_bytes()).unwrap()
+}
+
+fn window(class: &str, title: &str) -> FocusedWindow {
+    FocusedWindow {
+        class: class.to_string(),
+        title: title.to_string(),
+    }
+}
+
+// Hands out the windows it was given one poll at a time, nothing focused after
+struct Scripted(VecDeque<Option<FocusedWindow>>);
+
+impl WindowSource for Scripted {
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>> {
+        Ok(self.0.pop_front().flatten())
+    }
+}
+
+// The desktop never answers
+struct Broken;
+
+impl WindowSource for Broken {
+    fn focused(&mut self) -> io::Result<Option<FocusedWindow>> {
+        Err(io::Error::other("BadWindow"))
+    }
+}
+
+// The deck dropping off for the first `failures` requests
+struct Flaky {
+    loopback: Loopback,
+    failures: usize,
+}
+
+impl Transport for Flaky {
+    fn transact(&mut self, request: &str) -> io::Result<String> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.loopback.transact(request)
+    }
+}
+
+fn deck() -> Deck<Loopback> {
+    let mut loopback = Loopback::new(layer::default_keymap().values().copied().collect());
+    loopback.profile_names[1] = name("Zoom");
+    loopback.profile_names[2] = name("IDE");
+    Deck::new(loopback)
+}
+
+#[test]
+fn reads_rules() {
+    let rules = read_rules(Cursor::new(RULES)).unwrap();
+    assert_eq!(
+        rules,
+        vec![
+            Rule {
+                profile: name("Zoom"),
+                matches: Match::Class("zoom".to_string()),
+            },
+            Rule {
+                profile: name("IDE"),
+                matches: Match::Class("code".to_string()),
+            },
+            Rule {
+                profile: name("IDE"),
+                matches: Match::Title("- Visual Studio Code".to_string()),
+            },
+            Rule {
+                profile: name("Web"),
+                matches: Match::Default,
+            },
+        ]
+    );
+}
+
+#[test]
+fn rejects_bad_rules_with_their_line() {
+    for (rules, line, code) in [
+        ("Zoom class zoom\nZoom\n", 2, ErrorCode::BadArgument),
+        ("Zoom window zoom\n", 1, ErrorCode::BadArgument),
+        ("Zoom class\n", 1, ErrorCode::BadArgument),
+        (
+            "\n# comment\nWeb default firefox\n",
+            3,
+            ErrorCode::BadArgument,
+        ),
+        ("none default\n", 1, ErrorCode::BadArgument),
+        ("AVeryLongName default\n", 1, ErrorCode::OutOfRange),
+    ] {
+        match read_rules(Cursor::new(rules)) {
+            Err(Error::Rules {
+                line: got,
+                code: got_code,
+            }) => {
+                assert_eq!((got, got_code), (line, code), "{:?}", rules)
+            }
+            other => panic!("{:?} gave {:?}", rules, other.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let rules = read_rules(Cursor::new(RULES)).unwrap();
+    assert_eq!(
+        pick(&rules, Some(&window("Zoom", "Meeting"))),
+        Some(name("Zoom"))
+    );
+    assert_eq!(
+        pick(&rules, Some(&window("Code", "main.rs"))),
+        Some(name("IDE"))
+    );
+    assert_eq!(
+        pick(
+            &rules,
+            Some(&window("electron", "main.rs - Visual Studio Code"))
+        ),
+        Some(name("IDE"))
+    );
+    assert_eq!(
+        pick(&rules, Some(&window("firefox", "News"))),
+        Some(name("Web"))
+    );
+    // A class only matches whole
+    assert_eq!(pick(&rules, Some(&window("zoomer", ""))), Some(name("Web")));
+    assert_eq!(pick(&rules, None), None);
+
+    let rules = read_rules(Cursor::new("Zoom class zoom\n")).unwrap();
+    assert_eq!(pick(&rules, Some(&window("firefox", "News"))), None);
+}
+
+#[test]
+fn switches_only_when_the_pick_changes() {
+    let rules = read_rules(Cursor::new("Zoom class zoom\nIDE class code\n")).unwrap();
+    let mut switcher = Switcher::new(rules);
+    let mut source = Scripted(VecDeque::from([
+        Some(window("zoom", "Meeting")),
+        Some(window("zoom", "Another meeting")),
+        None,
+        Some(window("firefox", "News")),
+        Some(window("zoom", "Meeting")),
+        Some(window("code", "main.rs")),
+    ]));
+    let mut deck = deck();
+    let mut switched = Vec::new();
+    for _ in 0..6 {
+        switched.push(switcher.poll(&mut source, &mut deck).unwrap());
+    }
+    assert_eq!(
+        switched,
+        vec![
+            Some((1, name("Zoom"))),
+            None,
+            None,
+            None,
+            None,
+            Some((2, name("IDE"))),
+        ]
+    );
+
+    let loopback = deck.into_transport();
+    let selects: Vec<_> = loopback
+        .requests
+        .iter()
+        .filter(|request| request.starts_with("profile-select"))
+        .collect();
+    assert_eq!(selects, ["profile-select Zoom\n", "profile-select IDE\n"]);
+    assert_eq!(loopback.profile, 2);
+}
+
+#[test]
+fn reports_an_unknown_profile_once() {
+    let rules = read_rules(Cursor::new("Games class steam\nZoom class zoom\n")).unwrap();
+    let mut switcher = Switcher::new(rules);
+    let mut source = Scripted(VecDeque::from([
+        Some(window("steam", "Library")),
+        Some(window("steam", "Store")),
+        Some(window("zoom", "Meeting")),
+    ]));
+    let mut deck = deck();
+    assert!(matches!(
+        switcher.poll(&mut source, &mut deck),
+        Err(Error::Device(ErrorCode::UnknownProfile))
+    ));
+    assert_eq!(switcher.poll(&mut source, &mut deck).unwrap(), None);
+    assert_eq!(
+        switcher.poll(&mut source, &mut deck).unwrap(),
+        Some((1, name("Zoom")))
+    );
+}
+
+#[test]
+fn window_source_errors_leave_the_deck_alone() {
+    let rules = read_rules(Cursor::new("Zoom class zoom\n")).unwrap();
+    let mut switcher = Switcher::new(rules);
+    let mut deck = deck();
+    assert!(matches!(
+        switcher.poll(&mut Broken, &mut deck),
+        Err(Error::Window(_))
+    ));
+    assert!(deck.into_transport().requests.is_empty());
+}
+
+#[test]
+fn retries_a_profile_the_deck_failed_to_select() {
+    let rules = read_rules(Cursor::new("Zoom class zoom\n")).unwrap();
+    let mut switcher = Switcher::new(rules);
+    let mut source = Scripted(VecDeque::from([
+        Some(window("zoom", "Meeting")),
+        Some(window("zoom", "Meeting")),
+    ]));
+    let mut deck = Deck::new(Flaky {
+        loopback: deck().into_transport(),
+        failures: 1,
+    });
+    assert!(matches!(
+        switcher.poll(&mut source, &mut deck),
+        Err(Error::Io(_))
+    ));
+    assert_eq!(
+        switcher.poll(&mut source, &mut deck).unwrap(),
+        Some((1, name("Zoom")))
+    );
+}
+
+#[test]
+fn parses_xprop() {
+    assert_eq!(
+        parse_xprop_active("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n"),
+        Some("0x3a00007".to_string())
+    );
+    assert_eq!(
+        parse_xprop_active("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n"),
+        None
+    );
+    assert_eq!(
+        parse_xprop_active("_NET_ACTIVE_WINDOW:  not found.\n"),
+        None
+    );
+    assert_eq!(
+        parse_xprop_window(
+            "WM_CLASS(STRING) = \"Navigator\", \"firefox\"\n\
+             _NET_WM_NAME(UTF8_STRING) = \"Say \\\"hi\\\" - Mozilla Firefox\"\n"
+        ),
+        window("firefox", "Say \"hi\" - Mozilla Firefox")
+    );
+}
+
+#[test]
+fn parses_hyprctl() {
+    assert_eq!(
+        parse_hyprctl(
+            "Window 55d8c1b0 -> main.rs - Code:\n\
+             \tmapped: 1\n\
+             \tclass: code\n\
+             \ttitle: main.rs - Code\n\
+             \tpid: 1234\n"
+        ),
+        Some(window("code", "main.rs - Code"))
+    );
+    assert_eq!(parse_hyprctl("Invalid\n"), None);
+}
@@ -0,0 +1,395 @@
+use std::io::{self, Cursor};
+
+use pideck::backup::{read_backup, write_backup};
+use pideck::client::{Deck, Error};
+use pideck::transport::{Loopback, Transport};
+use pideck_core::action::Action;
+use pideck_core::combo::{Combo, ComboConfig, MAX_COMBOS};
+use pideck_core::constants::LAYER_COUNT;
+use pideck_core::debouncer::DebounceStrategy;
+use pideck_core::gesture::{Gesture, GestureTiming};
+use pideck_core::label::{self, LABEL_LEN};
+use pideck_core::layer;
+use pideck_core::layout::Layout;
+use pideck_core::macro_player::{assemble, MacroCode, MAX_MACROS};
+use pideck_core::profile::{self, MAX_PROFILES, PROFILE_NAME_LEN};
+use pideck_core::protocol::{Command, ErrorCode};
+use pideck_core::text::{self, UnicodeMethod};
+
+fn defaults() -> Vec<[Action; LAYER_COUNT]> {
+    layer::default_keymap().values().copied().collect()
+}
+
+fn deck() -> Deck<Loopback> {
+    Deck::new(Loopback::new(defaults()))
+}
+
+// Replies with a fixed line whatever is asked
+struct Garbage(&'static str);
+
+impl Transport for Garbage {
+    fn transact(&mut self, _request: &str) -> io::Result<String> {
+        Ok(self.0.to_string())
+    }
+}
+
+#[test]
+fn lists_every_button() {
+    let mut deck = deck();
+    assert_eq!(deck.list().unwrap(), defaults());
+}
+
+#[test]
+fn set_then_get() {
+    let mut deck = deck();
+    deck.set(3, (0x68, 0xB2)).unwrap();
+    assert_eq!(deck.get(3).unwrap(), (0x68, 0xB2));
+
+    let loopback = deck.into_transport();
+    assert!(loopback.requests.contains(&"set 3 0x68 0xb2\n".to_string()));
+    assert_eq!(loopback.saved, None);
+}
+
+#[test]
+fn out_of_range_button_is_a_device_error() {
+    let mut deck = deck();
+    assert!(matches!(
+        deck.get(7),
+        Err(Error::Device(ErrorCode::OutOfRange))
+    ));
+}
+
+#[test]
+fn set_is_the_base_and_media_layers() {
+    let mut deck = deck();
+    deck.set(2, (0x04, 0xE2)).unwrap();
+    assert_eq!(deck.map(0, 2).unwrap(), Action::Key(0x04));
+    assert_eq!(deck.map(1, 2).unwrap(), Action::Media(0xE2));
+    assert_eq!(deck.map(2, 2).unwrap(), Action::Transparent);
+}
+
+#[test]
+fn switches_layers() {
+    let mut deck = deck();
+    assert_eq!(deck.layers().unwrap(), vec![0]);
+    assert_eq!(deck.set_layer(2, true).unwrap(), vec![0, 2]);
+    assert_eq!(deck.set_layer(1, true).unwrap(), vec![0, 2, 1]);
+    assert_eq!(deck.set_layer(2, false).unwrap(), vec![0, 1]);
+    // The base layer can't be switched off
+    assert_eq!(deck.set_layer(0, false).unwrap(), vec![0, 1]);
+}
+
+#[test]
+fn maps_actions_on_layers() {
+    let mut deck = deck();
+    assert_eq!(
+        deck.set_map(3, 6, Action::Toggle(3)).unwrap(),
+        Action::Toggle(3)
+    );
+    assert_eq!(deck.list().unwrap()[5][3], Action::Toggle(3));
+    assert!(matches!(
+        deck.set_map(LAYER_COUNT as u8, 6, Action::None),
+        Err(Error::Device(ErrorCode::OutOfRange))
+    ));
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"map 3 6 toggle 3\n".to_string()));
+}
+
+#[test]
+fn changes_debounce_strategy() {
+    let mut deck = deck();
+    assert_eq!(deck.debounce(2).unwrap(), DebounceStrategy::DEFAULT);
+
+    let integrator = DebounceStrategy::Integrator { samples: 4 };
+    assert_eq!(deck.set_debounce(2, integrator).unwrap(), integrator);
+    assert_eq!(deck.debounce(2).unwrap(), integrator);
+    assert_eq!(deck.debounce(1).unwrap(), DebounceStrategy::DEFAULT);
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"debounce 2 integrator 4\n".to_string()));
+}
+
+#[test]
+fn binds_gestures_and_timing() {
+    let mut deck = deck();
+    assert_eq!(deck.gesture(4, Gesture::Hold).unwrap(), Action::None);
+    assert_eq!(
+        deck.set_gesture(4, Gesture::Hold, Action::Key(0x04))
+            .unwrap(),
+        Action::Key(0x04)
+    );
+    assert_eq!(deck.gesture(4, Gesture::Hold).unwrap(), Action::Key(0x04));
+
+    assert_eq!(deck.timing(4).unwrap(), GestureTiming::DEFAULT);
+    let timing = GestureTiming::from_ms([0, 400, 800]);
+    assert_eq!(deck.set_timing(4, timing).unwrap(), timing);
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"gesture 4 hold key 0x04\n".to_string()));
+    assert!(loopback
+        .requests
+        .contains(&"timing 4 0 400 800\n".to_string()));
+}
+
+#[test]
+fn changes_combos() {
+    let mut deck = deck();
+    assert_eq!(deck.combos().unwrap(), ComboConfig::DEFAULT);
+
+    let combo = Combo {
+        buttons: 0b00_1100,
+        action: Action::Key(0x68),
+    };
+    assert_eq!(deck.set_combo(1, combo).unwrap(), combo);
+    assert_eq!(deck.combo(1).unwrap(), combo);
+    assert_eq!(deck.set_combo_window(30).unwrap(), 30);
+    assert!(matches!(
+        deck.set_combo(MAX_COMBOS as u8, combo),
+        Err(Error::Device(ErrorCode::OutOfRange))
+    ));
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"combo 1 3+4 key 0x68\n".to_string()));
+    assert_eq!(loopback.combos.window_us, 30_000);
+}
+
+fn asm(text: &str) -> MacroCode {
+    assemble(text.split(' ').map(str::as_bytes)).unwrap()
+}
+
+#[test]
+fn changes_macros() {
+    let mut deck = deck();
+    assert!(deck.macros().unwrap().iter().all(|code| code[0] == 0));
+
+    let code = asm("press 0xe0 press 0xe1 tap 0x13 release-all");
+    assert_eq!(deck.set_macro(2, code).unwrap(), code);
+    assert_eq!(deck.macro_code(2).unwrap(), code);
+    assert!(matches!(
+        deck.set_macro(MAX_MACROS as u8, code),
+        Err(Error::Device(ErrorCode::OutOfRange))
+    ));
+    deck.set_map(0, 1, Action::Macro(2)).unwrap();
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"macro 2 01e001e1031306\n".to_string()));
+    assert_eq!(loopback.macros[2], code);
+    assert_eq!(loopback.keymap[0][0], Action::Macro(2));
+}
+
+#[test]
+fn changes_texts_the_unicode_method_and_the_layout() {
+    let mut deck = deck();
+    assert!(deck.texts().unwrap().iter().all(|text| text[0] == 0));
+
+    let text = text::from_str("naïve").unwrap();
+    assert_eq!(deck.set_text(4, text).unwrap(), text);
+    assert_eq!(deck.text(4).unwrap(), text);
+    assert_eq!(deck.unicode_method().unwrap(), UnicodeMethod::Linux);
+    assert_eq!(
+        deck.set_unicode_method(UnicodeMethod::Windows).unwrap(),
+        UnicodeMethod::Windows
+    );
+    assert_eq!(deck.layout().unwrap(), Layout::Us);
+    assert_eq!(deck.set_layout(Layout::De).unwrap(), Layout::De);
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"text 4 6e61c3af7665\n".to_string()));
+    assert_eq!(loopback.texts.texts[4], text);
+    assert_eq!(loopback.texts.method, UnicodeMethod::Windows);
+    assert_eq!(loopback.texts.layout, Layout::De);
+}
+
+#[test]
+fn switches_and_names_profiles() {
+    let mut deck = deck();
+    assert_eq!(deck.profile().unwrap(), (0, [0; PROFILE_NAME_LEN]));
+
+    let zoom = profile::parse_name(b"Zoom").unwrap();
+    assert_eq!(deck.set_profile_name(2, zoom).unwrap(), zoom);
+    assert_eq!(deck.select_profile(2).unwrap(), (2, zoom));
+    assert_eq!(deck.profile_name(1).unwrap(), [0; PROFILE_NAME_LEN]);
+    assert!(matches!(
+        deck.select_profile(MAX_PROFILES as u8),
+        Err(Error::Device(ErrorCode::OutOfRange))
+    ));
+    deck.set_map(0, 6, Action::NextProfile).unwrap();
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"profile-name 2 Zoom\n".to_string()));
+    assert!(loopback
+        .requests
+        .contains(&"map 0 6 profile next\n".to_string()));
+    assert_eq!(loopback.profile, 2);
+}
+
+#[test]
+fn selects_profiles_by_name() {
+    let mut deck = deck();
+    let zoom = profile::parse_name(b"Zoom").unwrap();
+    deck.set_profile_name(3, zoom).unwrap();
+    assert_eq!(deck.select_profile_named(zoom).unwrap(), (3, zoom));
+    assert!(matches!(
+        deck.select_profile_named(profile::parse_name(b"IDE").unwrap()),
+        Err(Error::Device(ErrorCode::UnknownProfile))
+    ));
+
+    let loopback = deck.into_transport();
+    assert!(loopback
+        .requests
+        .contains(&"profile-select Zoom\n".to_string()));
+    assert_eq!(loopback.profile, 3);
+}
+
+#[test]
+fn labels_buttons() {
+    let mut deck = deck();
+    let mute = label::parse_label(b"Mute").unwrap();
+    assert_eq!(deck.label(1, 4).unwrap(), [0; LABEL_LEN]);
+    assert_eq!(deck.set_label(1, 4, mute).unwrap(), mute);
+    assert_eq!(deck.label(1, 4).unwrap(), mute);
+    assert_eq!(deck.label(0, 4).unwrap(), [0; LABEL_LEN]);
+
+    let loopback = deck.into_transport();
+    assert!(loopback.requests.contains(&"label 1 4 Mute\n".to_string()));
+    assert_eq!(loopback.labels[3][1], mute);
+}
+
+#[test]
+fn reports_pressed_buttons() {
+    let mut loopback = Loopback::new(defaults());
+    loopback.pressed = 0b00_0101;
+    let mut deck = Deck::new(loopback);
+    assert_eq!(
+        deck.state().unwrap(),
+        vec![true, false, true, false, false, false]
+    );
+}
+
+#[test]
+fn version_and_save() {
+    let mut deck = deck();
+    assert_eq!(deck.version().unwrap(), "loopback");
+    deck.save().unwrap();
+    assert_eq!(deck.into_transport().saved, Some(defaults()));
+}
+
+#[test]
+fn unexpected_reply_is_rejected() {
+    let mut deck = Deck::new(Garbage("I'm here"));
+    assert!(matches!(deck.get(1), Err(Error::BadReply(_))));
+
+    let mut deck = Deck::new(Garbage("OK"));
+    assert!(matches!(deck.layers(), Err(Error::BadReply(_))));
+}
+
+#[test]
+fn backup_and_restore_round_trip() {
+    let mut source = deck();
+    source.set(1, (0x04, 0x192)).unwrap();
+    source.set_map(2, 5, Action::Momentary(3)).unwrap();
+    let bootloader = Combo {
+        buttons: 0b11_0000,
+        action: Action::Bootloader,
+    };
+    source.set_combo(4, bootloader).unwrap();
+    source.set_combo_window(70).unwrap();
+    source
+        .set_macro(5, asm("tap 0x04 wait 100 media 0xcd"))
+        .unwrap();
+
+    let mut file = Vec::new();
+    write_backup(
+        &source.list().unwrap(),
+        &source.combos().unwrap(),
+        &source.macros().unwrap(),
+        &mut file,
+    )
+    .unwrap();
+
+    let mut target = deck();
+    let commands = read_backup(Cursor::new(file), 6).unwrap();
+    assert_eq!(
+        commands.len(),
+        6 * LAYER_COUNT + MAX_COMBOS + 1 + MAX_MACROS
+    );
+    for command in commands {
+        match command {
+            Command::Map {
+                layer,
+                button,
+                action: Some(action),
+            } => {
+                target.set_map(layer, button, action).unwrap();
+            }
+            Command::Combo {
+                slot,
+                combo: Some(combo),
+            } => {
+                target.set_combo(slot, combo).unwrap();
+            }
+            Command::ComboWindow(Some(ms)) => {
+                target.set_combo_window(ms).unwrap();
+            }
+            Command::Macro {
+                slot,
+                code: Some(code),
+            } => {
+                target.set_macro(slot, code).unwrap();
+            }
+            command => panic!("unexpected {:?}", command),
+        }
+    }
+    assert_eq!(target.list().unwrap(), source.list().unwrap());
+    assert_eq!(target.combos().unwrap(), source.combos().unwrap());
+    assert_eq!(target.macros().unwrap(), source.macros().unwrap());
+}
+
+#[test]
+fn old_set_backups_are_read() {
+    let file = "set 1 0x04 0x00\n";
+    assert_eq!(
+        read_backup(Cursor::new(file), 6).unwrap(),
+        vec![Command::Set {
+            button: 1,
+            keycode: 0x04,
+            media_key: 0x00
+        }]
+    );
+}
+
+#[test]
+fn backup_errors_name_the_line() {
+    let file = "# comment\n\nset 1 0x04 0x00\nget 2\n";
+    assert!(matches!(
+        read_backup(Cursor::new(file), 6),
+        Err(Error::Backup {
+            line: 4,
+            code: ErrorCode::UnknownCommand
+        })
+    ));
+
+    let file = "set 9 0x04 0x00\n";
+    assert!(matches!(
+        read_backup(Cursor::new(file), 6),
+        Err(Error::Backup {
+            line: 1,
+            code: ErrorCode::OutOfRange
+        })
+    ));
+}
@@ -0,0 +1,83 @@
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use pideck::transport::{is_deck_uevent, HidTransport, Transport};
+use pideck_core::raw_hid::{self, Report};
+
+// Keeps what the transport writes where the test can still see it
+#[derive(Clone, Default)]
+struct Written(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Written {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn framed(text: &str) -> Vec<u8> {
+    raw_hid::frames(text.as_bytes()).flatten().collect()
+}
+
+#[test]
+fn hid_requests_go_out_as_numbered_reports() {
+    let written = Written::default();
+    let mut hid = HidTransport::new(written.clone(), Cursor::new(framed("OK\r\n")));
+
+    let request = format!("macro 0 {}\n", "01e0".repeat(16));
+    assert_eq!(hid.transact(&request).unwrap(), "OK");
+
+    let written = written.0.lock().unwrap();
+    let writes: Vec<&[u8]> = written.chunks(raw_hid::REPORT_LEN + 1).collect();
+    assert_eq!(writes.len(), 2);
+    let mut sent = Vec::new();
+    for write in writes {
+        // Report id, then the report
+        assert_eq!(write[0], 0);
+        let report: &Report = write[1..].try_into().unwrap();
+        sent.extend_from_slice(raw_hid::payload(report).unwrap());
+    }
+    assert_eq!(sent, request.as_bytes());
+}
+
+#[test]
+fn hid_replies_are_joined_across_reports() {
+    let reply = format!("MACRO 0 {}\r\n", "02e1".repeat(20));
+    let mut reports = framed(&reply);
+    reports.extend(framed("LAYERS 0\r\n"));
+    let mut hid = HidTransport::new(io::sink(), Cursor::new(reports));
+
+    assert_eq!(hid.transact("macro 0\n").unwrap(), reply.trim_end());
+    assert_eq!(hid.transact("layer\n").unwrap(), "LAYERS 0");
+    // Nothing more to read
+    assert_eq!(
+        hid.transact("version\n").unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[test]
+fn hid_rejects_a_broken_report() {
+    let mut report = [0u8; raw_hid::REPORT_LEN];
+    report[0] = raw_hid::REPORT_LEN as u8;
+    let mut hid = HidTransport::new(io::sink(), Cursor::new(report.to_vec()));
+
+    assert_eq!(
+        hid.transact("version\n").unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn finds_the_deck_by_its_hid_id() {
+    let uevent = "DRIVER=hid-generic\nHID_ID=0003:000016C0:000027DD\nHID_NAME=Pi Deck\n";
+    assert!(is_deck_uevent(uevent));
+    assert!(!is_deck_uevent("HID_ID=0003:0000046D:0000C52B\n"));
+    // Same ids over Bluetooth are not the deck
+    assert!(!is_deck_uevent("HID_ID=0005:000016C0:000027DD\n"));
+    assert!(!is_deck_uevent(""));
+}
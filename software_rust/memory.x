@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 4K
+    /* Last 4K sector is reserved for the persistent key config (see flash_store.rs) */
+    CONFIG : ORIGIN = 0x10000000 + 2048K - 4K, LENGTH = 4K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
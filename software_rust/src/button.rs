@@ -1,17 +1,20 @@
 use embedded_hal::digital::v2::InputPin;
 
 use rp_pico::hal;
-use rp_pico::hal::gpio::Interrupt::{EdgeHigh, EdgeLow};
+
+use pideck_core::button::{ButtonEvent, ButtonGesture};
+use pideck_core::debouncer::{DebounceStrategy, Debouncer};
+use pideck_core::gesture::{GestureConfig, GestureRecognizer};
+use pideck_core::key_config::KeyConfig;
 
 use crate::constants::*;
-use crate::debouncer::Debouncer;
-use crate::key_config::KeyConfig;
 
 pub struct Button {
     pub variant: ButtonVariant,
     debouncer: Debouncer,
+    gestures: GestureRecognizer,
+    has_gestures: bool,
     pub is_pressed: bool,
-    pub to_be_released: bool,
 }
 
 impl Button {
@@ -19,46 +22,61 @@ impl Button {
         Self {
             variant,
             debouncer: Debouncer::new(DEBOUNCE_US),
+            gestures: GestureRecognizer::new(&GestureConfig::default()),
+            has_gestures: false,
             is_pressed: false,
-            to_be_released: false,
         }
     }
 
-    pub fn debounce(&mut self, timer: &hal::timer::Timer, current_state: bool) {
-        let current_time = timer.get_counter_low();
-        self.debouncer.update(current_time, current_state);
-        self.is_pressed = self.debouncer.stabilised_state;
-        // self.is_released = {
-        //     let mut val = false;
-        //     if !self.debouncer.stabilised_state && self.debouncer.is_debounced {
-        //         val = true
-        //     }
-        //     val
-        // }
+    pub fn debounce_strategy(&self) -> DebounceStrategy {
+        self.debouncer.strategy()
     }
 
-    pub fn reset(&mut self) {
-        self.debouncer.current_state = false;
-        self.debouncer.stabilised_state = false;
-        self.is_pressed = false;
+    pub fn set_debounce_strategy(&mut self, strategy: DebounceStrategy) {
+        self.debouncer.set_strategy(strategy);
     }
 
-    pub fn is_released(&mut self) -> bool {
-        if !self.debouncer.stabilised_state && !self.debouncer.current_state {
-            return true;
-        }
-        false
+    /// Whether edges go through the gesture recognizer rather than out as presses.
+    pub fn has_gestures(&self) -> bool {
+        self.has_gestures
+    }
+
+    pub fn set_gestures(&mut self, config: &GestureConfig) {
+        self.gestures.configure(config);
+        self.has_gestures = config.is_enabled();
+    }
+
+    /// Sample the pin and feed it to the debouncer. Called from the periodic scan,
+    /// returns an event when the debounced state changes.
+    pub fn scan(&mut self, timer: &hal::timer::Timer) -> Option<ButtonEvent> {
+        // Buttons pull the pin low when pressed
+        let raw_state = self.variant.is_low().unwrap();
+        let current_time = timer.get_counter_low();
+
+        let pressed = self.debouncer.update(current_time, raw_state)?;
+        self.is_pressed = pressed;
+        Some(ButtonEvent {
+            id: self.variant.get_id(),
+            pressed,
+        })
     }
 
-    // pub fn is_pressed(&mut self) {
-    //     if self.debouncer.stabilised_state {
-    //         self.is_pressed = true;
-    //         self.is_released = false;
-    //     } else {
-    //         self.is_released = true;
-    //         self.is_pressed = false;
-    //     }
-    // }
+    /// Feed the result of this tick's `scan` to the gesture recognizer. Needs
+    /// calling on every tick, not just on edges, so held presses are timed.
+    pub fn gesture(
+        &mut self,
+        timer: &hal::timer::Timer,
+        event: Option<ButtonEvent>,
+    ) -> Option<ButtonGesture> {
+        let current_time = timer.get_counter_low();
+        let event = self
+            .gestures
+            .update(current_time, event.map(|event| event.pressed))?;
+        Some(ButtonGesture {
+            id: self.variant.get_id(),
+            event,
+        })
+    }
 }
 
 // TODO: Implement a stack for concurrently pressed buttons to send via usb_hid
@@ -108,50 +126,6 @@ impl ButtonVariant {
     // TODO: I can use traits so I don't have to create and redeclare the gpio attr.
     // Would've saved a lot of time.
 
-    pub fn set_button_low_interrupt(&self, set_state: bool) {
-        match self {
-            ButtonVariant::One { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-            ButtonVariant::Two { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-            ButtonVariant::Three { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-            ButtonVariant::Four { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-            ButtonVariant::Five { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-            ButtonVariant::Six { gpio, .. } => gpio.set_interrupt_enabled(EdgeLow, set_state),
-        }
-    }
-
-    pub fn set_button_high_interrupt(&self, set_state: bool) {
-        match self {
-            ButtonVariant::One { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-            ButtonVariant::Two { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-            ButtonVariant::Three { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-            ButtonVariant::Four { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-            ButtonVariant::Five { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-            ButtonVariant::Six { gpio, .. } => gpio.set_interrupt_enabled(EdgeHigh, set_state),
-        }
-    }
-
-    pub fn clear_button_low_interrupt(&mut self) {
-        match self {
-            ButtonVariant::One { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-            ButtonVariant::Two { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-            ButtonVariant::Three { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-            ButtonVariant::Four { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-            ButtonVariant::Five { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-            ButtonVariant::Six { gpio, .. } => gpio.clear_interrupt(EdgeLow),
-        }
-    }
-
-    pub fn clear_button_high_interrupt(&mut self) {
-        match self {
-            ButtonVariant::One { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-            ButtonVariant::Two { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-            ButtonVariant::Three { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-            ButtonVariant::Four { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-            ButtonVariant::Five { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-            ButtonVariant::Six { gpio, .. } => gpio.clear_interrupt(EdgeHigh),
-        }
-    }
-
     pub fn get_id(&self) -> KeyConfig {
         match self {
             ButtonVariant::One { id, .. } => *id,
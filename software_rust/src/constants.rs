@@ -11,27 +11,11 @@
 // PERF: hi : I'm a performance issue
 // FIX: hi : I need to be fixed
 
-// 25ms debounce - initial default
-pub const DEBOUNCE_US: u32 = 25_000;
+// Keycodes, button count and debounce defaults are shared with the host
+pub use pideck_core::constants::*;
 
-pub const BUTTON_COUNT: usize = 6;
-pub const INDEX_MAP_SIZE: usize = 8; // Must be power of 2
-
-pub const KEYCODE_1: u8 = 0x69;
-pub const KEYCODE_2: u8 = 0x6A;
-pub const KEYCODE_3: u8 = 0x6B;
-pub const KEYCODE_4: u8 = 0x6C;
-pub const KEYCODE_5: u8 = 0x6D;
-pub const KEYCODE_6: u8 = 0x6E;
-
-pub const MEDIAKEY_PLAYPAUSE: u8 = 0xCD;
-pub const MEDIAKEY_VOLUP: u8 = 0xE9;
-pub const MEDIAKEY_VOLDOWN: u8 = 0xEA;
-#[allow(dead_code)]
-pub const MEDIAKEY_MUTE: u8 = 0xB2;
-pub const MEDIAKEY_PREVTRACK: u8 = 0xB6;
-pub const MEDIAKEY_NEXTTRACK: u8 = 0xB5;
-pub const MEDIAKEY_NONE: u8 = 0x00;
-
-pub const KEY_MODE_BUTTONS: [u8; 2] = [KEYCODE_1, KEYCODE_2];
-pub const MEDIA_MODE_BUTTONS: [u8; 2] = [MEDIAKEY_PLAYPAUSE, MEDIAKEY_NONE];
+// Persistent key config - must match the CONFIG region in memory.x
+pub const XIP_BASE: u32 = 0x1000_0000;
+pub const CONFIG_FLASH_OFFSET: u32 = 2048 * 1024 - CONFIG_SECTOR_SIZE as u32;
+pub const CONFIG_SECTOR_SIZE: usize = 4096;
+pub const FLASH_PAGE_SIZE: usize = 256;
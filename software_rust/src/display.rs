@@ -1,3 +1,4 @@
+use display_interface::DisplayError;
 use rp_pico::hal;
 use ssd1306::{prelude::*, Ssd1306};
 type DisplayI2C = hal::I2C<
@@ -8,33 +9,20 @@ type DisplayI2C = hal::I2C<
     ),
 >;
 
-use embedded_graphics::{
-    mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
-    pixelcolor::BinaryColor,
-    prelude::*,
-    text::{Baseline, Text},
-};
+use pideck_core::screen::Screen;
 
-pub fn show_text(
+/// Draw the parts of `frame` that changed and send them to the display. Fails
+/// when the display doesn't answer, e.g. over a loose cable.
+pub fn show(
     display: &mut Ssd1306<
         I2CInterface<DisplayI2C>,
         DisplaySize128x32,
         ssd1306::mode::BufferedGraphicsMode<DisplaySize128x32>,
     >,
-    custom_text: &str,
-) {
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_6X10)
-        .text_color(BinaryColor::On)
-        .build();
-
-    display.clear();
-
-    Text::with_baseline(custom_text, Point::zero(), text_style, Baseline::Top)
-        .draw(display)
-        .unwrap();
-
-    display.flush().unwrap();
+    frame: &Screen,
+) -> Result<(), DisplayError> {
+    if frame.draw(display)? {
+        display.flush()?;
+    }
+    Ok(())
 }
-
-// pub fn
@@ -0,0 +1,121 @@
+// Persistent button config kept in the last flash sector (CONFIG in memory.x).
+// The image format lives in pideck_core::config_store so it can be tested on the host.
+
+use pideck_core::config_store::{self, StoreError};
+use pideck_core::profile::{Profile, MAX_PROFILES};
+use rp_pico::hal::rom_data;
+
+use crate::constants::*;
+
+// 64K block erase command, the ROM falls back to 4K sector erase for our single sector
+const BLOCK_ERASE_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+
+// boot2 is copied to RAM before a write so XIP can be restored with the fast
+// read mode it originally set up
+static mut BOOT2_COPY: [u32; 64] = [0; 64];
+
+// Flash is programmed in whole pages
+const IMAGE_BUF_LEN: usize = config_store::IMAGE_LEN.div_ceil(FLASH_PAGE_SIZE) * FLASH_PAGE_SIZE;
+const _: () = assert!(IMAGE_BUF_LEN <= CONFIG_SECTOR_SIZE);
+
+pub type Profiles = [Profile; MAX_PROFILES];
+
+fn stored_image() -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            (XIP_BASE + CONFIG_FLASH_OFFSET) as *const u8,
+            CONFIG_SECTOR_SIZE,
+        )
+    }
+}
+
+/// Load every profile and which one is active from flash, falling back to the
+/// compiled-in defaults when the sector is erased or the image does not check out.
+pub fn load() -> (Profiles, u8) {
+    let mut profiles = [Profile::default(); MAX_PROFILES];
+    match config_store::decode(stored_image(), &mut profiles) {
+        Ok(active) => (profiles, active),
+        Err(_) => (profiles, 0),
+    }
+}
+
+/// Erase the config sector and write `profiles` to it with `active` the active one.
+/// Interrupts are disabled for the duration as XIP is unavailable while writing.
+pub fn save(profiles: &Profiles, active: u8) -> Result<(), StoreError> {
+    let mut pages = [0xFFu8; IMAGE_BUF_LEN];
+    config_store::encode(profiles, active, &mut pages)?;
+    write(&pages);
+    Ok(())
+}
+
+/// Make `active` the active profile of the stored image, leaving the stored
+/// profiles as they were. Nothing is written if no valid image is stored or it
+/// already starts up in `active`.
+pub fn save_active_profile(active: u8) -> Result<(), StoreError> {
+    let mut pages = [0xFFu8; IMAGE_BUF_LEN];
+    pages.copy_from_slice(&stored_image()[..IMAGE_BUF_LEN]);
+    if config_store::set_active_profile(&mut pages, active)? {
+        write(&pages);
+    }
+    Ok(())
+}
+
+fn write(pages: &[u8; IMAGE_BUF_LEN]) {
+    let functions = FlashFunctions {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+    };
+
+    cortex_m::interrupt::free(|_| unsafe {
+        core::ptr::copy_nonoverlapping(
+            XIP_BASE as *const u32,
+            core::ptr::addr_of_mut!(BOOT2_COPY) as *mut u32,
+            64,
+        );
+        write_sector(
+            CONFIG_FLASH_OFFSET,
+            pages.as_ptr(),
+            pages.len(),
+            &functions,
+            core::ptr::addr_of!(BOOT2_COPY) as *const u32,
+        );
+    });
+}
+
+struct FlashFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+// Runs from RAM - nothing in here may touch flash until XIP is re-entered.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_sector(
+    offset: u32,
+    data: *const u8,
+    len: usize,
+    functions: &FlashFunctions,
+    boot2: *const u32,
+) {
+    (functions.connect_internal_flash)();
+    (functions.flash_exit_xip)();
+    (functions.flash_range_erase)(
+        offset,
+        CONFIG_SECTOR_SIZE,
+        BLOCK_ERASE_SIZE,
+        BLOCK_ERASE_CMD,
+    );
+    (functions.flash_range_program)(offset, data, len);
+    (functions.flash_flush_cache)();
+
+    // Thumb bit set as boot2 is entered like any other function
+    let enter_xip: unsafe extern "C" fn() = core::mem::transmute(boot2 as usize + 1);
+    enter_xip();
+}
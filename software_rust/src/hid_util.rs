@@ -1,231 +1,386 @@
-// TODO: Implement a queue like system for chaining HID reports and for mode switching for different functionality.
-
-use enum_map::EnumMap;
+// Glue between pideck_core's HIDUtil and the hardware: reports go out through
+// the USB HID classes and the buttons' labels are put on the screen whenever a
+// press, release or layer switch may have changed them. The display is drawn from
+// the screen later, by the render task, so no input waits for it.
+//
+// The keyboard is two interfaces. A boot keyboard with the fixed 6 key report for
+// BIOSes and other hosts that ask for the boot protocol, and a keyboard with a bit
+// per key for everything else. Hosts start every interface in the report protocol,
+// so the boot keyboard stays quiet unless the host switches it over, and then the
+// NKRO keyboard is the one that stays quiet.
+
+use heapless::Deque;
 use rp_pico::hal;
-use usbd_hid::hid_class::HIDClass;
-// use usbd_hid::UsbError;
-
-use crate::{gen_keyboard_report, gen_media_report};
-use usbd_hid::descriptor::{KeyboardReport, MediaKeyboardReport};
-
-use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
-type DisplayI2C = hal::I2C<
-    hal::pac::I2C0,
-    (
-        hal::gpio::Pin<hal::gpio::bank0::Gpio0, hal::gpio::Function<hal::gpio::I2C>>,
-        hal::gpio::Pin<hal::gpio::bank0::Gpio1, hal::gpio::Function<hal::gpio::I2C>>,
-    ),
->;
-
-use heapless::FnvIndexMap;
-use heapless::String;
-use heapless::Vec;
-// use heapless::spsc::Queue;
-
-use crate::constants::*;
-use crate::display;
-use crate::key_config::{KeyConfig, KeyMode};
-
-// #[derive(Clone)]
-pub struct CustomKeycode {
-    // array: Vec<u8, BUTTON_COUNT>, // Might still need this for keycode array if index_map does not suffice
-    index_map: FnvIndexMap<u8, bool, INDEX_MAP_SIZE>,
-}
-
-// Just use a hashmap only and iterate through to conver to array
-
-impl CustomKeycode {
-    pub fn new() -> Self {
-        CustomKeycode {
-            // array: Vec::new(),
-            index_map: FnvIndexMap::new(),
-        }
+use usb_device::class_prelude::UsbBusAllocator;
+use usb_device::device::UsbDevice;
+use usbd_hid::descriptor::generator_prelude::*;
+use usbd_hid::descriptor::{KeyboardReport, MediaKeyboardReport, MouseReport, SystemControlReport};
+use usbd_hid::hid_class::{
+    HIDClass, HidClassSettings, HidCountryCode, HidProtocol, HidProtocolMode, HidSubClass,
+    ProtocolModeConfig, ReportType,
+};
+use usbd_serial::SerialPort;
+
+use pideck_core::action::Action;
+use pideck_core::gesture::GestureEvent;
+use pideck_core::hid_util::HIDUtil;
+use pideck_core::key_config::KeyConfig;
+use pideck_core::lock::LockLeds;
+use pideck_core::mouse;
+use pideck_core::protocol::MAX_LINE_LEN;
+use pideck_core::raw_hid;
+use pideck_core::report::{self, ReportSink};
+use pideck_core::screen::Screen;
+
+// Modifier byte then one bit for each of the usages 0x00-0xDF. The host sends the
+// lock LEDs back in the output report, as it does to the boot keyboard.
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = KEYBOARD) = {
+        (usage_page = KEYBOARD, usage_min = 0xE0, usage_max = 0xE7) = {
+            #[packed_bits 8] #[item_settings data,variable,absolute] modifier=input;
+        };
+        (usage_page = LEDS, usage_min = 0x01, usage_max = 0x05) = {
+            #[packed_bits 5] #[item_settings data,variable,absolute] leds=output;
+        };
+        (usage_page = KEYBOARD, usage_min = 0x00, usage_max = 0xDF) = {
+            #[packed_bits 224] #[item_settings data,variable,absolute] keys=input;
+        };
     }
+)]
+pub struct NkroKeyboardReport {
+    pub modifier: u8,
+    pub leds: u8,
+    pub keys: [u8; 28],
+}
 
-    fn get_keycode_array(&mut self) -> [u8; 6] {
-        let mut array_vec = self
-            .index_map
-            .iter()
-            .map(|(k, _)| *k)
-            .collect::<Vec<u8, 6>>();
-        let _ = array_vec.resize(BUTTON_COUNT, 0);
-        array_vec.into_array().unwrap()
+// usbd-hid copies out its whole control buffer for a SET_REPORT
+const SET_REPORT_BUF_LEN: usize = 128;
+
+// 16 buttons, a bit each. No axes, the deck has nothing to drive them with.
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = GAMEPAD) = {
+        (usage_page = BUTTON, usage_min = 0x01, usage_max = 0x10) = {
+            #[packed_bits 16] #[item_settings data,variable,absolute] buttons=input;
+        };
     }
+)]
+pub struct GamepadReport {
+    // Little endian, button 1 in bit 0 of the first byte
+    pub buttons: [u8; 2],
 }
 
-impl Default for CustomKeycode {
-    fn default() -> Self {
-        Self::new()
+// `raw_hid::REPORT_LEN` bytes each way on the vendor page, the macro needs the
+// length written out
+#[gen_hid_descriptor(
+    (collection = APPLICATION, usage_page = VENDOR_DEFINED_START, usage = 0x01) = {
+        (usage = 0x02,) = {
+            #[item_settings data,variable,absolute] input=input;
+        };
+        (usage = 0x03,) = {
+            #[item_settings data,variable,absolute] output=output;
+        };
     }
+)]
+pub struct RawHidReport {
+    pub input: [u8; 64],
+    pub output: [u8; 64],
 }
 
-pub struct HIDUtil {
-    pub custom_keycode: CustomKeycode,
-    key_config: EnumMap<KeyConfig, [u8; 2]>,
-    // hid_keyboard: &'static HIDClass<'static, hal::usb::UsbBus>,
-    // hid_media: &'static HIDClass<'static, hal::usb::UsbBus>,
-    mode: KeyMode, // mode flag for keyboardreport and mediareport
+// Reports the longest reply line and its newline take
+const REPLY_FRAMES: usize = (MAX_LINE_LEN + 1).div_ceil(raw_hid::MAX_PAYLOAD);
+// Reply reports waiting for the host, room for the longest replies to a few of the
+// commands one OUT report can carry. A reply that doesn't fit is dropped whole.
+const RAW_HID_QUEUE: usize = 4 * REPLY_FRAMES;
+// Polled faster than the keyboard so replies spanning reports do not drag
+const RAW_HID_POLL_MS: u8 = 10;
+
+/// Vendor-defined interface that carries the config protocol next to serial.
+pub struct RawHid {
+    pub class: HIDClass<'static, hal::usb::UsbBus>,
+    pending: Deque<raw_hid::Report, RAW_HID_QUEUE>,
 }
 
-impl HIDUtil {
-    pub fn new(// hid_keyboard: &'static HIDClass<'static, hal::usb::UsbBus>,
-        // hid_media: &'static HIDClass<'static, hal::usb::UsbBus>,
-    ) -> Self {
-        HIDUtil {
-            custom_keycode: CustomKeycode::new(),
-            key_config: KeyConfig::new(),
-            // hid_keyboard,
-            // hid_media,
-            mode: KeyMode::Keyboard,
+impl RawHid {
+    pub fn new(usb_bus: &'static UsbBusAllocator<hal::usb::UsbBus>, poll_ms: u8) -> Self {
+        RawHid {
+            class: HIDClass::new(usb_bus, RawHidReport::desc(), poll_ms),
+            pending: Deque::new(),
         }
     }
 
-    pub fn push_input(
-        &mut self,
-        hid_keyboard: &HIDClass<'static, hal::usb::UsbBus>,
-        hid_media: &HIDClass<'static, hal::usb::UsbBus>,
-        button_id: KeyConfig,
-        display: &mut Ssd1306<
-            I2CInterface<DisplayI2C>,
-            DisplaySize128x32,
-            ssd1306::mode::BufferedGraphicsMode<DisplaySize128x32>,
-        >,
-    ) {
-        match self.mode {
-            KeyMode::Keyboard => {
-                let keycode = self.key_config[button_id][0];
-                self.custom_keycode.index_map.insert(keycode, true).unwrap();
-
-                // Keycode check - print keycode to display
-                let keycode_string: String<4> = String::from(keycode);
-                display::show_text(display, keycode_string.as_str());
-
-                // If mode changes, release all keys
-                if self.is_mode_switch_pressed() {
-                    let _ = hid_keyboard.push_input(&gen_keyboard_report!(@array [0; 6]));
-                    self.custom_keycode.index_map.clear();
-                    self.change_mode();
-                    return;
-                }
-
-                let _ = hid_keyboard.push_input(&gen_keyboard_report!(@array self
-                        .custom_keycode
-                        .get_keycode_array()));
-            }
-            KeyMode::Media => {
-                let media_key = self.key_config[button_id][1];
-                self.custom_keycode
-                    .index_map
-                    .insert(media_key, true)
-                    .unwrap();
-
-                // Media Key check - print keycode to display
-                let media_key_string: String<4> = String::from(media_key);
-                display::show_text(display, media_key_string.as_str());
-
-                // If mode changes, release all keys
-                if self.is_mode_switch_pressed() {
-                    let _ = hid_media.push_input(&gen_media_report!(MEDIAKEY_NONE));
-                    self.custom_keycode.index_map.clear();
-                    self.change_mode();
-                    return;
-                }
-
-                let _ = hid_media.push_input(&gen_media_report!(
-                    *self.custom_keycode.index_map.last().unwrap().0 // media_key as u16
-                                                                     // 0xCD
-                ));
-            }
+    /// Protocol bytes of the report the host sent, if there is one.
+    pub fn read<'a>(&self, report: &'a mut raw_hid::Report) -> Option<&'a [u8]> {
+        match self.class.pull_raw_output(report) {
+            Ok(_) => raw_hid::payload(report),
+            Err(_) => None,
         }
+    }
 
-        // Testing with display
-        // display::show_text(display, "pushed")
+    /// Queue `text` for the host and send what the endpoint takes now. A reply
+    /// that does not fit the queue is dropped whole, as the host would otherwise
+    /// read the start of it as the start of the next one.
+    pub fn write(&mut self, text: &str) {
+        self.flush();
+        let frames = text.len().div_ceil(raw_hid::MAX_PAYLOAD);
+        if frames > self.pending.capacity() - self.pending.len() {
+            return;
+        }
+        for report in raw_hid::frames(text.as_bytes()) {
+            let _ = self.pending.push_back(report);
+        }
+        self.flush();
     }
 
-    pub fn release_input(
-        &mut self,
-        hid_keyboard: &HIDClass<'static, hal::usb::UsbBus>,
-        hid_media: &HIDClass<'static, hal::usb::UsbBus>,
-        button_id: KeyConfig,
-        display: &mut Ssd1306<
-            I2CInterface<DisplayI2C>,
-            DisplaySize128x32,
-            ssd1306::mode::BufferedGraphicsMode<DisplaySize128x32>,
-        >,
-    ) {
-        match self.mode {
-            KeyMode::Keyboard => {
-                let keycode = self.key_config[button_id][0];
-
-                // Keycode check - print keycode to display
-                // let keycode_string: String<4> = String::from(keycode);
-                // display::show_text(display, keycode_string.as_str());
-                display::show_text(display, "released");
-
-                if !self.custom_keycode.index_map.is_empty() {
-                    self.custom_keycode.index_map.remove(&keycode);
-                    let _ = hid_keyboard.push_input(
-                        &gen_keyboard_report!(@array self.custom_keycode.get_keycode_array()),
-                    );
+    /// Send queued reports until the endpoint is busy. The host reading one
+    /// raises a USB interrupt, which calls this again for the next.
+    pub fn flush(&mut self) {
+        while let Some(report) = self.pending.front() {
+            match self.class.push_raw_input(report) {
+                Ok(_) => {
+                    self.pending.pop_front();
                 }
+                Err(_) => break,
             }
-            KeyMode::Media => {
-                let media_key = self.key_config[button_id][1];
+        }
+    }
+}
+
+pub struct KeyboardHid {
+    pub boot: HIDClass<'static, hal::usb::UsbBus>,
+    pub nkro: HIDClass<'static, hal::usb::UsbBus>,
+}
+
+impl KeyboardHid {
+    pub fn new(usb_bus: &'static UsbBusAllocator<hal::usb::UsbBus>, poll_ms: u8) -> Self {
+        // The boot subclass is what lets the host pick the protocol
+        let boot = HIDClass::new_with_settings(
+            usb_bus,
+            KeyboardReport::desc(),
+            poll_ms,
+            HidClassSettings {
+                subclass: HidSubClass::Boot,
+                protocol: HidProtocol::Keyboard,
+                config: ProtocolModeConfig::DefaultBehavior,
+                locale: HidCountryCode::NotSupported,
+            },
+        );
+        let nkro = HIDClass::new(usb_bus, NkroKeyboardReport::desc(), poll_ms);
+        KeyboardHid { boot, nkro }
+    }
 
-                // Media Key check - print keycode to display
-                // let media_key_string: String<4> = String::from(media_key);
-                // display::show_text(display, media_key_string.as_str());
-                display::show_text(display, "released");
+    // False if the endpoint still holds the last report
+    fn send(&self, report: report::KeyboardReport) -> bool {
+        match self.boot.get_protocol_mode() {
+            Ok(HidProtocolMode::Boot) => self
+                .boot
+                .push_input(&gen_keyboard_report!(
+                    @array report.boot_keycodes(),
+                    report.modifier
+                ))
+                .is_ok(),
+            _ => self
+                .nkro
+                .push_input(&NkroKeyboardReport {
+                    modifier: report.modifier,
+                    leds: 0,
+                    keys: report.keys,
+                })
+                .is_ok(),
+        }
+    }
+}
 
-                if !self.custom_keycode.index_map.is_empty() {
-                    self.custom_keycode.index_map.remove(&media_key);
-                    let _ = hid_media.push_input(&gen_media_report!(MEDIAKEY_NONE));
+impl KeyboardHid {
+    /// Lock LEDs of the latest output report the host sent either keyboard, on
+    /// the OUT endpoint or as a SET_REPORT, if it sent one.
+    pub fn read_leds(&mut self) -> Option<LockLeds> {
+        let mut leds = None;
+        for class in [&mut self.boot, &mut self.nkro] {
+            let mut buf = [0u8; SET_REPORT_BUF_LEN];
+            if let Ok(1..) = class.pull_raw_output(&mut buf) {
+                leds = Some(LockLeds(buf[0]));
+            }
+            match class.pull_raw_report(&mut buf) {
+                Ok(info) if matches!(info.report_type, ReportType::Output) && info.len > 0 => {
+                    leds = Some(LockLeds(buf[0]));
                 }
+                _ => {}
             }
         }
+        leds
+    }
+}
+
+/// Every HID interface of the deck, shared between the tasks as one resource.
+pub struct UsbHid {
+    pub keyboard: KeyboardHid,
+    pub media: HIDClass<'static, hal::usb::UsbBus>,
+    pub system: HIDClass<'static, hal::usb::UsbBus>,
+    pub mouse: HIDClass<'static, hal::usb::UsbBus>,
+    pub gamepad: HIDClass<'static, hal::usb::UsbBus>,
+    pub raw: RawHid,
+}
 
-        // Testing with display
-        // display::show_text(display, "released")
-    }
-
-    fn is_mode_switch_pressed(&mut self) -> bool {
-        if self.custom_keycode.index_map.len() > 1 {
-            let key_status: bool = self
-                .custom_keycode
-                .index_map
-                .iter()
-                .map(|(key, _)| KEY_MODE_BUTTONS.contains(key) || MEDIA_MODE_BUTTONS.contains(key))
-                // .map(|(key, _)| KEY_MODE_BUTTONS.contains(key))
-                .reduce(|a, b| a && b)
-                // .unwrap_or(false);
-                .unwrap();
-
-            // if key_status {
-            //     // WARN: Release all keys when mode changes
-            //     // self.custom_keycode.index_map.clear();
-
-            //     match self.mode {
-            //         KeyMode::Keyboard => self.mode = KeyMode::Media,
-            //         KeyMode::Media => self.mode = KeyMode::Keyboard,
-            //     }
-            // };
-
-            return key_status;
+impl UsbHid {
+    pub fn new(usb_bus: &'static UsbBusAllocator<hal::usb::UsbBus>, poll_ms: u8) -> Self {
+        UsbHid {
+            keyboard: KeyboardHid::new(usb_bus, poll_ms),
+            media: HIDClass::new(usb_bus, MediaKeyboardReport::desc(), poll_ms),
+            system: HIDClass::new(usb_bus, SystemControlReport::desc(), poll_ms),
+            // Polled at the tick rate so every movement step makes it to the host
+            mouse: HIDClass::new(usb_bus, MouseReport::desc(), mouse::TICK_MS as u8),
+            gamepad: HIDClass::new(usb_bus, GamepadReport::desc(), poll_ms),
+            raw: RawHid::new(usb_bus, RAW_HID_POLL_MS),
         }
-        // If condition not met, return false
-        false
     }
 
-    fn change_mode(&mut self) {
-        match self.mode {
-            KeyMode::Keyboard => self.mode = KeyMode::Media,
-            KeyMode::Media => self.mode = KeyMode::Keyboard,
-        }
+    /// Poll the USB device with the serial port and every HID interface. Returns
+    /// true if any of them has data to read.
+    pub fn poll(
+        &mut self,
+        usb_dev: &mut UsbDevice<'static, hal::usb::UsbBus>,
+        serial: &mut SerialPort<'static, hal::usb::UsbBus>,
+    ) -> bool {
+        usb_dev.poll(&mut [
+            serial,
+            &mut self.keyboard.boot,
+            &mut self.keyboard.nkro,
+            &mut self.media,
+            &mut self.system,
+            &mut self.mouse,
+            &mut self.gamepad,
+            &mut self.raw.class,
+        ])
+    }
+}
+
+pub struct UsbReportSink<'a> {
+    usb_hid: &'a UsbHid,
+}
+
+impl<'a> UsbReportSink<'a> {
+    pub fn new(usb_hid: &'a UsbHid) -> Self {
+        UsbReportSink { usb_hid }
     }
 }
 
-impl Default for HIDUtil {
-    fn default() -> Self {
-        Self::new()
+impl ReportSink for UsbReportSink<'_> {
+    fn send_keyboard(&mut self, report: report::KeyboardReport) -> bool {
+        self.usb_hid.keyboard.send(report)
     }
+
+    fn send_media(&mut self, report: report::MediaReport) -> bool {
+        self.usb_hid
+            .media
+            .push_input(&gen_media_report!(report.usage_id))
+            .is_ok()
+    }
+
+    fn send_system(&mut self, report: report::SystemReport) -> bool {
+        self.usb_hid
+            .system
+            .push_input(&SystemControlReport {
+                usage_id: report.usage_id,
+            })
+            .is_ok()
+    }
+
+    fn send_mouse(&mut self, report: report::MouseReport) -> bool {
+        self.usb_hid
+            .mouse
+            .push_input(&MouseReport {
+                buttons: report.buttons,
+                x: report.x,
+                y: report.y,
+                wheel: report.wheel,
+                pan: 0,
+            })
+            .is_ok()
+    }
+
+    fn send_gamepad(&mut self, report: report::GamepadReport) -> bool {
+        self.usb_hid
+            .gamepad
+            .push_input(&GamepadReport {
+                buttons: report.buttons.to_le_bytes(),
+            })
+            .is_ok()
+    }
+
+    fn enter_bootloader(&mut self) {
+        // Both the mass storage and PICOBOOT interfaces, as on a cold boot
+        hal::rom_data::reset_to_usb_boot(0, 0);
+    }
+}
+
+pub fn push_input(
+    hid_util: &mut HIDUtil,
+    usb_hid: &UsbHid,
+    button_id: KeyConfig,
+    screen: &mut Screen,
+) {
+    hid_util.push_input(&mut UsbReportSink::new(usb_hid), button_id);
+    update_keys(hid_util, screen);
+}
+
+/// Put the labels of the layers that are active now on the screen, held buttons
+/// highlighted. Only the screen changes, `render` draws it later.
+pub fn update_keys(hid_util: &HIDUtil, screen: &mut Screen) {
+    screen.set_keys(hid_util.label_texts(), hid_util.held());
+}
+
+pub fn release_input(
+    hid_util: &mut HIDUtil,
+    usb_hid: &UsbHid,
+    button_id: KeyConfig,
+    screen: &mut Screen,
+) {
+    hid_util.release_input(&mut UsbReportSink::new(usb_hid), button_id);
+    update_keys(hid_util, screen);
+}
+
+pub fn combo_input(
+    hid_util: &mut HIDUtil,
+    usb_hid: &UsbHid,
+    action: Action,
+    pressed: bool,
+    screen: &mut Screen,
+) {
+    hid_util.combo_input(&mut UsbReportSink::new(usb_hid), action, pressed);
+    update_keys(hid_util, screen);
+}
+
+/// Send the next step of the playing macro, called once per HID poll interval.
+pub fn macro_step(hid_util: &mut HIDUtil, usb_hid: &UsbHid, current_time: u32) {
+    hid_util.macro_step(&mut UsbReportSink::new(usb_hid), current_time);
+}
+
+/// Send the reports that waited for the host to read the endpoint's last one.
+/// Called from the USB interrupt, which the host reading a report raises.
+pub fn flush_reports(hid_util: &mut HIDUtil, usb_hid: &UsbHid) {
+    hid_util.flush_reports(&mut UsbReportSink::new(usb_hid));
+}
+
+/// Send one step of mouse movement and scrolling, called every `mouse::TICK_MS`.
+pub fn mouse_tick(hid_util: &mut HIDUtil, usb_hid: &UsbHid) {
+    hid_util.mouse_tick(&mut UsbReportSink::new(usb_hid));
+}
+
+/// Take the lock LEDs the host sent. A layer bound to a lock that changed shows
+/// its labels like any other layer switch.
+pub fn set_leds(hid_util: &mut HIDUtil, usb_hid: &UsbHid, leds: LockLeds, screen: &mut Screen) {
+    if hid_util.set_leds(&mut UsbReportSink::new(usb_hid), leds) {
+        update_keys(hid_util, screen);
+    }
+}
+
+pub fn gesture_input(
+    hid_util: &mut HIDUtil,
+    usb_hid: &UsbHid,
+    button_id: KeyConfig,
+    event: GestureEvent,
+    screen: &mut Screen,
+) {
+    hid_util.gesture_input(&mut UsbReportSink::new(usb_hid), button_id, event);
+    update_keys(hid_util, screen);
 }
@@ -17,9 +17,9 @@ macro_rules! gen_keyboard_report {
         }
     };
 
-    (@array $array_val:expr) => {
+    (@array $array_val:expr, $modifier:expr) => {
         KeyboardReport {
-            modifier: 0,
+            modifier: $modifier,
             reserved: 0,
             leds: 0,
             keycodes: $array_val,
@@ -31,7 +31,7 @@ macro_rules! gen_keyboard_report {
 macro_rules! gen_media_report {
     ($usage_id:expr) => {
         MediaKeyboardReport {
-            usage_id: $usage_id as u16,
+            usage_id: $usage_id,
         }
     };
 }
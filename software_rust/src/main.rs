@@ -6,21 +6,18 @@
 use panic_halt as _;
 
 mod button;
-mod debouncer;
 #[macro_use]
 mod macros;
 mod constants;
 mod display;
+mod flash_store;
 mod hid_util;
-mod key_config;
 
-#[rtic::app(device = rp_pico::hal::pac, peripherals = true)]
+// SPI is unused, its interrupts dispatch the software tasks
+#[rtic::app(device = rp_pico::hal::pac, peripherals = true, dispatchers = [SPI0_IRQ, SPI1_IRQ])]
 mod app {
 
-    use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
-    // Time handling traits
-    use embedded_hal::timer::CountDown;
-
+    use embedded_hal::digital::v2::{OutputPin, ToggleableOutputPin};
     // use embedded_time::rate::Extensions;
     use fugit::ExtU32;
     use fugit::MicrosDurationU32;
@@ -28,7 +25,7 @@ mod app {
     use fugit::RateExtU32;
 
     use fugit::SecsDurationU32;
-    use heapless::String;
+    use heapless::{String, Vec};
     // use nb;
 
     // A shorter alias for the Peripheral Access Crate, which provides low-level
@@ -45,10 +42,6 @@ mod app {
     use usb_device::{class_prelude::*, prelude::*};
     // USB Communications Class Device support
     use usbd_serial::SerialPort;
-    // USB HID Class Device support
-    use usbd_hid::descriptor::generator_prelude::*;
-    use usbd_hid::descriptor::{KeyboardReport, MediaKeyboardReport};
-    use usbd_hid::hid_class::HIDClass;
 
     use embedded_graphics::{
         image::{Image, ImageRaw},
@@ -65,15 +58,41 @@ mod app {
         ),
     >;
 
+    use core::fmt::Write;
+    use enum_map::Enum;
+    use pideck_core::protocol::{self, Command, ErrorCode, LineBuffer, Response};
+    use pideck_core::raw_hid;
+
     use crate::button::Button;
     use crate::button::ButtonVariant;
-    use crate::hid_util::HIDUtil;
-    use crate::key_config::KeyConfig;
+    use crate::constants::BUTTON_COUNT;
+    use crate::display;
+    use crate::flash_store;
+    use crate::hid_util::{self, UsbHid, UsbReportSink};
+    use pideck_core::action::Action;
+    use pideck_core::button::{ButtonEvent, ButtonGesture};
+    use pideck_core::combo::{ComboEngine, ComboOutput};
+    use pideck_core::constants::LAYER_COUNT;
+    use pideck_core::hid_util::HIDUtil;
+    use pideck_core::key_config::KeyConfig;
+    use pideck_core::layer;
+    use pideck_core::lock::{Lock, LockLeds};
+    use pideck_core::mouse;
+    use pideck_core::screen::{Screen, Status, UsbState};
 
     // Blink time 5 seconds
     // const SCAN_TIME_US: u32 = 12000000;
     const SCAN_TIME_US: SecsDurationU32 = SecsDurationU32::secs(12);
-    const DEBOUNCE_TIME: MicrosDurationU32 = MicrosDurationU32::micros(26_000);
+    // Buttons are sampled at 1kHz and debounced in software
+    const BUTTON_SCAN_PERIOD: MicrosDurationU32 = MicrosDurationU32::micros(1_000);
+    // Interval the host polls the HID endpoints at. Macros send one report per poll
+    // so none is overwritten before the host has read it.
+    const HID_POLL_MS: u8 = 60;
+    const MACRO_STEP_PERIOD: MillisDurationU32 = MillisDurationU32::millis(HID_POLL_MS as u32);
+    const MOUSE_TICK_PERIOD: MillisDurationU32 = MillisDurationU32::millis(mouse::TICK_MS);
+    // How long a profile picked on the deck has to stay active before it is written
+    // to flash, so stepping through several with NextProfile erases the sector once
+    const PROFILE_STORE_DELAY: MicrosDurationU32 = MicrosDurationU32::secs(3);
 
     #[shared]
     struct Shared {
@@ -82,21 +101,14 @@ mod app {
         alarm1: hal::timer::Alarm1,
         alarm2: hal::timer::Alarm2,
         alarm3: hal::timer::Alarm3,
-        // i2c: hal::i2c::I2C<i2c0, Pin>,
-        // display: ssd1306::Ssd1306<
-        //     ssd1306::I2CDisplayInterface,
-        //     ssd1306::size::DisplaySize128x32,
-        //     ssd1306::rotation::DisplayRotation,
-        // >,
-        display: Ssd1306<
-            I2CInterface<DisplayI2C>,
-            DisplaySize128x32,
-            ssd1306::mode::BufferedGraphicsMode<DisplaySize128x32>,
-        >,
+        // What the display should show, drawn by `render`
+        screen: Screen,
+        // Profile picked on the deck and when, until `store_profile` writes it
+        unstored_profile: Option<(u8, u32)>,
         serial: SerialPort<'static, hal::usb::UsbBus>,
-        usb_hid_keyboard: HIDClass<'static, hal::usb::UsbBus>,
-        usb_hid_media: HIDClass<'static, hal::usb::UsbBus>,
+        usb_hid: UsbHid,
         hid_util: HIDUtil,
+        combos: ComboEngine,
         usb_dev: usb_device::device::UsbDevice<'static, hal::usb::UsbBus>,
         button_array: [Button; 6],
         led: hal::gpio::Pin<hal::gpio::pin::bank0::Gpio25, hal::gpio::ReadableOutput>,
@@ -104,7 +116,14 @@ mod app {
     }
 
     #[local]
-    struct Local {}
+    struct Local {
+        // i2c: hal::i2c::I2C<i2c0, Pin>,
+        display: Ssd1306<
+            I2CInterface<DisplayI2C>,
+            DisplaySize128x32,
+            ssd1306::mode::BufferedGraphicsMode<DisplaySize128x32>,
+        >,
+    }
 
     #[init(local = [usb_bus: Option<usb_device::bus::UsbBusAllocator<hal::usb::UsbBus>> = None])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
@@ -145,28 +164,42 @@ mod app {
 
         // Set up the USB Communications Class Device driver.
         let serial = SerialPort::new(usb_bus);
-        let usb_hid_keyboard = HIDClass::new(usb_bus, KeyboardReport::desc(), 60);
-        let usb_hid_media = HIDClass::new(usb_bus, MediaKeyboardReport::desc(), 60);
+        let usb_hid = UsbHid::new(usb_bus, HID_POLL_MS);
 
         // Helper struct to manage the HID keyboard and media keys.
-        let hid_util = HIDUtil::new();
+        // Profiles come from flash, or the compiled-in defaults if none are stored.
+        let (profiles, active) = flash_store::load();
+        let mut hid_util = HIDUtil::new(profiles[0].keymap);
+        for (index, profile) in profiles.iter().enumerate() {
+            hid_util.set_profile(index as u8, *profile);
+        }
+        // Nothing is held and no lock is lit yet, the switch sends nothing that matters
+        hid_util.select_profile(&mut UsbReportSink::new(&usb_hid), active);
+        let mut combos = ComboEngine::new(*hid_util.combos());
 
         // Create a USB device with a fake VID and PID
-        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
-            .manufacturer("YomiTosh")
-            .product("Pi Deck Pico")
-            .serial_number("D001")
-            .device_class(2) // from: https://www.usb.org/defined-class-codes
-            .build();
+        let usb_dev =
+            UsbDeviceBuilder::new(usb_bus, UsbVidPid(protocol::USB_VID, protocol::USB_PID))
+                .manufacturer("YomiTosh")
+                .product("Pi Deck Pico")
+                .serial_number("D001")
+                .device_class(2) // from: https://www.usb.org/defined-class-codes
+                .build();
 
         let mut timer = hal::Timer::new(ctx.device.TIMER, &mut resets);
         let mut alarm0 = timer.alarm_0().unwrap();
         let _ = alarm0.schedule(SCAN_TIME_US);
         // let _ = alarm0.schedule();
         alarm0.enable_interrupt();
-        let alarm1 = timer.alarm_1().unwrap();
-        let alarm2 = timer.alarm_2().unwrap();
-        let alarm3 = timer.alarm_3().unwrap();
+        let mut alarm1 = timer.alarm_1().unwrap();
+        let _ = alarm1.schedule(BUTTON_SCAN_PERIOD);
+        alarm1.enable_interrupt();
+        // Only scheduled while a macro plays or a text is typed
+        let mut alarm2 = timer.alarm_2().unwrap();
+        alarm2.enable_interrupt();
+        // Only scheduled while mouse movement or scrolling is held
+        let mut alarm3 = timer.alarm_3().unwrap();
+        alarm3.enable_interrupt();
         // Consider using a shared delay in future
         // let mut delay = timer.count_down();
 
@@ -207,7 +240,7 @@ mod app {
         im.draw(&mut display).unwrap();
         display.flush().unwrap();
 
-        let button_array: [Button; 6] = [
+        let mut button_array: [Button; 6] = [
             Button::new(ButtonVariant::One {
                 gpio: pins.gpio26.into_mode(),
                 id: KeyConfig::One,
@@ -233,10 +266,11 @@ mod app {
                 id: KeyConfig::Six,
             }),
         ];
-
-        for button in button_array.iter() {
-            button.variant.set_button_low_interrupt(true)
-        }
+        apply_profile(&hid_util, &mut button_array, &mut combos);
+        let mut screen = Screen::new();
+        hid_util::update_keys(&hid_util, &mut screen);
+        // The logo stays up until then
+        let _ = render::spawn();
 
         (
             Shared {
@@ -245,29 +279,24 @@ mod app {
                 alarm1,
                 alarm2,
                 alarm3,
-                display,
+                screen,
+                unstored_profile: None,
                 serial,
-                usb_hid_keyboard,
-                usb_hid_media,
+                usb_hid,
                 hid_util,
+                combos,
                 usb_dev,
                 button_array,
                 led,
                 led_blink_enable,
             },
-            Local {},
+            Local { display },
             init::Monotonics(),
         )
     }
 
     #[idle(shared = [button_array])]
     fn idle(_ctx: idle::Context) -> ! {
-        // (_ctx.shared.button_array).lock(|button_array_a| {
-        //     for button in button_array_a.iter_mut() {
-        //         button.variant.set_button_low_interrupt(true)
-        //     }
-        // });
-
         loop {
             cortex_m::asm::nop();
         }
@@ -279,176 +308,745 @@ mod app {
     // #[task(shared = [])]
     // fn test_shared_task(mut ctx: test_shared_task::Context) {}
 
-    #[task(binds = USBCTRL_IRQ, priority = 3, shared = [serial, usb_dev, usb_hid_keyboard, usb_hid_media])]
+    #[task(
+        binds = USBCTRL_IRQ,
+        priority = 3,
+        shared = [serial, usb_dev, usb_hid, hid_util, button_array, combos, screen],
+        local = [
+            line_buffer: LineBuffer = LineBuffer::new(),
+            raw_line_buffer: LineBuffer = LineBuffer::new(),
+            // USB state and DTR as the status bar last heard of them
+            usb_seen: Option<(UsbDeviceState, bool)> = None,
+        ]
+    )]
     fn usb_rx(ctx: usb_rx::Context) {
         let usb_dev = ctx.shared.usb_dev;
         let serial = ctx.shared.serial;
-        let usb_hid = ctx.shared.usb_hid_keyboard;
-
-        (serial, usb_dev, usb_hid).lock(|serial_a, usb_dev_a, usb_hid_a| {
-            if usb_dev_a.poll(&mut [serial_a, usb_hid_a]) {
-                let mut buf = [0u8; 64];
-                match serial_a.read(&mut buf) {
-                    Err(_e) => {
-                        //Do Nothing
-                        // let _ = serial_a.write(b"Error.")
-                        // let _ = serial_a.flush()
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
+        let button_array = ctx.shared.button_array;
+        let combos = ctx.shared.combos;
+        let mut screen = ctx.shared.screen;
+        let line_buffer = ctx.local.line_buffer;
+        // Its own buffer so a line half sent over one channel is not finished by the other
+        let raw_line_buffer = ctx.local.raw_line_buffer;
+        let usb_seen = ctx.local.usb_seen;
+
+        (serial, usb_dev, usb_hid, hid_util, button_array, combos).lock(
+            |serial_a, usb_dev_a, usb_hid_a, hid_util_a, button_array_a, combos_a| {
+                if usb_hid_a.poll(usb_dev_a, serial_a) {
+                    // A command may have changed anything on the screen, the
+                    // labels, the keymap they fall back on or the layers
+                    let mut commanded = false;
+                    let mut buf = [0u8; 64];
+                    match serial_a.read(&mut buf) {
+                        Err(_e) => {
+                            //Do Nothing
+                        }
+                        Ok(0) => {
+                            // Do nothing
+                        }
+                        Ok(count) => {
+                            for byte in buf[..count].iter() {
+                                if let Some(reply) = handle_byte(
+                                    *byte,
+                                    line_buffer,
+                                    hid_util_a,
+                                    button_array_a,
+                                    combos_a,
+                                    usb_hid_a,
+                                ) {
+                                    write_serial(serial_a, reply.as_str(), false);
+                                    commanded = true;
+                                }
+                            }
+                        }
+                    }
+
+                    let mut report = [0u8; raw_hid::REPORT_LEN];
+                    if let Some(bytes) = usb_hid_a.raw.read(&mut report) {
+                        for byte in bytes.iter() {
+                            if let Some(reply) = handle_byte(
+                                *byte,
+                                raw_line_buffer,
+                                hid_util_a,
+                                button_array_a,
+                                combos_a,
+                                usb_hid_a,
+                            ) {
+                                usb_hid_a.raw.write(reply.as_str());
+                                commanded = true;
+                            }
+                        }
+                    }
+                    // The host may have read a report, send the next of a long reply
+                    usb_hid_a.raw.flush();
+                    // Or a report a tap's release waits behind
+                    hid_util::flush_reports(hid_util_a, usb_hid_a);
+
+                    if commanded {
+                        screen.lock(|screen_a| hid_util::update_keys(hid_util_a, screen_a));
+                        let _ = render::spawn();
+                    }
+
+                    if let Some(leds) = usb_hid_a.keyboard.read_leds() {
+                        let _ = leds_event::spawn(leds);
+                    }
+                }
+
+                // Suspend, resume and reset come through here without any data
+                let seen = Some((usb_dev_a.state(), serial_a.dtr()));
+                if seen != *usb_seen {
+                    *usb_seen = seen;
+                    let _ = render::spawn();
+                }
+            },
+        )
+    }
+
+    /// Feed a received byte to `line_buffer`. Returns the reply once it completes a
+    /// line, serial and raw HID answer the same way.
+    fn handle_byte(
+        byte: u8,
+        line_buffer: &mut LineBuffer,
+        hid_util: &mut HIDUtil,
+        button_array: &mut [Button; BUTTON_COUNT],
+        combos: &mut ComboEngine,
+        usb_hid: &UsbHid,
+    ) -> Option<String<{ protocol::MAX_LINE_LEN }>> {
+        if !line_buffer.push(byte) {
+            return None;
+        }
+
+        let response = match line_buffer.take_line() {
+            // Blank lines are ignored rather than answered
+            Ok([]) => return None,
+            Ok(line) => match protocol::parse(line, BUTTON_COUNT as u8) {
+                Ok(command) => run_command(command, hid_util, button_array, combos, usb_hid),
+                Err(code) => Response::Err(code),
+            },
+            Err(code) => Response::Err(code),
+        };
+
+        let mut reply: String<{ protocol::MAX_LINE_LEN }> = String::new();
+        let _ = write!(reply, "{}", response);
+        Some(reply)
+    }
+
+    /// Carry out a configuration command received over serial or raw HID.
+    fn run_command(
+        command: Command,
+        hid_util: &mut HIDUtil,
+        button_array: &mut [Button; BUTTON_COUNT],
+        combos: &mut ComboEngine,
+        usb_hid: &UsbHid,
+    ) -> Response<'static> {
+        match command {
+            Command::Version => Response::Version(env!("CARGO_PKG_VERSION")),
+            Command::Get { button } => {
+                let keys =
+                    layer::keys_of(&hid_util.keymap()[KeyConfig::from_usize(button as usize - 1)]);
+                Response::Key {
+                    button,
+                    keycode: keys.0,
+                    media_key: keys.1,
+                }
+            }
+            Command::Set {
+                button,
+                keycode,
+                media_key,
+            } => {
+                hid_util.set_keys(
+                    KeyConfig::from_usize(button as usize - 1),
+                    (keycode, media_key),
+                );
+                Response::Ok
+            }
+            Command::Map {
+                layer,
+                button,
+                action,
+            } => {
+                let id = KeyConfig::from_usize(button as usize - 1);
+                if let Some(action) = action {
+                    hid_util.set_action(layer, id, action);
+                }
+                Response::Map {
+                    layer,
+                    button,
+                    action: hid_util.keymap()[id][layer as usize],
+                }
+            }
+            Command::Label {
+                layer,
+                button,
+                label,
+            } => {
+                let id = KeyConfig::from_usize(button as usize - 1);
+                if let Some(label) = label {
+                    hid_util.set_label(layer, id, label);
+                }
+                Response::Label {
+                    layer,
+                    button,
+                    label: *hid_util.label(layer, id),
+                }
+            }
+            Command::Layer(change) => {
+                if let Some((layer, active)) = change {
+                    hid_util.set_layer(&mut UsbReportSink::new(usb_hid), layer, active);
+                }
+                let layers = hid_util.layers().as_slice();
+                let mut active = [0u8; LAYER_COUNT];
+                active[..layers.len()].copy_from_slice(layers);
+                Response::Layers {
+                    active,
+                    count: layers.len() as u8,
+                }
+            }
+            Command::State => {
+                let mut pressed = 0u8;
+                for (index, button) in button_array.iter().enumerate() {
+                    if button.is_pressed {
+                        pressed |= 1 << index;
                     }
-                    Ok(0) => {
-                        // Do nothing
-                        let _ = serial_a.write(b"Didn't received data.");
-                        let _ = serial_a.flush();
+                }
+                Response::State {
+                    pressed,
+                    count: BUTTON_COUNT as u8,
+                }
+            }
+            Command::Debounce { button, strategy } => {
+                let id = KeyConfig::from_usize(button as usize - 1);
+                if let Some(strategy) = strategy {
+                    hid_util.set_debounce(id, strategy);
+                    button_array[id.into_usize()].set_debounce_strategy(strategy);
+                }
+                Response::Debounce {
+                    button,
+                    strategy: hid_util.debounce(id),
+                }
+            }
+            Command::Gesture {
+                button,
+                gesture,
+                action,
+            } => {
+                let id = KeyConfig::from_usize(button as usize - 1);
+                if let Some(action) = action {
+                    let mut gestures = *hid_util.gestures(id);
+                    gestures.actions[gesture] = action;
+                    hid_util.set_gestures(id, gestures);
+                    button_array[id.into_usize()].set_gestures(&gestures);
+                }
+                Response::Gesture {
+                    button,
+                    gesture,
+                    action: hid_util.gestures(id).actions[gesture],
+                }
+            }
+            Command::Timing { button, timing } => {
+                let id = KeyConfig::from_usize(button as usize - 1);
+                if let Some(timing) = timing {
+                    let mut gestures = *hid_util.gestures(id);
+                    gestures.timing = timing;
+                    hid_util.set_gestures(id, gestures);
+                    button_array[id.into_usize()].set_gestures(&gestures);
+                }
+                Response::Timing {
+                    button,
+                    timing: hid_util.gestures(id).timing,
+                }
+            }
+            Command::Combo { slot, combo } => {
+                if let Some(combo) = combo {
+                    let mut config = *combos.config();
+                    config.combos[slot as usize] = combo;
+                    hid_util.set_combos(config);
+                    combos.configure(config);
+                }
+                Response::Combo {
+                    slot,
+                    combo: combos.config().combos[slot as usize],
+                }
+            }
+            Command::ComboWindow(ms) => {
+                if let Some(ms) = ms {
+                    let mut config = *combos.config();
+                    config.window_us = ms as u32 * 1000;
+                    hid_util.set_combos(config);
+                    combos.configure(config);
+                }
+                Response::ComboWindow((combos.config().window_us / 1000) as u16)
+            }
+            Command::Macro { slot, code } => {
+                if let Some(code) = code {
+                    hid_util.set_macro(slot, code);
+                }
+                Response::Macro {
+                    slot,
+                    code: hid_util.macros()[slot as usize],
+                }
+            }
+            Command::Leds => Response::Leds(hid_util.leds()),
+            Command::LockLayer { lock, layer } => {
+                if let Some(layer) = layer {
+                    hid_util.set_lock_layer(&mut UsbReportSink::new(usb_hid), lock, layer);
+                }
+                Response::LockLayer {
+                    lock,
+                    layer: hid_util.lock_layers()[lock],
+                }
+            }
+            Command::Text { slot, text } => {
+                if let Some(text) = text {
+                    hid_util.set_text(slot, text);
+                }
+                Response::Text {
+                    slot,
+                    text: hid_util.text_config().texts[slot as usize],
+                }
+            }
+            Command::Unicode(method) => {
+                if let Some(method) = method {
+                    hid_util.set_unicode_method(method);
+                }
+                Response::Unicode(hid_util.text_config().method)
+            }
+            Command::Layout(layout) => {
+                if let Some(layout) = layout {
+                    hid_util.set_layout(layout);
+                }
+                Response::Layout(hid_util.text_config().layout)
+            }
+            Command::Profile(profile) => {
+                if let Some(profile) = profile {
+                    if hid_util.select_profile(&mut UsbReportSink::new(usb_hid), profile) {
+                        let _ = profile_event::spawn(true);
                     }
-                    Ok(_count) => {
-                        // match_usb_serial_buf(&buf, led_a, led_blink_enable_a, serial_a, counter_a);
-                        write_serial(serial_a, "I'm here", false)
+                }
+                let active = hid_util.active_profile();
+                Response::Profile {
+                    profile: active,
+                    name: hid_util.profiles()[active as usize].name,
+                }
+            }
+            Command::ProfileName { profile, name } => {
+                if let Some(name) = name {
+                    hid_util.set_profile_name(profile, name);
+                }
+                Response::Profile {
+                    profile,
+                    name: hid_util.profiles()[profile as usize].name,
+                }
+            }
+            Command::SelectProfile(name) => match hid_util.find_profile(&name) {
+                Some(profile) => {
+                    // The host daemon switches on every change of focus. The deck
+                    // keeps its switches in RAM, so following the focus all day
+                    // doesn't wear the flash, it starts up in the last profile picked
+                    // on the deck or with `profile`.
+                    if hid_util.select_profile(&mut UsbReportSink::new(usb_hid), profile) {
+                        let _ = profile_event::spawn(false);
                     }
+                    Response::Profile { profile, name }
+                }
+                None => Response::Err(ErrorCode::UnknownProfile),
+            },
+            Command::Save => {
+                match flash_store::save(hid_util.profiles(), hid_util.active_profile()) {
+                    Ok(()) => Response::Ok,
+                    Err(_) => Response::Err(ErrorCode::StorageFailed),
                 }
-                // TODO: USB HID action here
-                // usb_hid_a.match
-                // let key = MediaKey
             }
-        })
+        }
     }
 
+    /// Hand the active profile's debounce, gestures and combos to the buttons and
+    /// the combo engine, which keep their own copies.
+    fn apply_profile(
+        hid_util: &HIDUtil,
+        button_array: &mut [Button; BUTTON_COUNT],
+        combos: &mut ComboEngine,
+    ) {
+        for button in button_array.iter_mut() {
+            let id = button.variant.get_id();
+            button.set_debounce_strategy(hid_util.debounce(id));
+            button.set_gestures(hid_util.gestures(id));
+        }
+        combos.configure(*hid_util.combos());
+    }
+
+    // Periodic button scan. Every pin is sampled each tick so the debouncer sees the
+    // real pin state. Debounced edges go through the combo engine first, recognised
+    // combos are queued to `combo_event` and the edges it lets through to `hid_event`
+    // at a lower priority. Buttons with gestures bound queue recognised gestures to
+    // `gesture_event` instead. It also times how long a picked profile has been
+    // active and hands it to `store_profile` once it has settled.
     #[task(
-        binds = IO_IRQ_BANK0,
+        binds = TIMER_IRQ_1,
         priority = 4,
-        shared = [led, serial, timer, alarm1, alarm2, display, button_array, usb_hid_keyboard, usb_hid_media, hid_util]
+        shared = [timer, alarm1, button_array, combos, unstored_profile]
     )]
-    fn handle_button(ctx: handle_button::Context) {
-        let led = ctx.shared.led;
+    fn scan_buttons(ctx: scan_buttons::Context) {
+        let timer = ctx.shared.timer;
+        let alarm1 = ctx.shared.alarm1;
         let button_array = ctx.shared.button_array;
+        let combos = ctx.shared.combos;
+        let mut unstored_profile = ctx.shared.unstored_profile;
 
-        let display = ctx.shared.display;
+        (timer, alarm1, button_array, combos).lock(|timer_a, alarm_a, button_array_a, combos_a| {
+            alarm_a.clear_interrupt();
+            let _ = alarm_a.schedule(BUTTON_SCAN_PERIOD);
+
+            // The queues only fill if HID handling stalls, dropping is all we can do
+            let current_time = timer_a.get_counter_low();
+            let mut edges: Vec<ButtonEvent, { 2 * BUTTON_COUNT }> = Vec::new();
+            for button in button_array_a.iter_mut() {
+                let event = button.scan(timer_a);
+                combos_a.update(current_time, event, |output| match output {
+                    ComboOutput::Button(event) => {
+                        let _ = edges.push(event);
+                    }
+                    ComboOutput::Action { action, pressed } => {
+                        let _ = combo_event::spawn(action, pressed);
+                    }
+                });
+            }
 
-        let usb_hid_keyboard = ctx.shared.usb_hid_keyboard;
-        let usb_hid_media = ctx.shared.usb_hid_media;
-        let hid_util = ctx.shared.hid_util;
+            for button in button_array_a.iter_mut() {
+                let id = button.variant.get_id();
+                let mut button_edges = edges
+                    .iter()
+                    .filter(|event| event.id == id)
+                    .copied()
+                    .peekable();
+                if button.has_gestures() {
+                    // Ticks without an edge still time the held presses
+                    if button_edges.peek().is_none() {
+                        if let Some(gesture) = button.gesture(timer_a, None) {
+                            let _ = gesture_event::spawn(gesture);
+                        }
+                    }
+                    for event in button_edges {
+                        if let Some(gesture) = button.gesture(timer_a, Some(event)) {
+                            let _ = gesture_event::spawn(gesture);
+                        }
+                    }
+                } else {
+                    for event in button_edges {
+                        let _ = hid_event::spawn(event);
+                    }
+                }
+            }
 
-        let serial = ctx.shared.serial;
+            unstored_profile.lock(|unstored_a| {
+                if let Some((profile, since)) = *unstored_a {
+                    if current_time.wrapping_sub(since) >= PROFILE_STORE_DELAY.to_micros()
+                        && store_profile::spawn(profile).is_ok()
+                    {
+                        *unstored_a = None;
+                    }
+                }
+            });
+        });
+    }
 
-        let timer = ctx.shared.timer;
-        let alarm1 = ctx.shared.alarm1;
+    #[task(
+        priority = 2,
+        capacity = 8,
+        shared = [led, led_blink_enable, screen, usb_hid, hid_util, alarm2, alarm3]
+    )]
+    fn hid_event(ctx: hid_event::Context, event: ButtonEvent) {
+        let led = ctx.shared.led;
+        let led_blink_enable = ctx.shared.led_blink_enable;
+        let screen = ctx.shared.screen;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
         let alarm2 = ctx.shared.alarm2;
+        let alarm3 = ctx.shared.alarm3;
 
         (
-            serial,
-            timer,
-            alarm1,
-            alarm2,
-            display,
-            button_array,
             led,
-            usb_hid_keyboard,
-            usb_hid_media,
+            led_blink_enable,
+            screen,
+            usb_hid,
             hid_util,
+            alarm2,
+            alarm3,
         )
             .lock(
-                |serial_a,
-                 timer_a,
+                |led_a,
+                 led_blink_enable_a,
+                 screen_a,
+                 usb_hid_a,
+                 hid_util_a,
                  alarm_a,
-                 alarm_b,
-                 display_a,
-                 button_array_a,
-                 led_a,
-                 usb_hid_keyboard_a,
-                 usb_hid_media_a,
-                 hid_util_a| {
-                    // TODO: This is running multiple times, not expected behaviour.
-                    // It does not break and turns off led as it turns it on except last key.
-                    // Return boolean from is_low and use it to determine break.
-                    // To possibly detect multiple keys, save keys pressed into array then act
-                    // on it later.
-
-                    for (index, button) in button_array_a.iter_mut().enumerate() {
-                        let mut serial_message: String<8> = String::new();
-                        let mut serial_message_2: String<8> = String::new();
-                        let mut serial_message_3: String<8> = String::new();
-                        let mut serial_message_4: String<8> = String::new();
-
-                        let index_string: String<1> = String::from(index as u8);
-
-                        serial_message.push_str("\nKey ").unwrap();
-                        serial_message.push_str(&index_string).unwrap();
-                        // write_serial(serial_a, serial_message.as_str(), false);
-
-                        // TODO: the raw value is always 0 when the interrupt is triggered
-                        // This does not allow the debouncer to reset its state
-                        let button_state = button.variant.is_low().unwrap();
-                        button.debounce(timer_a, button_state);
-
-                        let mut count_down = timer_a.count_down();
-                        count_down.start(DEBOUNCE_TIME);
-                        let _ = nb::block!(count_down.wait());
-
-                        button.debounce(timer_a, button_state);
-                        // if button_state {
-                        //     serial_message_4
-                        //         .push_str(&String::<32>::from("LOW_"))
-                        //         .unwrap();
-                        //     write_serial(serial_a, serial_message_4.as_str(), false);
-                        // } else {
-                        //     serial_message_4
-                        //         .push_str(&String::<32>::from("HIGH_"))
-                        //         .unwrap();
-                        //     write_serial(serial_a, serial_message_4.as_str(), false);
-                        // }
-
-                        if !button.is_pressed && button.to_be_released {
-                            button.to_be_released = false;
-
-                            serial_message_3
-                                .push_str(&String::<32>::from("B_"))
-                                .unwrap();
-                            write_serial(serial_a, serial_message_3.as_str(), false);
-
-                            // usb hid action
-                            // let _ = button.variant.release_key(usb_hid_keyboard_a);
-                            hid_util_a.release_input(
-                                usb_hid_keyboard_a,
-                                usb_hid_media_a,
-                                button.variant.get_id(),
-                                display_a,
-                            );
-
-                            //     let _ = led_a.toggle();
-                            // button.variant.clear_button_high_interrupt();
-                            button.variant.set_button_high_interrupt(false);
-                            // button.variant.set_button_low_interrupt(true);
+                 mouse_alarm_a| {
+                    let profile = hid_util_a.active_profile();
+                    if event.pressed {
+                        // Until the host sends lock LEDs, then it shows Caps Lock
+                        if *led_blink_enable_a {
+                            let _ = led_a.toggle();
                         }
 
-                        if button.is_pressed {
-                            // improvements using this but there are numerous miss clicks and delayed presses
-                            button.reset();
+                        hid_util::push_input(hid_util_a, usb_hid_a, event.id, screen_a);
+                    } else {
+                        hid_util::release_input(hid_util_a, usb_hid_a, event.id, screen_a);
+                    }
+                    if hid_util_a.active_profile() != profile {
+                        let _ = profile_event::spawn(true);
+                    }
+                    let _ = render::spawn();
+                    start_macro_steps(alarm_a, hid_util_a);
+                    start_mouse_ticks(mouse_alarm_a, hid_util_a);
+                },
+            );
+    }
 
-                            serial_message_2
-                                .push_str(&String::<32>::from("A_"))
-                                .unwrap();
-                            write_serial(serial_a, serial_message_2.as_str(), false);
+    // Lock LEDs the host sent the keyboards. The onboard LED stops blinking and
+    // follows Caps Lock from then on.
+    #[task(
+        priority = 2,
+        capacity = 4,
+        shared = [led, led_blink_enable, screen, usb_hid, hid_util]
+    )]
+    fn leds_event(ctx: leds_event::Context, leds: LockLeds) {
+        let led = ctx.shared.led;
+        let led_blink_enable = ctx.shared.led_blink_enable;
+        let screen = ctx.shared.screen;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
 
-                            let _ = led_a.toggle();
+        (led, led_blink_enable, screen, usb_hid, hid_util).lock(
+            |led_a, led_blink_enable_a, screen_a, usb_hid_a, hid_util_a| {
+                *led_blink_enable_a = false;
+                if leds.is_on(Lock::Caps) {
+                    led_a.set_high().unwrap();
+                } else {
+                    led_a.set_low().unwrap();
+                }
+                hid_util::set_leds(hid_util_a, usb_hid_a, leds, screen_a);
+            },
+        );
+        let _ = render::spawn();
+    }
 
-                            // usb hid action
-                            // let _ = button.variant.send_key(usb_hid_keyboard_a);
-                            hid_util_a.push_input(
-                                usb_hid_keyboard_a,
-                                usb_hid_media_a,
-                                button.variant.get_id(),
-                                display_a,
-                            );
+    #[task(
+        priority = 2,
+        capacity = 8,
+        shared = [screen, usb_hid, hid_util, alarm2, alarm3]
+    )]
+    fn gesture_event(ctx: gesture_event::Context, gesture: ButtonGesture) {
+        let screen = ctx.shared.screen;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
+        let alarm2 = ctx.shared.alarm2;
+        let alarm3 = ctx.shared.alarm3;
+
+        (screen, usb_hid, hid_util, alarm2, alarm3).lock(
+            |screen_a, usb_hid_a, hid_util_a, alarm_a, mouse_alarm_a| {
+                let profile = hid_util_a.active_profile();
+                hid_util::gesture_input(hid_util_a, usb_hid_a, gesture.id, gesture.event, screen_a);
+                if hid_util_a.active_profile() != profile {
+                    let _ = profile_event::spawn(true);
+                }
+                let _ = render::spawn();
+                start_macro_steps(alarm_a, hid_util_a);
+                start_mouse_ticks(mouse_alarm_a, hid_util_a);
+            },
+        );
+    }
 
-                            button.variant.set_button_high_interrupt(true);
+    #[task(
+        priority = 2,
+        capacity = 8,
+        shared = [screen, usb_hid, hid_util, alarm2, alarm3]
+    )]
+    fn combo_event(ctx: combo_event::Context, action: Action, pressed: bool) {
+        let screen = ctx.shared.screen;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
+        let alarm2 = ctx.shared.alarm2;
+        let alarm3 = ctx.shared.alarm3;
+
+        (screen, usb_hid, hid_util, alarm2, alarm3).lock(
+            |screen_a, usb_hid_a, hid_util_a, alarm_a, mouse_alarm_a| {
+                let profile = hid_util_a.active_profile();
+                hid_util::combo_input(hid_util_a, usb_hid_a, action, pressed, screen_a);
+                if hid_util_a.active_profile() != profile {
+                    let _ = profile_event::spawn(true);
+                }
+                let _ = render::spawn();
+                start_macro_steps(alarm_a, hid_util_a);
+                start_mouse_ticks(mouse_alarm_a, hid_util_a);
+            },
+        );
+    }
 
-                            button.to_be_released = true;
-                        }
-                    }
+    // The active profile changed, from a button or the config protocol. The buttons
+    // and combo engine pick up its config and the display shows its labels. With
+    // `store` the switch is also noted down for `store_profile`, so the deck starts
+    // up in it. Runs for switches in a row all read the profile active by then.
+    #[task(
+        priority = 2,
+        capacity = 4,
+        shared = [timer, screen, hid_util, button_array, combos, unstored_profile]
+    )]
+    fn profile_event(ctx: profile_event::Context, store: bool) {
+        let timer = ctx.shared.timer;
+        let screen = ctx.shared.screen;
+        let hid_util = ctx.shared.hid_util;
+        let button_array = ctx.shared.button_array;
+        let combos = ctx.shared.combos;
+        let unstored_profile = ctx.shared.unstored_profile;
 
-                    for button in button_array_a.iter_mut() {
-                        button.variant.clear_button_low_interrupt();
+        (
+            timer,
+            screen,
+            hid_util,
+            button_array,
+            combos,
+            unstored_profile,
+        )
+            .lock(
+                |timer_a, screen_a, hid_util_a, button_array_a, combos_a, unstored_a| {
+                    apply_profile(hid_util_a, button_array_a, combos_a);
+                    hid_util::update_keys(hid_util_a, screen_a);
+                    if store {
+                        *unstored_a =
+                            Some((hid_util_a.active_profile(), timer_a.get_counter_low()));
                     }
                 },
             );
+        let _ = render::spawn();
+    }
+
+    // Write a profile picked on the deck to flash once it has stayed active for
+    // PROFILE_STORE_DELAY. Only the active profile byte changes, unsaved changes to
+    // the config are not saved with it. Erasing the sector stalls everything for up
+    // to hundreds of ms, which is why a switch isn't written straight away.
+    #[task(priority = 1)]
+    fn store_profile(_ctx: store_profile::Context, profile: u8) {
+        // Without a saved image there is nothing to switch, the deck starts in the
+        // first profile until the config is saved
+        let _ = flash_store::save_active_profile(profile);
+    }
+
+    // Draw what changed on the screen and send it to the display. Spawned after
+    // everything that may change it, and below the HID tasks so the slow I2C
+    // transfer never holds up a key press, and a burst of presses is one redraw
+    // once they are through. The display is this task's alone, the others only
+    // update the screen.
+    #[task(
+        priority = 1,
+        shared = [screen, hid_util, usb_dev, serial],
+        local = [display]
+    )]
+    fn render(ctx: render::Context) {
+        let mut screen = ctx.shared.screen;
+        let mut hid_util = ctx.shared.hid_util;
+        let mut usb_dev = ctx.shared.usb_dev;
+        let mut serial = ctx.shared.serial;
+        let display = ctx.local.display;
+
+        // Nothing tells the status bar about its fields, they are read here
+        let (profile, profile_name, layer, caps_lock) = hid_util.lock(|hid_util_a| {
+            (
+                hid_util_a.active_profile(),
+                hid_util_a.profile().name,
+                hid_util_a.layers().top(),
+                hid_util_a.leds().is_on(Lock::Caps),
+            )
+        });
+        let status = Status {
+            profile,
+            profile_name,
+            layer,
+            caps_lock,
+            usb: usb_dev.lock(|usb_dev_a| usb_state(usb_dev_a.state())),
+            serial: serial.lock(|serial_a| serial_a.dtr()),
+        };
+
+        // Only copied under the lock, drawn after it
+        let frame = screen.lock(|screen_a| {
+            screen_a.set_status(status);
+            screen_a.take()
+        });
+        // A display that doesn't answer mustn't take the deck down with it. It
+        // gets everything again on the next render.
+        if display::show(display, &frame).is_err() {
+            screen.lock(|screen_a| screen_a.redraw());
+        }
+    }
+
+    fn usb_state(state: UsbDeviceState) -> UsbState {
+        match state {
+            UsbDeviceState::Configured => UsbState::Configured,
+            UsbDeviceState::Suspend => UsbState::Suspended,
+            UsbDeviceState::Default | UsbDeviceState::Addressed => UsbState::Enumerating,
+        }
+    }
+
+    // Start the macro tick if the input just started a macro or text. It keeps
+    // itself going from then on, so a press while one is already playing leaves it
+    // alone.
+    fn start_macro_steps(alarm: &mut hal::timer::Alarm2, hid_util: &HIDUtil) {
+        if hid_util.is_playing_macro() && alarm.finished() {
+            // Shortest delay the alarm takes, the first step goes out right away
+            let _ = alarm.schedule(MicrosDurationU32::micros(10));
+        }
+    }
+
+    // Plays the running macro or text one step per HID poll interval, so the host
+    // reads every report before the next replaces it.
+    #[task(
+        binds = TIMER_IRQ_2,
+        priority = 2,
+        shared = [timer, alarm2, usb_hid, hid_util]
+    )]
+    fn macro_tick(ctx: macro_tick::Context) {
+        let timer = ctx.shared.timer;
+        let alarm2 = ctx.shared.alarm2;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
+
+        (timer, alarm2, usb_hid, hid_util).lock(|timer_a, alarm_a, usb_hid_a, hid_util_a| {
+            alarm_a.clear_interrupt();
+            hid_util::macro_step(hid_util_a, usb_hid_a, timer_a.get_counter_low());
+            if hid_util_a.is_playing_macro() {
+                let _ = alarm_a.schedule(MACRO_STEP_PERIOD);
+            }
+        });
+    }
+
+    // Start the mouse tick if the input started moving or scrolling, like the macro
+    // tick it keeps itself going until nothing is held.
+    fn start_mouse_ticks(alarm: &mut hal::timer::Alarm3, hid_util: &HIDUtil) {
+        if hid_util.is_moving_mouse() && alarm.finished() {
+            let _ = alarm.schedule(MicrosDurationU32::micros(10));
+        }
+    }
+
+    // Sends mouse movement and scrolling every TICK_MS while their buttons are held
+    #[task(
+        binds = TIMER_IRQ_3,
+        priority = 2,
+        shared = [alarm3, usb_hid, hid_util]
+    )]
+    fn mouse_tick(ctx: mouse_tick::Context) {
+        let alarm3 = ctx.shared.alarm3;
+        let usb_hid = ctx.shared.usb_hid;
+        let hid_util = ctx.shared.hid_util;
+
+        (alarm3, usb_hid, hid_util).lock(|alarm_a, usb_hid_a, hid_util_a| {
+            alarm_a.clear_interrupt();
+            hid_util::mouse_tick(hid_util_a, usb_hid_a);
+            if hid_util_a.is_moving_mouse() {
+                let _ = alarm_a.schedule(MOUSE_TICK_PERIOD);
+            }
+        });
     }
 
     //This works - timer_irq; LED light turns off after SCAN_TIME_US